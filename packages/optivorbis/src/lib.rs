@@ -31,16 +31,24 @@
 //! third-party remuxers or other advanced use cases. Most end-users should try
 //! the simpler remuxing interfaces first.
 //!
+//! Finally, [`VorbisStreamInfo`] provides read-only access to the information in the
+//! header packets of a Vorbis stream, such as its codebooks and audio packet modes, as
+//! parsed by the lenient parsers of the optimizer. This is useful for building validators
+//! and other inspection tools.
+//!
 //! ## Ogg Vorbis to Ogg Vorbis remuxer
 //!
 //! OptiVorbis currently ships with an Ogg Vorbis to Ogg Vorbis remuxer, [`OggToOgg`],
 //! which deals with Vorbis packets on unmultiplexed Ogg containers, usually stored in
-//! files with `.ogg` extension. These Ogg Vorbis streams must not contain other
-//! interleaved streams, such as a video stream, but may contain chained
+//! files with `.ogg` extension. By default, these Ogg Vorbis streams must not contain
+//! other interleaved streams, such as a video stream, but may contain chained
 //! (concatenated) Vorbis streams. Non-Vorbis streams (such as Ogg Skeleton metadata
-//! streams) are ignored and not copied. Granule positions (timestamps) are
-//! recomputed, correcting any erroneous information that may be present in the original
-//! stream. Non-zero initial timestamps, chiefly used in live recordings and for
+//! streams) are ignored and not copied. Optionally, grouped (interleaved) streams can be
+//! remuxed too, keeping their original page interleaving, and non-Vorbis streams can be
+//! copied through, updating Ogg Skeleton metadata to match. Opus, Speex and FLAC streams
+//! are recognized, and can be repaginated without changes to their codec data. Granule
+//! positions (timestamps) are recomputed, correcting any erroneous information that may be
+//! present in the original stream. Non-zero initial timestamps, chiefly used in live recordings and for
 //! lossless sample truncation, are supported. Stream serials can be randomized,
 //! making it easier to concatenate (chain) multiple generated files. Ogg page CRC
 //! checksum errors may be ignored, allowing the remuxer to fix more kinds of container
@@ -49,6 +57,41 @@
 //! This encapsulation is the most common means of storing and distributing Vorbis audio
 //! streams.
 //!
+//! ## Matroska Vorbis to Matroska Vorbis remuxer
+//!
+//! The [`MatroskaToMatroska`] remuxer deals with Vorbis tracks in Matroska containers,
+//! including their WebM subset, usually stored in files with `.mka`, `.mkv` or `.webm`
+//! extensions. Unlike the Ogg remuxer, this remuxer supports multiplexed files: tracks
+//! that are not Vorbis, such as video or subtitle tracks, and every other Matroska
+//! element are copied as-is, and the positions referenced by seek and cue elements are
+//! updated to account for the optimized Vorbis data. Vorbis tracks with content encodings
+//! (e.g., compression or encryption) are not supported and also copied as-is.
+//!
+//! ## Ogg Vorbis to WebM remuxer
+//!
+//! The [`OggToWebm`] remuxer reads Vorbis streams from Ogg containers just like
+//! [`OggToOgg`] does, but encapsulates the optimized stream into a WebM file with a single
+//! audio track, which some web browsers play more reliably than Ogg files. Chained Vorbis
+//! streams are not supported, as a WebM track can only have a single set of Vorbis headers.
+//! Block timestamps are computed from the decoded sample counts, and samples truncated at
+//! the end of the original stream are signaled to decoders.
+//!
+//! ## Raw Vorbis packet stream remuxer
+//!
+//! The [`RawToRaw`] remuxer deals with Vorbis streams stored without a container, as
+//! length-prefixed packets, which is a common choice for applications such as game
+//! engines that do their own stream management. Its simple framing is documented in the
+//! [`raw_to_raw`](remuxer::raw_to_raw) module, and its source code is a good starting
+//! point for implementing remuxers for other containers.
+//!
+//! ## Non-seekable sources
+//!
+//! Remuxers need to read their source more than once, so they require it to be seekable.
+//! Data coming from pipes or network streams can be remuxed anyway with the
+//! [`Remuxer::remux_unseekable`] method, which keeps a copy of the source data in memory
+//! and, past a configurable limit, in a temporary file. See the
+//! [`spooled_source`](remuxer::spooled_source) module for more details.
+//!
 //! # Implemented optimizations
 //!
 //! Currently, OptiVorbis optimizes Ogg Vorbis streams in the following ways, leveraging
@@ -128,12 +171,11 @@
 //! The Vorbis I setup header codebook format is vulnerable to denial of service
 //! attacks, as extremely dense prefix code trees, which take a significantly long
 //! time to parse, are valid according to the specification. OptiVorbis does not
-//! impose a depth or density limit in such trees, which guarantees its
-//! interoperability, but renders it vulnerable to specially-crafted files. This
-//! may be addressed in the future as information about the interoperability and
-//! mitigation impact of limiting the tree depth is gathered. In the meantime,
-//! applications dealing with untrusted files should be aware of this and resort
-//! to using OS features to bound resource consumption when applicable.
+//! impose a depth or density limit in such trees by default, which guarantees its
+//! interoperability, but renders it vulnerable to specially-crafted files.
+//! Applications dealing with untrusted files should bound the resources that
+//! processing a stream may take with the
+//! [`resource_limits`](VorbisOptimizerSettings::resource_limits) optimizer setting.
 //!
 //! Some embedded applications ship with Vorbis decoders that deviate from
 //! the Vorbis specification in a critical way: instead of parsing codebooks
@@ -160,6 +202,9 @@
 //! - `source-date-epoch` (enabled by default): enables compatibility with the
 //!   [`SOURCE_DATE_EPOCH` specification] in supporting remuxers, which assists in
 //!   generating reproducible artifacts.
+//! - `parallel`: analyzes and optimizes the links of chained Ogg Vorbis streams in
+//!   parallel, using the [`rayon`](https://docs.rs/rayon) global thread pool, which
+//!   speeds up remuxing long chains on multi-core systems.
 //!
 //! # Examples
 //!
@@ -231,17 +276,24 @@
 #![warn(clippy::redundant_feature_names)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
-pub use remuxer::{Remuxer, ogg_to_ogg::OggToOgg};
+pub use remuxer::{
+	Remuxer, matroska_to_matroska::MatroskaToMatroska, ogg_to_ogg::OggToOgg,
+	ogg_to_webm::OggToWebm, raw_to_raw::RawToRaw
+};
 #[doc(inline)]
 pub use vorbis::codebook::VorbisCodebookError;
 #[doc(inline)]
 pub use vorbis::optimizer::{
-	VorbisCommentFieldsAction, VorbisOptimizer, VorbisOptimizerError, VorbisOptimizerSettings,
-	VorbisVendorStringAction
+	VorbisCodebookInfo, VorbisCommentFieldsAction, VorbisCommentOperation, VorbisFloorInfo,
+	VorbisIdentificationHeaderData, VorbisMappingInfo, VorbisModeInfo, VorbisOptimizer,
+	VorbisOptimizerError, VorbisOptimizerSettings, VorbisOptimizerStatistics, VorbisPicture,
+	VorbisPictureAction, VorbisResidueInfo, VorbisResourceLimit, VorbisResourceLimits,
+	VorbisStreamInfo, VorbisVendorStringAction
 };
 #[doc(inline)]
 pub use vorbis::{
-	PacketType, TryPacketTypeFromInt, TryResidueTypeFromInt, TryVectorLookupTypeFromInt
+	PacketType, ResidueType, TryPacketTypeFromInt, TryResidueTypeFromInt,
+	TryVectorLookupTypeFromInt, VectorLookupType
 };
 
 /// A text tag that identifies this OptiVorbis build.
@@ -5,9 +5,16 @@ use std::{
 	io::{Read, Seek, Write}
 };
 
+use spooled_source::{SpoolSettings, SpooledSource};
+
 use crate::vorbis::optimizer::VorbisOptimizerSettings;
 
+mod matroska;
+pub mod matroska_to_matroska;
 pub mod ogg_to_ogg;
+pub mod ogg_to_webm;
+pub mod raw_to_raw;
+pub mod spooled_source;
 
 /// Defines the contract for any remuxer, responsible for reading Vorbis streams from a container,
 /// optimizing them and encapsulating their optimized representation to a container.
@@ -52,4 +59,17 @@ pub trait Remuxer {
 	/// guarantees are made about the stream position of either `source` or `sink` when this method
 	/// returns.
 	fn remux<R: Read + Seek, W: Write>(&self, source: R, sink: W) -> Result<W, Self::RemuxError>;
+
+	/// Like [`remux`](Self::remux), but for sources that are not seekable, such as pipes or
+	/// network streams. The data read from `source` is kept in a [`SpooledSource`] configured
+	/// with `spool_settings`, so that it can be read again. Errors related to spooling are
+	/// reported as I/O errors.
+	fn remux_unseekable<R: Read, W: Write>(
+		&self,
+		source: R,
+		sink: W,
+		spool_settings: SpoolSettings
+	) -> Result<W, Self::RemuxError> {
+		self.remux(SpooledSource::new(source, spool_settings), sink)
+	}
 }
@@ -0,0 +1,486 @@
+//! Contains EBML and Matroska encoding primitives shared by the remuxers that deal with
+//! Matroska containers, such as WebM files.
+//!
+//! The relevant specifications are [RFC 8794 "Extensible Binary Meta Language"] and
+//! [RFC 9559 "Matroska Media Container Format Specification"]. The encapsulation of Vorbis
+//! streams in Matroska is described in the [Matroska codec mappings].
+//!
+//! [RFC 8794 "Extensible Binary Meta Language"]: https://www.rfc-editor.org/rfc/rfc8794
+//! [RFC 9559 "Matroska Media Container Format Specification"]: https://www.rfc-editor.org/rfc/rfc9559
+//! [Matroska codec mappings]: https://www.matroska.org/technical/codec_specs.html
+
+use std::io::{self, Write};
+
+/// EBML and Matroska element IDs, including their VINT marker bits.
+pub(super) mod element_id {
+	pub(crate) const EBML: u32 = 0x1A45DFA3;
+	pub(crate) const EBML_VERSION: u32 = 0x4286;
+	pub(crate) const EBML_READ_VERSION: u32 = 0x42F7;
+	pub(crate) const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+	pub(crate) const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+	pub(crate) const DOC_TYPE: u32 = 0x4282;
+	pub(crate) const DOC_TYPE_VERSION: u32 = 0x4287;
+	pub(crate) const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+	pub(crate) const CRC_32: u32 = 0xBF;
+	pub(crate) const VOID: u32 = 0xEC;
+
+	pub(crate) const SEGMENT: u32 = 0x18538067;
+
+	pub(crate) const SEEK_HEAD: u32 = 0x114D9B74;
+	pub(crate) const SEEK: u32 = 0x4DBB;
+	pub(crate) const SEEK_ID: u32 = 0x53AB;
+	pub(crate) const SEEK_POSITION: u32 = 0x53AC;
+
+	pub(crate) const INFO: u32 = 0x1549A966;
+	pub(crate) const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+	pub(crate) const DURATION: u32 = 0x4489;
+	pub(crate) const MUXING_APP: u32 = 0x4D80;
+	pub(crate) const WRITING_APP: u32 = 0x5741;
+
+	pub(crate) const TRACKS: u32 = 0x1654AE6B;
+	pub(crate) const TRACK_ENTRY: u32 = 0xAE;
+	pub(crate) const TRACK_NUMBER: u32 = 0xD7;
+	pub(crate) const TRACK_UID: u32 = 0x73C5;
+	pub(crate) const TRACK_TYPE: u32 = 0x83;
+	pub(crate) const CODEC_ID: u32 = 0x86;
+	pub(crate) const CODEC_PRIVATE: u32 = 0x63A2;
+	pub(crate) const AUDIO: u32 = 0xE1;
+	pub(crate) const SAMPLING_FREQUENCY: u32 = 0xB5;
+	pub(crate) const CHANNELS: u32 = 0x9F;
+	pub(crate) const CONTENT_ENCODINGS: u32 = 0x6D80;
+
+	pub(crate) const CLUSTER: u32 = 0x1F43B675;
+	pub(crate) const TIMESTAMP: u32 = 0xE7;
+	pub(crate) const SILENT_TRACKS: u32 = 0x5854;
+	pub(crate) const POSITION: u32 = 0xA7;
+	pub(crate) const PREV_SIZE: u32 = 0xAB;
+	pub(crate) const SIMPLE_BLOCK: u32 = 0xA3;
+	pub(crate) const BLOCK_GROUP: u32 = 0xA0;
+	pub(crate) const BLOCK: u32 = 0xA1;
+	pub(crate) const DISCARD_PADDING: u32 = 0x75A2;
+	pub(crate) const ENCRYPTED_BLOCK: u32 = 0xAF;
+
+	pub(crate) const CUES: u32 = 0x1C53BB6B;
+	pub(crate) const CUE_POINT: u32 = 0xBB;
+	pub(crate) const CUE_TIME: u32 = 0xB3;
+	pub(crate) const CUE_TRACK_POSITIONS: u32 = 0xB7;
+	pub(crate) const CUE_TRACK: u32 = 0xF7;
+	pub(crate) const CUE_CLUSTER_POSITION: u32 = 0xF1;
+	pub(crate) const CUE_RELATIVE_POSITION: u32 = 0xF0;
+}
+
+/// The Matroska codec ID for Vorbis tracks.
+pub(super) const VORBIS_CODEC_ID: &[u8] = b"A_VORBIS";
+/// The value of the `TrackType` element for audio tracks.
+pub(super) const AUDIO_TRACK_TYPE: u64 = 2;
+
+/// The mask of the block header flag bits that signal the lacing used by a block.
+const BLOCK_LACING_FLAGS_MASK: u8 = 0b0000_0110;
+/// The block header flag bits that signal Xiph lacing.
+const BLOCK_XIPH_LACING_FLAGS: u8 = 0b0000_0010;
+/// The block header flag bits that signal fixed-size lacing.
+const BLOCK_FIXED_SIZE_LACING_FLAGS: u8 = 0b0000_0100;
+/// The block header flag bits that signal EBML lacing.
+const BLOCK_EBML_LACING_FLAGS: u8 = 0b0000_0110;
+
+/// Decodes the EBML variable-size integer (VINT) at the beginning of `bytes`, returning its
+/// value and length in bytes. If `keep_marker` is `true`, the VINT marker bit will not be
+/// cleared, which is the convention for representing element IDs. `None` is returned if
+/// `bytes` does not start with a valid VINT of at most 8 bytes.
+pub(super) fn parse_vint(bytes: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+	let first_byte = *bytes.first()?;
+	let length = first_byte.leading_zeros() as usize + 1;
+	if length > 8 {
+		return None;
+	}
+	let vint_bytes = bytes.get(..length)?;
+
+	let mut value = if keep_marker {
+		first_byte as u64
+	} else {
+		// Clear the VINT marker bit and the zero bits that precede it
+		first_byte as u64 & (0xFF >> length)
+	};
+	for byte in &vint_bytes[1..] {
+		value = (value << 8) | *byte as u64;
+	}
+
+	Some((value, length))
+}
+
+/// Returns whether the specified VINT value, of the specified length, has all of its value
+/// bits set to one. For element data sizes, this means that the size is unknown.
+pub(super) const fn is_all_ones_vint(value: u64, length: usize) -> bool {
+	value == (1 << (7 * length)) - 1
+}
+
+/// Returns the minimum length, in bytes, of the VINT that represents the specified element
+/// data size.
+const fn vint_length(value: u64) -> usize {
+	let mut length = 1;
+	// VINTs with all value bits set to one are reserved, so they can't be used for known sizes
+	while length < 8 && value >= (1 << (7 * length)) - 1 {
+		length += 1;
+	}
+	length
+}
+
+/// Returns the length, in bytes, of the specified element ID.
+const fn element_id_length(id: u32) -> usize {
+	4 - id.leading_zeros() as usize / 8
+}
+
+/// Returns the length, in bytes, of the header of an element with the specified ID and
+/// data size.
+pub(super) const fn element_header_length(id: u32, data_size: u64) -> u64 {
+	(element_id_length(id) + vint_length(data_size)) as u64
+}
+
+/// Writes the header of an EBML element with the specified ID and data size to `sink`.
+/// The data size is written with the minimum possible length.
+pub(super) fn write_element_header<W: Write>(
+	mut sink: W,
+	id: u32,
+	data_size: u64
+) -> io::Result<()> {
+	let id_length = element_id_length(id);
+	sink.write_all(&id.to_be_bytes()[4 - id_length..])?;
+
+	let size_length = vint_length(data_size);
+	let size_vint = data_size | 1 << (7 * size_length);
+	sink.write_all(&size_vint.to_be_bytes()[8 - size_length..])
+}
+
+/// Writes an EBML element with the specified ID and binary data to `sink`. Master elements
+/// can be written by passing the concatenation of their child elements as data.
+pub(super) fn write_binary_element(sink: &mut Vec<u8>, id: u32, data: &[u8]) {
+	// Writing to a Vec never fails
+	write_element_header(&mut *sink, id, data.len() as u64).unwrap();
+	sink.extend_from_slice(data);
+}
+
+/// Writes an EBML unsigned integer element with the specified ID and value to `sink`,
+/// using the minimum number of bytes.
+pub(super) fn write_unsigned_integer_element(sink: &mut Vec<u8>, id: u32, value: u64) {
+	let length = (8 - value.leading_zeros() as usize / 8).max(1);
+	write_binary_element(sink, id, &value.to_be_bytes()[8 - length..]);
+}
+
+/// Writes an EBML signed integer element with the specified ID and value to `sink`,
+/// using the minimum number of bytes that keep its two's complement sign bit.
+pub(super) fn write_signed_integer_element(sink: &mut Vec<u8>, id: u32, value: i64) {
+	let redundant_sign_bits = if value < 0 {
+		value.leading_ones()
+	} else {
+		value.leading_zeros()
+	} as usize
+		- 1;
+	let length = (8 - redundant_sign_bits / 8).max(1);
+	write_binary_element(sink, id, &value.to_be_bytes()[8 - length..]);
+}
+
+/// Writes an EBML 8-byte float element with the specified ID and value to `sink`.
+pub(super) fn write_float_element(sink: &mut Vec<u8>, id: u32, value: f64) {
+	write_binary_element(sink, id, &value.to_be_bytes());
+}
+
+/// Decodes the big-endian EBML unsigned integer contained in the data of an element.
+/// Returns `None` if the data is too long to be an unsigned integer.
+pub(super) fn parse_unsigned_integer(data: &[u8]) -> Option<u64> {
+	(data.len() <= 8).then(|| {
+		data.iter()
+			.fold(0, |value, byte| (value << 8) | *byte as u64)
+	})
+}
+
+/// Splits the data of the `CodecPrivate` element of a Matroska Vorbis track into the
+/// identification, comment and setup header packets. As mandated by the Matroska codec
+/// mappings, these packets are stored with Xiph lacing.
+pub(super) fn parse_vorbis_codec_private(codec_private: &[u8]) -> Option<[&[u8]; 3]> {
+	let (&packet_count_minus_one, mut laced_data) = codec_private.split_first()?;
+	if packet_count_minus_one != 2 {
+		return None;
+	}
+
+	let identification_header_length = parse_xiph_lace_size(&mut laced_data)?;
+	let comment_header_length = parse_xiph_lace_size(&mut laced_data)?;
+
+	let (identification_header, laced_data) =
+		laced_data.split_at_checked(identification_header_length)?;
+	let (comment_header, setup_header) = laced_data.split_at_checked(comment_header_length)?;
+
+	Some([identification_header, comment_header, setup_header])
+}
+
+/// Generates the data for the `CodecPrivate` element of a Matroska Vorbis track, given the
+/// identification, comment and setup header packets.
+pub(super) fn vorbis_codec_private(header_packets: [&[u8]; 3]) -> Vec<u8> {
+	let mut codec_private = Vec::with_capacity(
+		header_packets
+			.iter()
+			.map(|packet| packet.len() + packet.len() / 255 + 1)
+			.sum::<usize>()
+			+ 1
+	);
+
+	codec_private.push(2);
+	write_xiph_lace_size(&mut codec_private, header_packets[0].len());
+	write_xiph_lace_size(&mut codec_private, header_packets[1].len());
+	for packet in header_packets {
+		codec_private.extend_from_slice(packet);
+	}
+
+	codec_private
+}
+
+/// Parses a Xiph lacing size from `data`, advancing it past the size.
+fn parse_xiph_lace_size(data: &mut &[u8]) -> Option<usize> {
+	let mut size = 0usize;
+
+	loop {
+		let (&byte, remaining_data) = data.split_first()?;
+		*data = remaining_data;
+		size = size.checked_add(byte as usize)?;
+
+		if byte != 255 {
+			break Some(size);
+		}
+	}
+}
+
+/// Writes the Xiph lacing representation of the specified size to `sink`.
+fn write_xiph_lace_size(sink: &mut Vec<u8>, mut size: usize) {
+	while size >= 255 {
+		sink.push(255);
+		size -= 255;
+	}
+	sink.push(size as u8);
+}
+
+/// A parsed Matroska `SimpleBlock` or `Block` element, as defined in RFC 9559, § 10.
+pub(super) struct Block<'data> {
+	/// The bytes of the block header that precede its flags: the track number and the
+	/// relative timestamp.
+	pub(super) track_number_and_timestamp: &'data [u8],
+	/// The block header flags.
+	pub(super) flags: u8,
+	/// The frames contained in this block, after undoing any lacing.
+	pub(super) frames: Vec<&'data [u8]>
+}
+
+impl<'data> Block<'data> {
+	/// Parses the data of a `SimpleBlock` or `Block` element. Returns `None` if the
+	/// block header or its lacing is not valid.
+	pub(super) fn parse(data: &'data [u8]) -> Option<Self> {
+		let (_, track_number_length) = parse_vint(data, false)?;
+		let track_number_and_timestamp = data.get(..track_number_length + 2)?;
+		let flags = *data.get(track_number_length + 2)?;
+		let laced_data = &data[track_number_length + 3..];
+
+		let frames = match flags & BLOCK_LACING_FLAGS_MASK {
+			0 => vec![laced_data],
+			lacing_flags => {
+				let (&frame_count_minus_one, mut laced_data) = laced_data.split_first()?;
+				let frame_count = frame_count_minus_one as usize + 1;
+
+				let mut frame_sizes = Vec::with_capacity(frame_count);
+				match lacing_flags {
+					BLOCK_XIPH_LACING_FLAGS => {
+						for _ in 1..frame_count {
+							frame_sizes.push(parse_xiph_lace_size(&mut laced_data)?);
+						}
+					}
+					BLOCK_FIXED_SIZE_LACING_FLAGS => {
+						if laced_data.len() % frame_count != 0 {
+							return None;
+						}
+						frame_sizes.resize(frame_count - 1, laced_data.len() / frame_count);
+					}
+					BLOCK_EBML_LACING_FLAGS => {
+						let (first_frame_size, first_frame_size_length) =
+							parse_vint(laced_data, false)?;
+						laced_data = &laced_data[first_frame_size_length..];
+						frame_sizes.push(usize::try_from(first_frame_size).ok()?);
+
+						for _ in 2..frame_count {
+							let (size_difference, size_difference_length) =
+								parse_vint(laced_data, false)?;
+							laced_data = &laced_data[size_difference_length..];
+
+							// Frame size differences are stored as signed VINTs, with
+							// a bias that depends on their length
+							let bias = (1i64 << (7 * size_difference_length - 1)) - 1;
+							let frame_size = (*frame_sizes.last().unwrap() as i64)
+								.checked_add(size_difference as i64 - bias)?;
+							frame_sizes.push(usize::try_from(frame_size).ok()?);
+						}
+					}
+					_ => unreachable!()
+				}
+
+				let mut frames = Vec::with_capacity(frame_count);
+				for frame_size in frame_sizes {
+					let (frame, remaining_data) = laced_data.split_at_checked(frame_size)?;
+					frames.push(frame);
+					laced_data = remaining_data;
+				}
+				frames.push(laced_data);
+
+				frames
+			}
+		};
+
+		Some(Self {
+			track_number_and_timestamp,
+			flags,
+			frames
+		})
+	}
+}
+
+/// Returns the bytes of a `SimpleBlock` or `Block` element header that precede its flags,
+/// for the specified track number, which must be lower than 127, and timestamp relative
+/// to its cluster.
+pub(super) const fn block_track_number_and_timestamp(
+	track_number: u8,
+	relative_timestamp: i16
+) -> [u8; 3] {
+	let [timestamp_high, timestamp_low] = relative_timestamp.to_be_bytes();
+	[0x80 | track_number, timestamp_high, timestamp_low]
+}
+
+/// Writes the data of a `SimpleBlock` or `Block` element to `sink`, given the bytes of
+/// the block header that precede its flags, its flags, and the frames it contains.
+/// Several frames will be stored with Xiph lacing, which is the most compact for audio
+/// frames of differing sizes. At most 256 frames may be stored in a block.
+pub(super) fn write_block(
+	sink: &mut Vec<u8>,
+	track_number_and_timestamp: &[u8],
+	flags: u8,
+	frames: &[&[u8]]
+) {
+	debug_assert!(!frames.is_empty() && frames.len() <= 256);
+
+	sink.extend_from_slice(track_number_and_timestamp);
+
+	match frames {
+		[frame] => {
+			sink.push(flags & !BLOCK_LACING_FLAGS_MASK);
+			sink.extend_from_slice(frame);
+		}
+		[laced_frames @ .., last_frame] => {
+			sink.push(flags & !BLOCK_LACING_FLAGS_MASK | BLOCK_XIPH_LACING_FLAGS);
+			sink.push(laced_frames.len() as u8);
+			for frame in laced_frames {
+				write_xiph_lace_size(sink, frame.len());
+			}
+			for frame in laced_frames {
+				sink.extend_from_slice(frame);
+			}
+			sink.extend_from_slice(last_frame);
+		}
+		[] => ()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn vint_round_trip_works() {
+		for value in [0, 1, 126, 127, 128, 16382, 16383, 1 << 40, (1 << 56) - 2] {
+			let mut encoded = vec![];
+			write_element_header(&mut encoded, element_id::VOID, value).unwrap();
+
+			assert_eq!(
+				encoded.len() as u64,
+				element_header_length(element_id::VOID, value)
+			);
+			assert_eq!(
+				parse_vint(&encoded[1..], false),
+				Some((value, vint_length(value))),
+				"Unexpected decoded size for {value}"
+			);
+		}
+
+		assert_eq!(
+			parse_vint(&element_id::SEGMENT.to_be_bytes(), true),
+			Some((element_id::SEGMENT as u64, 4))
+		);
+		assert_eq!(parse_vint(&[0x00, 0xFF], false), None);
+		assert!(is_all_ones_vint(0x7F, 1));
+	}
+
+	#[test]
+	fn integer_encoding_works() {
+		let encode_unsigned = |value| {
+			let mut encoded = vec![];
+			write_unsigned_integer_element(&mut encoded, element_id::VOID, value);
+			encoded[2..].to_vec()
+		};
+		let encode_signed = |value| {
+			let mut encoded = vec![];
+			write_signed_integer_element(&mut encoded, element_id::VOID, value);
+			encoded[2..].to_vec()
+		};
+
+		assert_eq!(encode_unsigned(0), [0]);
+		assert_eq!(encode_unsigned(255), [255]);
+		assert_eq!(encode_unsigned(256), [1, 0]);
+		assert_eq!(
+			parse_unsigned_integer(&encode_unsigned(u64::MAX)),
+			Some(u64::MAX)
+		);
+
+		assert_eq!(encode_signed(0), [0]);
+		assert_eq!(encode_signed(-1), [0xFF]);
+		assert_eq!(encode_signed(127), [0x7F]);
+		assert_eq!(encode_signed(128), [0x00, 0x80]);
+		assert_eq!(encode_signed(-128), [0x80]);
+		assert_eq!(encode_signed(-129), [0xFF, 0x7F]);
+		assert_eq!(encode_signed(i64::MIN), i64::MIN.to_be_bytes());
+	}
+
+	#[test]
+	fn block_lacing_round_trip_works() {
+		let frames: [&[u8]; 3] = [&[1; 300], &[2; 7], &[3; 20]];
+
+		let mut block_data = vec![];
+		write_block(
+			&mut block_data,
+			&block_track_number_and_timestamp(1, -5),
+			0x80,
+			&frames
+		);
+
+		let block = Block::parse(&block_data).expect("Unexpected block parse error");
+		assert_eq!(block.track_number_and_timestamp, [0x81, 0xFF, 0xFB]);
+		assert_eq!(block.flags, 0x80 | BLOCK_XIPH_LACING_FLAGS);
+		assert_eq!(block.frames, frames);
+	}
+
+	#[test]
+	fn ebml_and_fixed_size_lacing_parsing_works() {
+		// EBML lacing with frame sizes 3, 1 and 2
+		let block = Block::parse(&[0x81, 0x00, 0x00, 0x06, 0x02, 0x83, 0xBD, 1, 1, 1, 2, 3, 3])
+			.expect("Unexpected block parse error");
+		assert_eq!(block.frames, [&[1, 1, 1][..], &[2], &[3, 3]]);
+
+		// Fixed-size lacing with two frames of two bytes each
+		let block = Block::parse(&[0x81, 0x00, 0x00, 0x04, 0x01, 1, 1, 2, 2])
+			.expect("Unexpected block parse error");
+		assert_eq!(block.frames, [&[1, 1][..], &[2, 2]]);
+
+		assert!(Block::parse(&[0x81, 0x00, 0x00, 0x04, 0x01, 1, 1, 2]).is_none());
+	}
+
+	#[test]
+	fn vorbis_codec_private_round_trip_works() {
+		let headers: [&[u8]; 3] = [&[1; 30], &[3; 600], &[5; 4000]];
+
+		let codec_private = vorbis_codec_private(headers);
+		assert_eq!(parse_vorbis_codec_private(&codec_private), Some(headers));
+		assert_eq!(parse_vorbis_codec_private(&codec_private[..40]), None);
+	}
+}
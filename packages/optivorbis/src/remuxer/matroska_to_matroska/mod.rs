@@ -0,0 +1,931 @@
+//! Contains the [`MatroskaToMatroska`] remuxer struct and helper data types.
+
+use std::{
+	collections::{HashMap, HashSet},
+	io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write}
+};
+
+use indexmap::IndexMap;
+use log::{debug, info, warn};
+use thiserror::Error;
+
+use super::{
+	Remuxer,
+	matroska::{
+		Block, VORBIS_CODEC_ID, element_header_length, element_id, is_all_ones_vint,
+		parse_unsigned_integer, parse_vint, parse_vorbis_codec_private, vorbis_codec_private,
+		write_block, write_element_header
+	}
+};
+use crate::vorbis::optimizer::{VorbisOptimizer, VorbisOptimizerError, VorbisOptimizerSettings};
+
+#[cfg(test)]
+mod test;
+
+/// A [`Remuxer`] that processes Vorbis tracks in Matroska containers, including their WebM
+/// subset, generating another Matroska file of the same document type. Tracks that are not
+/// Vorbis, such as video or subtitle tracks, are copied as-is.
+///
+/// Every Matroska element is kept, except for `CRC-32` elements in the elements that this
+/// remuxer may rewrite, as their checksums would be invalidated. The element positions
+/// stored in `SeekHead` and `Cues` elements are updated to match the generated file.
+pub struct MatroskaToMatroska {
+	remuxer_settings: Settings,
+	optimizer_settings: VorbisOptimizerSettings
+}
+
+/// Settings that influence how the remuxing from a Matroska file to another Matroska file
+/// is done.
+pub struct Settings {
+	/// Sets whether not finding any Vorbis track within the Matroska container will be
+	/// considered an error condition. Returning an error when this happens usually is a good
+	/// thing because running OptiVorbis in such cases tends to be a usage mistake. However,
+	/// not returning an error may be helpful for advanced use cases where copying files
+	/// without Vorbis tracks is desirable.
+	///
+	/// **Default value**: `true`
+	pub error_on_no_vorbis_tracks: bool
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			error_on_no_vorbis_tracks: true
+		}
+	}
+}
+
+/// Represents an error that may happen while remuxing with the [`MatroskaToMatroska`]
+/// remuxer.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RemuxError {
+	/// The source data does not start with an EBML header, so it is not a Matroska file.
+	#[error("No EBML header found. Is this Matroska data?")]
+	NotMatroska,
+	/// The EBML document type of the source is not supported by this remuxer.
+	#[error("Unsupported EBML document type: {0}")]
+	UnsupportedDocType(String),
+	/// An EBML element is malformed or does not fit in its parent element.
+	#[error("Malformed EBML element at byte {0}")]
+	MalformedElement(u64),
+	/// The `CodecPrivate` element of a Vorbis track does not contain the three Vorbis
+	/// header packets with the expected lacing.
+	#[error("Invalid Vorbis CodecPrivate data for track {0}")]
+	InvalidCodecPrivate(u64),
+	/// Several `TrackEntry` elements of a segment have the same track number, so it is not
+	/// possible to tell which track their blocks belong to.
+	#[error("Duplicate track number: {0}")]
+	DuplicateTrackNumber(u64),
+	/// A `SimpleBlock` or `Block` element of a Vorbis track has an invalid header or
+	/// frame lacing.
+	#[error("Invalid Matroska block at byte {0}")]
+	InvalidBlock(u64),
+	/// Represents a Vorbis stream optimizer error. This may happen in corrupt Vorbis
+	/// streams, or streams that use unsupported features.
+	#[error("Vorbis optimization error: {0}")]
+	OptimizerError(#[from] VorbisOptimizerError),
+	/// Represents a missing Vorbis track error, which signals that no Vorbis audio
+	/// data was found in the Matroska container.
+	#[error("No Vorbis track found. Is this Matroska Vorbis data?")]
+	NoVorbisTrackFound,
+	/// An I/O error outside any of the previously mentioned error contexts happened.
+	#[error("I/O error: {0}")]
+	IoError(#[from] io::Error)
+}
+
+impl RemuxError {
+	/// Returns a short, stable, machine-readable identifier for the kind of this error.
+	pub const fn code(&self) -> &'static str {
+		match self {
+			Self::NotMatroska => "not_matroska",
+			Self::UnsupportedDocType(_) => "unsupported_doc_type",
+			Self::MalformedElement(_) => "malformed_element",
+			Self::InvalidCodecPrivate(_) => "invalid_codec_private",
+			Self::DuplicateTrackNumber(_) => "duplicate_track_number",
+			Self::InvalidBlock(_) => "invalid_block",
+			Self::OptimizerError(_) => "optimizer_error",
+			Self::NoVorbisTrackFound => "no_vorbis_track_found",
+			Self::IoError(_) => "io_error"
+		}
+	}
+}
+
+impl Remuxer for MatroskaToMatroska {
+	type RemuxError = RemuxError;
+	type RemuxerSettings = Settings;
+
+	fn new(remuxer_settings: Settings, optimizer_settings: VorbisOptimizerSettings) -> Self {
+		Self {
+			remuxer_settings,
+			optimizer_settings
+		}
+	}
+
+	fn remux<R: Read + Seek, W: Write>(&self, source: R, sink: W) -> Result<W, Self::RemuxError> {
+		let mut reader = EbmlReader::new(source)?;
+
+		// Matroska elements must be written with their size in front of them, and the size
+		// of the rewritten Vorbis blocks is not known until they are optimized, so read the
+		// element structure into memory. Only the data that may change is kept in memory
+		let mut elements = read_top_level_elements(&mut reader)?;
+
+		let mut found_vorbis_track = false;
+		for segment in elements
+			.iter_mut()
+			.filter(|element| element.id == element_id::SEGMENT)
+		{
+			let ElementPayload::Master(segment_children) = &mut segment.payload else {
+				unreachable!()
+			};
+
+			// First pass: gather Vorbis track data for optimization
+			info!("Starting first Matroska to Matroska remux pass");
+			let mut vorbis_tracks =
+				first_pass(&mut reader, segment_children, &self.optimizer_settings)?;
+			info!("First Matroska to Matroska remux pass completed");
+
+			found_vorbis_track |= !vorbis_tracks.is_empty();
+
+			// Second pass: optimizing Vorbis CodecPrivate and block rewrite
+			info!("Starting second Matroska to Matroska remux pass");
+			second_pass(&mut reader, segment_children, &mut vorbis_tracks)?;
+			info!("Second Matroska to Matroska remux pass completed");
+		}
+
+		if !found_vorbis_track && self.remuxer_settings.error_on_no_vorbis_tracks {
+			return Err(RemuxError::NoVorbisTrackFound);
+		}
+
+		let mut writer = ElementWriter::new(&mut reader, BufWriter::new(sink), &elements);
+		writer.write_elements(&elements)?;
+
+		writer
+			.sink
+			.into_inner()
+			.map_err(|err| RemuxError::IoError(err.into_error()))
+	}
+}
+
+/// An EBML element read from a Matroska file. The data of the master elements this remuxer
+/// may need to rewrite is parsed into child elements.
+struct Element {
+	id: u32,
+	/// The position of the first byte of the element ID in the source.
+	source_position: u64,
+	/// The position of the first byte of the element data in the source.
+	source_data_position: u64,
+	payload: ElementPayload
+}
+
+/// The data of an [`Element`].
+enum ElementPayload {
+	/// The data is a sequence of child elements.
+	Master(Vec<Element>),
+	/// The data is stored in the source, and will be copied as-is.
+	Source { length: u64 },
+	/// The data is stored in memory, and may have been rewritten.
+	Owned(Vec<u8>),
+	/// The data is an unsigned integer that contains the position of an element relative to
+	/// the beginning of the data of its segment, which should be updated when writing.
+	SegmentPosition(u64),
+	/// The data is an unsigned integer that contains the position of an element relative to
+	/// the beginning of the data of its cluster, which should be updated when writing.
+	ClusterRelativePosition(u64)
+}
+
+impl Element {
+	/// Returns the size of the data of this element, as it will be written.
+	fn data_size(&self) -> u64 {
+		match &self.payload {
+			ElementPayload::Master(children) => children.iter().map(Self::size).sum(),
+			ElementPayload::Source { length } => *length,
+			ElementPayload::Owned(data) => data.len() as u64,
+			// Positions are written with a fixed size, so that the size of the elements
+			// that contain them does not depend on other element positions
+			ElementPayload::SegmentPosition(_) | ElementPayload::ClusterRelativePosition(_) => 8
+		}
+	}
+
+	/// Returns the total size of this element, including its header, as it will be written.
+	fn size(&self) -> u64 {
+		let data_size = self.data_size();
+		element_header_length(self.id, data_size) + data_size
+	}
+
+	/// Returns an iterator over the child elements of this element that have the specified ID.
+	fn children_with_id(&self, id: u32) -> impl Iterator<Item = &Self> {
+		match &self.payload {
+			ElementPayload::Master(children) => children.as_slice(),
+			_ => &[]
+		}
+		.iter()
+		.filter(move |child| child.id == id)
+	}
+
+	/// Returns the in-memory data of the first child element with the specified ID, if any.
+	fn owned_child_data(&self, id: u32) -> Option<&[u8]> {
+		self.children_with_id(id)
+			.find_map(|child| match &child.payload {
+				ElementPayload::Owned(data) => Some(data.as_slice()),
+				_ => None
+			})
+	}
+}
+
+/// How the data of an element is read into memory by [`read_child_elements`].
+enum ElementReadMode {
+	Master,
+	Source,
+	Owned,
+	SegmentPosition,
+	ClusterRelativePosition,
+	Skip
+}
+
+/// The pseudo-ID used for the parent of top-level elements.
+const TOP_LEVEL_PARENT_ID: u32 = 0;
+
+/// Returns how the data of an element with the specified ID should be read, depending on
+/// the ID of its parent.
+fn element_read_mode(parent_id: u32, id: u32) -> ElementReadMode {
+	use element_id::*;
+
+	match (parent_id, id) {
+		(TOP_LEVEL_PARENT_ID, EBML | SEGMENT)
+		| (SEGMENT, SEEK_HEAD | TRACKS | CLUSTER | CUES)
+		| (SEEK_HEAD, SEEK)
+		| (TRACKS, TRACK_ENTRY)
+		| (CLUSTER, BLOCK_GROUP)
+		| (CUES, CUE_POINT)
+		| (CUE_POINT, CUE_TRACK_POSITIONS) => ElementReadMode::Master,
+		(EBML, DOC_TYPE)
+		| (SEEK, SEEK_ID)
+		| (TRACK_ENTRY, TRACK_NUMBER | CODEC_ID | CODEC_PRIVATE | CONTENT_ENCODINGS) => {
+			ElementReadMode::Owned
+		}
+		(SEEK, SEEK_POSITION) | (CUE_TRACK_POSITIONS, CUE_CLUSTER_POSITION) => {
+			ElementReadMode::SegmentPosition
+		}
+		(CUE_TRACK_POSITIONS, CUE_RELATIVE_POSITION) => ElementReadMode::ClusterRelativePosition,
+		(TOP_LEVEL_PARENT_ID | EBML, _) => ElementReadMode::Source,
+		// The checksums of the master elements we parse might be invalidated when writing
+		// them, so drop them. They are optional anyway
+		(_, CRC_32) => ElementReadMode::Skip,
+		_ => ElementReadMode::Source
+	}
+}
+
+/// Returns whether an element with the specified ID may be a child of an element of
+/// unknown size with the specified ID. If it cannot, the element of unknown size ends
+/// just before it.
+fn may_continue_unknown_size_element(parent_id: u32, id: u32) -> bool {
+	use element_id::*;
+
+	match parent_id {
+		SEGMENT => id != EBML,
+		CLUSTER => {
+			matches!(
+				id,
+				TIMESTAMP
+					| SILENT_TRACKS | POSITION
+					| PREV_SIZE | SIMPLE_BLOCK
+					| BLOCK_GROUP | ENCRYPTED_BLOCK
+					| CRC_32 | VOID
+			)
+		}
+		_ => false
+	}
+}
+
+/// Reads the top-level elements of a Matroska file, validating that it starts with an
+/// EBML header for a supported document type.
+fn read_top_level_elements<R: Read + Seek>(
+	reader: &mut EbmlReader<R>
+) -> Result<Vec<Element>, RemuxError> {
+	// Check the first element ID before reading anything else, to not report confusing
+	// errors for data that is not EBML
+	match reader.read_element_header() {
+		Ok(header) if header.id == element_id::EBML => reader.seek(0)?,
+		Ok(_) | Err(RemuxError::MalformedElement(_)) => return Err(RemuxError::NotMatroska),
+		Err(err) => return Err(err)
+	}
+
+	let end_position = reader.length;
+	let elements = read_child_elements(reader, TOP_LEVEL_PARENT_ID, end_position, true)?;
+	let ebml_header = &elements[0];
+
+	// The Matroska specification defines "matroska" as the default document type
+	let doc_type = ebml_header
+		.owned_child_data(element_id::DOC_TYPE)
+		.unwrap_or(b"matroska");
+	match doc_type {
+		b"matroska" | b"webm" => Ok(elements),
+		doc_type => Err(RemuxError::UnsupportedDocType(
+			String::from_utf8_lossy(doc_type).into_owned()
+		))
+	}
+}
+
+/// Reads the child elements of a master element with the specified ID, up to the specified
+/// end position. If the size of the master element is unknown, the children will be read
+/// until an element that can't be a child of it is found.
+fn read_child_elements<R: Read + Seek>(
+	reader: &mut EbmlReader<R>,
+	parent_id: u32,
+	end_position: u64,
+	is_size_known: bool
+) -> Result<Vec<Element>, RemuxError> {
+	let mut children = vec![];
+
+	while reader.position < end_position {
+		let header = reader.read_element_header()?;
+
+		if !is_size_known && !may_continue_unknown_size_element(parent_id, header.id) {
+			reader.seek(header.position)?;
+			break;
+		}
+
+		let read_mode = element_read_mode(parent_id, header.id);
+
+		let (data_end_position, is_child_size_known) = match header.data_size {
+			Some(data_size) => (
+				header
+					.data_position
+					.checked_add(data_size)
+					.filter(|data_end_position| *data_end_position <= end_position)
+					.ok_or(RemuxError::MalformedElement(header.position))?,
+				true
+			),
+			// Only segments and clusters are allowed to have an unknown size, which is
+			// used for live streaming
+			None if matches!(header.id, element_id::SEGMENT | element_id::CLUSTER)
+				&& matches!(read_mode, ElementReadMode::Master) =>
+			{
+				(end_position, false)
+			}
+			None => return Err(RemuxError::MalformedElement(header.position))
+		};
+		let data_length = data_end_position - header.data_position;
+
+		let payload = match read_mode {
+			ElementReadMode::Master => ElementPayload::Master(read_child_elements(
+				reader,
+				header.id,
+				data_end_position,
+				is_child_size_known
+			)?),
+			ElementReadMode::Source => {
+				reader.seek(data_end_position)?;
+				ElementPayload::Source {
+					length: data_length
+				}
+			}
+			ElementReadMode::Owned => ElementPayload::Owned(reader.read_data(data_length)?),
+			ElementReadMode::SegmentPosition => ElementPayload::SegmentPosition(
+				parse_unsigned_integer(&reader.read_data(data_length)?)
+					.ok_or(RemuxError::MalformedElement(header.position))?
+			),
+			ElementReadMode::ClusterRelativePosition => ElementPayload::ClusterRelativePosition(
+				parse_unsigned_integer(&reader.read_data(data_length)?)
+					.ok_or(RemuxError::MalformedElement(header.position))?
+			),
+			ElementReadMode::Skip => {
+				debug!(
+					"Dropping element {:#X} at byte {}",
+					header.id, header.position
+				);
+				reader.seek(data_end_position)?;
+				continue;
+			}
+		};
+
+		children.push(Element {
+			id: header.id,
+			source_position: header.position,
+			source_data_position: header.data_position,
+			payload
+		});
+	}
+
+	Ok(children)
+}
+
+/// Executes the first remuxing pass for a segment, where its Vorbis tracks are read and
+/// analyzed for optimization. The returned map associates the number of each Vorbis track
+/// with its optimizer.
+fn first_pass<'settings, R: Read + Seek>(
+	reader: &mut EbmlReader<R>,
+	segment_children: &mut [Element],
+	optimizer_settings: &'settings VorbisOptimizerSettings
+) -> Result<IndexMap<u64, VorbisOptimizer<'settings>>, RemuxError> {
+	let mut vorbis_tracks = IndexMap::with_capacity(1);
+	let mut track_numbers = HashSet::new();
+
+	for track_entry in segment_children
+		.iter()
+		.filter(|element| element.id == element_id::TRACKS)
+		.flat_map(|tracks| tracks.children_with_id(element_id::TRACK_ENTRY))
+	{
+		let Some(track_number) = track_entry
+			.owned_child_data(element_id::TRACK_NUMBER)
+			.and_then(parse_unsigned_integer)
+		else {
+			continue;
+		};
+
+		if !track_numbers.insert(track_number) {
+			return Err(RemuxError::DuplicateTrackNumber(track_number));
+		}
+
+		if track_entry.owned_child_data(element_id::CODEC_ID) != Some(VORBIS_CODEC_ID) {
+			info!("Copying non-Vorbis track {track_number}");
+			continue;
+		}
+
+		if track_entry
+			.owned_child_data(element_id::CONTENT_ENCODINGS)
+			.is_some()
+		{
+			// Compressed or encrypted frames can't be optimized
+			warn!("Copying Vorbis track {track_number} as-is, as its frames are encoded");
+			continue;
+		}
+
+		info!("Analyzing Vorbis track {track_number}");
+
+		let [identification_header, comment_header, setup_header] = track_entry
+			.owned_child_data(element_id::CODEC_PRIVATE)
+			.and_then(parse_vorbis_codec_private)
+			.ok_or(RemuxError::InvalidCodecPrivate(track_number))?;
+
+		let mut optimizer = VorbisOptimizer::new(optimizer_settings, identification_header)?;
+		optimizer.analyze_packet(comment_header)?;
+		optimizer.analyze_packet(setup_header)?;
+
+		vorbis_tracks.insert(track_number, optimizer);
+	}
+
+	if vorbis_tracks.is_empty() {
+		return Ok(vorbis_tracks);
+	}
+
+	for_each_block(segment_children, |block_element| {
+		let Some(optimizer) = vorbis_tracks.get_mut(&reader.block_track_number(block_element)?)
+		else {
+			return Ok(());
+		};
+
+		let block_data = reader.read_element_data(block_element)?;
+		let block = Block::parse(&block_data)
+			.ok_or(RemuxError::InvalidBlock(block_element.source_position))?;
+
+		for frame in block.frames {
+			optimizer.analyze_packet(frame)?;
+		}
+
+		Ok(())
+	})?;
+
+	Ok(vorbis_tracks)
+}
+
+/// Executes the second remuxing pass for a segment, where its Vorbis tracks are read again,
+/// and their `CodecPrivate` elements and blocks replaced with their optimized versions.
+fn second_pass<R: Read + Seek>(
+	reader: &mut EbmlReader<R>,
+	segment_children: &mut [Element],
+	vorbis_tracks: &mut IndexMap<u64, VorbisOptimizer<'_>>
+) -> Result<(), RemuxError> {
+	if vorbis_tracks.is_empty() {
+		return Ok(());
+	}
+
+	for track_entry in segment_children
+		.iter_mut()
+		.filter(|element| element.id == element_id::TRACKS)
+		.flat_map(|tracks| match &mut tracks.payload {
+			ElementPayload::Master(children) => children.as_mut_slice(),
+			_ => &mut []
+		})
+		.filter(|element| element.id == element_id::TRACK_ENTRY)
+	{
+		let Some((_, &track_number, optimizer)) = track_entry
+			.owned_child_data(element_id::TRACK_NUMBER)
+			.and_then(parse_unsigned_integer)
+			.and_then(|track_number| vorbis_tracks.get_full_mut(&track_number))
+		else {
+			continue;
+		};
+
+		info!("Optimizing Vorbis track {track_number}");
+
+		let ElementPayload::Master(track_entry_children) = &mut track_entry.payload else {
+			unreachable!()
+		};
+		let Some(ElementPayload::Owned(codec_private_data)) = track_entry_children
+			.iter_mut()
+			.find(|element| element.id == element_id::CODEC_PRIVATE)
+			.map(|codec_private| &mut codec_private.payload)
+		else {
+			return Err(RemuxError::InvalidCodecPrivate(track_number));
+		};
+		let header_packets = parse_vorbis_codec_private(codec_private_data)
+			.ok_or(RemuxError::InvalidCodecPrivate(track_number))?;
+
+		let mut optimized_header_packets = Vec::with_capacity(3);
+		for header_packet in header_packets {
+			if let Some((optimized_header_packet, _)) = optimizer.optimize_packet(header_packet)? {
+				optimized_header_packets.push(optimized_header_packet.into_owned());
+			}
+		}
+		let [identification_header, comment_header, setup_header] =
+			optimized_header_packets.as_slice()
+		else {
+			// Header packets are never discarded by the optimizer
+			unreachable!()
+		};
+
+		*codec_private_data =
+			vorbis_codec_private([identification_header, comment_header, setup_header]);
+	}
+
+	for_each_block(segment_children, |block_element| {
+		let Some(optimizer) = vorbis_tracks.get_mut(&reader.block_track_number(block_element)?)
+		else {
+			return Ok(());
+		};
+
+		let block_data = reader.read_element_data(block_element)?;
+		let block = Block::parse(&block_data)
+			.ok_or(RemuxError::InvalidBlock(block_element.source_position))?;
+
+		let mut optimized_frames = Vec::with_capacity(block.frames.len());
+		for frame in block.frames {
+			if let Some((optimized_frame, _)) = optimizer.optimize_packet(frame)? {
+				optimized_frames.push(optimized_frame);
+			}
+		}
+
+		// Frames that a decoder would discard are removed. However, if every frame in a block
+		// is discarded, keep an empty frame, which decoders ignore just as well, so that
+		// the block numbers in any Cues element are not disturbed
+		if optimized_frames.is_empty() {
+			optimized_frames.push(Default::default());
+		}
+
+		let mut optimized_block_data = Vec::with_capacity(block_data.len());
+		write_block(
+			&mut optimized_block_data,
+			block.track_number_and_timestamp,
+			block.flags,
+			&optimized_frames
+				.iter()
+				.map(AsRef::as_ref)
+				.collect::<Vec<_>>()
+		);
+		block_element.payload = ElementPayload::Owned(optimized_block_data);
+
+		Ok(())
+	})
+}
+
+/// Calls the specified closure for every `SimpleBlock` and `Block` element in the clusters
+/// of a segment, in the order they appear.
+fn for_each_block(
+	segment_children: &mut [Element],
+	mut block_callback: impl FnMut(&mut Element) -> Result<(), RemuxError>
+) -> Result<(), RemuxError> {
+	for cluster in segment_children
+		.iter_mut()
+		.filter(|element| element.id == element_id::CLUSTER)
+	{
+		let ElementPayload::Master(cluster_children) = &mut cluster.payload else {
+			unreachable!()
+		};
+
+		for cluster_child in cluster_children {
+			match (cluster_child.id, &mut cluster_child.payload) {
+				(element_id::SIMPLE_BLOCK, _) => block_callback(cluster_child)?,
+				(element_id::BLOCK_GROUP, ElementPayload::Master(block_group_children)) => {
+					for block in block_group_children
+						.iter_mut()
+						.filter(|element| element.id == element_id::BLOCK)
+					{
+						block_callback(block)?;
+					}
+				}
+				_ => ()
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// The header of an EBML element, as read by an [`EbmlReader`].
+struct ElementHeader {
+	id: u32,
+	/// The size of the element data, or `None` if it is unknown.
+	data_size: Option<u64>,
+	/// The position of the first byte of the element ID.
+	position: u64,
+	/// The position of the first byte of the element data.
+	data_position: u64
+}
+
+/// A buffered reader of EBML elements that keeps track of its position, relative to the
+/// position the source had when this reader was created.
+struct EbmlReader<R: Read + Seek> {
+	source: BufReader<R>,
+	initial_source_position: u64,
+	position: u64,
+	length: u64
+}
+
+impl<R: Read + Seek> EbmlReader<R> {
+	fn new(mut source: R) -> io::Result<Self> {
+		let initial_source_position = source.stream_position()?;
+		let end_source_position = source.seek(SeekFrom::End(0))?;
+		source.seek(SeekFrom::Start(initial_source_position))?;
+
+		Ok(Self {
+			source: BufReader::new(source),
+			initial_source_position,
+			position: 0,
+			length: end_source_position.saturating_sub(initial_source_position)
+		})
+	}
+
+	fn seek(&mut self, position: u64) -> io::Result<()> {
+		if position != self.position {
+			// Seeking relatively allows reusing the buffered data if possible
+			match i64::try_from(position as i128 - self.position as i128) {
+				Ok(offset) => self.source.seek_relative(offset)?,
+				Err(_) => {
+					self.source
+						.seek(SeekFrom::Start(self.initial_source_position + position))?;
+				}
+			}
+			self.position = position;
+		}
+
+		Ok(())
+	}
+
+	fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+		self.source.read_exact(buf)?;
+		self.position += buf.len() as u64;
+		Ok(())
+	}
+
+	/// Reads a VINT at the current position, which must have a length of at most
+	/// `maximum_length` bytes.
+	fn read_vint(
+		&mut self,
+		keep_marker: bool,
+		maximum_length: usize
+	) -> Result<(u64, usize), RemuxError> {
+		let vint_position = self.position;
+		let mut vint_bytes = [0; 8];
+
+		self.read_exact(&mut vint_bytes[..1])?;
+		let length = vint_bytes[0].leading_zeros() as usize + 1;
+		if length > maximum_length {
+			return Err(RemuxError::MalformedElement(vint_position));
+		}
+		self.read_exact(&mut vint_bytes[1..length])?;
+
+		Ok(parse_vint(&vint_bytes[..length], keep_marker).unwrap())
+	}
+
+	fn read_element_header(&mut self) -> Result<ElementHeader, RemuxError> {
+		let position = self.position;
+
+		// Truncated element headers are malformed elements
+		let map_eof_err = |err| match err {
+			RemuxError::IoError(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+				RemuxError::MalformedElement(position)
+			}
+			err => err
+		};
+
+		let (id, _) = self.read_vint(true, 4).map_err(map_eof_err)?;
+		let (data_size, data_size_length) = self.read_vint(false, 8).map_err(map_eof_err)?;
+
+		Ok(ElementHeader {
+			id: id as u32,
+			data_size: (!is_all_ones_vint(data_size, data_size_length)).then_some(data_size),
+			position,
+			data_position: self.position
+		})
+	}
+
+	fn read_data(&mut self, length: u64) -> io::Result<Vec<u8>> {
+		let mut data =
+			Vec::with_capacity(length.min(self.length.saturating_sub(self.position)) as usize);
+		(&mut self.source).take(length).read_to_end(&mut data)?;
+		self.position += data.len() as u64;
+
+		if data.len() as u64 == length {
+			Ok(data)
+		} else {
+			Err(io::ErrorKind::UnexpectedEof.into())
+		}
+	}
+
+	/// Reads the data of the specified element, which must not be a master element.
+	fn read_element_data(&mut self, element: &Element) -> io::Result<Vec<u8>> {
+		match &element.payload {
+			ElementPayload::Source { length } => {
+				self.seek(element.source_data_position)?;
+				self.read_data(*length)
+			}
+			ElementPayload::Owned(data) => Ok(data.clone()),
+			_ => unreachable!()
+		}
+	}
+
+	/// Reads the track number of the specified `SimpleBlock` or `Block` element.
+	fn block_track_number(&mut self, block_element: &Element) -> Result<u64, RemuxError> {
+		let ElementPayload::Source { length } = block_element.payload else {
+			unreachable!()
+		};
+
+		self.seek(block_element.source_data_position)?;
+		let track_number_bytes = self.read_data(length.min(8))?;
+
+		parse_vint(&track_number_bytes, false)
+			.map(|(track_number, _)| track_number)
+			.ok_or(RemuxError::InvalidBlock(block_element.source_position))
+	}
+
+	/// Copies the data of the specified length, starting at the specified position, to `sink`.
+	fn copy_data<W: Write>(&mut self, position: u64, length: u64, sink: &mut W) -> io::Result<()> {
+		self.seek(position)?;
+
+		let copied_length = io::copy(&mut (&mut self.source).take(length), sink)?;
+		self.position += copied_length;
+
+		if copied_length == length {
+			Ok(())
+		} else {
+			Err(io::ErrorKind::UnexpectedEof.into())
+		}
+	}
+}
+
+/// Writes the elements of a Matroska file to a sink, updating the element positions stored
+/// within them to match their new values.
+struct ElementWriter<'reader, R: Read + Seek, W: Write> {
+	reader: &'reader mut EbmlReader<R>,
+	sink: W,
+	/// Maps the source position of every element to its position in the sink.
+	element_positions: HashMap<u64, u64>,
+	/// Maps the source position of segments and clusters to the source and sink positions of
+	/// their data.
+	data_positions: HashMap<u64, (u64, u64)>,
+	/// The source and sink positions of the data of the segment being written.
+	current_segment_data_positions: (u64, u64),
+	/// The source and sink positions of the data of the cluster referenced by the
+	/// `CueTrackPositions` element being written, if any.
+	current_cue_cluster_data_positions: Option<(u64, u64)>
+}
+
+impl<'reader, R: Read + Seek, W: Write> ElementWriter<'reader, R, W> {
+	fn new(reader: &'reader mut EbmlReader<R>, sink: W, elements: &[Element]) -> Self {
+		let mut element_writer = Self {
+			reader,
+			sink,
+			element_positions: HashMap::new(),
+			data_positions: HashMap::new(),
+			current_segment_data_positions: (0, 0),
+			current_cue_cluster_data_positions: None
+		};
+		element_writer.lay_out_elements(elements, 0);
+
+		element_writer
+	}
+
+	/// Computes the sink positions of the specified elements, which will be written
+	/// consecutively starting at the specified position.
+	fn lay_out_elements(&mut self, elements: &[Element], mut position: u64) {
+		for element in elements {
+			let data_size = element.data_size();
+			let data_position = position + element_header_length(element.id, data_size);
+
+			self.element_positions
+				.insert(element.source_position, position);
+
+			if let ElementPayload::Master(children) = &element.payload {
+				if matches!(element.id, element_id::SEGMENT | element_id::CLUSTER) {
+					self.data_positions.insert(
+						element.source_position,
+						(element.source_data_position, data_position)
+					);
+				}
+
+				self.lay_out_elements(children, data_position);
+			}
+
+			position = data_position + data_size;
+		}
+	}
+
+	fn write_elements(&mut self, elements: &[Element]) -> Result<(), RemuxError> {
+		for element in elements {
+			write_element_header(&mut self.sink, element.id, element.data_size())?;
+
+			match &element.payload {
+				ElementPayload::Master(children) => {
+					match element.id {
+						element_id::SEGMENT => {
+							self.current_segment_data_positions =
+								self.data_positions[&element.source_position];
+						}
+						element_id::CUE_TRACK_POSITIONS => {
+							let segment_data_source_position =
+								self.current_segment_data_positions.0;
+
+							self.current_cue_cluster_data_positions = element
+								.children_with_id(element_id::CUE_CLUSTER_POSITION)
+								.find_map(|child| match child.payload {
+									ElementPayload::SegmentPosition(position) => {
+										segment_data_source_position.checked_add(position)
+									}
+									_ => None
+								})
+								.and_then(|cluster_source_position| {
+									self.data_positions.get(&cluster_source_position).copied()
+								});
+						}
+						_ => ()
+					}
+
+					self.write_elements(children)?;
+				}
+				ElementPayload::Source { length } => {
+					self.reader
+						.copy_data(element.source_data_position, *length, &mut self.sink)?;
+				}
+				ElementPayload::Owned(data) => self.sink.write_all(data)?,
+				ElementPayload::SegmentPosition(position) => {
+					let (source_data_position, sink_data_position) =
+						self.current_segment_data_positions;
+
+					self.write_position(
+						element,
+						*position,
+						source_data_position,
+						sink_data_position
+					)?;
+				}
+				ElementPayload::ClusterRelativePosition(position) => {
+					match self.current_cue_cluster_data_positions {
+						Some((source_data_position, sink_data_position)) => self.write_position(
+							element,
+							*position,
+							source_data_position,
+							sink_data_position
+						)?,
+						None => {
+							warn!(
+								"Could not update the position in the element at byte {}",
+								element.source_position
+							);
+							self.sink.write_all(&position.to_be_bytes())?;
+						}
+					}
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Writes the data of an element that contains a position relative to the data of its
+	/// parent, given the source and sink positions of that data.
+	fn write_position(
+		&mut self,
+		element: &Element,
+		position: u64,
+		source_data_position: u64,
+		sink_data_position: u64
+	) -> io::Result<()> {
+		let new_position = source_data_position
+			.checked_add(position)
+			.and_then(|source_position| self.element_positions.get(&source_position))
+			.map(|sink_position| sink_position - sink_data_position)
+			.unwrap_or_else(|| {
+				// This happens if the position does not point to the start of an element we
+				// have read, which happens in files with broken indexes. Keep the value as-is
+				warn!(
+					"Could not update the position in the element at byte {}",
+					element.source_position
+				);
+				position
+			});
+
+		self.sink.write_all(&new_position.to_be_bytes())
+	}
+}
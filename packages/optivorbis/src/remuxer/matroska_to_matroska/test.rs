@@ -0,0 +1,447 @@
+use std::io::Cursor;
+
+use ogg::PacketReader;
+
+use super::*;
+use crate::remuxer::matroska::{parse_vint, vorbis_codec_private};
+
+/// The relative position of the subtitle block within the cluster data of the Matroska
+/// files generated by [`matroska_file`].
+const SUBTITLE_BLOCK_CLUSTER_RELATIVE_POSITION: u64 = 10;
+/// The data of the subtitle block in the Matroska files generated by [`matroska_file`].
+const SUBTITLE_BLOCK_DATA: &[u8] = b"\x82\x00\x00\x80Hello, world!";
+/// The number of the Vorbis block with an empty frame in the cluster of the Matroska files
+/// generated by [`matroska_file`], counting from one.
+const EMPTY_VORBIS_BLOCK_NUMBER: u64 = 4;
+/// The ID of the `CueBlockNumber` element, which the remuxer copies as-is.
+const CUE_BLOCK_NUMBER: u32 = 0x5378;
+
+fn element(id: u32, data: &[u8]) -> Vec<u8> {
+	let mut element = vec![];
+	write_element_header(&mut element, id, data.len() as u64).unwrap();
+	element.extend_from_slice(data);
+	element
+}
+
+fn unsigned_integer_element(id: u32, value: u64) -> Vec<u8> {
+	// Always use 8 bytes, so that the element size does not depend on its value
+	element(id, &value.to_be_bytes())
+}
+
+/// Generates a Matroska file with the specified EBML document type, holding a Vorbis track with
+/// the packets of the specified Ogg Vorbis file and a subtitle track, indexed by a `SeekHead`
+/// and a `Cues` element. The Vorbis track also has a block with an empty frame, which is
+/// indexed by the `Cues` element too.
+fn matroska_file(ogg_vorbis_data: &[u8], doc_type: &str, with_vorbis_track: bool) -> Vec<u8> {
+	let mut packet_reader = PacketReader::new(Cursor::new(ogg_vorbis_data));
+	let mut packets = vec![];
+	while let Some(packet) = packet_reader.read_packet().unwrap() {
+		packets.push(packet.data);
+	}
+
+	let mut track_entries = element(
+		element_id::TRACK_ENTRY,
+		&[
+			unsigned_integer_element(element_id::TRACK_NUMBER, 2),
+			unsigned_integer_element(element_id::TRACK_TYPE, 0x11),
+			element(element_id::CODEC_ID, b"S_TEXT/UTF8")
+		]
+		.concat()
+	);
+	if with_vorbis_track {
+		track_entries.extend(element(
+			element_id::TRACK_ENTRY,
+			&[
+				unsigned_integer_element(element_id::TRACK_NUMBER, 1),
+				unsigned_integer_element(element_id::TRACK_TYPE, 2),
+				element(element_id::CODEC_ID, VORBIS_CODEC_ID),
+				element(
+					element_id::CODEC_PRIVATE,
+					&vorbis_codec_private([&packets[0], &packets[1], &packets[2]])
+				)
+			]
+			.concat()
+		));
+	}
+	let tracks = element(element_id::TRACKS, &track_entries);
+
+	let mut cluster_data = [
+		unsigned_integer_element(element_id::TIMESTAMP, 0),
+		element(element_id::SIMPLE_BLOCK, SUBTITLE_BLOCK_DATA),
+		element(element_id::CRC_32, &[0; 4])
+	]
+	.concat();
+	assert_eq!(
+		element_header_length(element_id::TIMESTAMP, 8) + 8,
+		SUBTITLE_BLOCK_CLUSTER_RELATIVE_POSITION
+	);
+
+	let mut empty_vorbis_block_cluster_relative_position = None;
+	if with_vorbis_track {
+		// Put some frames laced in a simple block, one in a block group, an empty frame
+		// that decoders discard in a simple block, and the rest in simple blocks of their own
+		let audio_packets = &packets[3..];
+
+		let mut block_data = vec![];
+		write_block(
+			&mut block_data,
+			&[0x81, 0x00, 0x00],
+			0x80,
+			&audio_packets[..3]
+				.iter()
+				.map(Vec::as_slice)
+				.collect::<Vec<_>>()
+		);
+		cluster_data.extend(element(element_id::SIMPLE_BLOCK, &block_data));
+
+		block_data.clear();
+		write_block(
+			&mut block_data,
+			&[0x81, 0x00, 0x01],
+			0,
+			&[&audio_packets[3]]
+		);
+		cluster_data.extend(element(
+			element_id::BLOCK_GROUP,
+			&element(element_id::BLOCK, &block_data)
+		));
+
+		empty_vorbis_block_cluster_relative_position = Some(cluster_data.len() as u64);
+		block_data.clear();
+		write_block(&mut block_data, &[0x81, 0x00, 0x02], 0x80, &[&[]]);
+		cluster_data.extend(element(element_id::SIMPLE_BLOCK, &block_data));
+
+		for (i, audio_packet) in audio_packets[4..].iter().enumerate() {
+			block_data.clear();
+			write_block(
+				&mut block_data,
+				&[0x81, 0x00, 3 + i as u8],
+				0x80,
+				&[audio_packet]
+			);
+			cluster_data.extend(element(element_id::SIMPLE_BLOCK, &block_data));
+		}
+	}
+	let cluster = element(element_id::CLUSTER, &cluster_data);
+
+	let info = element(
+		element_id::INFO,
+		&unsigned_integer_element(element_id::TIMESTAMP_SCALE, 1_000_000)
+	);
+
+	let seek_head = |tracks_position, cues_position| {
+		element(
+			element_id::SEEK_HEAD,
+			&[
+				element(
+					element_id::SEEK,
+					&[
+						element(element_id::SEEK_ID, &element_id::TRACKS.to_be_bytes()),
+						unsigned_integer_element(element_id::SEEK_POSITION, tracks_position)
+					]
+					.concat()
+				),
+				element(
+					element_id::SEEK,
+					&[
+						element(element_id::SEEK_ID, &element_id::CUES.to_be_bytes()),
+						unsigned_integer_element(element_id::SEEK_POSITION, cues_position)
+					]
+					.concat()
+				)
+			]
+			.concat()
+		)
+	};
+
+	let tracks_position = (seek_head(0, 0).len() + info.len()) as u64;
+	let cluster_position = tracks_position + tracks.len() as u64;
+	let cues_position = cluster_position + cluster.len() as u64;
+
+	let mut cue_point_data = [
+		unsigned_integer_element(element_id::CUE_TIME, 0),
+		element(
+			element_id::CUE_TRACK_POSITIONS,
+			&[
+				unsigned_integer_element(element_id::CUE_TRACK, 2),
+				unsigned_integer_element(element_id::CUE_CLUSTER_POSITION, cluster_position),
+				unsigned_integer_element(
+					element_id::CUE_RELATIVE_POSITION,
+					SUBTITLE_BLOCK_CLUSTER_RELATIVE_POSITION
+				)
+			]
+			.concat()
+		)
+	]
+	.concat();
+	if let Some(empty_vorbis_block_cluster_relative_position) =
+		empty_vorbis_block_cluster_relative_position
+	{
+		cue_point_data.extend(element(
+			element_id::CUE_TRACK_POSITIONS,
+			&[
+				unsigned_integer_element(element_id::CUE_TRACK, 1),
+				unsigned_integer_element(element_id::CUE_CLUSTER_POSITION, cluster_position),
+				unsigned_integer_element(
+					element_id::CUE_RELATIVE_POSITION,
+					empty_vorbis_block_cluster_relative_position
+				),
+				unsigned_integer_element(CUE_BLOCK_NUMBER, EMPTY_VORBIS_BLOCK_NUMBER)
+			]
+			.concat()
+		));
+	}
+	let cues = element(
+		element_id::CUES,
+		&element(element_id::CUE_POINT, &cue_point_data)
+	);
+
+	[
+		element(
+			element_id::EBML,
+			&element(element_id::DOC_TYPE, doc_type.as_bytes())
+		),
+		element(
+			element_id::SEGMENT,
+			&[
+				seek_head(tracks_position, cues_position),
+				info,
+				tracks,
+				cluster,
+				cues
+			]
+			.concat()
+		)
+	]
+	.concat()
+}
+
+/// Returns the ID of the element that starts at the specified position.
+fn element_id_at(data: &[u8], position: u64) -> u32 {
+	parse_vint(&data[position as usize..], true).unwrap().0 as u32
+}
+
+/// Returns the value of the unsigned integer child element with the specified ID.
+fn child_position(element: &Element, id: u32) -> u64 {
+	match element.children_with_id(id).next().unwrap().payload {
+		ElementPayload::SegmentPosition(position)
+		| ElementPayload::ClusterRelativePosition(position) => position,
+		_ => panic!("Unexpected element payload")
+	}
+}
+
+/// Returns the value of the unsigned integer child element with the specified ID, which must
+/// have been copied as-is from the specified data.
+fn source_unsigned_integer_child(data: &[u8], element: &Element, id: u32) -> u64 {
+	let child = element.children_with_id(id).next().unwrap();
+	let ElementPayload::Source { length } = child.payload else {
+		panic!("Unexpected element payload")
+	};
+	parse_unsigned_integer(&data[child.source_data_position as usize..][..length as usize]).unwrap()
+}
+
+fn remux(matroska_data: &[u8]) -> Result<Vec<u8>, RemuxError> {
+	MatroskaToMatroska::new_with_defaults().remux(Cursor::new(matroska_data), vec![])
+}
+
+#[test_log::test]
+fn remuxing_works() {
+	let original_data = matroska_file(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+		"webm",
+		true
+	);
+
+	let remuxed_data = remux(&original_data).expect("Unexpected remuxing error");
+
+	let elements =
+		read_top_level_elements(&mut EbmlReader::new(Cursor::new(&remuxed_data)).unwrap())
+			.expect("Unexpected error reading the remuxed data");
+	let segment = &elements[1];
+	let segment_data_position = remuxed_data.len() as u64 - segment.data_size();
+
+	// The seek entries and cue points should point to the right elements
+	for seek in segment
+		.children_with_id(element_id::SEEK_HEAD)
+		.flat_map(|seek_head| seek_head.children_with_id(element_id::SEEK))
+	{
+		let seek_id = seek.owned_child_data(element_id::SEEK_ID).unwrap();
+		let seek_position = child_position(seek, element_id::SEEK_POSITION);
+
+		assert_eq!(
+			element_id_at(&remuxed_data, segment_data_position + seek_position).to_be_bytes(),
+			seek_id,
+			"Unexpected element at seek position"
+		);
+	}
+
+	let cue_track_positions = segment
+		.children_with_id(element_id::CUES)
+		.flat_map(|cues| cues.children_with_id(element_id::CUE_POINT))
+		.flat_map(|cue_point| cue_point.children_with_id(element_id::CUE_TRACK_POSITIONS))
+		.next()
+		.unwrap();
+	let cluster_position = segment_data_position
+		+ child_position(cue_track_positions, element_id::CUE_CLUSTER_POSITION);
+	let cluster = segment
+		.children_with_id(element_id::CLUSTER)
+		.next()
+		.unwrap();
+	assert_eq!(
+		element_id_at(&remuxed_data, cluster_position),
+		element_id::CLUSTER
+	);
+
+	// The subtitle block should have been copied as-is, and the CRC-32 element dropped
+	let subtitle_block_position = cluster.source_data_position
+		+ child_position(cue_track_positions, element_id::CUE_RELATIVE_POSITION);
+	let subtitle_block_data_position = subtitle_block_position
+		+ element_header_length(element_id::SIMPLE_BLOCK, SUBTITLE_BLOCK_DATA.len() as u64);
+	assert_eq!(
+		&remuxed_data[subtitle_block_data_position as usize..][..SUBTITLE_BLOCK_DATA.len()],
+		SUBTITLE_BLOCK_DATA
+	);
+	assert_eq!(cluster.children_with_id(element_id::CRC_32).count(), 0);
+
+	assert!(
+		remuxed_data.len() < original_data.len(),
+		"The remuxed file is not smaller than the original"
+	);
+
+	// Dogfooding asserts the invariant that the result of optimizing a Matroska file is
+	// another Matroska file we can parse
+	remux(&remuxed_data).expect("Unexpected error remuxing the remuxed data");
+}
+
+#[test_log::test]
+fn cues_keep_pointing_at_emptied_vorbis_blocks() {
+	let original_data = matroska_file(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+		"matroska",
+		true
+	);
+
+	let remuxed_data = remux(&original_data).expect("Unexpected remuxing error");
+
+	let elements =
+		read_top_level_elements(&mut EbmlReader::new(Cursor::new(&remuxed_data)).unwrap())
+			.expect("Unexpected error reading the remuxed data");
+	let segment = &elements[1];
+	let cluster = segment
+		.children_with_id(element_id::CLUSTER)
+		.next()
+		.unwrap();
+	let ElementPayload::Master(cluster_children) = &cluster.payload else {
+		panic!("Unexpected cluster payload")
+	};
+
+	// The second cue track positions element points at the Vorbis block with an empty
+	// frame, both by its position and its number
+	let cue_track_positions = segment
+		.children_with_id(element_id::CUES)
+		.flat_map(|cues| cues.children_with_id(element_id::CUE_POINT))
+		.flat_map(|cue_point| cue_point.children_with_id(element_id::CUE_TRACK_POSITIONS))
+		.nth(1)
+		.unwrap();
+	let cue_block_position = cluster.source_data_position
+		+ child_position(cue_track_positions, element_id::CUE_RELATIVE_POSITION);
+	let cue_block_number =
+		source_unsigned_integer_child(&remuxed_data, cue_track_positions, CUE_BLOCK_NUMBER);
+	assert_eq!(cue_block_number, EMPTY_VORBIS_BLOCK_NUMBER);
+
+	let cue_block = cluster_children
+		.iter()
+		.filter(|element| {
+			matches!(
+				element.id,
+				element_id::SIMPLE_BLOCK | element_id::BLOCK_GROUP
+			)
+		})
+		.nth(cue_block_number as usize - 1)
+		.expect("The cue block number should point at a block");
+	assert_eq!(
+		cue_block.source_position, cue_block_position,
+		"The cue block number and relative position should point at the same block"
+	);
+
+	// The block should still be there, with an empty frame
+	let ElementPayload::Source { length } = cue_block.payload else {
+		panic!("Unexpected block payload")
+	};
+	let block =
+		Block::parse(&remuxed_data[cue_block.source_data_position as usize..][..length as usize])
+			.expect("Unexpected invalid block");
+	assert_eq!(block.track_number_and_timestamp, [0x81, 0x00, 0x02]);
+	assert_eq!(block.frames, [&[] as &[u8]]);
+}
+
+#[test_log::test]
+fn no_vorbis_tracks_returns_error() {
+	let original_data = matroska_file(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+		"matroska",
+		false
+	);
+
+	assert!(matches!(
+		remux(&original_data),
+		Err(RemuxError::NoVorbisTrackFound)
+	));
+
+	let remuxed_data = MatroskaToMatroska::new(
+		Settings {
+			error_on_no_vorbis_tracks: false
+		},
+		Default::default()
+	)
+	.remux(Cursor::new(&original_data), vec![])
+	.expect("Unexpected remuxing error");
+	assert!(
+		remuxed_data.len() < original_data.len(),
+		"The CRC-32 element was not dropped"
+	);
+}
+
+#[test_log::test]
+fn non_matroska_data_returns_error() {
+	assert!(matches!(
+		remux(include_bytes!(
+			"../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"
+		)),
+		Err(RemuxError::NotMatroska)
+	));
+
+	let original_data = matroska_file(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+		"notmatroska",
+		true
+	);
+	assert!(matches!(
+		remux(&original_data),
+		Err(RemuxError::UnsupportedDocType(_))
+	));
+}
+
+#[test_log::test]
+fn duplicate_track_number_returns_error() {
+	let mut original_data = matroska_file(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+		"matroska",
+		true
+	);
+
+	// Make the subtitle track, which has no CodecPrivate element, reuse the number of
+	// the Vorbis track
+	let subtitle_track_number_element = unsigned_integer_element(element_id::TRACK_NUMBER, 2);
+	let subtitle_track_number_position = original_data
+		.windows(subtitle_track_number_element.len())
+		.position(|window| window == subtitle_track_number_element)
+		.unwrap();
+	original_data[subtitle_track_number_position..][..subtitle_track_number_element.len()]
+		.copy_from_slice(&unsigned_integer_element(element_id::TRACK_NUMBER, 1));
+
+	assert!(matches!(
+		remux(&original_data),
+		Err(RemuxError::DuplicateTrackNumber(1))
+	));
+}
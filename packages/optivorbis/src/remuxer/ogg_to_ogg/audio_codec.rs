@@ -0,0 +1,210 @@
+//! Contains code for recognizing Ogg logical bitstreams of audio codecs other than Vorbis,
+//! and computing the sample counts needed to remux them without looking into their audio.
+
+use std::fmt::{self, Display, Formatter};
+
+/// An audio codec other than Vorbis that OptiVorbis can recognize in Ogg logical bitstreams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OggAudioCodec {
+	/// The [Opus](https://opus-codec.org/) codec, encapsulated as specified in RFC 7845.
+	Opus,
+	/// The [Speex](https://www.speex.org/) codec.
+	Speex,
+	/// The [FLAC](https://xiph.org/flac/) codec, encapsulated according to its Ogg mapping.
+	Flac
+}
+
+impl Display for OggAudioCodec {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Opus => "Opus",
+			Self::Speex => "Speex",
+			Self::Flac => "FLAC"
+		})
+	}
+}
+
+/// The codec of a non-Vorbis audio logical bitstream, along with the parameters read from
+/// its identification header that are needed to remux it.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum AudioCodecParameters {
+	Opus,
+	Speex {
+		samples_per_packet: u64,
+		header_packet_count: usize
+	},
+	Flac
+}
+
+impl AudioCodecParameters {
+	/// Recognizes the codec of a logical bitstream from its first packet, returning `None`
+	/// if it is not a known non-Vorbis audio codec.
+	pub(super) fn from_identification_header(packet: &[u8]) -> Option<Self> {
+		if packet.starts_with(b"OpusHead") {
+			Some(Self::Opus)
+		} else if packet.starts_with(b"Speex   ") && packet.len() >= 72 {
+			let read_u32 =
+				|offset: usize| u32::from_le_bytes(packet[offset..offset + 4].try_into().unwrap());
+
+			Some(Self::Speex {
+				samples_per_packet: read_u32(56) as u64 * read_u32(64).max(1) as u64,
+				header_packet_count: (read_u32(68) as usize).saturating_add(2)
+			})
+		} else if packet.starts_with(b"\x7FFLAC") {
+			Some(Self::Flac)
+		} else {
+			None
+		}
+	}
+
+	/// Returns the codec these parameters are for.
+	pub(super) const fn codec(&self) -> OggAudioCodec {
+		match self {
+			Self::Opus => OggAudioCodec::Opus,
+			Self::Speex { .. } => OggAudioCodec::Speex,
+			Self::Flac => OggAudioCodec::Flac
+		}
+	}
+
+	/// Checks whether the packet with the specified number, counting from zero, is a header
+	/// packet. Header packets are always at the beginning of a logical bitstream.
+	pub(super) fn is_header_packet(&self, packet_number: usize, packet: &[u8]) -> bool {
+		match self {
+			Self::Opus => packet_number < 2,
+			Self::Speex {
+				header_packet_count,
+				..
+			} => packet_number < *header_packet_count,
+			// Audio frames begin with a sync code whose first byte is never the first
+			// byte of a metadata block header
+			Self::Flac => packet_number == 0 || packet.first() != Some(&0xFF)
+		}
+	}
+
+	/// Returns the number of samples, in granule position units, encoded in the specified
+	/// audio packet. Packets whose sample count can't be determined are assumed to be empty.
+	pub(super) fn audio_packet_sample_count(&self, packet: &[u8]) -> u64 {
+		match self {
+			Self::Opus => opus_packet_sample_count(packet),
+			Self::Speex {
+				samples_per_packet, ..
+			} => *samples_per_packet,
+			Self::Flac => flac_frame_block_size(packet)
+		}
+	}
+}
+
+/// Returns the number of 48 kHz samples encoded in an Opus packet, as defined in RFC 6716,
+/// § 3.1.
+fn opus_packet_sample_count(packet: &[u8]) -> u64 {
+	let Some(&toc) = packet.first() else {
+		return 0;
+	};
+
+	let config = toc >> 3;
+	let frame_sample_count = match config {
+		// SILK-only modes: 10, 20, 40 or 60 ms frames
+		0..=11 => [480, 960, 1920, 2880][config as usize % 4],
+		// Hybrid modes: 10 or 20 ms frames
+		12..=15 => [480, 960][config as usize % 2],
+		// CELT-only modes: 2.5, 5, 10 or 20 ms frames
+		_ => [120, 240, 480, 960][config as usize % 4]
+	};
+
+	let frame_count = match toc & 0x3 {
+		0 => 1,
+		1 | 2 => 2,
+		_ => packet.get(1).map_or(0, |&frame_count| frame_count & 0x3F)
+	};
+
+	frame_sample_count * frame_count as u64
+}
+
+/// Returns the block size of a FLAC frame, read from its header.
+fn flac_frame_block_size(frame: &[u8]) -> u64 {
+	let Some(&block_size_and_sample_rate) = frame.get(2) else {
+		return 0;
+	};
+
+	match block_size_and_sample_rate >> 4 {
+		1 => 192,
+		block_size @ 2..=5 => 576 << (block_size - 2),
+		block_size @ (6 | 7) => {
+			// The block size is stored after the coded frame or sample number, whose
+			// length is signaled like in UTF-8
+			let coded_number_length = match frame.get(4) {
+				Some(first_byte) => (first_byte.leading_ones() as usize).max(1),
+				None => return 0
+			};
+			let block_size_offset = 4 + coded_number_length;
+
+			match (block_size, frame.get(block_size_offset..)) {
+				(6, Some([block_size, ..])) => *block_size as u64 + 1,
+				(7, Some([block_size_high, block_size_low, ..])) => {
+					u16::from_be_bytes([*block_size_high, *block_size_low]) as u64 + 1
+				}
+				_ => 0
+			}
+		}
+		block_size @ 8..=15 => 256 << (block_size - 8),
+		// Reserved
+		_ => 0
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn opus_packet_sample_count_works() {
+		// CELT 20 ms, one frame
+		assert_eq!(opus_packet_sample_count(&[0xF8, 0x00]), 960);
+		// SILK 60 ms, two frames
+		assert_eq!(opus_packet_sample_count(&[0x1D, 0x00]), 5760);
+		// CELT 2.5 ms, arbitrary number of frames
+		assert_eq!(opus_packet_sample_count(&[0x83, 0x05]), 600);
+		assert_eq!(opus_packet_sample_count(&[]), 0);
+	}
+
+	#[test]
+	fn flac_frame_block_size_works() {
+		// Common block size code
+		assert_eq!(flac_frame_block_size(&[0xFF, 0xF8, 0xC9, 0x08, 0x00]), 4096);
+		// 8-bit block size after a two byte coded frame number
+		assert_eq!(
+			flac_frame_block_size(&[0xFF, 0xF8, 0x69, 0x08, 0xC2, 0xA2, 0x63]),
+			100
+		);
+		// 16-bit block size after a one byte coded frame number
+		assert_eq!(
+			flac_frame_block_size(&[0xFF, 0xF8, 0x79, 0x08, 0x05, 0x01, 0x2B]),
+			300
+		);
+	}
+
+	#[test]
+	fn codec_recognition_works() {
+		let mut speex_header = b"Speex   ".to_vec();
+		speex_header.resize(80, 0);
+		speex_header[56..60].copy_from_slice(&160u32.to_le_bytes());
+		speex_header[64..68].copy_from_slice(&2u32.to_le_bytes());
+
+		assert!(matches!(
+			AudioCodecParameters::from_identification_header(&speex_header),
+			Some(AudioCodecParameters::Speex {
+				samples_per_packet: 320,
+				header_packet_count: 2
+			})
+		));
+		assert!(matches!(
+			AudioCodecParameters::from_identification_header(b"\x7FFLAC\x01\x00\x00\x01fLaC"),
+			Some(AudioCodecParameters::Flac)
+		));
+		assert!(
+			AudioCodecParameters::from_identification_header(b"\x01vorbis\x00\x00\x00\x00")
+				.is_none()
+		);
+	}
+}
@@ -1,6 +1,6 @@
 //! Contains code for calculating valid, sensible granule positions for Ogg Vorbis packets.
 
-use super::{Settings, VorbisStreamState, ogg_vorbis_stream_mangler::OggVorbisStreamMangler};
+use super::VorbisStreamState;
 
 /// Calculates a valid granule position for an Ogg Vorbis packet, assuming that:
 ///
@@ -19,13 +19,13 @@ use super::{Settings, VorbisStreamState, ogg_vorbis_stream_mangler::OggVorbisStr
 /// granule position of the original first audio page from the expected one, which is assumed
 /// to start at time zero. This offset is used to adjust the granule positions of any following
 /// packets, so any lossless beginning sample truncation or livestream running time information
-/// is also kept. This offset can be ignored via the appropriate `remuxer_settings` configuration.
-pub(super) fn granule_position_for_packet<M: OggVorbisStreamMangler>(
+/// is also kept. This offset is ignored if `ignore_start_sample_offset` is `true`.
+pub(crate) fn granule_position_for_packet(
 	packet_sample_block_size: Option<u16>,
 	packet_number: usize,
 	packet_page_granule_position: u64,
 	is_last_stream_packet: bool,
-	remuxer_settings: &Settings<M>,
+	ignore_start_sample_offset: bool,
 	stream_state: &mut VorbisStreamState
 ) -> i64 {
 	match (
@@ -89,12 +89,11 @@ pub(super) fn granule_position_for_packet<M: OggVorbisStreamMangler>(
 			#[allow(clippy::identity_op)]
 			let start_granule_position_offset = first_audio_page_granule_position - 0;
 
-			let actual_granule_position =
-				0i64.wrapping_add(if remuxer_settings.ignore_start_sample_offset {
-					0
-				} else {
-					start_granule_position_offset
-				});
+			let actual_granule_position = 0i64.wrapping_add(if ignore_start_sample_offset {
+				0
+			} else {
+				start_granule_position_offset
+			});
 
 			stream_state.start_granule_position_offset = Some(start_granule_position_offset);
 			stream_state.last_written_packet_granule_position = Some(actual_granule_position);
@@ -194,13 +193,12 @@ pub(super) fn granule_position_for_packet<M: OggVorbisStreamMangler>(
 			let start_granule_position_offset =
 				first_audio_page_granule_position.saturating_sub(calculated_granule_position);
 
-			let actual_granule_position = calculated_granule_position.wrapping_add(
-				if remuxer_settings.ignore_start_sample_offset {
+			let actual_granule_position =
+				calculated_granule_position.wrapping_add(if ignore_start_sample_offset {
 					0
 				} else {
 					start_granule_position_offset
-				}
-			);
+				});
 
 			stream_state.start_granule_position_offset = Some(start_granule_position_offset);
 			stream_state.last_written_packet_granule_position = Some(actual_granule_position);
@@ -241,7 +239,7 @@ pub(super) fn granule_position_for_packet<M: OggVorbisStreamMangler>(
 			let start_granule_position_offset =
 				stream_state.start_granule_position_offset.unwrap_or(0);
 
-			let minimum_expected_granule_position = if remuxer_settings.ignore_start_sample_offset {
+			let minimum_expected_granule_position = if ignore_start_sample_offset {
 				// Undo original stream offset ignore for comparison
 				last_written_packet_granule_position
 					.wrapping_add(start_granule_position_offset)
@@ -249,7 +247,7 @@ pub(super) fn granule_position_for_packet<M: OggVorbisStreamMangler>(
 			} else {
 				last_written_packet_granule_position.wrapping_add(1)
 			};
-			let maximum_expected_granule_position = if remuxer_settings.ignore_start_sample_offset {
+			let maximum_expected_granule_position = if ignore_start_sample_offset {
 				// Undo original stream offset ignore for comparison
 				calculated_granule_position.wrapping_add(start_granule_position_offset)
 			} else {
@@ -265,7 +263,7 @@ pub(super) fn granule_position_for_packet<M: OggVorbisStreamMangler>(
 			if (minimum_expected_granule_position..=maximum_expected_granule_position)
 				.contains(&original_granule_position)
 			{
-				if remuxer_settings.ignore_start_sample_offset {
+				if ignore_start_sample_offset {
 					original_granule_position.wrapping_sub(start_granule_position_offset)
 				} else {
 					original_granule_position
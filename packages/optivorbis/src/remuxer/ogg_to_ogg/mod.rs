@@ -1,8 +1,9 @@
 //! Contains the [`OggToOgg`] remuxer struct and helper data types.
 
 use std::{
+	borrow::Cow,
 	cell::RefCell,
-	collections::hash_map::DefaultHasher,
+	collections::{HashMap, HashSet, hash_map::DefaultHasher},
 	env,
 	hash::Hasher,
 	io::{self, Read, Seek, SeekFrom, Write},
@@ -11,29 +12,59 @@ use std::{
 	time::UNIX_EPOCH
 };
 
-use granulator::granule_position_for_packet;
-use indexmap::{IndexMap, map::Entry};
+use audio_codec::AudioCodecParameters;
+#[doc(inline)]
+pub use audio_codec::OggAudioCodec;
+pub(super) use granulator::granule_position_for_packet;
+use indexmap::IndexMap;
 use log::info;
-use ogg::{OggReadError, PacketReader, PacketWriteEndInfo, PacketWriter, PageParsingOptions};
+use ogg::{
+	OggReadError, Packet, PacketReader, PacketWriteEndInfo, PacketWriter, PageParsingOptions,
+	reading::BasePacketReader
+};
 #[doc(inline)]
 pub use ogg_vorbis_stream_mangler::{OggVorbisStreamMangler, OggVorbisStreamPassthroughMangler};
 use rand_xoshiro::{
 	Xoshiro256PlusPlus,
 	rand_core::{Rng, SeedableRng}
 };
+use raw_page_reader::RawOggPageReader;
+#[doc(inline)]
+pub use report::{RemuxReport, VorbisStreamReport};
+#[doc(inline)]
+pub use size_breakdown::OggVorbisSizeBreakdown;
+use size_breakdown::{SizeBreakdownCounter, SizeBreakdownWriter};
+use skeleton::SkeletonRewriter;
 use thiserror::Error;
 
 use super::Remuxer;
 use crate::vorbis::optimizer::{VorbisOptimizer, VorbisOptimizerError, VorbisOptimizerSettings};
 
+mod audio_codec;
 mod granulator;
 mod ogg_vorbis_stream_mangler;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod raw_page_reader;
+mod report;
+mod size_breakdown;
+mod skeleton;
 #[cfg(test)]
 mod test;
 
 /// A [`Remuxer`] that processes Vorbis streams in unmultiplexed Ogg containers, generating
-/// another Ogg Vorbis file. Non-Vorbis streams will be ignored. Chained Vorbis streams are
-/// supported.
+/// another Ogg Vorbis file. Non-Vorbis streams will be ignored, unless the
+/// [`preserve_stream_multiplexing`](Settings::preserve_stream_multiplexing),
+/// [`preserve_non_vorbis_streams`](Settings::preserve_non_vorbis_streams) or
+/// [`remux_non_vorbis_audio_streams`](Settings::remux_non_vorbis_audio_streams) settings are
+/// enabled.
+/// Chained Vorbis streams are supported. Grouped (concurrently multiplexed) streams are
+/// supported too if the
+/// [`preserve_stream_multiplexing`](Settings::preserve_stream_multiplexing) setting is
+/// enabled.
+///
+/// If the `parallel` feature is enabled, the links of chained Vorbis streams are analyzed and
+/// optimized in parallel, yielding the same results.
 ///
 /// If the `source-date-epoch` feature is enabled, this remuxer honors the
 /// [`SOURCE_DATE_EPOCH` specification]: it reads the `SOURCE_DATE_EPOCH` environment variable
@@ -134,6 +165,87 @@ pub struct Settings<M: OggVorbisStreamMangler> {
 	///
 	/// **Default value**: `true`
 	pub verify_ogg_page_checksums: bool,
+	/// Sets whether Ogg physical bitstreams with grouped (concurrently multiplexed) logical
+	/// bitstreams, such as files with several audio tracks or with Theora video, will be
+	/// remuxed keeping their structure. When enabled, the optimized Vorbis pages are written
+	/// out at the same points of the original page interleaving order, and the pages of
+	/// non-Vorbis streams are copied through byte-for-byte. When disabled, non-Vorbis streams
+	/// are dropped, unless [`preserve_non_vorbis_streams`](Self::preserve_non_vorbis_streams)
+	/// is enabled, and finding grouped Vorbis streams is an error.
+	///
+	/// The Vorbis streams are still assigned new serials, skipping the serials of the copied
+	/// streams to avoid clashes. Streams that refer to other streams, such as Ogg Skeleton
+	/// metadata streams, are copied as-is, so their references may become stale, unless
+	/// [`preserve_non_vorbis_streams`](Self::preserve_non_vorbis_streams) is enabled too.
+	///
+	/// Ending pages at the original interleaving points adds some container overhead, so
+	/// this should be left disabled unless the other streams are needed.
+	///
+	/// **Default value**: `false`
+	pub preserve_stream_multiplexing: bool,
+	/// Sets whether non-Vorbis logical bitstreams, such as Ogg Skeleton metadata, Kate
+	/// subtitles or Theora video streams, will be copied through to the generated Ogg file
+	/// byte-for-byte instead of being dropped, even if
+	/// [`preserve_stream_multiplexing`](Self::preserve_stream_multiplexing) is disabled. Like
+	/// with that setting, the optimized Vorbis pages are written out at the same points of
+	/// the original page interleaving order, so that the copied streams stay in sync with the
+	/// Vorbis streams.
+	///
+	/// The Vorbis streams are still assigned new serials, skipping the serials of the copied
+	/// streams to avoid clashes. Ogg Skeleton streams are rewritten to refer to these new
+	/// serials, and their keyframe indexes and segment information to refer to the new page
+	/// offsets.
+	///
+	/// Rewriting Skeleton streams requires holding all the generated data that follows the
+	/// first Skeleton page in memory until the remux finishes, because Skeleton pages refer
+	/// to the pages after them with variable-length offsets, so their rewritten size is not
+	/// known until those pages are written. This holds even for seekable sinks. Skeleton streams
+	/// usually start at the beginning of the file, so for files that have one, such as most
+	/// Theora and Kate files, memory usage grows with the size of the whole generated file.
+	/// Files without Skeleton streams are written out as they are generated.
+	///
+	/// **Default value**: `false`
+	pub preserve_non_vorbis_streams: bool,
+	/// Sets whether Opus, Speex and FLAC logical bitstreams will be remuxed losslessly, like
+	/// Vorbis streams are: their packets are put in new pages, and their granule positions are
+	/// recomputed. Their codec data is not optimized, but is not changed in any way either.
+	/// When disabled, these streams are dealt with like any other non-Vorbis stream, and finding
+	/// only them is reported with a [`RemuxError::UnsupportedCodec`] error if
+	/// [`error_on_no_vorbis_streams`](Self::error_on_no_vorbis_streams) is enabled.
+	///
+	/// This is mainly useful for repairing the container metadata of files with these codecs,
+	/// or for processing collections of Ogg files that do not only contain Vorbis audio.
+	///
+	/// **Default value**: `false`
+	pub remux_non_vorbis_audio_streams: bool,
+	/// Sets the maximum size, in bytes, of the Vorbis audio packet data that will be put in a
+	/// single Ogg page. When adding a packet to a page would exceed this size, a new page is
+	/// started for it instead. Header packets are not affected. Packets larger than this size
+	/// still take a page of their own, and the page size limit of the Ogg format always
+	/// applies. If `None`, pages will be as large as the Ogg format allows.
+	///
+	/// By default, OptiVorbis packs as many packets per page as possible to minimize container
+	/// overhead. Smaller pages reduce the amount of data that players need to fetch to start
+	/// or seek playback, which matters for streaming applications.
+	///
+	/// **Default value**: `None`
+	pub max_page_payload_size: Option<usize>,
+	/// Sets the maximum duration, in samples, of the Vorbis audio packets that will be put in
+	/// a single Ogg page, measured as the difference between its granule position and the
+	/// granule position of the previous page. When adding a packet to a page would exceed this
+	/// duration, a new page is started for it instead. If `None`, page duration is not limited.
+	///
+	/// Ogg pages are the finest seeking granularity available to players, so this setting
+	/// allows bounding seek precision independently of the bitrate.
+	///
+	/// **Default value**: `None`
+	pub max_page_duration: Option<u64>,
+	/// Sets the number of audio packets at the beginning of every Vorbis stream that will be
+	/// put in a page of their own. This reduces the amount of data that players need to fetch
+	/// to start playback, at the cost of some container overhead.
+	///
+	/// **Default value**: `0`
+	pub single_packet_audio_page_count: usize,
 	/// Sets the [mangler](OggVorbisStreamMangler) that will have a final say on some values
 	/// generated for the Ogg page and packet encapsulations. OptiVorbis almost always does the
 	/// right thing by itself, so **using manglers others than the
@@ -149,25 +261,73 @@ impl Default for Settings<OggVorbisStreamPassthroughMangler> {
 			ignore_start_sample_offset: false,
 			error_on_no_vorbis_streams: true,
 			verify_ogg_page_checksums: true,
+			preserve_stream_multiplexing: false,
+			preserve_non_vorbis_streams: false,
+			remux_non_vorbis_audio_streams: false,
+			max_page_payload_size: None,
+			max_page_duration: None,
+			single_packet_audio_page_count: 0,
 			vorbis_stream_mangler: OggVorbisStreamPassthroughMangler
 		}
 	}
 }
 
 /// Holds the state needed for an optimizing remux of an Ogg Vorbis stream.
-struct VorbisStreamState<'settings> {
-	optimizer: VorbisOptimizer<'settings>,
+pub(super) struct VorbisStreamState<'settings> {
+	pub(super) optimizer: VorbisOptimizer<'settings>,
 	original_last_audio_packet_in_first_audio_page_granule_position: Option<(i64, usize)>,
 	last_written_packet_granule_position: Option<i64>,
 	last_written_packet_sample_block_size: Option<u16>,
 	start_granule_position_offset: Option<i64>,
-	analyzed_packet_count: usize,
+	pub(super) analyzed_packet_count: usize,
 	optimized_packet_count: usize,
-	checksum: u32
+	checksum: u32,
+	pending_packet: Option<OptimizedPacket>,
+	page_payload_size: usize,
+	page_start_granule_position: i64
+}
+
+/// An optimized Vorbis packet, ready to be written to an Ogg page.
+struct OptimizedPacket {
+	data: Cow<'static, [u8]>,
+	stream_serial: u32,
+	packet_number: usize,
+	is_last_stream_packet: bool,
+	page_end_info: PacketWriteEndInfo,
+	granule_position: i64
+}
+
+/// Holds the state needed for a lossless remux of a non-Vorbis audio stream, whose packets
+/// are copied as-is to new pages.
+struct AudioStreamState {
+	codec_parameters: AudioCodecParameters,
+	stream_serial: u32,
+	packet_count: usize,
+	header_packet_count: usize,
+	sample_count: u64,
+	page_sample_count: u64,
+	last_page_sample_count: u64,
+	last_page_granule_position: u64,
+	first_audio_page_granule_position: Option<(u64, u64)>,
+	granule_position: u64,
+	written_packet_count: usize
+}
+
+/// Holds the results of the first remuxing pass.
+pub(super) struct FirstPassResults<'settings> {
+	/// The state of the Vorbis streams to remux, keyed by their serial.
+	pub(super) vorbis_streams: IndexMap<u32, VorbisStreamState<'settings>>,
+	/// The state of the non-Vorbis audio streams to remux, keyed by their serial.
+	audio_streams: IndexMap<u32, AudioStreamState>,
+	/// The serials of the non-Vorbis streams.
+	non_vorbis_stream_serials: HashSet<u32>,
+	/// The serials of the Ogg Skeleton streams, which are a subset of the non-Vorbis streams.
+	skeleton_stream_serials: HashSet<u32>
 }
 
 /// Represents an error that may happen while remuxing with the [`OggToOgg`] remuxer.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum RemuxError {
 	/// Represents an Ogg container decoding error, which may be an I/O error.
 	#[error("Ogg read error: {0}")]
@@ -177,13 +337,24 @@ pub enum RemuxError {
 	#[error("Vorbis optimization error: {0}")]
 	OptimizerError(#[from] VorbisOptimizerError),
 	/// Represents an unsupported Ogg container multiplexing error, which happens if
-	/// several streams are concurrently multiplexed.
+	/// several Vorbis streams are concurrently multiplexed and the
+	/// [`preserve_stream_multiplexing`](Settings::preserve_stream_multiplexing) setting
+	/// is disabled.
 	#[error("Remuxing Ogg bitstreams with grouped logical bitstreams is not supported")]
 	UnsupportedStreamMultiplexing,
 	/// Represents a missing Vorbis stream error, which signals that no Vorbis audio
 	/// data was found in the Ogg container.
 	#[error("No Vorbis bitstream found. Is this Ogg Vorbis data?")]
 	NoVorbisStreamFound,
+	/// Represents an unsupported codec error, which signals that no Vorbis audio data was
+	/// found in the Ogg container, but data encoded with another known audio codec was. This
+	/// error is only returned when the
+	/// [`remux_non_vorbis_audio_streams`](Settings::remux_non_vorbis_audio_streams) setting is
+	/// disabled.
+	#[error(
+		"No Vorbis bitstream found, but found {0} audio data. Only Vorbis audio can be optimized"
+	)]
+	UnsupportedCodec(OggAudioCodec),
 	/// The value of the `SOURCE_DATE_EPOCH` environment variable does not conform to
 	/// the [`SOURCE_DATE_EPOCH` specification].
 	///
@@ -196,6 +367,24 @@ pub enum RemuxError {
 	IoError(#[from] io::Error)
 }
 
+impl RemuxError {
+	/// Returns a short, stable, machine-readable identifier for the kind of this error.
+	/// Optimizer errors have a more specific code, available via
+	/// [`VorbisOptimizerError::code`].
+	pub const fn code(&self) -> &'static str {
+		match self {
+			Self::OggError(_) => "ogg_error",
+			Self::OptimizerError(_) => "optimizer_error",
+			Self::UnsupportedStreamMultiplexing => "unsupported_stream_multiplexing",
+			Self::NoVorbisStreamFound => "no_vorbis_stream_found",
+			Self::UnsupportedCodec(_) => "unsupported_codec",
+			#[cfg(any(doc, feature = "source-date-epoch"))]
+			Self::InvalidSourceDateEpoch => "invalid_source_date_epoch",
+			Self::IoError(_) => "io_error"
+		}
+	}
+}
+
 impl<M: OggVorbisStreamMangler> Remuxer for OggToOgg<M> {
 	type RemuxError = RemuxError;
 	type RemuxerSettings = Settings<M>;
@@ -211,18 +400,88 @@ impl<M: OggVorbisStreamMangler> Remuxer for OggToOgg<M> {
 		}
 	}
 
-	fn remux<R: Read + Seek, W: Write>(
+	fn remux<R: Read + Seek, W: Write>(&self, source: R, sink: W) -> Result<W, Self::RemuxError> {
+		self.remux_vorbis_streams(source, sink)
+			.map(|(sink, _)| sink)
+	}
+}
+
+impl<M: OggVorbisStreamMangler> OggToOgg<M> {
+	/// Remuxes like [`remux`](Remuxer::remux) does, but also returns a report about the
+	/// remux, which includes a breakdown of the size of the source and the generated data,
+	/// and details about the optimizations done to each Vorbis stream.
+	///
+	/// Computing the size breakdown of the source requires reading it once more, so this
+	/// method is somewhat slower than [`remux`](Remuxer::remux).
+	pub fn remux_with_report<R: Read + Seek, W: Write>(
+		&self,
+		mut source: R,
+		sink: W
+	) -> Result<(W, RemuxReport), RemuxError> {
+		let initial_source_pos = source.stream_position()?;
+
+		let mut original_size_counter = SizeBreakdownCounter::default();
+		let mut page_reader =
+			RawOggPageReader::new(&mut source, Arc::clone(&self.ogg_page_parsing_options));
+		while let Some(page) = page_reader.read_page()? {
+			original_size_counter.count_page(&page.data);
+		}
+
+		source.seek(SeekFrom::Start(initial_source_pos))?;
+		let (optimized_size_writer, vorbis_streams) =
+			self.remux_vorbis_streams(source, SizeBreakdownWriter::new(sink))?;
+
+		Ok((
+			optimized_size_writer.sink,
+			RemuxReport {
+				original: original_size_counter.breakdown,
+				optimized: optimized_size_writer.counter.breakdown,
+				vorbis_streams: vorbis_streams
+					.into_iter()
+					.map(|(stream_serial, stream_state)| VorbisStreamReport {
+						stream_serial,
+						identification_header_data: *stream_state
+							.optimizer
+							.identification_header_data(),
+						optimizer_statistics: stream_state.optimizer.statistics().clone()
+					})
+					.collect()
+			}
+		))
+	}
+
+	/// Runs both remuxing passes like [`remux`](Remuxer::remux) does, but discards the
+	/// generated data instead of writing it to a sink, returning a report like
+	/// [`remux_with_report`](Self::remux_with_report) does. This is useful to know how
+	/// much smaller a file would become before remuxing it.
+	///
+	/// The stream position of `source` is handled like [`remux`](Remuxer::remux) does. The
+	/// [`OggVorbisStreamMangler`] is used as usual, so any side effects it may have will
+	/// happen.
+	pub fn dry_run<R: Read + Seek>(&self, source: R) -> Result<RemuxReport, RemuxError> {
+		self.remux_with_report(source, io::sink())
+			.map(|(_, report)| report)
+	}
+
+	/// Implements [`remux`](Remuxer::remux), returning the final state of the remuxed
+	/// Vorbis streams too.
+	fn remux_vorbis_streams<R: Read + Seek, W: Write>(
 		&self,
 		mut source: R,
 		mut sink: W
-	) -> Result<W, Self::RemuxError> {
+	) -> Result<(W, IndexMap<u32, VorbisStreamState<'_>>), RemuxError> {
 		// Remember the source stream position to rewind to it later
 		let initial_source_pos = source.stream_position()?;
 		let remuxer_settings = &mut *self.remuxer_settings.borrow_mut();
 
 		// First pass: validate and gather stream data for optimization
 		info!("Starting first Ogg to Ogg remux pass");
-		let mut vorbis_streams = first_pass(
+		let FirstPassResults {
+			mut vorbis_streams,
+			mut audio_streams,
+			non_vorbis_stream_serials,
+			skeleton_stream_serials
+		} = first_pass(
 			&mut source,
 			&self.optimizer_settings,
 			remuxer_settings,
@@ -246,54 +505,126 @@ impl<M: OggVorbisStreamMangler> Remuxer for OggToOgg<M> {
 				(remuxer_settings.first_stream_serial_offset, 1)
 			};
 
+		// Streams we copy through keep their serials, so don't reuse them
+		let reserved_stream_serials = if remuxer_settings.preserve_stream_multiplexing
+			|| remuxer_settings.preserve_non_vorbis_streams
+		{
+			non_vorbis_stream_serials
+		} else {
+			HashSet::new()
+		};
+		let mut vorbis_stream_serials = vorbis_stream_serials(
+			vorbis_streams.len() + audio_streams.len(),
+			first_stream_serial,
+			stream_serial_increment,
+			&reserved_stream_serials
+		);
+		for (stream_state, stream_serial) in audio_streams
+			.values_mut()
+			.zip(vorbis_stream_serials.split_off(vorbis_streams.len()))
+		{
+			stream_state.stream_serial = stream_serial;
+		}
+
 		// Rewind for the second pass
 		source.seek(SeekFrom::Start(initial_source_pos))?;
 
 		// Second pass: optimizing Vorbis packet rewrite
 		info!("Starting second Ogg to Ogg remux pass");
-		second_pass(
-			source,
-			&mut sink,
-			&mut vorbis_streams,
-			remuxer_settings,
-			&self.ogg_page_parsing_options,
-			first_stream_serial,
-			stream_serial_increment
-		)?;
+		if remuxer_settings.preserve_non_vorbis_streams && !skeleton_stream_serials.is_empty() {
+			// Skeleton streams refer to pages that come after them, so buffer the remuxed data
+			// to rewrite them at the end
+			let mut skeleton_rewriter = SkeletonRewriter::new();
+			let buffered_data = multiplexed_second_pass(
+				source,
+				vec![],
+				&mut vorbis_streams,
+				&mut audio_streams,
+				remuxer_settings,
+				&self.ogg_page_parsing_options,
+				&vorbis_stream_serials,
+				Some((&mut skeleton_rewriter, &skeleton_stream_serials))
+			)?;
+
+			let stream_serials = vorbis_streams
+				.keys()
+				.copied()
+				.zip(vorbis_stream_serials)
+				.chain(audio_streams.iter().map(|(&stream_serial, stream_state)| {
+					(stream_serial, stream_state.stream_serial)
+				}))
+				.collect::<HashMap<_, _>>();
+			skeleton_rewriter.write_rewritten_data(&buffered_data, &stream_serials, &mut sink)?;
+		} else if remuxer_settings.preserve_stream_multiplexing
+			|| remuxer_settings.preserve_non_vorbis_streams
+		{
+			multiplexed_second_pass(
+				source,
+				&mut sink,
+				&mut vorbis_streams,
+				&mut audio_streams,
+				remuxer_settings,
+				&self.ogg_page_parsing_options,
+				&vorbis_stream_serials,
+				None
+			)?;
+		} else {
+			second_pass(
+				source,
+				&mut sink,
+				&mut vorbis_streams,
+				&mut audio_streams,
+				remuxer_settings,
+				&self.ogg_page_parsing_options,
+				&vorbis_stream_serials
+			)?;
+		}
 		info!("Second Ogg to Ogg remux pass completed");
 
-		Ok(sink)
+		Ok((sink, vorbis_streams))
 	}
 }
 
 /// Executes the first remuxing pass, where the Vorbis streams within the source Ogg physical
-/// bitstream are read and analyzed for optimization.
-fn first_pass<'settings, R: Read + Seek, M: OggVorbisStreamMangler>(
+/// bitstream are read and analyzed for optimization. Non-Vorbis audio streams are analyzed
+/// for remuxing too, if requested, and the serials of the ignored non-Vorbis streams are
+/// returned.
+pub(super) fn first_pass<'settings, R: Read + Seek, M: OggVorbisStreamMangler>(
 	source: R,
 	optimizer_settings: &'settings VorbisOptimizerSettings,
 	remuxer_settings: &mut Settings<M>,
 	ogg_page_parsing_options: &Arc<PageParsingOptions>
-) -> Result<IndexMap<u32, VorbisStreamState<'settings>>, RemuxError> {
+) -> Result<FirstPassResults<'settings>, RemuxError> {
 	let mut packet_reader =
 		PacketReader::new_with_page_parse_opts(source, Arc::clone(ogg_page_parsing_options));
 
 	let mut vorbis_streams = IndexMap::with_capacity(1);
-	let mut reading_vorbis_stream = false;
+	let mut audio_streams = IndexMap::new();
+	let mut non_vorbis_stream_serials = HashSet::new();
+	let mut skeleton_stream_serials = HashSet::new();
+	let mut unsupported_codec = None;
+	let mut reading_remuxed_stream = false;
+	#[cfg(feature = "parallel")]
+	let mut link_batch = parallel::LinkBatch::default();
 
 	while let Some(packet) = packet_reader.read_packet()? {
 		let stream_serial = packet.stream_serial();
 		let page_checksum = packet.checksum_page();
 
 		if packet.first_in_stream() {
+			let is_skeleton_stream = skeleton::is_skeleton_identification_header(&packet.data);
+			let audio_codec_parameters =
+				AudioCodecParameters::from_identification_header(&packet.data);
+
 			match VorbisOptimizer::new(optimizer_settings, packet.data) {
 				Ok(mut stream_optimizer) => {
 					// The just-started logical bitstream looks like Vorbis
 
-					if reading_vorbis_stream {
+					if reading_remuxed_stream && !remuxer_settings.preserve_stream_multiplexing {
 						// A logical Vorbis bitstream starts while we're already optimizing another.
 						// This means that streams are grouped (concurrently multiplexed), and we
-						// don't support that: we won't know how to interleave their pages properly
-						// later on
+						// were not asked to keep the original page interleaving, so we won't know
+						// how to interleave their pages properly later on
 						return Err(RemuxError::UnsupportedStreamMultiplexing);
 					}
 
@@ -318,6 +649,13 @@ fn first_pass<'settings, R: Read + Seek, M: OggVorbisStreamMangler>(
 					stream_optimizer.identification_data.nominal_bitrate = nominal_bitrate;
 					stream_optimizer.identification_data.maximum_bitrate = maximum_bitrate;
 
+					// Links that reuse a serial replace the state of the previous stream, so
+					// analyze the packets of that stream first
+					#[cfg(feature = "parallel")]
+					if link_batch.contains_link(stream_serial) {
+						link_batch.analyze(&mut vorbis_streams)?;
+					}
+
 					vorbis_streams.insert(
 						stream_serial,
 						VorbisStreamState {
@@ -328,10 +666,13 @@ fn first_pass<'settings, R: Read + Seek, M: OggVorbisStreamMangler>(
 							start_granule_position_offset: None,
 							analyzed_packet_count: 1, // Just processed the identification header packet
 							optimized_packet_count: 0,
-							checksum: page_checksum
+							checksum: page_checksum,
+							pending_packet: None,
+							page_payload_size: 0,
+							page_start_granule_position: 0
 						}
 					);
-					reading_vorbis_stream = true;
+					reading_remuxed_stream = true;
 				}
 				Err(
 					VorbisOptimizerError::TooSmallPacket(_)
@@ -341,178 +682,705 @@ fn first_pass<'settings, R: Read + Seek, M: OggVorbisStreamMangler>(
 				) => {
 					// These errors signal that the basic Vorbis header packet validation did
 					// not pass. This signals non-Vorbis data
-					info!("Ignoring non-Vorbis logical bitstream with serial {stream_serial}");
+					match audio_codec_parameters {
+						Some(codec_parameters)
+							if remuxer_settings.remux_non_vorbis_audio_streams =>
+						{
+							if reading_remuxed_stream
+								&& !remuxer_settings.preserve_stream_multiplexing
+							{
+								// Same as above
+								return Err(RemuxError::UnsupportedStreamMultiplexing);
+							}
+
+							info!(
+								"Analyzing Ogg {} bitstream with serial {stream_serial}",
+								codec_parameters.codec()
+							);
+
+							audio_streams.insert(
+								stream_serial,
+								AudioStreamState {
+									codec_parameters,
+									stream_serial: 0, // Assigned after this pass
+									packet_count: 1,
+									header_packet_count: 1,
+									sample_count: 0,
+									page_sample_count: 0,
+									last_page_sample_count: 0,
+									last_page_granule_position: 0,
+									first_audio_page_granule_position: None,
+									granule_position: 0,
+									written_packet_count: 0
+								}
+							);
+							reading_remuxed_stream = true;
+						}
+						_ => {
+							info!(
+								"Ignoring non-Vorbis logical bitstream with serial {stream_serial}"
+							);
+							non_vorbis_stream_serials.insert(stream_serial);
+							if is_skeleton_stream {
+								skeleton_stream_serials.insert(stream_serial);
+							}
+							unsupported_codec = unsupported_codec
+								.or(audio_codec_parameters.map(|parameters| parameters.codec()));
+						}
+					}
 				}
 				Err(error) => {
 					// The stream has an identification header that looks like Vorbis, but is corrupt
 					return Err(error.into());
 				}
 			}
-		} else if let Some(stream_state) = vorbis_streams.get_mut(&stream_serial) {
+		} else if vorbis_streams.contains_key(&stream_serial) {
 			// The second and next Vorbis packets of a Vorbis logical bitstream
 
 			// last_in_stream() may return false for the last packet of a bitstream
 			// if its page does not set the EOS flag, but that's not a concern if
 			// no other packets follow. If they do, and are not for this stream,
 			// we will rightfully return an error
-			reading_vorbis_stream = !packet.last_in_stream();
-
-			// Hand over the packet to the optimizer for analysis
-			let packet_sample_block_size = stream_state.optimizer.analyze_packet(&packet.data)?;
-			let is_not_discarded_audio_packet = packet_sample_block_size.is_some();
-
-			// When optimizing, audio packets that are discarded from the stream won't be
-			// written out. It's convenient to pretend they weren't analyzed either, to
-			// handle them with almost the same code path on the second pass
-			if stream_state.analyzed_packet_count <= 2 || is_not_discarded_audio_packet {
-				// Remember the granule position of the last audio packet in the first audio page.
-				// This is needed to compute granule positions properly for streams that originally
-				// did not start at time zero (livestream recordings, truncating some samples in the
-				// beginning)
-				if let (None, true, true) = (
-					stream_state.original_last_audio_packet_in_first_audio_page_granule_position,
-					packet.last_in_page(),
-					is_not_discarded_audio_packet
-				) {
-					stream_state.original_last_audio_packet_in_first_audio_page_granule_position =
-						Some((
-							packet.absgp_page() as i64,
-							stream_state.analyzed_packet_count
-						));
+			reading_remuxed_stream = !packet.last_in_stream();
+
+			// Analyze the packets of several streams at once, if possible. Whether any link
+			// follows the first one is not known yet, so analyze the first link right away
+			// instead of holding what may be a whole unchained file in memory
+			#[cfg(feature = "parallel")]
+			if vorbis_streams.len() > 1 {
+				if link_batch.is_full_for(stream_serial) {
+					link_batch.analyze(&mut vorbis_streams)?;
 				}
+				link_batch.push(packet);
+				continue;
+			}
 
-				stream_state.analyzed_packet_count =
-					stream_state.analyzed_packet_count.saturating_add(1);
+			analyze_packet(vorbis_streams.get_mut(&stream_serial).unwrap(), &packet)?;
+		} else if let Some(stream_state) = audio_streams.get_mut(&stream_serial) {
+			// The second and next packets of a non-Vorbis audio logical bitstream
+			reading_remuxed_stream = !packet.last_in_stream();
 
-				stream_state.checksum ^= page_checksum;
+			let packet_number = stream_state.packet_count;
+			stream_state.packet_count = stream_state.packet_count.saturating_add(1);
+
+			if stream_state
+				.codec_parameters
+				.is_header_packet(packet_number, &packet.data)
+			{
+				stream_state.header_packet_count = stream_state.packet_count;
+			} else {
+				let packet_sample_count = stream_state
+					.codec_parameters
+					.audio_packet_sample_count(&packet.data);
+				stream_state.sample_count = stream_state
+					.sample_count
+					.saturating_add(packet_sample_count);
+				stream_state.page_sample_count = stream_state
+					.page_sample_count
+					.saturating_add(packet_sample_count);
+
+				// Remember the granule position and sample count of the first audio page, to
+				// carry over the start offset of streams that did not start at time zero
+				if packet.last_in_page() {
+					stream_state
+						.first_audio_page_granule_position
+						.get_or_insert((packet.absgp_page(), stream_state.sample_count));
+				}
+			}
+
+			// Remember the granule position and sample count of the last page, to carry over
+			// the samples truncated at the end of the stream
+			if packet.last_in_page() {
+				stream_state.last_page_granule_position = packet.absgp_page();
+				stream_state.last_page_sample_count = stream_state.page_sample_count;
+				stream_state.page_sample_count = 0;
 			}
 		}
 	}
 
-	if vorbis_streams.is_empty() && remuxer_settings.error_on_no_vorbis_streams {
-		Err(RemuxError::NoVorbisStreamFound)
+	#[cfg(feature = "parallel")]
+	link_batch.analyze(&mut vorbis_streams)?;
+
+	for stream_state in audio_streams.values_mut() {
+		// Carry over the start offset of the stream, unless its first audio page is also
+		// the last. In that case, the granule position may be lower than the sample count
+		// due to end truncation, so the offset can't be known
+		stream_state.granule_position = match stream_state.first_audio_page_granule_position {
+			Some((granule_position, sample_count)) if sample_count < stream_state.sample_count => {
+				granule_position.saturating_sub(sample_count)
+			}
+			_ => 0
+		};
+	}
+
+	if vorbis_streams.is_empty()
+		&& audio_streams.is_empty()
+		&& remuxer_settings.error_on_no_vorbis_streams
+	{
+		Err(unsupported_codec.map_or(
+			RemuxError::NoVorbisStreamFound,
+			RemuxError::UnsupportedCodec
+		))
 	} else {
-		Ok(vorbis_streams)
+		Ok(FirstPassResults {
+			vorbis_streams,
+			audio_streams,
+			non_vorbis_stream_serials,
+			skeleton_stream_serials
+		})
 	}
 }
 
+/// Hands over the second and next packets of a Vorbis stream to its optimizer for analysis,
+/// updating the stream state with the information needed for the second remuxing pass.
+fn analyze_packet(
+	stream_state: &mut VorbisStreamState<'_>,
+	packet: &Packet
+) -> Result<(), RemuxError> {
+	// Hand over the packet to the optimizer for analysis
+	let packet_sample_block_size = stream_state.optimizer.analyze_packet(&packet.data)?;
+	let is_not_discarded_audio_packet = packet_sample_block_size.is_some();
+
+	// When optimizing, audio packets that are discarded from the stream won't be
+	// written out. It's convenient to pretend they weren't analyzed either, to
+	// handle them with almost the same code path on the second pass
+	if stream_state.analyzed_packet_count <= 2 || is_not_discarded_audio_packet {
+		// Remember the granule position of the last audio packet in the first audio page.
+		// This is needed to compute granule positions properly for streams that originally
+		// did not start at time zero (livestream recordings, truncating some samples in the
+		// beginning)
+		if let (None, true, true) = (
+			stream_state.original_last_audio_packet_in_first_audio_page_granule_position,
+			packet.last_in_page(),
+			is_not_discarded_audio_packet
+		) {
+			stream_state.original_last_audio_packet_in_first_audio_page_granule_position = Some((
+				packet.absgp_page() as i64,
+				stream_state.analyzed_packet_count
+			));
+		}
+
+		stream_state.analyzed_packet_count = stream_state.analyzed_packet_count.saturating_add(1);
+
+		stream_state.checksum ^= packet.checksum_page();
+	}
+
+	Ok(())
+}
+
 /// Executes the second remuxing pass, where Vorbis streams within the source Ogg physical
 /// bitstream are read again, and their optimized versions written out to new Vorbis streams
-/// in a new Ogg physical bitstream.
+/// in a new Ogg physical bitstream. Non-Vorbis audio streams are written out too, if requested.
 fn second_pass<R: Read + Seek, W: Write, M: OggVorbisStreamMangler>(
 	source: R,
 	sink: W,
 	vorbis_streams: &mut IndexMap<u32, VorbisStreamState<'_>>,
+	audio_streams: &mut IndexMap<u32, AudioStreamState>,
 	remuxer_settings: &mut Settings<M>,
 	ogg_page_parsing_options: &Arc<PageParsingOptions>,
-	first_stream_serial: u32,
-	stream_serial_increment: u32
+	vorbis_stream_serials: &[u32]
 ) -> Result<(), RemuxError> {
 	let mut packet_reader =
 		PacketReader::new_with_page_parse_opts(source, Arc::clone(ogg_page_parsing_options));
 	let mut packet_writer = PacketWriter::new(sink);
 
 	let mut last_seen_vorbis_stream_serial = None;
+	#[cfg(feature = "parallel")]
+	let mut link_batch = parallel::LinkBatch::default();
 
 	while let Some(packet) = packet_reader.read_packet()? {
 		let stream_serial = packet.stream_serial();
 
 		// Ignore non-Vorbis streams we skipped in the first pass
-		if let Entry::Occupied(mut entry) = vorbis_streams.entry(stream_serial) {
+		if vorbis_streams.contains_key(&stream_serial) {
 			if last_seen_vorbis_stream_serial != Some(stream_serial) {
 				info!("Optimizing Ogg Vorbis bitstream with serial {stream_serial}");
 			}
 			last_seen_vorbis_stream_serial = Some(stream_serial);
 
-			let stream_index = entry.index() as u32;
-			let stream_state = entry.get_mut();
-
-			// Optimize the packet
-			let packet_page_granule_position = packet.absgp_page();
-			let Some((optimized_packet, packet_sample_block_size)) =
-				stream_state.optimizer.optimize_packet(packet.data)?
-			else {
-				// Discard the packet. Pretend it never existed by not writing it and
-				// not incrementing the optimized packet count
+			// Optimize the packets of several streams at once, if possible. Physical bitstreams
+			// with a single link gain nothing from it, so write their packets right away instead
+			// of holding them in memory
+			#[cfg(feature = "parallel")]
+			if vorbis_streams.len() > 1 {
+				if link_batch.is_full_for(stream_serial) {
+					link_batch.optimize_and_write(
+						vorbis_streams,
+						vorbis_stream_serials,
+						&mut packet_writer,
+						remuxer_settings
+					)?;
+				}
+				link_batch.push(packet);
 				continue;
-			};
+			}
 
-			let packet_number = stream_state.optimized_packet_count;
-			let is_header_packet = packet_number < 3;
-			// The last_in_stream() method relies on the physical Ogg bitstream to set
-			// the EOS flag on the last page to return a proper result. However, it
-			// happens in practice that some physical bitstreams end with a page that
-			// does not have EOS set. Handle that by not relying on what the stream
-			// says, using the packet count we computed during the first pass
-			let is_last_stream_packet = packet_number == stream_state.analyzed_packet_count - 1;
-
-			// Vorbis stream encapsulation in Ogg is documented in the Vorbis I specification,
-			// § A.2. Putting Vorbis packets in Ogg pages is pretty straightforward: the
-			// identification and setup headers must end the page they are in, but the rest
-			// of packets may be stuffed in pages as desired, according to the ease of seeking,
-			// container overhead and maximum livestream recapture time requirements. In our
-			// case, we only care about minimizing container overhead (we are dealing with
-			// seekable sources in any case), so just put as many packets per page as possible
-			let page_end_info = if is_last_stream_packet {
-				PacketWriteEndInfo::EndStream
-			} else if packet_number == 0 || packet_number == 2 {
-				PacketWriteEndInfo::EndPage
-			} else {
-				PacketWriteEndInfo::NormalPacket
-			};
+			// We only care about minimizing container overhead (we are dealing with seekable
+			// sources in any case), so just put as many packets per page as possible
+			let (stream_index, _, stream_state) =
+				vorbis_streams.get_full_mut(&stream_serial).unwrap();
 
-			let calculated_granule_position = granule_position_for_packet(
-				packet_sample_block_size,
-				packet_number,
-				packet_page_granule_position,
-				is_last_stream_packet,
-				remuxer_settings,
-				stream_state
+			optimize_and_write_packet(
+				packet,
+				stream_state,
+				vorbis_stream_serials[stream_index],
+				false,
+				&mut packet_writer,
+				remuxer_settings
+			)?;
+		} else if let Some(stream_state) = audio_streams.get_mut(&stream_serial) {
+			if last_seen_vorbis_stream_serial != Some(stream_serial) {
+				info!(
+					"Remuxing Ogg {} bitstream with serial {stream_serial}",
+					stream_state.codec_parameters.codec()
+				);
+			}
+			last_seen_vorbis_stream_serial = Some(stream_serial);
+
+			// Write the Vorbis packets that came before first
+			#[cfg(feature = "parallel")]
+			link_batch.optimize_and_write(
+				vorbis_streams,
+				vorbis_stream_serials,
+				&mut packet_writer,
+				remuxer_settings
+			)?;
+
+			write_audio_packet(packet, stream_state, false, &mut packet_writer)?;
+		}
+	}
+
+	#[cfg(feature = "parallel")]
+	link_batch.optimize_and_write(
+		vorbis_streams,
+		vorbis_stream_serials,
+		&mut packet_writer,
+		remuxer_settings
+	)?;
+
+	Ok(())
+}
+
+/// Executes the second remuxing pass for Ogg physical bitstreams whose page interleaving
+/// should be kept. Like [`second_pass`], the optimized versions of the Vorbis streams are
+/// written out to a new Ogg physical bitstream, but their pages end at the same points of the
+/// original page interleaving order, and so do the pages of the non-Vorbis audio streams to
+/// remux. The pages of any other stream are copied as-is, except for the pages of the
+/// specified Skeleton streams, which are handed over to the specified Skeleton rewriter
+/// instead.
+///
+/// Optimized Vorbis packets that were discarded can't end a page. The pages with the packets
+/// before them end with the next written packet that does instead, so their order relative to
+/// the pages of other streams is approximate in that case.
+#[allow(clippy::too_many_arguments)]
+fn multiplexed_second_pass<R: Read, W: Write, M: OggVorbisStreamMangler>(
+	source: R,
+	sink: W,
+	vorbis_streams: &mut IndexMap<u32, VorbisStreamState<'_>>,
+	audio_streams: &mut IndexMap<u32, AudioStreamState>,
+	remuxer_settings: &mut Settings<M>,
+	ogg_page_parsing_options: &Arc<PageParsingOptions>,
+	vorbis_stream_serials: &[u32],
+	mut skeleton_rewriter: Option<(&mut SkeletonRewriter, &HashSet<u32>)>
+) -> Result<W, RemuxError> {
+	let mut page_reader = RawOggPageReader::new(source, Arc::clone(ogg_page_parsing_options));
+	let mut packet_reader = BasePacketReader::new();
+	let mut packet_writer = PacketWriter::new(PositionTrackingWriter {
+		inner: sink,
+		position: 0
+	});
+
+	while let Some(raw_page) = page_reader.read_page()? {
+		let sink_position = packet_writer.inner().position;
+
+		if let Some((skeleton_rewriter, skeleton_stream_serials)) = &mut skeleton_rewriter {
+			skeleton_rewriter.add_source_page(
+				raw_page.source_offset,
+				raw_page.is_first_in_stream,
+				sink_position
 			);
 
-			// Letting the stream serial addition to overflow is the most sensible thing:
-			// the Ogg specification just requires serials to be unique per stream, so by
-			// wrapping we make a good use of the available bit space
-			let packet_stream_serial = first_stream_serial
-				.wrapping_add(stream_serial_increment.wrapping_mul(stream_index));
-
-			// Mangle some Ogg page data. The mangler usually is a no-op
-			let packet_stream_serial = remuxer_settings
-				.vorbis_stream_mangler
-				.mangle_packet_stream_serial(
-					packet_stream_serial,
-					packet_number,
-					is_last_stream_packet
-				);
-			let page_end_info = remuxer_settings
-				.vorbis_stream_mangler
-				.mangle_packet_page_end_info(page_end_info, packet_number, is_last_stream_packet);
-			let granule_position = remuxer_settings
-				.vorbis_stream_mangler
-				.mangle_granule_position(
-					calculated_granule_position,
-					packet_number,
-					is_header_packet,
-					is_last_stream_packet
+			if skeleton_stream_serials.contains(&raw_page.stream_serial) {
+				skeleton_rewriter.add_skeleton_page(
+					raw_page.page,
+					raw_page.stream_serial,
+					raw_page.granule_position,
+					sink_position
+				)?;
+				continue;
+			}
+		}
+
+		if let Some(stream_state) = audio_streams.get_mut(&raw_page.stream_serial) {
+			if stream_state.written_packet_count == 0 {
+				info!(
+					"Remuxing Ogg {} bitstream with serial {}",
+					stream_state.codec_parameters.codec(),
+					raw_page.stream_serial
 				);
+			}
+
+			packet_reader.push_page(raw_page.page)?;
+			while let Some(packet) = packet_reader.read_packet() {
+				let is_last_in_original_page = packet.last_in_page();
+				write_audio_packet(
+					packet,
+					stream_state,
+					is_last_in_original_page,
+					&mut packet_writer
+				)?;
+			}
+			continue;
+		}
+
+		let Some((stream_index, _, stream_state)) =
+			vorbis_streams.get_full_mut(&raw_page.stream_serial)
+		else {
+			// Copy the pages of non-Vorbis streams through
+			packet_writer.inner_mut().write_all(&raw_page.data)?;
+			continue;
+		};
+
+		if stream_state.optimized_packet_count == 0 {
+			info!(
+				"Optimizing Ogg Vorbis bitstream with serial {}",
+				raw_page.stream_serial
+			);
+		}
 
-			packet_writer.write_packet(
-				optimized_packet,
-				packet_stream_serial,
-				page_end_info,
-				// Ogg does not care about the signedness of the granule position, but in Vorbis
-				// we may interpret it as a signed integer, and doing so is convenient for us
-				granule_position as u64
+		packet_reader.push_page(raw_page.page)?;
+		while let Some(packet) = packet_reader.read_packet() {
+			// End the page where the original one ended, so that the optimized pages
+			// are interleaved with the pages of the other streams as they were
+			let is_last_in_original_page = packet.last_in_page();
+
+			optimize_and_write_packet(
+				packet,
+				stream_state,
+				vorbis_stream_serials[stream_index],
+				is_last_in_original_page,
+				&mut packet_writer,
+				remuxer_settings
 			)?;
+		}
+	}
+
+	Ok(packet_writer.into_inner().inner)
+}
+
+/// Optimizes a packet of a Vorbis stream and writes it out to a Vorbis stream with the
+/// specified serial, unless the optimizer discards it. If `end_page` is `true`, the page
+/// will end with this packet even if the Vorbis encapsulation rules do not require it.
+fn optimize_and_write_packet<'packet, W: Write, M: OggVorbisStreamMangler>(
+	packet: Packet,
+	stream_state: &mut VorbisStreamState<'_>,
+	stream_serial: u32,
+	end_page: bool,
+	packet_writer: &mut PacketWriter<'packet, W>,
+	remuxer_settings: &mut Settings<M>
+) -> Result<(), RemuxError> {
+	let Some(optimized_packet) = optimize_packet(
+		packet,
+		stream_state,
+		stream_serial,
+		end_page,
+		remuxer_settings.ignore_start_sample_offset,
+		remuxer_settings.single_packet_audio_page_count
+	)?
+	else {
+		return Ok(());
+	};
+
+	write_optimized_packet(
+		optimized_packet,
+		stream_state,
+		packet_writer,
+		remuxer_settings
+	)
+}
 
-			stream_state.optimized_packet_count =
-				stream_state.optimized_packet_count.saturating_add(1);
+/// Optimizes a packet of a Vorbis stream, computing how it should be written out to a Vorbis
+/// stream with the specified serial. Returns `None` if the optimizer discards the packet.
+/// The meaning of `end_page` and the remaining parameters matches the remuxer settings and
+/// [`optimize_and_write_packet`] parameters of the same name.
+fn optimize_packet(
+	packet: Packet,
+	stream_state: &mut VorbisStreamState<'_>,
+	stream_serial: u32,
+	end_page: bool,
+	ignore_start_sample_offset: bool,
+	single_packet_audio_page_count: usize
+) -> Result<Option<OptimizedPacket>, RemuxError> {
+	// Optimize the packet
+	let packet_page_granule_position = packet.absgp_page();
+	let Some((optimized_packet, packet_sample_block_size)) =
+		stream_state.optimizer.optimize_packet(packet.data)?
+	else {
+		// Discard the packet. Pretend it never existed by not writing it and
+		// not incrementing the optimized packet count
+		return Ok(None);
+	};
+
+	let packet_number = stream_state.optimized_packet_count;
+	// The last_in_stream() method relies on the physical Ogg bitstream to set
+	// the EOS flag on the last page to return a proper result. However, it
+	// happens in practice that some physical bitstreams end with a page that
+	// does not have EOS set. Handle that by not relying on what the stream
+	// says, using the packet count we computed during the first pass
+	let is_last_stream_packet = packet_number == stream_state.analyzed_packet_count - 1;
+
+	// Vorbis stream encapsulation in Ogg is documented in the Vorbis I specification,
+	// § A.2. Putting Vorbis packets in Ogg pages is pretty straightforward: the
+	// identification and setup headers must end the page they are in, but the rest
+	// of packets may be stuffed in pages as desired, according to the ease of seeking,
+	// container overhead and maximum livestream recapture time requirements
+	let page_end_info = if is_last_stream_packet {
+		PacketWriteEndInfo::EndStream
+	} else if packet_number == 0
+		|| packet_number == 2
+		|| end_page
+		|| (3..3usize.saturating_add(single_packet_audio_page_count)).contains(&packet_number)
+	{
+		PacketWriteEndInfo::EndPage
+	} else {
+		PacketWriteEndInfo::NormalPacket
+	};
+
+	let calculated_granule_position = granule_position_for_packet(
+		packet_sample_block_size,
+		packet_number,
+		packet_page_granule_position,
+		is_last_stream_packet,
+		ignore_start_sample_offset,
+		stream_state
+	);
+
+	let optimized_packet = OptimizedPacket {
+		data: optimized_packet,
+		stream_serial,
+		packet_number,
+		is_last_stream_packet,
+		page_end_info,
+		granule_position: calculated_granule_position
+	};
+	stream_state.optimized_packet_count = stream_state.optimized_packet_count.saturating_add(1);
+
+	Ok(Some(optimized_packet))
+}
+
+/// Writes out an optimized Vorbis packet. When page size or duration limits are configured,
+/// packets that do not end a page are written out when the next packet of the same stream is
+/// handed over to this function.
+fn write_optimized_packet<'packet, W: Write, M: OggVorbisStreamMangler>(
+	optimized_packet: OptimizedPacket,
+	stream_state: &mut VorbisStreamState<'_>,
+	packet_writer: &mut PacketWriter<'packet, W>,
+	remuxer_settings: &mut Settings<M>
+) -> Result<(), RemuxError> {
+	if optimized_packet.packet_number < 3
+		|| remuxer_settings.max_page_payload_size.is_none()
+			&& remuxer_settings.max_page_duration.is_none()
+	{
+		// Header packets are not subject to page limits, and without page limits, packets
+		// can be written right away
+		return mangle_and_write_packet(
+			optimized_packet,
+			stream_state,
+			packet_writer,
+			remuxer_settings
+		);
+	}
+
+	// Page limits require knowing the next packet to decide whether the page should end
+	// with the previous one, so delay writing packets that may not end a page by one packet
+	if let Some(mut pending_packet) = stream_state.pending_packet.take() {
+		let exceeds_max_page_payload_size =
+			remuxer_settings
+				.max_page_payload_size
+				.is_some_and(|max_page_payload_size| {
+					stream_state.page_payload_size
+						+ pending_packet.data.len()
+						+ optimized_packet.data.len()
+						> max_page_payload_size
+				});
+		let exceeds_max_page_duration =
+			remuxer_settings
+				.max_page_duration
+				.is_some_and(|max_page_duration| {
+					optimized_packet
+						.granule_position
+						.saturating_sub(stream_state.page_start_granule_position)
+						.unsigned_abs() > max_page_duration
+				});
+
+		if exceeds_max_page_payload_size || exceeds_max_page_duration {
+			pending_packet.page_end_info = PacketWriteEndInfo::EndPage;
 		}
+
+		mangle_and_write_packet(
+			pending_packet,
+			stream_state,
+			packet_writer,
+			remuxer_settings
+		)?;
 	}
 
+	if optimized_packet.page_end_info == PacketWriteEndInfo::NormalPacket {
+		stream_state.pending_packet = Some(optimized_packet);
+		Ok(())
+	} else {
+		mangle_and_write_packet(
+			optimized_packet,
+			stream_state,
+			packet_writer,
+			remuxer_settings
+		)
+	}
+}
+
+/// Mangles and writes out an optimized Vorbis packet, keeping track of the size and duration
+/// of the current page.
+fn mangle_and_write_packet<'packet, W: Write, M: OggVorbisStreamMangler>(
+	optimized_packet: OptimizedPacket,
+	stream_state: &mut VorbisStreamState<'_>,
+	packet_writer: &mut PacketWriter<'packet, W>,
+	remuxer_settings: &mut Settings<M>
+) -> Result<(), RemuxError> {
+	let OptimizedPacket {
+		data,
+		stream_serial,
+		packet_number,
+		is_last_stream_packet,
+		page_end_info,
+		granule_position: calculated_granule_position
+	} = optimized_packet;
+	let is_header_packet = packet_number < 3;
+
+	// Mangle some Ogg page data. The mangler usually is a no-op
+	let packet_stream_serial = remuxer_settings
+		.vorbis_stream_mangler
+		.mangle_packet_stream_serial(stream_serial, packet_number, is_last_stream_packet);
+	let page_end_info = remuxer_settings
+		.vorbis_stream_mangler
+		.mangle_packet_page_end_info(page_end_info, packet_number, is_last_stream_packet);
+	let granule_position = remuxer_settings
+		.vorbis_stream_mangler
+		.mangle_granule_position(
+			calculated_granule_position,
+			packet_number,
+			is_header_packet,
+			is_last_stream_packet
+		);
+
+	if page_end_info == PacketWriteEndInfo::NormalPacket {
+		stream_state.page_payload_size += data.len();
+	} else {
+		stream_state.page_payload_size = 0;
+		stream_state.page_start_granule_position = calculated_granule_position;
+	}
+
+	packet_writer.write_packet(
+		data,
+		packet_stream_serial,
+		page_end_info,
+		// Ogg does not care about the signedness of the granule position, but in Vorbis
+		// we may interpret it as a signed integer, and doing so is convenient for us
+		granule_position as u64
+	)?;
+
+	Ok(())
+}
+
+/// Writes out a packet of a non-Vorbis audio stream as-is, computing its granule position from
+/// the sample counts of the packets written so far. If `end_page` is `true`, the page will end
+/// with this packet even if the encapsulation rules of the codec do not require it.
+fn write_audio_packet<'packet, W: Write>(
+	packet: Packet,
+	stream_state: &mut AudioStreamState,
+	end_page: bool,
+	packet_writer: &mut PacketWriter<'packet, W>
+) -> Result<(), RemuxError> {
+	let packet_number = stream_state.written_packet_count;
+	let is_last_stream_packet = packet_number == stream_state.packet_count - 1;
+	let is_header_packet = packet_number < stream_state.header_packet_count;
+
+	// The Ogg mappings of Opus, Speex and FLAC require the first header packet to be alone
+	// in its page, and audio packets to start in a new page after the last header packet
+	let page_end_info = if is_last_stream_packet {
+		PacketWriteEndInfo::EndStream
+	} else if packet_number == 0
+		|| packet_number == stream_state.header_packet_count - 1
+		|| end_page
+	{
+		PacketWriteEndInfo::EndPage
+	} else {
+		PacketWriteEndInfo::NormalPacket
+	};
+
+	let granule_position = if is_header_packet {
+		0
+	} else {
+		let calculated_granule_position = stream_state.granule_position.saturating_add(
+			stream_state
+				.codec_parameters
+				.audio_packet_sample_count(&packet.data)
+		);
+		stream_state.granule_position = calculated_granule_position;
+
+		// The granule position of the last page may be lower than the sample count, which
+		// truncates samples at the end. Keep the original value if it does that sensibly
+		let original_granule_position = stream_state.last_page_granule_position;
+		if is_last_stream_packet
+			&& original_granule_position < calculated_granule_position
+			&& original_granule_position
+				>= calculated_granule_position.saturating_sub(stream_state.last_page_sample_count)
+		{
+			original_granule_position
+		} else {
+			calculated_granule_position
+		}
+	};
+
+	packet_writer.write_packet(
+		packet.data,
+		stream_state.stream_serial,
+		page_end_info,
+		granule_position
+	)?;
+
+	stream_state.written_packet_count = stream_state.written_packet_count.saturating_add(1);
+
 	Ok(())
 }
 
+/// Assigns a serial to each of the specified number of Vorbis logical bitstreams, starting
+/// with `first_stream_serial` and adding `stream_serial_increment` to get the serials of the
+/// next streams. Serials in `reserved_stream_serials` are skipped.
+fn vorbis_stream_serials(
+	stream_count: usize,
+	first_stream_serial: u32,
+	stream_serial_increment: u32,
+	reserved_stream_serials: &HashSet<u32>
+) -> Vec<u32> {
+	let mut stream_serials = Vec::with_capacity(stream_count);
+	let mut stream_serial = first_stream_serial;
+
+	for _ in 0..stream_count {
+		while reserved_stream_serials.contains(&stream_serial) {
+			stream_serial = stream_serial.wrapping_add(stream_serial_increment);
+		}
+		stream_serials.push(stream_serial);
+
+		// Letting the stream serial addition to overflow is the most sensible thing:
+		// the Ogg specification just requires serials to be unique per stream, so by
+		// wrapping we make a good use of the available bit space. The increment is odd,
+		// so every serial is eventually reached and this does not loop forever
+		stream_serial = stream_serial.wrapping_add(stream_serial_increment);
+	}
+
+	stream_serials
+}
+
 /// Computes a random serial for the first Vorbis logical bitstream in an Ogg physical
 /// bitstream, and the increment to add to that serial with wrapping arithmetic to
 /// cheaply generate fairly unique serials for other bitstreams. This should be done
@@ -590,3 +1458,21 @@ fn random_stream_serial_and_increment(
 		1 + 2 * random_bytes[4] as u32 % 32
 	))
 }
+
+/// A [`Write`] wrapper that keeps track of the number of bytes written so far.
+struct PositionTrackingWriter<W: Write> {
+	inner: W,
+	position: u64
+}
+
+impl<W: Write> Write for PositionTrackingWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let written_bytes = self.inner.write(buf)?;
+		self.position += written_bytes as u64;
+		Ok(written_bytes)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
@@ -0,0 +1,158 @@
+//! Contains code for analyzing and optimizing the logical bitstreams of chained Ogg Vorbis
+//! physical bitstreams in parallel.
+
+use std::{collections::HashMap, io::Write};
+
+use indexmap::IndexMap;
+use ogg::{Packet, PacketWriter};
+use rayon::prelude::*;
+
+use super::{
+	OggVorbisStreamMangler, RemuxError, Settings, VorbisStreamState, analyze_packet,
+	optimize_packet, write_optimized_packet
+};
+
+/// The packet data size, in bytes, above which a [`LinkBatch`] is considered full. This
+/// bounds the memory used to hold the packets of long logical bitstreams.
+const MAX_BATCH_PACKET_DATA_SIZE: usize = 32 * 1024 * 1024;
+
+/// Holds the packets of consecutive Vorbis logical bitstreams, or links, of an Ogg physical
+/// bitstream, so that each link can be processed in a different thread. Links are processed
+/// in batches of as many links as threads are available.
+///
+/// The packets of a single link are always processed in order by the same thread, so
+/// processing a long link in several batches is equivalent to processing it at once.
+/// Packets are only batched once a physical bitstream is known to have several links, so
+/// that the packets of unchained physical bitstreams are not held in memory for nothing.
+#[derive(Default)]
+pub(super) struct LinkBatch {
+	links: IndexMap<u32, Vec<Packet>>,
+	packet_data_size: usize
+}
+
+impl LinkBatch {
+	/// Checks whether this batch should be processed before adding a packet of the logical
+	/// bitstream with the specified serial to it.
+	pub(super) fn is_full_for(&self, stream_serial: u32) -> bool {
+		self.packet_data_size >= MAX_BATCH_PACKET_DATA_SIZE
+			|| !self.links.contains_key(&stream_serial)
+				&& self.links.len() >= rayon::current_num_threads()
+	}
+
+	/// Checks whether this batch holds packets of the logical bitstream with the specified
+	/// serial.
+	pub(super) fn contains_link(&self, stream_serial: u32) -> bool {
+		self.links.contains_key(&stream_serial)
+	}
+
+	/// Adds a Vorbis packet to this batch.
+	pub(super) fn push(&mut self, packet: Packet) {
+		self.packet_data_size += packet.data.len();
+		self.links
+			.entry(packet.stream_serial())
+			.or_default()
+			.push(packet);
+	}
+
+	/// Hands over the packets in this batch to the optimizers of their Vorbis streams for
+	/// analysis, emptying it. This is the parallel counterpart of calling [`analyze_packet`]
+	/// for each packet.
+	pub(super) fn analyze(
+		&mut self,
+		vorbis_streams: &mut IndexMap<u32, VorbisStreamState<'_>>
+	) -> Result<(), RemuxError> {
+		self.take_links(vorbis_streams)
+			.into_par_iter()
+			.map(|(_, stream_state, packets)| {
+				packets
+					.iter()
+					.try_for_each(|packet| analyze_packet(stream_state, packet))
+			})
+			// Collect every result before returning the first error, to keep error
+			// reporting deterministic
+			.collect::<Vec<_>>()
+			.into_iter()
+			.collect()
+	}
+
+	/// Optimizes the packets in this batch, emptying it, and then writes them out in order.
+	/// This is the parallel counterpart of calling
+	/// [`optimize_and_write_packet`](super::optimize_and_write_packet) for each packet,
+	/// without ending pages at packets that do not require it.
+	pub(super) fn optimize_and_write<'packet, W: Write, M: OggVorbisStreamMangler>(
+		&mut self,
+		vorbis_streams: &mut IndexMap<u32, VorbisStreamState<'_>>,
+		vorbis_stream_serials: &[u32],
+		packet_writer: &mut PacketWriter<'packet, W>,
+		remuxer_settings: &mut Settings<M>
+	) -> Result<(), RemuxError> {
+		let ignore_start_sample_offset = remuxer_settings.ignore_start_sample_offset;
+		let single_packet_audio_page_count = remuxer_settings.single_packet_audio_page_count;
+
+		let optimized_links = self
+			.take_links(vorbis_streams)
+			.into_par_iter()
+			.map(|(stream_index, stream_state, packets)| {
+				packets
+					.into_iter()
+					.filter_map(|packet| {
+						optimize_packet(
+							packet,
+							stream_state,
+							vorbis_stream_serials[stream_index],
+							false,
+							ignore_start_sample_offset,
+							single_packet_audio_page_count
+						)
+						.transpose()
+					})
+					.collect::<Result<Vec<_>, _>>()
+					.map(|optimized_packets| (stream_index, optimized_packets))
+			})
+			.collect::<Vec<_>>();
+
+		// Writing is cheap, and manglers are not required to be thread-safe, so do it
+		// sequentially, in the original link order
+		for optimized_link in optimized_links {
+			let (stream_index, optimized_packets) = optimized_link?;
+			let stream_state = &mut vorbis_streams[stream_index];
+
+			for optimized_packet in optimized_packets {
+				write_optimized_packet::<_, M>(
+					optimized_packet,
+					stream_state,
+					packet_writer,
+					remuxer_settings
+				)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Empties this batch, returning its links in order, along with the index and state of
+	/// their Vorbis streams.
+	fn take_links<'state, 'settings>(
+		&mut self,
+		vorbis_streams: &'state mut IndexMap<u32, VorbisStreamState<'settings>>
+	) -> Vec<(usize, &'state mut VorbisStreamState<'settings>, Vec<Packet>)> {
+		let mut stream_states = vorbis_streams
+			.iter_mut()
+			.enumerate()
+			.filter(|(_, (stream_serial, _))| self.links.contains_key(*stream_serial))
+			.map(|(stream_index, (&stream_serial, stream_state))| {
+				(stream_serial, (stream_index, stream_state))
+			})
+			.collect::<HashMap<_, _>>();
+
+		self.packet_data_size = 0;
+		self.links
+			.drain(..)
+			.map(|(stream_serial, packets)| {
+				// Only packets of Vorbis streams are added to batches
+				let (stream_index, stream_state) = stream_states.remove(&stream_serial).unwrap();
+				(stream_index, stream_state, packets)
+			})
+			.collect()
+	}
+}
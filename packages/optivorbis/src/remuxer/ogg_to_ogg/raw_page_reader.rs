@@ -0,0 +1,116 @@
+//! Contains code for reading Ogg pages along with their raw bytes, so that they can be copied
+//! as-is to another Ogg physical bitstream.
+
+use std::{
+	io::{self, Read},
+	sync::Arc
+};
+
+use ogg::{
+	OggReadError, PageParsingOptions,
+	reading::{OggPage, PageParser}
+};
+
+/// The maximum number of junk bytes that will be skipped while looking for the capture pattern
+/// of the next page. This matches the limit used by the `ogg` crate [`ogg::PacketReader`].
+const MAX_SKIPPED_BYTES: usize = 150 * 1024;
+
+/// An Ogg page read by a [`RawOggPageReader`].
+pub(super) struct RawOggPage {
+	/// The offset of this page from the position the source was at when the reader was
+	/// created.
+	pub(super) source_offset: u64,
+	/// The serial of the logical bitstream this page belongs to.
+	pub(super) stream_serial: u32,
+	/// Whether this page begins its logical bitstream.
+	pub(super) is_first_in_stream: bool,
+	/// The granule position of this page.
+	pub(super) granule_position: u64,
+	/// The bytes of the page, including its header, exactly as they were read.
+	pub(super) data: Vec<u8>,
+	/// The parsed page, ready to be pushed to a [`ogg::reading::BasePacketReader`].
+	pub(super) page: OggPage
+}
+
+/// Reads the pages of an Ogg physical bitstream one at a time, keeping their raw bytes.
+/// Like the [`ogg::PacketReader`], junk data before and after pages is skipped.
+pub(super) struct RawOggPageReader<R: Read> {
+	source: R,
+	page_parsing_options: Arc<PageParsingOptions>,
+	source_offset: u64,
+	read_some_page: bool
+}
+
+impl<R: Read> RawOggPageReader<R> {
+	/// Creates a new raw Ogg page reader that reads pages from the specified source.
+	pub(super) fn new(source: R, page_parsing_options: Arc<PageParsingOptions>) -> Self {
+		Self {
+			source,
+			page_parsing_options,
+			source_offset: 0,
+			read_some_page: false
+		}
+	}
+
+	/// Reads the next page from the source, returning `None` if there are no more pages.
+	pub(super) fn read_page(&mut self) -> Result<Option<RawOggPage>, OggReadError> {
+		let mut header = [0; 27];
+
+		// Look for the capture pattern, shifting in one byte at a time when junk is found
+		if let Err(err) = self.source.read_exact(&mut header[..4]) {
+			return self.end_of_data(err);
+		}
+		let mut skipped_bytes = 0;
+		while header[..4] != *b"OggS" {
+			if skipped_bytes == MAX_SKIPPED_BYTES {
+				return Err(OggReadError::NoCapturePatternFound);
+			}
+
+			header.copy_within(1..4, 0);
+			if let Err(err) = self.source.read_exact(&mut header[3..4]) {
+				return self.end_of_data(err);
+			}
+			skipped_bytes += 1;
+		}
+
+		self.source.read_exact(&mut header[4..])?;
+		let source_offset = self.source_offset + skipped_bytes as u64;
+		let is_first_in_stream = header[5] & 0x02 != 0;
+		let granule_position = u64::from_le_bytes(header[6..14].try_into().unwrap());
+		let stream_serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+
+		let (mut page_parser, segment_count) =
+			PageParser::new_with_parse_opts(header, Arc::clone(&self.page_parsing_options))?;
+
+		let mut segment_table = vec![0; segment_count];
+		self.source.read_exact(&mut segment_table)?;
+		let page_body_length = page_parser.parse_segments(segment_table.clone());
+
+		let mut page_body = vec![0; page_body_length];
+		self.source.read_exact(&mut page_body)?;
+
+		let data = [&header[..], &segment_table, &page_body].concat();
+		let page = page_parser.parse_packet_data(page_body)?;
+		self.source_offset = source_offset + data.len() as u64;
+		self.read_some_page = true;
+
+		Ok(Some(RawOggPage {
+			source_offset,
+			stream_serial,
+			is_first_in_stream,
+			granule_position,
+			data,
+			page
+		}))
+	}
+
+	/// Handles reaching the end of the source while looking for a page capture pattern.
+	/// Trailing junk is tolerated, but a source without any page is not Ogg data.
+	fn end_of_data(&self, err: io::Error) -> Result<Option<RawOggPage>, OggReadError> {
+		match err.kind() {
+			io::ErrorKind::UnexpectedEof if self.read_some_page => Ok(None),
+			io::ErrorKind::UnexpectedEof => Err(OggReadError::NoCapturePatternFound),
+			_ => Err(err.into())
+		}
+	}
+}
@@ -0,0 +1,34 @@
+//! Contains the types returned by
+//! [`OggToOgg::remux_with_report`](super::OggToOgg::remux_with_report), which describe what
+//! a remux did.
+
+use super::OggVorbisSizeBreakdown;
+use crate::vorbis::optimizer::{VorbisIdentificationHeaderData, VorbisOptimizerStatistics};
+
+/// A report about the result of remuxing an Ogg Vorbis physical bitstream with the
+/// [`OggToOgg`](super::OggToOgg) remuxer, which tells how much smaller it became and what
+/// was optimized in each of its Vorbis streams.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RemuxReport {
+	/// The size breakdown of the source physical bitstream.
+	pub original: OggVorbisSizeBreakdown,
+	/// The size breakdown of the generated physical bitstream.
+	pub optimized: OggVorbisSizeBreakdown,
+	/// The reports for every Vorbis logical bitstream in the source physical bitstream, in
+	/// the order they started. A source with chained Vorbis streams has several of these.
+	pub vorbis_streams: Vec<VorbisStreamReport>
+}
+
+/// A report about the result of remuxing a Vorbis logical bitstream, contained in a
+/// [`RemuxReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VorbisStreamReport {
+	/// The serial of the logical bitstream in the source physical bitstream.
+	pub stream_serial: u32,
+	/// The data of the identification header of the Vorbis stream.
+	pub identification_header_data: VorbisIdentificationHeaderData,
+	/// Statistics about the optimizations done to the Vorbis stream.
+	pub optimizer_statistics: VorbisOptimizerStatistics
+}
@@ -0,0 +1,137 @@
+//! Contains code for measuring how the size of Ogg Vorbis physical bitstreams is distributed
+//! among their different kinds of data, used by
+//! [`OggToOgg::remux_with_report`](super::OggToOgg::remux_with_report).
+
+use std::{
+	collections::HashMap,
+	io::{self, Write}
+};
+
+/// A breakdown of the size, in bytes, of an Ogg Vorbis physical bitstream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OggVorbisSizeBreakdown {
+	/// The size of the identification header packets of the Vorbis streams.
+	pub identification_headers: u64,
+	/// The size of the comment header packets of the Vorbis streams.
+	pub comment_headers: u64,
+	/// The size of the setup header packets of the Vorbis streams.
+	pub setup_headers: u64,
+	/// The size of the audio packets of the Vorbis streams.
+	pub audio_packets: u64,
+	/// The size of the packets of non-Vorbis streams.
+	pub other_streams: u64,
+	/// The size of the Ogg page headers, which is the overhead of the Ogg container.
+	pub ogg_overhead: u64
+}
+
+impl OggVorbisSizeBreakdown {
+	/// Returns the total size of the physical bitstream, in bytes. Any junk data between pages
+	/// is not accounted for.
+	pub const fn total(&self) -> u64 {
+		self.identification_headers
+			+ self.comment_headers
+			+ self.setup_headers
+			+ self.audio_packets
+			+ self.other_streams
+			+ self.ogg_overhead
+	}
+}
+
+/// Adds up the sizes of the different kinds of data in the pages of an Ogg physical bitstream.
+#[derive(Default)]
+pub(super) struct SizeBreakdownCounter {
+	pub(super) breakdown: OggVorbisSizeBreakdown,
+	/// For every logical bitstream seen so far, whether it is a Vorbis stream and its
+	/// number of complete packets.
+	streams: HashMap<u32, (bool, usize)>
+}
+
+impl SizeBreakdownCounter {
+	/// Adds the sizes of the data in the specified Ogg page, which must be complete and well
+	/// formed, to the breakdown.
+	pub(super) fn count_page(&mut self, page: &[u8]) {
+		let is_first_in_stream = page[5] & 0x02 != 0;
+		let stream_serial = u32::from_le_bytes(page[14..18].try_into().unwrap());
+		let segment_table = &page[27..27 + page[26] as usize];
+		let page_body = &page[27 + segment_table.len()..];
+
+		let (is_vorbis_stream, packet_count) = self.streams.entry(stream_serial).or_default();
+		if is_first_in_stream {
+			*is_vorbis_stream = page_body.starts_with(b"\x01vorbis");
+		}
+
+		self.breakdown.ogg_overhead += 27 + segment_table.len() as u64;
+
+		for &lacing_value in segment_table {
+			let packet_size = match (*is_vorbis_stream, *packet_count) {
+				(false, _) => &mut self.breakdown.other_streams,
+				(true, 0) => &mut self.breakdown.identification_headers,
+				(true, 1) => &mut self.breakdown.comment_headers,
+				(true, 2) => &mut self.breakdown.setup_headers,
+				(true, _) => &mut self.breakdown.audio_packets
+			};
+			*packet_size += lacing_value as u64;
+
+			// Lacing values below 255 end packets
+			if lacing_value < 255 {
+				*packet_count += 1;
+			}
+		}
+	}
+}
+
+/// A [`Write`] sink that passes Ogg pages written to it through to another sink, adding up
+/// the sizes of their data with a [`SizeBreakdownCounter`].
+pub(super) struct SizeBreakdownWriter<W: Write> {
+	pub(super) sink: W,
+	pub(super) counter: SizeBreakdownCounter,
+	/// The data of the page being written, which is buffered until it is complete.
+	pending_page_data: Vec<u8>
+}
+
+impl<W: Write> SizeBreakdownWriter<W> {
+	/// Creates a new size breakdown writer that passes written data through to the
+	/// specified sink.
+	pub(super) fn new(sink: W) -> Self {
+		Self {
+			sink,
+			counter: SizeBreakdownCounter::default(),
+			pending_page_data: vec![]
+		}
+	}
+
+	/// Returns the length of the page at the beginning of the pending page data, if it was
+	/// completely written.
+	fn complete_page_length(&self) -> Option<usize> {
+		let segment_count = *self.pending_page_data.get(26)? as usize;
+		let segment_table = self.pending_page_data.get(27..27 + segment_count)?;
+		let page_length = 27
+			+ segment_count
+			+ segment_table
+				.iter()
+				.map(|&lacing_value| lacing_value as usize)
+				.sum::<usize>();
+
+		(self.pending_page_data.len() >= page_length).then_some(page_length)
+	}
+}
+
+impl<W: Write> Write for SizeBreakdownWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let written_length = self.sink.write(buf)?;
+		self.pending_page_data
+			.extend_from_slice(&buf[..written_length]);
+
+		while let Some(page_length) = self.complete_page_length() {
+			self.counter
+				.count_page(&self.pending_page_data[..page_length]);
+			self.pending_page_data.drain(..page_length);
+		}
+
+		Ok(written_length)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.sink.flush()
+	}
+}
@@ -0,0 +1,392 @@
+//! Contains code for rewriting [Ogg Skeleton] metadata streams copied to a remuxed Ogg
+//! physical bitstream, so that they refer to the serials and pages of the remuxed streams.
+//!
+//! The Skeleton stream header pages come before the data pages they refer to, so the
+//! remuxed data after the first Skeleton page is buffered in memory, and the rewritten
+//! Skeleton pages are inserted into it once the positions of every page are known.
+//!
+//! [Ogg Skeleton]: https://wiki.xiph.org/Ogg_Skeleton_4
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	io::{self, Write},
+	mem
+};
+
+use ogg::{
+	OggReadError, PacketWriteEndInfo, PacketWriter,
+	reading::{BasePacketReader, OggPage}
+};
+
+/// The magic bytes at the start of Skeleton identification header (`fishead`) packets.
+const FISHEAD_MAGIC: &[u8] = b"fishead\0";
+/// The magic bytes at the start of Skeleton stream information (`fisbone`) packets.
+const FISBONE_MAGIC: &[u8] = b"fisbone\0";
+/// The magic bytes at the start of Skeleton 4 keyframe index packets.
+const INDEX_MAGIC: &[u8] = b"index\0";
+
+/// The length of Skeleton 4 `fishead` packets, which contain the segment length and content
+/// byte offset fields.
+const SKELETON_4_FISHEAD_LENGTH: usize = 80;
+/// The position of the serial field in `fisbone` packets.
+const FISBONE_SERIAL_POSITION: usize = 12;
+/// The position of the serial field in index packets.
+const INDEX_SERIAL_POSITION: usize = 6;
+/// The position of the keypoint count field in index packets.
+const INDEX_KEYPOINT_COUNT_POSITION: usize = 10;
+/// The position of the first keypoint in index packets.
+const INDEX_KEYPOINTS_POSITION: usize = 42;
+
+/// Checks whether the specified packet is the identification header of an Ogg Skeleton stream.
+pub(super) fn is_skeleton_identification_header(packet: &[u8]) -> bool {
+	packet.starts_with(FISHEAD_MAGIC)
+}
+
+/// A page of a Skeleton stream, held until the positions of the pages it refers to are known.
+struct SkeletonPage {
+	stream_serial: u32,
+	granule_position: u64,
+	/// The index of the chain link this page belongs to.
+	link_index: usize,
+	/// The position in the buffered remuxed data where this page will be inserted.
+	buffer_position: u64,
+	/// The packets that end in this page, and whether they are the last packet of the stream.
+	packets: Vec<(Vec<u8>, bool)>
+}
+
+/// A link of a chained Ogg physical bitstream, which Skeleton streams call a segment.
+struct ChainLink {
+	/// The offset of the first page of this link in the source.
+	source_offset: u64,
+	/// The position of the first page of this link in the buffered remuxed data.
+	buffer_position: u64
+}
+
+/// Rewrites the Skeleton streams of an Ogg physical bitstream as it is remuxed. Fisbone and
+/// index serials are translated to the new stream serials, and the keyframe index and the
+/// `fishead` segment length and content byte offset fields are updated to the page positions
+/// in the remuxed data.
+///
+/// Byte offsets are relative to the start of the chain link (segment) the Skeleton stream is
+/// in. The positions of any page the remuxer writes out while handling a source page are
+/// assumed to be at or after the position it had when the source page was read, so seeking to
+/// a keypoint never skips over data.
+pub(super) struct SkeletonRewriter {
+	packet_reader: BasePacketReader,
+	/// The position in the buffered remuxed data of the pages written for each source page,
+	/// keyed by source page offset.
+	page_positions: BTreeMap<u64, u64>,
+	links: Vec<ChainLink>,
+	pages: Vec<SkeletonPage>,
+	last_page_was_first_in_stream: bool
+}
+
+impl SkeletonRewriter {
+	/// Creates a new Skeleton rewriter.
+	pub(super) fn new() -> Self {
+		Self {
+			packet_reader: BasePacketReader::new(),
+			page_positions: BTreeMap::new(),
+			links: vec![],
+			pages: vec![],
+			last_page_was_first_in_stream: false
+		}
+	}
+
+	/// Records the position in the buffered remuxed data where the remuxed version of the
+	/// specified source page starts. This must be called for every source page, in order.
+	pub(super) fn add_source_page(
+		&mut self,
+		source_offset: u64,
+		is_first_in_stream: bool,
+		buffer_position: u64
+	) {
+		// A beginning of stream page after other pages starts a new chain link
+		if (is_first_in_stream && !self.last_page_was_first_in_stream) || self.links.is_empty() {
+			self.links.push(ChainLink {
+				source_offset,
+				buffer_position
+			});
+		}
+		self.last_page_was_first_in_stream = is_first_in_stream;
+
+		self.page_positions.insert(source_offset, buffer_position);
+	}
+
+	/// Holds the specified Skeleton stream page, to be rewritten and inserted at the specified
+	/// position in the buffered remuxed data later on.
+	pub(super) fn add_skeleton_page(
+		&mut self,
+		page: OggPage,
+		stream_serial: u32,
+		granule_position: u64,
+		buffer_position: u64
+	) -> Result<(), OggReadError> {
+		self.packet_reader.push_page(page)?;
+
+		let mut packets = vec![];
+		while let Some(packet) = self.packet_reader.read_packet() {
+			let is_last_in_stream = packet.last_in_stream();
+			packets.push((packet.data, is_last_in_stream));
+		}
+
+		self.pages.push(SkeletonPage {
+			stream_serial,
+			granule_position,
+			link_index: self.links.len().saturating_sub(1),
+			buffer_position,
+			packets
+		});
+
+		Ok(())
+	}
+
+	/// Writes the buffered remuxed data to the specified sink, with the rewritten Skeleton pages
+	/// inserted. `stream_serials` maps the source serials of remuxed streams to their new ones.
+	pub(super) fn write_rewritten_data<W: Write>(
+		&self,
+		buffered_data: &[u8],
+		stream_serials: &HashMap<u32, u32>,
+		mut sink: W
+	) -> io::Result<()> {
+		// The length of the Skeleton pages depends on the page positions, which depend on the
+		// length of the Skeleton pages. Starting from empty pages, the page lengths can only
+		// grow until they are consistent with the positions they were computed from
+		let mut pages_data = vec![vec![]; self.pages.len()];
+		loop {
+			let new_pages_data =
+				self.paginate(&pages_data, buffered_data.len() as u64, stream_serials)?;
+
+			let converged = new_pages_data
+				.iter()
+				.map(Vec::len)
+				.eq(pages_data.iter().map(Vec::len));
+			pages_data = new_pages_data;
+
+			if converged {
+				break;
+			}
+		}
+
+		let mut written_data_length = 0;
+		for (page, page_data) in self.pages.iter().zip(&pages_data) {
+			let buffer_position = page.buffer_position as usize;
+			sink.write_all(&buffered_data[written_data_length..buffer_position])?;
+			sink.write_all(page_data)?;
+			written_data_length = buffer_position;
+		}
+		sink.write_all(&buffered_data[written_data_length..])
+	}
+
+	/// Rewrites and paginates the held Skeleton pages, assuming that the pages previously
+	/// generated have the lengths of `pages_data`.
+	fn paginate(
+		&self,
+		pages_data: &[Vec<u8>],
+		buffered_data_length: u64,
+		stream_serials: &HashMap<u32, u32>
+	) -> io::Result<Vec<Vec<u8>>> {
+		let layout = Layout {
+			rewriter: self,
+			pages_data
+		};
+		let data_length = layout.output_position(buffered_data_length, true);
+
+		let mut packet_writer = PacketWriter::new(vec![]);
+		let mut new_pages_data = Vec::with_capacity(self.pages.len());
+		for page in &self.pages {
+			let link_start =
+				layout.output_position(self.links[page.link_index].buffer_position, false);
+			let link_end = self
+				.links
+				.get(page.link_index + 1)
+				.map_or(data_length, |next_link| {
+					layout.output_position(next_link.buffer_position, false)
+				});
+
+			for (i, (packet, is_last_in_stream)) in page.packets.iter().enumerate() {
+				let packet = layout.rewrite_packet(
+					packet,
+					&self.links[page.link_index],
+					link_start,
+					link_end,
+					stream_serials
+				);
+
+				let page_end_info = match (i == page.packets.len() - 1, is_last_in_stream) {
+					(true, true) => PacketWriteEndInfo::EndStream,
+					(true, false) => PacketWriteEndInfo::EndPage,
+					_ => PacketWriteEndInfo::NormalPacket
+				};
+
+				packet_writer.write_packet(
+					packet,
+					page.stream_serial,
+					page_end_info,
+					page.granule_position
+				)?;
+			}
+
+			new_pages_data.push(mem::take(packet_writer.inner_mut()));
+		}
+
+		Ok(new_pages_data)
+	}
+}
+
+/// The layout of the remuxed data, given some tentative Skeleton page lengths.
+struct Layout<'rewriter> {
+	rewriter: &'rewriter SkeletonRewriter,
+	pages_data: &'rewriter [Vec<u8>]
+}
+
+impl Layout<'_> {
+	/// Converts a position in the buffered remuxed data to a position in the remuxed data
+	/// with the Skeleton pages inserted. The Skeleton pages inserted at that very position
+	/// are counted only if `after_skeleton_pages` is `true`.
+	fn output_position(&self, buffer_position: u64, after_skeleton_pages: bool) -> u64 {
+		buffer_position
+			+ self
+				.rewriter
+				.pages
+				.iter()
+				.zip(self.pages_data)
+				.take_while(|(page, _)| {
+					page.buffer_position < buffer_position
+						|| (after_skeleton_pages && page.buffer_position == buffer_position)
+				})
+				.map(|(_, page_data)| page_data.len() as u64)
+				.sum::<u64>()
+	}
+
+	/// Returns the position in the remuxed data of the page at or after the specified
+	/// source offset, if any.
+	fn output_page_position(&self, source_offset: u64) -> Option<u64> {
+		self.rewriter
+			.page_positions
+			.range(source_offset..)
+			.next()
+			.map(|(_, &buffer_position)| self.output_position(buffer_position, true))
+	}
+
+	/// Rewrites a Skeleton packet for its new place in the remuxed data. Packets that are
+	/// not understood are returned as-is.
+	fn rewrite_packet(
+		&self,
+		packet: &[u8],
+		link: &ChainLink,
+		link_start: u64,
+		link_end: u64,
+		stream_serials: &HashMap<u32, u32>
+	) -> Vec<u8> {
+		let mut packet = packet.to_vec();
+
+		if packet.starts_with(FISHEAD_MAGIC) && packet.len() >= SKELETON_4_FISHEAD_LENGTH {
+			let version_major = u16::from_le_bytes([packet[8], packet[9]]);
+			if version_major >= 4 {
+				packet[64..72].copy_from_slice(&(link_end - link_start).to_le_bytes());
+
+				let content_offset = u64::from_le_bytes(packet[72..80].try_into().unwrap());
+				if let Some(position) =
+					self.output_page_position(link.source_offset.saturating_add(content_offset))
+				{
+					packet[72..80].copy_from_slice(&(position - link_start).to_le_bytes());
+				}
+			}
+		} else if packet.starts_with(FISBONE_MAGIC) {
+			rewrite_serial(&mut packet, FISBONE_SERIAL_POSITION, stream_serials);
+		} else if packet.starts_with(INDEX_MAGIC) {
+			rewrite_serial(&mut packet, INDEX_SERIAL_POSITION, stream_serials);
+
+			if let Some(keypoints) = parse_keypoints(&packet) {
+				let keypoints = keypoints
+					.into_iter()
+					.filter_map(|(offset, time)| {
+						let position =
+							self.output_page_position(link.source_offset.saturating_add(offset))?;
+						Some((position - link_start, time))
+					})
+					.collect::<Vec<_>>();
+
+				packet.truncate(INDEX_KEYPOINTS_POSITION);
+				packet[INDEX_KEYPOINT_COUNT_POSITION..][..8]
+					.copy_from_slice(&(keypoints.len() as i64).to_le_bytes());
+
+				let (mut last_offset, mut last_time) = (0, 0);
+				for (offset, time) in keypoints {
+					write_variable_length_integer(&mut packet, offset - last_offset);
+					write_variable_length_integer(&mut packet, time.wrapping_sub(last_time));
+					(last_offset, last_time) = (offset, time);
+				}
+			}
+		}
+
+		packet
+	}
+}
+
+/// Translates the serial at the specified position of a Skeleton packet to its new value,
+/// if it belongs to a remuxed stream.
+fn rewrite_serial(packet: &mut [u8], position: usize, stream_serials: &HashMap<u32, u32>) {
+	let Some(serial_bytes) = packet.get_mut(position..position + 4) else {
+		return;
+	};
+
+	let serial = u32::from_le_bytes((&*serial_bytes).try_into().unwrap());
+	if let Some(new_serial) = stream_serials.get(&serial) {
+		serial_bytes.copy_from_slice(&new_serial.to_le_bytes());
+	}
+}
+
+/// Parses the keypoints of a Skeleton 4 index packet, returning their absolute byte offsets
+/// and timestamp numerators, or `None` if the packet is malformed.
+fn parse_keypoints(packet: &[u8]) -> Option<Vec<(u64, u64)>> {
+	let keypoint_count = u64::from_le_bytes(
+		packet
+			.get(INDEX_KEYPOINT_COUNT_POSITION..)?
+			.get(..8)?
+			.try_into()
+			.unwrap()
+	);
+
+	let mut data = packet.get(INDEX_KEYPOINTS_POSITION..)?;
+	// Every keypoint takes at least two bytes, so bound the allocation by the packet size
+	let mut keypoints = Vec::with_capacity((keypoint_count as usize).min(data.len() / 2));
+	let (mut offset, mut time) = (0u64, 0u64);
+	for _ in 0..keypoint_count {
+		offset = offset.checked_add(read_variable_length_integer(&mut data)?)?;
+		time = time.wrapping_add(read_variable_length_integer(&mut data)?);
+		keypoints.push((offset, time));
+	}
+
+	Some(keypoints)
+}
+
+/// Reads a Skeleton 4 variable-length integer, which is stored in little-endian groups of
+/// seven bits, with the most significant bit set in the last byte.
+fn read_variable_length_integer(data: &mut &[u8]) -> Option<u64> {
+	let mut value = 0;
+	let mut shift = 0;
+
+	loop {
+		let (&byte, remaining_data) = data.split_first()?;
+		*data = remaining_data;
+
+		value |= u64::from(byte & 0x7F).checked_shl(shift)?;
+		shift += 7;
+
+		if byte & 0x80 != 0 {
+			return Some(value);
+		}
+	}
+}
+
+/// Writes a Skeleton 4 variable-length integer. This is the inverse of
+/// [`read_variable_length_integer`].
+fn write_variable_length_integer(data: &mut Vec<u8>, mut value: u64) {
+	while value > 0x7F {
+		data.push((value & 0x7F) as u8);
+		value >>= 7;
+	}
+	data.push(value as u8 | 0x80);
+}
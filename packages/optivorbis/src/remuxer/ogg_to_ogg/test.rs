@@ -1,11 +1,15 @@
-use std::io::Cursor;
+use std::{io::Cursor, num::NonZeroU8};
 
+use ogg::PacketReader;
 use oggvorbismeta::CommentHeader;
 
 use super::*;
 use crate::{
 	OPTIVORBIS_VERSION_TAG,
-	vorbis::optimizer::{VorbisCommentFieldsAction, VorbisVendorStringAction}
+	vorbis::optimizer::{
+		VorbisCommentFieldsAction, VorbisCommentOperation, VorbisOptimizer, VorbisResourceLimit,
+		VorbisResourceLimits, VorbisStreamInfo, VorbisVendorStringAction
+	}
 };
 
 fn remux_with_settings<B: AsRef<[u8]>, M: OggVorbisStreamMangler>(
@@ -13,7 +17,7 @@ fn remux_with_settings<B: AsRef<[u8]>, M: OggVorbisStreamMangler>(
 	mut settings_supplier: impl FnMut() -> Settings<M>,
 	mut optimizer_settings_supplier: impl FnMut() -> VorbisOptimizerSettings,
 	comment_header_predicate: impl FnOnce(CommentHeader)
-) -> Result<(), RemuxError> {
+) -> Result<Vec<u8>, RemuxError> {
 	let mut byte_destination = vec![];
 
 	OggToOgg::new(settings_supplier(), optimizer_settings_supplier())
@@ -26,9 +30,31 @@ fn remux_with_settings<B: AsRef<[u8]>, M: OggVorbisStreamMangler>(
 	// Dogfooding asserts the invariant that the result of optimizing an Ogg Vorbis
 	// file is another Ogg Vorbis file we can parse
 	OggToOgg::new(settings_supplier(), optimizer_settings_supplier())
-		.remux(Cursor::new(byte_destination), io::sink())?;
+		.remux(Cursor::new(&byte_destination), io::sink())?;
 
-	Ok(())
+	Ok(byte_destination)
+}
+
+/// Splits the specified Ogg physical bitstream into its pages, returning their stream
+/// serials and data.
+fn pages(mut ogg_data: &[u8]) -> Vec<(u32, &[u8])> {
+	let mut pages = vec![];
+
+	while !ogg_data.is_empty() {
+		let segment_count = ogg_data[26] as usize;
+		let page_length = 27
+			+ segment_count
+			+ ogg_data[27..27 + segment_count]
+				.iter()
+				.map(|&segment_length| segment_length as usize)
+				.sum::<usize>();
+		let (page, remaining_data) = ogg_data.split_at(page_length);
+
+		pages.push((u32::from_le_bytes(page[14..18].try_into().unwrap()), page));
+		ogg_data = remaining_data;
+	}
+
+	pages
 }
 
 #[test_log::test]
@@ -68,7 +94,8 @@ fn remuxing_with_comment_header_changes_works() {
 		Default::default,
 		|| VorbisOptimizerSettings {
 			vendor_string_action: VorbisVendorStringAction::Empty,
-			comment_fields_action: VorbisCommentFieldsAction::Delete
+			comment_fields_action: VorbisCommentFieldsAction::Delete,
+			..Default::default()
 		},
 		|comment_header| {
 			assert!(
@@ -80,6 +107,32 @@ fn remuxing_with_comment_header_changes_works() {
 	.expect("Unexpected remuxing error");
 }
 
+#[test_log::test]
+fn remuxing_with_comment_operations_works() {
+	remux_with_settings(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave_comments.ogg"),
+		Default::default,
+		|| VorbisOptimizerSettings {
+			comment_operations: vec![
+				VorbisCommentOperation::Remove("hello".into()),
+				VorbisCommentOperation::Set {
+					field_name: "TITLE".into(),
+					value: "Sine wave".into()
+				},
+			],
+			..Default::default()
+		},
+		|comment_header| {
+			assert_eq!(
+				comment_header.comment_list,
+				[("TITLE".to_string(), "Sine wave".to_string())],
+				"Unexpected comment list"
+			);
+		}
+	)
+	.expect("Unexpected remuxing error");
+}
+
 #[test_log::test]
 fn remuxing_and_mangling_works() {
 	remux_with_settings(
@@ -90,6 +143,12 @@ fn remuxing_and_mangling_works() {
 			ignore_start_sample_offset: true,
 			error_on_no_vorbis_streams: true,
 			verify_ogg_page_checksums: true,
+			preserve_stream_multiplexing: false,
+			preserve_non_vorbis_streams: false,
+			remux_non_vorbis_audio_streams: false,
+			max_page_payload_size: None,
+			max_page_duration: None,
+			single_packet_audio_page_count: 0,
 			vorbis_stream_mangler: {
 				struct Mangler;
 
@@ -128,7 +187,7 @@ fn empty_last_audio_packet_works() {
 
 #[test_log::test]
 fn non_vorbis_data_returns_error() {
-	remux_with_settings(
+	let remux_error = remux_with_settings(
 		include_bytes!("../../../resources/test/44100hz_500ms_mono_440hz_sine_wave_ogg_opus.ogg"),
 		|| Settings {
 			error_on_no_vorbis_streams: true,
@@ -138,6 +197,54 @@ fn non_vorbis_data_returns_error() {
 		|_| ()
 	)
 	.expect_err("Expected remuxing error");
+
+	assert!(
+		matches!(
+			remux_error,
+			RemuxError::UnsupportedCodec(OggAudioCodec::Opus)
+		),
+		"Unexpected remuxing error: {remux_error}"
+	);
+}
+
+#[test_log::test]
+fn remuxing_non_vorbis_audio_streams_works() {
+	let ogg_opus_data =
+		include_bytes!("../../../resources/test/44100hz_500ms_mono_440hz_sine_wave_ogg_opus.ogg");
+
+	let remux = |ogg_data: &[u8]| {
+		OggToOgg::new(
+			Settings {
+				randomize_stream_serials: false,
+				remux_non_vorbis_audio_streams: true,
+				..Default::default()
+			},
+			Default::default()
+		)
+		.remux(Cursor::new(ogg_data), vec![])
+		.expect("Unexpected remuxing error")
+	};
+	let packets = |ogg_data: &[u8]| {
+		let mut packet_reader = PacketReader::new(Cursor::new(ogg_data));
+		let mut packets = vec![];
+		while let Some(packet) = packet_reader.read_packet().unwrap() {
+			packets.push((
+				packet.data.clone(),
+				packet.last_in_page(),
+				packet.absgp_page()
+			));
+		}
+		packets
+	};
+
+	let remuxed_data = remux(ogg_opus_data);
+	let original_packets = packets(ogg_opus_data);
+	let remuxed_packets = packets(&remuxed_data);
+
+	// The packets, their pagination and the end truncation of the original stream should
+	// be kept. Remuxing should be idempotent too
+	assert_eq!(remuxed_packets, original_packets);
+	assert_eq!(remux(&remuxed_data), remuxed_data);
 }
 
 #[test_log::test]
@@ -164,3 +271,544 @@ fn ogg_page_crc_verification_works() {
 	)
 	.expect("Unexpected remuxing error");
 }
+
+#[test_log::test]
+fn remuxing_with_page_limits_works() {
+	const MAX_PAGE_PAYLOAD_SIZE: usize = 512;
+	const MAX_PAGE_DURATION: u64 = 4096;
+	const SINGLE_PACKET_AUDIO_PAGE_COUNT: usize = 4;
+
+	let remuxed_data = remux_with_settings(
+		include_bytes!("../../../resources/test/44100hz_500ms_stereo_400hz_sine_wave_aotuv.ogg"),
+		|| Settings {
+			max_page_payload_size: Some(MAX_PAGE_PAYLOAD_SIZE),
+			max_page_duration: Some(MAX_PAGE_DURATION),
+			single_packet_audio_page_count: SINGLE_PACKET_AUDIO_PAGE_COUNT,
+			..Default::default()
+		},
+		Default::default,
+		|_| ()
+	)
+	.expect("Unexpected remuxing error");
+
+	// Group the packet sizes by page, along with the page granule position
+	let mut packet_reader = PacketReader::new(Cursor::new(&remuxed_data));
+	let mut pages = vec![(vec![], 0)];
+	while let Some(packet) = packet_reader.read_packet().unwrap() {
+		let (page_packet_sizes, page_granule_position) = pages.last_mut().unwrap();
+		page_packet_sizes.push(packet.data.len());
+
+		if packet.last_in_page() {
+			*page_granule_position = packet.absgp_page();
+			pages.push((vec![], 0));
+		}
+	}
+	pages.pop();
+
+	// Skip the header pages
+	let audio_pages = &pages[2..];
+	assert!(audio_pages.len() > SINGLE_PACKET_AUDIO_PAGE_COUNT + 1);
+
+	for (page_number, (page_packet_sizes, page_granule_position)) in audio_pages.iter().enumerate()
+	{
+		if page_number < SINGLE_PACKET_AUDIO_PAGE_COUNT {
+			assert_eq!(page_packet_sizes.len(), 1, "Expected a single packet page");
+		}
+
+		if page_packet_sizes.len() > 1 {
+			let previous_page_granule_position = audio_pages[..page_number]
+				.last()
+				.map_or(0, |(_, granule_position)| *granule_position);
+
+			assert!(page_packet_sizes.iter().sum::<usize>() <= MAX_PAGE_PAYLOAD_SIZE);
+			assert!(page_granule_position - previous_page_granule_position <= MAX_PAGE_DURATION);
+		}
+	}
+
+	// Putting every audio packet in a page of its own should not overflow
+	remux_with_settings(
+		include_bytes!("../../../resources/test/44100hz_500ms_stereo_400hz_sine_wave_aotuv.ogg"),
+		|| Settings {
+			single_packet_audio_page_count: usize::MAX,
+			..Default::default()
+		},
+		Default::default,
+		|_| ()
+	)
+	.expect("Unexpected remuxing error");
+}
+
+#[test_log::test]
+fn remuxing_with_grouped_streams_works() {
+	let ogg_vorbis_data = include_bytes!(
+		"../../../resources/test/8khz_mono_and_44100hz_stereo_500ms_400hz_sine_waves_grouped.ogg"
+	);
+
+	assert!(matches!(
+		remux_with_settings(ogg_vorbis_data, Default::default, Default::default, |_| ()),
+		Err(RemuxError::UnsupportedStreamMultiplexing)
+	));
+
+	let remuxed_data = remux_with_settings(
+		ogg_vorbis_data,
+		|| Settings {
+			preserve_stream_multiplexing: true,
+			..Default::default()
+		},
+		Default::default,
+		|_| ()
+	)
+	.expect("Unexpected remuxing error");
+
+	// The pages of each stream should be interleaved in the same way as in the original file
+	let interleaving_order = |ogg_data| {
+		let mut stream_serials = vec![];
+		let mut interleaving_order = pages(ogg_data)
+			.into_iter()
+			.map(|(stream_serial, _)| {
+				stream_serials
+					.iter()
+					.position(|&serial| serial == stream_serial)
+					.unwrap_or_else(|| {
+						stream_serials.push(stream_serial);
+						stream_serials.len() - 1
+					})
+			})
+			.collect::<Vec<_>>();
+		interleaving_order.dedup();
+		interleaving_order
+	};
+
+	assert_eq!(
+		interleaving_order(&remuxed_data),
+		interleaving_order(ogg_vorbis_data)
+	);
+}
+
+#[test_log::test]
+fn remuxing_with_non_vorbis_streams_preservation_works() {
+	let ogg_opus_data =
+		include_bytes!("../../../resources/test/44100hz_500ms_mono_440hz_sine_wave_ogg_opus.ogg");
+
+	// Without Vorbis streams to optimize, the Opus stream should be copied as-is
+	let remuxed_data = OggToOgg::new(
+		Settings {
+			error_on_no_vorbis_streams: false,
+			preserve_non_vorbis_streams: true,
+			..Default::default()
+		},
+		Default::default()
+	)
+	.remux(Cursor::new(ogg_opus_data), vec![])
+	.expect("Unexpected remuxing error");
+
+	assert_eq!(remuxed_data, ogg_opus_data);
+}
+
+#[test_log::test]
+fn remuxing_with_skeleton_stream_preservation_works() {
+	let remux = |ogg_vorbis_data: &[u8], settings: fn() -> Settings<_>| {
+		// The comment header in the remuxed data is not in the first packet, so
+		// remux_with_settings can't be used
+		let remuxed_data = OggToOgg::new(settings(), Default::default())
+			.remux(Cursor::new(ogg_vorbis_data), vec![])
+			.expect("Unexpected remuxing error");
+		OggToOgg::new(settings(), Default::default())
+			.remux(Cursor::new(&remuxed_data), io::sink())
+			.expect("Unexpected error remuxing the remuxed data");
+		remuxed_data
+	};
+
+	for ogg_vorbis_data in [
+		&include_bytes!(
+			"../../../resources/test/44100hz_500ms_stereo_400hz_sine_wave_skeleton.ogg"
+		)[..],
+		&include_bytes!(
+			"../../../resources/test/44100hz_500ms_stereo_400hz_sine_wave_skeleton_4.ogg"
+		)[..]
+	] {
+		// Preserving the page interleaving alone should copy the Skeleton stream as-is
+		let remuxed_data = remux(ogg_vorbis_data, || Settings {
+			preserve_stream_multiplexing: true,
+			..Default::default()
+		});
+		let original_skeleton_stream_serial = pages(ogg_vorbis_data)[0].0;
+		let skeleton_pages = |ogg_data: &[u8]| {
+			pages(ogg_data)
+				.into_iter()
+				.filter(|(stream_serial, _)| *stream_serial == original_skeleton_stream_serial)
+				.map(|(_, page)| page.to_vec())
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(
+			skeleton_pages(&remuxed_data),
+			skeleton_pages(ogg_vorbis_data),
+			"The Skeleton stream was not copied as-is"
+		);
+
+		let remuxed_data = remux(ogg_vorbis_data, || Settings {
+			preserve_non_vorbis_streams: true,
+			..Default::default()
+		});
+		let pages = pages(&remuxed_data);
+		let skeleton_stream_serial = pages[0].0;
+		let vorbis_stream_serial = pages[1].0;
+		let page_at = |offset: u64| {
+			let page = &remuxed_data[offset as usize..];
+			assert!(page.starts_with(b"OggS"), "No page found at offset");
+			(
+				u32::from_le_bytes(page[14..18].try_into().unwrap()),
+				u64::from_le_bytes(page[6..14].try_into().unwrap())
+			)
+		};
+
+		// The Skeleton stream packets should refer to the new serials and page offsets
+		for (_, page) in pages
+			.iter()
+			.filter(|(stream_serial, _)| *stream_serial == skeleton_stream_serial)
+		{
+			let packet = &page[27 + page[26] as usize..];
+			let read_u64 = |position: usize| {
+				u64::from_le_bytes(packet[position..position + 8].try_into().unwrap())
+			};
+
+			if packet.starts_with(b"fishead\0") && packet.len() >= 80 {
+				assert_eq!(read_u64(64), remuxed_data.len() as u64);
+
+				// The content byte offset should point to the first audio page
+				let (stream_serial, granule_position) = page_at(read_u64(72));
+				assert_eq!(stream_serial, vorbis_stream_serial);
+				assert_ne!(granule_position, 0);
+			} else if packet.starts_with(b"fisbone\0") {
+				assert_eq!(&packet[12..16], vorbis_stream_serial.to_le_bytes());
+			} else if packet.starts_with(b"index\0") {
+				assert_eq!(&packet[6..10], vorbis_stream_serial.to_le_bytes());
+
+				let mut keypoint_data = &packet[42..];
+				let mut read_variable_length_integer = || {
+					let mut value = 0;
+					for (i, byte) in keypoint_data.iter().enumerate() {
+						value |= ((byte & 0x7F) as u64) << (7 * i);
+						if byte & 0x80 != 0 {
+							keypoint_data = &keypoint_data[i + 1..];
+							break;
+						}
+					}
+					value
+				};
+
+				let mut keypoint_offset = 0;
+				for _ in 0..read_u64(10) {
+					keypoint_offset += read_variable_length_integer();
+					read_variable_length_integer();
+
+					assert_eq!(page_at(keypoint_offset).0, vorbis_stream_serial);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(feature = "parallel")]
+#[test_log::test]
+fn parallel_remuxing_matches_sequential_remuxing() {
+	let ogg_vorbis_data =
+		include_bytes!("../../../resources/test/8khz_2x500ms_mono_400hz_sine_wave_chained.ogg");
+
+	let remux_with_threads = |thread_count| {
+		rayon::ThreadPoolBuilder::new()
+			.num_threads(thread_count)
+			.build()
+			.unwrap()
+			.install(|| {
+				remux_with_settings(
+					ogg_vorbis_data,
+					|| Settings {
+						randomize_stream_serials: false,
+						..Default::default()
+					},
+					Default::default,
+					|_| ()
+				)
+			})
+			.expect("Unexpected remuxing error")
+	};
+
+	// A single thread processes each link on its own, while more threads process both
+	// links of the chained stream in the same batch
+	assert_eq!(remux_with_threads(1), remux_with_threads(2));
+}
+
+#[test_log::test]
+fn dry_run_works() {
+	let ogg_vorbis_data =
+		include_bytes!("../../../resources/test/8khz_2x500ms_mono_400hz_sine_wave_chained.ogg");
+	let settings = || Settings {
+		randomize_stream_serials: false,
+		..Default::default()
+	};
+
+	let report = OggToOgg::new(settings(), Default::default())
+		.dry_run(Cursor::new(ogg_vorbis_data))
+		.expect("Unexpected dry run error");
+	let remuxed_data = OggToOgg::new(settings(), Default::default())
+		.remux(Cursor::new(ogg_vorbis_data), vec![])
+		.expect("Unexpected remuxing error");
+
+	assert_eq!(report.original.total(), ogg_vorbis_data.len() as u64);
+	assert_eq!(report.optimized.total(), remuxed_data.len() as u64);
+	// Two links, each with a 30 bytes long identification header
+	assert_eq!(report.original.identification_headers, 60);
+	assert_eq!(report.optimized.identification_headers, 60);
+	assert_eq!(report.original.other_streams, 0);
+	assert!(report.optimized.setup_headers < report.original.setup_headers);
+	assert!(report.optimized.audio_packets < report.original.audio_packets);
+}
+
+#[test_log::test]
+fn remux_with_report_works() {
+	let ogg_vorbis_data =
+		include_bytes!("../../../resources/test/8khz_2x500ms_mono_400hz_sine_wave_chained.ogg");
+	let settings = || Settings {
+		randomize_stream_serials: false,
+		..Default::default()
+	};
+
+	let (remuxed_data, report) = OggToOgg::new(settings(), Default::default())
+		.remux_with_report(Cursor::new(ogg_vorbis_data), vec![])
+		.expect("Unexpected remuxing error");
+
+	assert_eq!(
+		remuxed_data,
+		OggToOgg::new(settings(), Default::default())
+			.remux(Cursor::new(ogg_vorbis_data), vec![])
+			.expect("Unexpected remuxing error")
+	);
+	assert_eq!(report.optimized.total(), remuxed_data.len() as u64);
+	assert_eq!(report.vorbis_streams.len(), 2);
+
+	for stream_report in &report.vorbis_streams {
+		let identification_header_data = &stream_report.identification_header_data;
+		assert_eq!(identification_header_data.channels.get(), 1);
+		assert_eq!(identification_header_data.sampling_frequency.get(), 8000);
+
+		let optimizer_statistics = &stream_report.optimizer_statistics;
+		assert_eq!(optimizer_statistics.discarded_audio_packets, 0);
+		assert!(!optimizer_statistics.codebook_entry_counts.is_empty());
+		assert!(
+			optimizer_statistics
+				.codebook_entry_counts
+				.iter()
+				.all(|(original_entry_count, entry_count)| entry_count <= original_entry_count)
+		);
+	}
+}
+
+#[test_log::test]
+fn remuxing_with_max_codeword_length_works() {
+	const MAX_CODEWORD_LENGTH: u8 = 4;
+
+	let optimizer_settings = || VorbisOptimizerSettings {
+		max_codeword_length: NonZeroU8::new(MAX_CODEWORD_LENGTH),
+		..Default::default()
+	};
+
+	let (remuxed_data, report) = OggToOgg::new(Default::default(), optimizer_settings())
+		.remux_with_report(
+			Cursor::new(include_bytes!(
+				"../../../resources/test/44100hz_500ms_stereo_400hz_sine_wave_skeleton.ogg"
+			)),
+			vec![]
+		)
+		.expect("Unexpected remuxing error");
+
+	let mut packet_reader = PacketReader::new(Cursor::new(&remuxed_data));
+	let mut read_packet = || packet_reader.read_packet_expected().unwrap().data;
+	let stream_info = VorbisStreamInfo::from_headers(
+		read_packet(),
+		read_packet(),
+		read_packet(),
+		Default::default()
+	)
+	.expect("Unexpected header parsing error");
+
+	assert!(stream_info.codebooks.iter().all(|codebook| {
+		let used_entry_count = codebook
+			.codeword_lengths
+			.iter()
+			.filter(|&&codeword_length| codeword_length != 0)
+			.count();
+
+		codebook.codeword_lengths.iter().all(|&codeword_length| {
+			codeword_length
+				<= MAX_CODEWORD_LENGTH.max(used_entry_count.next_power_of_two().ilog2() as u8)
+		})
+	}));
+	assert!(
+		report.vorbis_streams[0]
+			.optimizer_statistics
+			.codeword_length_limit_cost_bytes
+			> 0
+	);
+
+	// The limited codewords must still decode to the same codebook entries. Optimizing
+	// both files again without a codeword length limit recomputes their codewords from
+	// the decoded entries, so it should yield the same audio packets
+	let remux_without_limit = |data: &[u8]| {
+		let remuxed_data = OggToOgg::new(
+			Settings {
+				randomize_stream_serials: false,
+				..Default::default()
+			},
+			Default::default()
+		)
+		.remux(Cursor::new(data), vec![])
+		.expect("Unexpected remuxing error");
+
+		let mut packet_reader = PacketReader::new(Cursor::new(remuxed_data));
+		let mut packets = vec![];
+		while let Some(packet) = packet_reader.read_packet().unwrap() {
+			packets.push(packet.data);
+		}
+		packets
+	};
+	assert_eq!(
+		remux_without_limit(&remuxed_data),
+		remux_without_limit(include_bytes!(
+			"../../../resources/test/44100hz_500ms_stereo_400hz_sine_wave_skeleton.ogg"
+		))
+	);
+}
+
+#[test_log::test]
+fn exceeding_resource_limits_returns_error() {
+	let limits: [(VorbisResourceLimits, VorbisResourceLimit); 4] = [
+		(
+			VorbisResourceLimits {
+				max_setup_header_size: Some(64),
+				..Default::default()
+			},
+			VorbisResourceLimit::SetupHeaderSize
+		),
+		(
+			VorbisResourceLimits {
+				max_total_codebook_entries: Some(1),
+				..Default::default()
+			},
+			VorbisResourceLimit::TotalCodebookEntries
+		),
+		(
+			VorbisResourceLimits {
+				max_codebook_tree_depth: Some(1),
+				..Default::default()
+			},
+			VorbisResourceLimit::CodebookTreeDepth
+		),
+		(
+			VorbisResourceLimits {
+				max_packets: Some(3),
+				..Default::default()
+			},
+			VorbisResourceLimit::Packets
+		)
+	];
+
+	for (resource_limits, expected_exceeded_limit) in limits {
+		let remux_error = remux_with_settings(
+			include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+			Settings::default,
+			|| VorbisOptimizerSettings {
+				resource_limits,
+				..Default::default()
+			},
+			|_| ()
+		)
+		.expect_err("Expected remuxing error");
+
+		assert!(
+			matches!(
+				remux_error,
+				RemuxError::OptimizerError(VorbisOptimizerError::ResourceLimitExceeded(
+					exceeded_limit
+				)) if exceeded_limit == expected_exceeded_limit
+			),
+			"Unexpected remuxing error: {remux_error}"
+		);
+	}
+}
+
+#[test_log::test]
+fn remuxing_within_resource_limits_works() {
+	remux_with_settings(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+		Settings::default,
+		|| VorbisOptimizerSettings {
+			resource_limits: VorbisResourceLimits {
+				max_setup_header_size: Some(8192),
+				max_total_codebook_entries: Some(16384),
+				max_codebook_tree_depth: Some(32),
+				max_packets: Some(1024)
+			},
+			..Default::default()
+		},
+		|_| ()
+	)
+	.expect("Unexpected remuxing error");
+}
+
+#[test_log::test]
+fn remux_report_counts_discarded_audio_packets() {
+	let (_, report) = OggToOgg::new_with_defaults()
+		.remux_with_report(
+			Cursor::new(include_bytes!(
+				"../../../resources/test/zero_bytes_last_audio_packet.ogg"
+			)),
+			io::sink()
+		)
+		.expect("Unexpected remuxing error");
+
+	assert_eq!(report.vorbis_streams.len(), 1);
+	assert_eq!(
+		report.vorbis_streams[0]
+			.optimizer_statistics
+			.discarded_audio_packets,
+		1
+	);
+}
+
+#[test_log::test]
+fn padded_audio_packets_are_counted() {
+	const PADDING_BYTES: usize = 3;
+
+	let mut packet_reader = PacketReader::new(Cursor::new(include_bytes!(
+		"../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"
+	)));
+	let mut packets = vec![];
+	while let Some(packet) = packet_reader.read_packet().unwrap() {
+		packets.push(packet.data);
+	}
+
+	// Pad the first audio packet with bytes that a decoder would ignore
+	packets[3].extend_from_slice(&[0; PADDING_BYTES]);
+
+	let optimizer_settings = VorbisOptimizerSettings::default();
+	let mut optimizer =
+		VorbisOptimizer::new(&optimizer_settings, &packets[0]).expect("Unexpected optimizer error");
+
+	for packet in &packets[1..] {
+		optimizer
+			.analyze_packet(packet)
+			.expect("Unexpected analysis error");
+	}
+	for packet in &packets {
+		optimizer
+			.optimize_packet(&packet[..])
+			.expect("Unexpected optimization error");
+	}
+
+	assert_eq!(optimizer.statistics().padded_audio_packets, 1);
+	assert_eq!(
+		optimizer.statistics().removed_padding_bytes,
+		PADDING_BYTES as u64
+	);
+}
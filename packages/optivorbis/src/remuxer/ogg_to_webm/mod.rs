@@ -0,0 +1,547 @@
+//! Contains the [`OggToWebm`] remuxer struct and helper data types.
+
+use std::{
+	io::{self, Read, Seek, SeekFrom, Write},
+	num::NonZeroU32,
+	sync::Arc
+};
+
+use log::info;
+use ogg::{PacketReader, PageParsingOptions};
+use thiserror::Error;
+
+use super::{
+	Remuxer,
+	matroska::{
+		AUDIO_TRACK_TYPE, VORBIS_CODEC_ID, block_track_number_and_timestamp, element_id,
+		vorbis_codec_private, write_binary_element, write_block, write_element_header,
+		write_float_element, write_signed_integer_element, write_unsigned_integer_element
+	},
+	ogg_to_ogg::{
+		self, OggVorbisStreamPassthroughMangler, VorbisStreamState, first_pass,
+		granule_position_for_packet
+	}
+};
+use crate::{
+	OPTIVORBIS_VERSION_TAG,
+	vorbis::optimizer::{VorbisOptimizerError, VorbisOptimizerSettings}
+};
+
+#[cfg(test)]
+mod test;
+
+/// The number of nanoseconds each timestamp tick in the generated WebM files lasts. This
+/// is the default value recommended by the Matroska specification, which yields
+/// millisecond precision timestamps.
+const TIMESTAMP_SCALE: u64 = 1_000_000;
+/// The maximum duration of a cluster in the generated WebM files, in timestamp ticks. The
+/// WebM guidelines recommend keeping clusters at or under five seconds long.
+const MAXIMUM_CLUSTER_DURATION: u64 = 5000;
+/// The track number of the Vorbis track in the generated WebM files.
+const VORBIS_TRACK_NUMBER: u8 = 1;
+
+/// A [`Remuxer`] that processes a Vorbis stream in an unmultiplexed Ogg container,
+/// generating a WebM file with a single Vorbis audio track. Non-Vorbis streams will be
+/// ignored.
+///
+/// Chained Ogg Vorbis streams are not supported, as every Vorbis stream in a chain has its
+/// own header packets, but a WebM track can only have a single set of them. Block timestamps
+/// are computed from the decoded sample counts, starting at zero, so any non-zero start
+/// sample offset of the source stream will be ignored. The end samples truncated by the
+/// source stream are signaled with a `DiscardPadding` element on the last block.
+pub struct OggToWebm {
+	remuxer_settings: Settings,
+	optimizer_settings: VorbisOptimizerSettings,
+	ogg_page_parsing_options: Arc<PageParsingOptions>
+}
+
+/// Settings that influence how the remuxing from an Ogg file to a WebM file is done.
+pub struct Settings {
+	/// Sets whether the CRC checksum value embedded in Ogg pages will be verified to match their
+	/// data. The considerations documented for the [`OggToOgg`](super::ogg_to_ogg::OggToOgg)
+	/// remuxer
+	/// [setting](super::ogg_to_ogg::Settings::verify_ogg_page_checksums)
+	/// of the same name apply.
+	///
+	/// **Default value**: `true`
+	pub verify_ogg_page_checksums: bool
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			verify_ogg_page_checksums: true
+		}
+	}
+}
+
+/// Represents an error that may happen while remuxing with the [`OggToWebm`] remuxer.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RemuxError {
+	/// Represents an error that happened while reading the Vorbis stream from the source Ogg
+	/// container. These errors are shared with the [`OggToOgg`](super::ogg_to_ogg::OggToOgg)
+	/// remuxer, which reads Ogg containers the same way.
+	#[error(transparent)]
+	OggDemuxError(#[from] ogg_to_ogg::RemuxError),
+	/// Represents a Vorbis stream optimizer error. This may happen in corrupt Vorbis
+	/// streams, or streams that use unsupported features.
+	#[error("Vorbis optimization error: {0}")]
+	OptimizerError(#[from] VorbisOptimizerError),
+	/// Represents an unsupported Ogg stream chaining error, which happens if the source
+	/// contains several chained Vorbis streams.
+	#[error("Remuxing chained Ogg Vorbis streams to WebM is not supported")]
+	UnsupportedStreamChaining,
+	/// An I/O error outside any of the previously mentioned error contexts happened.
+	#[error("I/O error: {0}")]
+	IoError(#[from] io::Error)
+}
+
+impl RemuxError {
+	/// Returns a short, stable, machine-readable identifier for the kind of this error.
+	pub const fn code(&self) -> &'static str {
+		match self {
+			Self::OggDemuxError(_) => "ogg_demux_error",
+			Self::OptimizerError(_) => "optimizer_error",
+			Self::UnsupportedStreamChaining => "unsupported_stream_chaining",
+			Self::IoError(_) => "io_error"
+		}
+	}
+}
+
+impl Remuxer for OggToWebm {
+	type RemuxError = RemuxError;
+	type RemuxerSettings = Settings;
+
+	fn new(remuxer_settings: Settings, optimizer_settings: VorbisOptimizerSettings) -> Self {
+		let mut ogg_page_parsing_options = PageParsingOptions::default();
+		ogg_page_parsing_options.verify_checksum = remuxer_settings.verify_ogg_page_checksums;
+
+		Self {
+			remuxer_settings,
+			optimizer_settings,
+			ogg_page_parsing_options: Arc::new(ogg_page_parsing_options)
+		}
+	}
+
+	fn remux<R: Read + Seek, W: Write>(
+		&self,
+		mut source: R,
+		mut sink: W
+	) -> Result<W, Self::RemuxError> {
+		// Remember the source stream position to rewind to it later
+		let initial_source_pos = source.stream_position()?;
+
+		// Demux the Ogg container the same way the Ogg to Ogg remuxer does. Timestamps always
+		// start at zero in the generated file, so ignore the start sample offset
+		let mut ogg_remuxer_settings = ogg_to_ogg::Settings {
+			randomize_stream_serials: false,
+			first_stream_serial_offset: 0,
+			ignore_start_sample_offset: true,
+			error_on_no_vorbis_streams: true,
+			verify_ogg_page_checksums: self.remuxer_settings.verify_ogg_page_checksums,
+			preserve_stream_multiplexing: false,
+			preserve_non_vorbis_streams: false,
+			remux_non_vorbis_audio_streams: false,
+			max_page_payload_size: None,
+			max_page_duration: None,
+			single_packet_audio_page_count: 0,
+			vorbis_stream_mangler: OggVorbisStreamPassthroughMangler
+		};
+
+		// First pass: validate and gather stream data for optimization
+		info!("Starting first Ogg to WebM remux pass");
+		let mut vorbis_streams = first_pass(
+			&mut source,
+			&self.optimizer_settings,
+			&mut ogg_remuxer_settings,
+			&self.ogg_page_parsing_options
+		)?
+		.vorbis_streams;
+		info!("First Ogg to WebM remux pass completed");
+
+		if vorbis_streams.len() > 1 {
+			return Err(RemuxError::UnsupportedStreamChaining);
+		}
+		// The first pass returns an error if no Vorbis stream was found
+		let (stream_serial, stream_state) = vorbis_streams.first_mut().unwrap();
+
+		// Rewind for the second pass
+		source.seek(SeekFrom::Start(initial_source_pos))?;
+
+		// Second pass: optimizing Vorbis packet rewrite
+		info!("Starting second Ogg to WebM remux pass");
+		let vorbis_track = second_pass(
+			source,
+			*stream_serial,
+			stream_state,
+			&ogg_remuxer_settings,
+			&self.ogg_page_parsing_options
+		)?;
+		info!("Second Ogg to WebM remux pass completed");
+
+		vorbis_track.write_webm_file(&mut sink)?;
+
+		Ok(sink)
+	}
+}
+
+/// An optimized Vorbis track, muxed into WebM clusters, that is ready to be written to a
+/// WebM file.
+struct WebmVorbisTrack {
+	codec_private: Vec<u8>,
+	sampling_frequency: NonZeroU32,
+	channels: u8,
+	/// The concatenation of the `Cluster` elements of the track.
+	clusters: Vec<u8>,
+	/// The timestamp and position relative to the first cluster of each cluster.
+	cluster_positions: Vec<(u64, u64)>,
+	/// The data of the `Cluster` element being muxed, and its timestamp.
+	current_cluster: Option<(Vec<u8>, u64)>,
+	/// The number of samples that decoding the track yields.
+	sample_count: u64
+}
+
+/// Executes the second remuxing pass, where the Vorbis stream with the specified serial
+/// within the source Ogg physical bitstream is read again, and its optimized packets muxed
+/// into a WebM track.
+fn second_pass<R: Read + Seek>(
+	source: R,
+	stream_serial: u32,
+	stream_state: &mut VorbisStreamState<'_>,
+	ogg_remuxer_settings: &ogg_to_ogg::Settings<OggVorbisStreamPassthroughMangler>,
+	ogg_page_parsing_options: &Arc<PageParsingOptions>
+) -> Result<WebmVorbisTrack, RemuxError> {
+	let mut packet_reader =
+		PacketReader::new_with_page_parse_opts(source, Arc::clone(ogg_page_parsing_options));
+
+	let identification_data = &stream_state.optimizer.identification_data;
+	let mut vorbis_track = WebmVorbisTrack {
+		codec_private: vec![],
+		sampling_frequency: identification_data.sampling_frequency,
+		channels: identification_data.channels.get(),
+		clusters: vec![],
+		cluster_positions: vec![],
+		current_cluster: None,
+		sample_count: 0
+	};
+
+	let mut header_packets = Vec::with_capacity(3);
+	let mut packet_number = 0;
+	let mut last_packet_granule_position_and_sample_block_size = None;
+
+	while let Some(packet) = packet_reader
+		.read_packet()
+		.map_err(ogg_to_ogg::RemuxError::from)?
+	{
+		// Ignore non-Vorbis streams we skipped in the first pass
+		if packet.stream_serial() != stream_serial {
+			continue;
+		}
+
+		let packet_page_granule_position = packet.absgp_page();
+		let Some((optimized_packet, packet_sample_block_size)) =
+			stream_state.optimizer.optimize_packet(packet.data)?
+		else {
+			// Discard the packet. Pretend it never existed
+			continue;
+		};
+
+		// Rely on the packet count we computed during the first pass, like the Ogg to Ogg
+		// remuxer does, because the EOS flag may not be set
+		let is_last_stream_packet = packet_number == stream_state.analyzed_packet_count - 1;
+
+		// The granule position of a packet is the number of samples decoded after it is
+		// decoded, with the samples truncated at the end of the stream already accounted for
+		let granule_position = granule_position_for_packet(
+			packet_sample_block_size,
+			packet_number,
+			packet_page_granule_position,
+			is_last_stream_packet,
+			ogg_remuxer_settings.ignore_start_sample_offset,
+			stream_state
+		);
+
+		if packet_number < 3 {
+			header_packets.push(optimized_packet.into_owned());
+
+			if let [identification_header, comment_header, setup_header] = header_packets.as_slice()
+			{
+				vorbis_track.codec_private =
+					vorbis_codec_private([identification_header, comment_header, setup_header]);
+			}
+		} else {
+			let packet_sample_block_size = packet_sample_block_size.unwrap();
+
+			// The timestamp of a Matroska frame is the timestamp of the first sample it
+			// decodes to, which directly follows the samples decoded for the previous packet
+			let (first_sample, untruncated_granule_position) =
+				match last_packet_granule_position_and_sample_block_size {
+					Some((last_packet_granule_position, last_packet_sample_block_size)) => (
+						last_packet_granule_position,
+						last_packet_granule_position
+							+ (last_packet_sample_block_size as i64
+								+ packet_sample_block_size as i64)
+								/ 4
+					),
+					// The first audio packet does not decode to any sample
+					None => (0, 0)
+				};
+
+			// Any sample past the granule position of the last packet should be discarded
+			let discarded_sample_count = if is_last_stream_packet {
+				(untruncated_granule_position - granule_position).max(0)
+			} else {
+				0
+			};
+
+			vorbis_track.mux_frame(
+				&optimized_packet,
+				first_sample.max(0) as u64,
+				discarded_sample_count as u64
+			);
+
+			last_packet_granule_position_and_sample_block_size =
+				Some((granule_position, packet_sample_block_size));
+			vorbis_track.sample_count = granule_position.max(0) as u64;
+		}
+
+		packet_number += 1;
+	}
+
+	vorbis_track.finish_cluster();
+
+	Ok(vorbis_track)
+}
+
+impl WebmVorbisTrack {
+	/// Converts a sample count to a duration in nanoseconds, rounding down.
+	fn samples_to_nanoseconds(&self, samples: u64) -> u64 {
+		(samples as u128 * 1_000_000_000 / self.sampling_frequency.get() as u128) as u64
+	}
+
+	/// Muxes a Vorbis audio packet into a block of the current cluster, starting a new
+	/// cluster if necessary.
+	fn mux_frame(&mut self, frame: &[u8], first_sample: u64, discarded_sample_count: u64) {
+		let timestamp = self.samples_to_nanoseconds(first_sample) / TIMESTAMP_SCALE;
+		let discard_padding = self.samples_to_nanoseconds(discarded_sample_count) as i64;
+
+		if self
+			.current_cluster
+			.as_ref()
+			.is_some_and(|(_, cluster_timestamp)| {
+				timestamp.saturating_sub(*cluster_timestamp) > MAXIMUM_CLUSTER_DURATION
+			}) {
+			self.finish_cluster();
+		}
+
+		let (cluster_data, cluster_timestamp) = self.current_cluster.get_or_insert_with(|| {
+			let mut cluster_data = vec![];
+			write_unsigned_integer_element(&mut cluster_data, element_id::TIMESTAMP, timestamp);
+			(cluster_data, timestamp)
+		});
+
+		// The maximum cluster duration guarantees that this fits in an i16
+		let track_number_and_timestamp = block_track_number_and_timestamp(
+			VORBIS_TRACK_NUMBER,
+			(timestamp - *cluster_timestamp) as i16
+		);
+
+		let mut block_data = Vec::with_capacity(track_number_and_timestamp.len() + 1 + frame.len());
+		if discarded_sample_count == 0 {
+			// Every Vorbis frame can be decoded independently, so they all are keyframes
+			write_block(
+				&mut block_data,
+				&track_number_and_timestamp,
+				0b1000_0000,
+				&[frame]
+			);
+			write_binary_element(cluster_data, element_id::SIMPLE_BLOCK, &block_data);
+		} else {
+			// Discarding samples requires using a block group. Block groups do not have a
+			// keyframe flag: their frames are keyframes if they have no reference blocks
+			write_block(&mut block_data, &track_number_and_timestamp, 0, &[frame]);
+
+			let mut block_group_data = vec![];
+			write_binary_element(&mut block_group_data, element_id::BLOCK, &block_data);
+			write_signed_integer_element(
+				&mut block_group_data,
+				element_id::DISCARD_PADDING,
+				discard_padding
+			);
+			write_binary_element(cluster_data, element_id::BLOCK_GROUP, &block_group_data);
+		}
+	}
+
+	/// Appends the cluster being muxed, if any, to the muxed clusters.
+	fn finish_cluster(&mut self) {
+		if let Some((cluster_data, cluster_timestamp)) = self.current_cluster.take() {
+			self.cluster_positions
+				.push((cluster_timestamp, self.clusters.len() as u64));
+			write_binary_element(&mut self.clusters, element_id::CLUSTER, &cluster_data);
+		}
+	}
+
+	/// Writes a complete WebM file containing this track to `sink`.
+	fn write_webm_file<W: Write>(&self, mut sink: W) -> io::Result<()> {
+		let mut ebml_header = vec![];
+		let ebml_header_data = {
+			let mut data = vec![];
+			write_unsigned_integer_element(&mut data, element_id::EBML_VERSION, 1);
+			write_unsigned_integer_element(&mut data, element_id::EBML_READ_VERSION, 1);
+			write_unsigned_integer_element(&mut data, element_id::EBML_MAX_ID_LENGTH, 4);
+			write_unsigned_integer_element(&mut data, element_id::EBML_MAX_SIZE_LENGTH, 8);
+			write_binary_element(&mut data, element_id::DOC_TYPE, b"webm");
+			// DiscardPadding elements were introduced in version 4
+			write_unsigned_integer_element(&mut data, element_id::DOC_TYPE_VERSION, 4);
+			write_unsigned_integer_element(&mut data, element_id::DOC_TYPE_READ_VERSION, 2);
+			data
+		};
+		write_binary_element(&mut ebml_header, element_id::EBML, &ebml_header_data);
+
+		let mut info = vec![];
+		let info_data = {
+			let mut data = vec![];
+			write_unsigned_integer_element(&mut data, element_id::TIMESTAMP_SCALE, TIMESTAMP_SCALE);
+			write_binary_element(
+				&mut data,
+				element_id::MUXING_APP,
+				OPTIVORBIS_VERSION_TAG.as_bytes()
+			);
+			write_binary_element(
+				&mut data,
+				element_id::WRITING_APP,
+				OPTIVORBIS_VERSION_TAG.as_bytes()
+			);
+			write_float_element(
+				&mut data,
+				element_id::DURATION,
+				self.samples_to_nanoseconds(self.sample_count) as f64 / TIMESTAMP_SCALE as f64
+			);
+			data
+		};
+		write_binary_element(&mut info, element_id::INFO, &info_data);
+
+		let mut tracks = vec![];
+		let track_entry_data = {
+			let mut data = vec![];
+			write_unsigned_integer_element(
+				&mut data,
+				element_id::TRACK_NUMBER,
+				VORBIS_TRACK_NUMBER as u64
+			);
+			write_unsigned_integer_element(
+				&mut data,
+				element_id::TRACK_UID,
+				VORBIS_TRACK_NUMBER as u64
+			);
+			write_unsigned_integer_element(&mut data, element_id::TRACK_TYPE, AUDIO_TRACK_TYPE);
+			write_binary_element(&mut data, element_id::CODEC_ID, VORBIS_CODEC_ID);
+			write_binary_element(&mut data, element_id::CODEC_PRIVATE, &self.codec_private);
+
+			let mut audio_data = vec![];
+			write_float_element(
+				&mut audio_data,
+				element_id::SAMPLING_FREQUENCY,
+				self.sampling_frequency.get() as f64
+			);
+			write_unsigned_integer_element(
+				&mut audio_data,
+				element_id::CHANNELS,
+				self.channels as u64
+			);
+			write_binary_element(&mut data, element_id::AUDIO, &audio_data);
+
+			data
+		};
+		let mut track_entry = vec![];
+		write_binary_element(&mut track_entry, element_id::TRACK_ENTRY, &track_entry_data);
+		write_binary_element(&mut tracks, element_id::TRACKS, &track_entry);
+
+		// The seek head is written first, so its size must not depend on the positions
+		// it contains. Write them with a fixed size to achieve that
+		let seek_head_with_positions = |positions: &[(u32, u64)]| {
+			let mut seek_head_data = vec![];
+			for (id, position) in positions {
+				let mut seek_data = vec![];
+				write_binary_element(
+					&mut seek_data,
+					element_id::SEEK_ID,
+					&id.to_be_bytes()[id.leading_zeros() as usize / 8..]
+				);
+				write_binary_element(
+					&mut seek_data,
+					element_id::SEEK_POSITION,
+					&position.to_be_bytes()
+				);
+				write_binary_element(&mut seek_head_data, element_id::SEEK, &seek_data);
+			}
+
+			let mut seek_head = vec![];
+			write_binary_element(&mut seek_head, element_id::SEEK_HEAD, &seek_head_data);
+			seek_head
+		};
+
+		let mut seek_positions = vec![(element_id::INFO, 0), (element_id::TRACKS, 0)];
+		// Cues must have at least a cue point
+		if !self.cluster_positions.is_empty() {
+			seek_positions.push((element_id::CUES, 0));
+		}
+
+		let info_position = seek_head_with_positions(&seek_positions).len() as u64;
+		let tracks_position = info_position + info.len() as u64;
+		let clusters_position = tracks_position + tracks.len() as u64;
+		let cues_position = clusters_position + self.clusters.len() as u64;
+
+		seek_positions[0].1 = info_position;
+		seek_positions[1].1 = tracks_position;
+		if let Some((_, position)) = seek_positions.get_mut(2) {
+			*position = cues_position;
+		}
+		let seek_head = seek_head_with_positions(&seek_positions);
+
+		let mut cues = vec![];
+		if !self.cluster_positions.is_empty() {
+			let mut cues_data = vec![];
+			for (cluster_timestamp, cluster_position) in &self.cluster_positions {
+				let mut cue_track_positions_data = vec![];
+				write_unsigned_integer_element(
+					&mut cue_track_positions_data,
+					element_id::CUE_TRACK,
+					VORBIS_TRACK_NUMBER as u64
+				);
+				write_unsigned_integer_element(
+					&mut cue_track_positions_data,
+					element_id::CUE_CLUSTER_POSITION,
+					clusters_position + cluster_position
+				);
+
+				let mut cue_point_data = vec![];
+				write_unsigned_integer_element(
+					&mut cue_point_data,
+					element_id::CUE_TIME,
+					*cluster_timestamp
+				);
+				write_binary_element(
+					&mut cue_point_data,
+					element_id::CUE_TRACK_POSITIONS,
+					&cue_track_positions_data
+				);
+
+				write_binary_element(&mut cues_data, element_id::CUE_POINT, &cue_point_data);
+			}
+			write_binary_element(&mut cues, element_id::CUES, &cues_data);
+		}
+
+		let segment_data_size = (seek_head.len() + info.len() + tracks.len() + cues.len()) as u64
+			+ self.clusters.len() as u64;
+
+		sink.write_all(&ebml_header)?;
+		write_element_header(&mut sink, element_id::SEGMENT, segment_data_size)?;
+		sink.write_all(&seek_head)?;
+		sink.write_all(&info)?;
+		sink.write_all(&tracks)?;
+		sink.write_all(&self.clusters)?;
+		sink.write_all(&cues)
+	}
+}
@@ -0,0 +1,153 @@
+use std::io::Cursor;
+
+use super::*;
+use crate::remuxer::{
+	matroska::{parse_unsigned_integer, parse_vint},
+	matroska_to_matroska::MatroskaToMatroska
+};
+
+/// Splits the specified EBML data into its elements, returning their IDs and data.
+fn elements(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+	let mut elements = vec![];
+
+	while !data.is_empty() {
+		let (id, id_length) = parse_vint(data, true).unwrap();
+		let (data_size, data_size_length) = parse_vint(&data[id_length..], false).unwrap();
+		let (element_data, remaining_data) =
+			data[id_length + data_size_length..].split_at(data_size as usize);
+
+		elements.push((id as u32, element_data));
+		data = remaining_data;
+	}
+
+	elements
+}
+
+/// Returns the data of the first element with the specified ID.
+fn element_data(elements: &[(u32, &'static [u8])], id: u32) -> &'static [u8] {
+	elements
+		.iter()
+		.find(|(element_id, _)| *element_id == id)
+		.unwrap()
+		.1
+}
+
+fn remux(ogg_vorbis_data: &[u8]) -> Result<Vec<u8>, RemuxError> {
+	OggToWebm::new_with_defaults().remux(Cursor::new(ogg_vorbis_data), vec![])
+}
+
+#[test_log::test]
+fn remuxing_works() {
+	let webm_data = remux(include_bytes!(
+		"../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"
+	))
+	.expect("Unexpected remuxing error");
+	let webm_data = &*webm_data.leak();
+
+	let top_level_elements = elements(webm_data);
+	assert_eq!(
+		element_data(
+			&elements(element_data(&top_level_elements, element_id::EBML)),
+			element_id::DOC_TYPE
+		),
+		b"webm"
+	);
+
+	let segment_data = element_data(&top_level_elements, element_id::SEGMENT);
+	let segment_elements = elements(segment_data);
+
+	// The seek entries should point to the right elements
+	for (_, seek_data) in elements(element_data(&segment_elements, element_id::SEEK_HEAD)) {
+		let seek_elements = elements(seek_data);
+		let seek_id = element_data(&seek_elements, element_id::SEEK_ID);
+		let seek_position =
+			parse_unsigned_integer(element_data(&seek_elements, element_id::SEEK_POSITION))
+				.unwrap();
+
+		assert!(
+			segment_data[seek_position as usize..].starts_with(seek_id),
+			"Unexpected element at seek position"
+		);
+	}
+
+	// 500 ms of audio should have been muxed, with non-decreasing block timestamps
+	let info_elements = elements(element_data(&segment_elements, element_id::INFO));
+	assert_eq!(
+		f64::from_be_bytes(
+			element_data(&info_elements, element_id::DURATION)
+				.try_into()
+				.unwrap()
+		),
+		500.0
+	);
+
+	let mut last_block_timestamp = 0;
+	let mut last_block_id = 0;
+	for (_, cluster_data) in segment_elements
+		.iter()
+		.filter(|(id, _)| *id == element_id::CLUSTER)
+	{
+		let cluster_elements = elements(cluster_data);
+		let cluster_timestamp =
+			parse_unsigned_integer(element_data(&cluster_elements, element_id::TIMESTAMP)).unwrap();
+
+		for (id, block_data) in cluster_elements
+			.iter()
+			.filter(|(id, _)| *id != element_id::TIMESTAMP)
+		{
+			let block_data = match *id {
+				element_id::BLOCK_GROUP => element_data(&elements(block_data), element_id::BLOCK),
+				_ => block_data
+			};
+			let block_timestamp = cluster_timestamp
+				.checked_add_signed(i16::from_be_bytes([block_data[1], block_data[2]]) as i64)
+				.unwrap();
+
+			assert!(
+				block_timestamp >= last_block_timestamp,
+				"Block timestamps are not monotonic"
+			);
+			last_block_timestamp = block_timestamp;
+			last_block_id = *id;
+		}
+	}
+
+	// The encoder truncated the last audio packet, so a discard padding should be present
+	assert_eq!(last_block_id, element_id::BLOCK_GROUP);
+
+	// Dogfooding asserts the invariant that the result of remuxing to WebM is a WebM file
+	// with Vorbis data we can parse
+	MatroskaToMatroska::new_with_defaults()
+		.remux(Cursor::new(webm_data), io::sink())
+		.expect("Unexpected error remuxing the WebM file");
+}
+
+#[test_log::test]
+fn remuxing_with_skeleton_works() {
+	remux(include_bytes!(
+		"../../../resources/test/44100hz_500ms_stereo_400hz_sine_wave_skeleton.ogg"
+	))
+	.expect("Unexpected remuxing error");
+}
+
+#[test_log::test]
+fn chained_streams_return_error() {
+	assert!(matches!(
+		remux(include_bytes!(
+			"../../../resources/test/8khz_2x500ms_mono_400hz_sine_wave_chained.ogg"
+		)),
+		Err(RemuxError::UnsupportedStreamChaining)
+	));
+}
+
+#[test_log::test]
+fn non_vorbis_streams_return_error() {
+	assert!(matches!(
+		remux(include_bytes!(
+			"../../../resources/test/44100hz_500ms_mono_440hz_sine_wave_ogg_opus.ogg"
+		)),
+		Err(RemuxError::OggDemuxError(
+			ogg_to_ogg::RemuxError::UnsupportedCodec(ogg_to_ogg::OggAudioCodec::Opus)
+		))
+	));
+}
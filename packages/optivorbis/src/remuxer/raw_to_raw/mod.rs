@@ -0,0 +1,373 @@
+//! Contains the [`RawToRaw`] remuxer struct and helper data types.
+//!
+//! # Raw Vorbis packet stream format
+//!
+//! This remuxer reads and writes Vorbis packets stored in a minimal framing, meant for
+//! applications that do not store Vorbis streams in a container. Every integer is stored
+//! in little-endian byte order:
+//!
+//! 1. The identification, comment and setup header packets, in that order, each prefixed
+//!    by its length in bytes as an unsigned 32-bit integer.
+//! 2. If the granule side table is enabled, the number of audio packets as an unsigned
+//!    32-bit integer.
+//! 3. Every audio packet, each prefixed by its length in bytes as an unsigned 16-bit or
+//!    32-bit integer. Without a granule side table, audio packets follow until the end of
+//!    the data.
+//! 4. If the granule side table is enabled, the granule position of every audio packet as
+//!    a signed 64-bit integer. Like in Ogg Vorbis streams, the granule position of an audio
+//!    packet is the number of samples a decoder has returned after decoding it, which for
+//!    the last packet may be lower than the number of samples it decodes to, signaling that
+//!    the surplus samples at the end of the stream should be discarded.
+//!
+//! The code of this remuxer is intentionally kept simple, so it can also serve as a
+//! reference on how to use the [`VorbisOptimizer`] API to implement other remuxers.
+
+use std::{
+	io::{self, BufReader, Read, Seek, SeekFrom, Write},
+	ops::RangeInclusive
+};
+
+use log::info;
+use thiserror::Error;
+
+use super::Remuxer;
+use crate::vorbis::optimizer::{VorbisOptimizer, VorbisOptimizerError, VorbisOptimizerSettings};
+
+#[cfg(test)]
+mod test;
+
+/// A [`Remuxer`] that processes a Vorbis stream stored as raw, length-prefixed packets,
+/// generating another raw Vorbis packet stream with the same framing. The framing is
+/// documented in the [module documentation](self).
+///
+/// Granule positions are recomputed from zero. When a granule side table is read, the
+/// granule position of the last audio packet is kept if it is valid, so that sample-exact
+/// decoding is still possible.
+pub struct RawToRaw {
+	remuxer_settings: Settings,
+	optimizer_settings: VorbisOptimizerSettings
+}
+
+/// Settings that influence how raw Vorbis packet streams are read and written.
+pub struct Settings {
+	/// Sets the width of the length prefix of audio packets. Header packets are always
+	/// prefixed by a 32-bit length.
+	///
+	/// **Default value**: [`AudioPacketLengthWidth::U32`]
+	pub audio_packet_length_width: AudioPacketLengthWidth,
+	/// Sets whether the packet stream contains an audio packet count before the audio
+	/// packets and a granule side table after them.
+	///
+	/// **Default value**: `false`
+	pub granule_side_table: bool
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			audio_packet_length_width: AudioPacketLengthWidth::U32,
+			granule_side_table: false
+		}
+	}
+}
+
+/// The width of the integer that prefixes audio packets with their length.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AudioPacketLengthWidth {
+	/// An unsigned 16-bit integer, which limits audio packets to at most 65535 bytes.
+	U16,
+	/// An unsigned 32-bit integer.
+	U32
+}
+
+/// Represents an error that may happen while remuxing with the [`RawToRaw`] remuxer.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RemuxError {
+	/// Represents a Vorbis stream optimizer error. This may happen in corrupt Vorbis
+	/// streams, or streams that use unsupported features.
+	#[error("Vorbis optimization error: {0}")]
+	OptimizerError(#[from] VorbisOptimizerError),
+	/// An optimized audio packet is too long to have its length represented with the
+	/// configured audio packet length width.
+	#[error("An optimized audio packet of {0} bytes is too long for its length prefix")]
+	AudioPacketTooLong(usize),
+	/// An I/O error happened. This includes the packet stream ending unexpectedly.
+	#[error("I/O error: {0}")]
+	IoError(#[from] io::Error)
+}
+
+impl RemuxError {
+	/// Returns a short, stable, machine-readable identifier for the kind of this error.
+	pub const fn code(&self) -> &'static str {
+		match self {
+			Self::OptimizerError(_) => "optimizer_error",
+			Self::AudioPacketTooLong(_) => "audio_packet_too_long",
+			Self::IoError(_) => "io_error"
+		}
+	}
+}
+
+/// The information gathered in the first remuxing pass.
+struct FirstPassResult<'settings> {
+	optimizer: VorbisOptimizer<'settings>,
+	/// The number of audio packets that will be written to the optimized stream.
+	audio_packet_count: u32,
+	/// The granule position of the last audio packet in the source granule side table,
+	/// if any.
+	last_audio_packet_granule_position: Option<i64>
+}
+
+impl Remuxer for RawToRaw {
+	type RemuxError = RemuxError;
+	type RemuxerSettings = Settings;
+
+	fn new(remuxer_settings: Settings, optimizer_settings: VorbisOptimizerSettings) -> Self {
+		Self {
+			remuxer_settings,
+			optimizer_settings
+		}
+	}
+
+	fn remux<R: Read + Seek, W: Write>(
+		&self,
+		mut source: R,
+		mut sink: W
+	) -> Result<W, Self::RemuxError> {
+		// Remember the source stream position to rewind to it later
+		let initial_source_pos = source.stream_position()?;
+
+		// First pass: gather stream data for optimization
+		info!("Starting first raw to raw remux pass");
+		let mut first_pass_result = self.first_pass(BufReader::new(&mut source))?;
+		info!("First raw to raw remux pass completed");
+
+		// Rewind for the second pass
+		source.seek(SeekFrom::Start(initial_source_pos))?;
+
+		// Second pass: optimizing Vorbis packet rewrite
+		info!("Starting second raw to raw remux pass");
+		self.second_pass(BufReader::new(source), &mut sink, &mut first_pass_result)?;
+		info!("Second raw to raw remux pass completed");
+
+		Ok(sink)
+	}
+}
+
+impl RawToRaw {
+	/// Executes the first remuxing pass, where every packet is read and analyzed for
+	/// optimization.
+	fn first_pass<R: Read>(&self, mut source: R) -> Result<FirstPassResult<'_>, RemuxError> {
+		let mut optimizer =
+			VorbisOptimizer::new(&self.optimizer_settings, read_header_packet(&mut source)?)?;
+		optimizer.analyze_packet(read_header_packet(&mut source)?)?;
+		optimizer.analyze_packet(read_header_packet(&mut source)?)?;
+
+		let source_audio_packet_count = self.read_audio_packet_count(&mut source)?;
+		let mut remaining_audio_packet_count = source_audio_packet_count;
+
+		let mut audio_packet = vec![];
+		let mut audio_packet_count = 0;
+		while self.read_audio_packet(
+			&mut source,
+			&mut audio_packet,
+			&mut remaining_audio_packet_count
+		)? {
+			// Audio packets that will be discarded from the stream won't be written out
+			if optimizer.analyze_packet(&audio_packet)?.is_some() {
+				audio_packet_count += 1;
+			}
+		}
+
+		let mut last_audio_packet_granule_position = None;
+		for _ in 0..source_audio_packet_count.unwrap_or(0) {
+			let mut granule_position = [0; 8];
+			source.read_exact(&mut granule_position)?;
+			last_audio_packet_granule_position = Some(i64::from_le_bytes(granule_position));
+		}
+
+		Ok(FirstPassResult {
+			optimizer,
+			audio_packet_count,
+			last_audio_packet_granule_position
+		})
+	}
+
+	/// Executes the second remuxing pass, where every packet is read again, and its optimized
+	/// version written out to the sink.
+	fn second_pass<R: Read, W: Write>(
+		&self,
+		mut source: R,
+		mut sink: W,
+		first_pass_result: &mut FirstPassResult<'_>
+	) -> Result<(), RemuxError> {
+		let optimizer = &mut first_pass_result.optimizer;
+
+		for _ in 0..3 {
+			// Header packets are never discarded by the optimizer
+			let (header_packet, _) = optimizer
+				.optimize_packet(read_header_packet(&mut source)?)?
+				.unwrap();
+
+			sink.write_all(&(header_packet.len() as u32).to_le_bytes())?;
+			sink.write_all(&header_packet)?;
+		}
+
+		let mut remaining_audio_packet_count = self.read_audio_packet_count(&mut source)?;
+		if self.remuxer_settings.granule_side_table {
+			sink.write_all(&first_pass_result.audio_packet_count.to_le_bytes())?;
+		}
+
+		let mut audio_packet = vec![];
+		let mut granule_positions =
+			Vec::with_capacity(if self.remuxer_settings.granule_side_table {
+				first_pass_result.audio_packet_count as usize
+			} else {
+				0
+			});
+		let mut last_granule_position_and_sample_block_size = None;
+		let mut written_audio_packet_count = 0;
+
+		while self.read_audio_packet(
+			&mut source,
+			&mut audio_packet,
+			&mut remaining_audio_packet_count
+		)? {
+			let Some((optimized_packet, Some(sample_block_size))) =
+				optimizer.optimize_packet(&audio_packet[..])?
+			else {
+				// Discard the packet. Pretend it never existed
+				continue;
+			};
+
+			match self.remuxer_settings.audio_packet_length_width {
+				AudioPacketLengthWidth::U16 => sink.write_all(
+					&u16::try_from(optimized_packet.len())
+						.map_err(|_| RemuxError::AudioPacketTooLong(optimized_packet.len()))?
+						.to_le_bytes()
+				)?,
+				AudioPacketLengthWidth::U32 => sink.write_all(
+					&u32::try_from(optimized_packet.len())
+						.map_err(|_| RemuxError::AudioPacketTooLong(optimized_packet.len()))?
+						.to_le_bytes()
+				)?
+			}
+			sink.write_all(&optimized_packet)?;
+
+			written_audio_packet_count += 1;
+
+			let granule_position = match last_granule_position_and_sample_block_size {
+				// Vorbis I specification, § 4.3.8: the first audio packet does not return
+				// any samples
+				None => 0,
+				Some((last_granule_position, last_sample_block_size)) => {
+					// Vorbis I specification, § 4.3.8: decoding an audio packet returns
+					// samples from the center of the previous block to the center of this
+					let granule_position = last_granule_position
+						+ (last_sample_block_size as i64 + sample_block_size as i64) / 4;
+
+					// The source may have signaled that some samples at the end of the last
+					// packet are to be discarded. Honor that if it looks sensible
+					match first_pass_result.last_audio_packet_granule_position {
+						Some(last_audio_packet_granule_position)
+							if written_audio_packet_count
+								== first_pass_result.audio_packet_count
+								&& RangeInclusive::new(
+									last_granule_position + 1,
+									granule_position
+								)
+								.contains(&last_audio_packet_granule_position) =>
+						{
+							last_audio_packet_granule_position
+						}
+						_ => granule_position
+					}
+				}
+			};
+
+			if self.remuxer_settings.granule_side_table {
+				granule_positions.push(granule_position);
+			}
+			last_granule_position_and_sample_block_size =
+				Some((granule_position, sample_block_size));
+		}
+
+		for granule_position in granule_positions {
+			sink.write_all(&granule_position.to_le_bytes())?;
+		}
+
+		Ok(())
+	}
+
+	/// Reads the number of audio packets that precedes the audio packets, if the granule
+	/// side table is enabled.
+	fn read_audio_packet_count<R: Read>(&self, mut source: R) -> Result<Option<u32>, RemuxError> {
+		if !self.remuxer_settings.granule_side_table {
+			return Ok(None);
+		}
+
+		let mut audio_packet_count = [0; 4];
+		source.read_exact(&mut audio_packet_count)?;
+		Ok(Some(u32::from_le_bytes(audio_packet_count)))
+	}
+
+	/// Reads the next audio packet into `audio_packet`, returning whether there was any
+	/// audio packet left to read. If the number of audio packets left to read is known,
+	/// it is decremented. Otherwise, audio packets are read until the end of the source.
+	fn read_audio_packet<R: Read>(
+		&self,
+		mut source: R,
+		audio_packet: &mut Vec<u8>,
+		remaining_audio_packet_count: &mut Option<u32>
+	) -> Result<bool, RemuxError> {
+		let mut length = [0; 4];
+		let length_prefix = match self.remuxer_settings.audio_packet_length_width {
+			AudioPacketLengthWidth::U16 => &mut length[..2],
+			AudioPacketLengthWidth::U32 => &mut length[..]
+		};
+
+		match remaining_audio_packet_count {
+			Some(0) => return Ok(false),
+			Some(remaining_audio_packet_count) => {
+				source.read_exact(length_prefix)?;
+				*remaining_audio_packet_count -= 1;
+			}
+			None => {
+				// Tell apart the end of the source from a truncated length prefix
+				if source.read(&mut length_prefix[..1])? == 0 {
+					return Ok(false);
+				}
+				source.read_exact(&mut length_prefix[1..])?;
+			}
+		}
+
+		read_packet_data(source, u32::from_le_bytes(length), audio_packet)?;
+
+		Ok(true)
+	}
+}
+
+/// Reads a header packet prefixed by its 32-bit length.
+fn read_header_packet<R: Read>(mut source: R) -> Result<Vec<u8>, RemuxError> {
+	let mut length = [0; 4];
+	source.read_exact(&mut length)?;
+
+	let mut header_packet = vec![];
+	read_packet_data(source, u32::from_le_bytes(length), &mut header_packet)?;
+
+	Ok(header_packet)
+}
+
+/// Reads the data of a packet with the specified length into `packet`, replacing its
+/// previous contents.
+fn read_packet_data<R: Read>(source: R, length: u32, packet: &mut Vec<u8>) -> io::Result<()> {
+	// Don't trust the length to allocate a buffer upfront, as it may be corrupt
+	packet.clear();
+	source.take(length as u64).read_to_end(packet)?;
+
+	if packet.len() == length as usize {
+		Ok(())
+	} else {
+		Err(io::ErrorKind::UnexpectedEof.into())
+	}
+}
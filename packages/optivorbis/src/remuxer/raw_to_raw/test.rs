@@ -0,0 +1,116 @@
+use std::io::Cursor;
+
+use ogg::PacketReader;
+
+use super::*;
+
+/// Converts the specified Ogg Vorbis file to a raw Vorbis packet stream with the framing
+/// described by the specified settings. The granule positions in the granule side table
+/// are the granule positions of the pages where packets end.
+fn raw_packet_stream(ogg_vorbis_data: &[u8], settings: &Settings) -> Vec<u8> {
+	let mut packet_reader = PacketReader::new(Cursor::new(ogg_vorbis_data));
+	let mut packets = vec![];
+	while let Some(packet) = packet_reader.read_packet().unwrap() {
+		packets.push((packet.absgp_page() as i64, packet.data));
+	}
+
+	let mut raw_packet_stream = vec![];
+	for (_, header_packet) in &packets[..3] {
+		raw_packet_stream.extend((header_packet.len() as u32).to_le_bytes());
+		raw_packet_stream.extend(header_packet);
+	}
+
+	let audio_packets = &packets[3..];
+	if settings.granule_side_table {
+		raw_packet_stream.extend((audio_packets.len() as u32).to_le_bytes());
+	}
+	for (_, audio_packet) in audio_packets {
+		match settings.audio_packet_length_width {
+			AudioPacketLengthWidth::U16 => {
+				raw_packet_stream.extend((audio_packet.len() as u16).to_le_bytes())
+			}
+			AudioPacketLengthWidth::U32 => {
+				raw_packet_stream.extend((audio_packet.len() as u32).to_le_bytes())
+			}
+		}
+		raw_packet_stream.extend(audio_packet);
+	}
+	if settings.granule_side_table {
+		for (granule_position, _) in audio_packets {
+			raw_packet_stream.extend(granule_position.to_le_bytes());
+		}
+	}
+
+	raw_packet_stream
+}
+
+fn remux_with_settings(
+	raw_packet_stream: &[u8],
+	settings_supplier: impl Fn() -> Settings
+) -> Result<Vec<u8>, RemuxError> {
+	let optimized_raw_packet_stream = RawToRaw::new(settings_supplier(), Default::default())
+		.remux(Cursor::new(raw_packet_stream), vec![])?;
+
+	// Dogfooding asserts the invariant that the result of optimizing a raw packet stream
+	// is another raw packet stream we can parse
+	RawToRaw::new(settings_supplier(), Default::default())
+		.remux(Cursor::new(&optimized_raw_packet_stream), io::sink())?;
+
+	Ok(optimized_raw_packet_stream)
+}
+
+#[test_log::test]
+fn remuxing_works() {
+	let raw_packet_stream = raw_packet_stream(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+		&Settings::default()
+	);
+
+	let optimized_raw_packet_stream = remux_with_settings(&raw_packet_stream, Default::default)
+		.expect("Unexpected remuxing error");
+
+	assert!(
+		optimized_raw_packet_stream.len() < raw_packet_stream.len(),
+		"The optimized stream is not smaller than the original"
+	);
+}
+
+#[test_log::test]
+fn remuxing_with_granule_side_table_works() {
+	let settings = || Settings {
+		audio_packet_length_width: AudioPacketLengthWidth::U16,
+		granule_side_table: true
+	};
+	let raw_packet_stream = raw_packet_stream(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+		&settings()
+	);
+
+	let optimized_raw_packet_stream =
+		remux_with_settings(&raw_packet_stream, settings).expect("Unexpected remuxing error");
+
+	// The truncated end of the stream should be kept, so the last granule position
+	// should be the stream length in samples
+	let last_granule_position = i64::from_le_bytes(
+		optimized_raw_packet_stream[optimized_raw_packet_stream.len() - 8..]
+			.try_into()
+			.unwrap()
+	);
+	assert_eq!(last_granule_position, 4000);
+}
+
+#[test_log::test]
+fn truncated_packet_stream_returns_error() {
+	let raw_packet_stream = raw_packet_stream(
+		include_bytes!("../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"),
+		&Settings::default()
+	);
+
+	assert!(matches!(
+		remux_with_settings(
+			&raw_packet_stream[..raw_packet_stream.len() - 1],
+			Default::default
+		),
+		Err(RemuxError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+	));
+}
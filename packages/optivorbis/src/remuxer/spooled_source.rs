@@ -0,0 +1,316 @@
+//! Contains the [`SpooledSource`] adapter, which allows remuxing data from non-seekable
+//! sources.
+
+use std::{
+	env,
+	fs::{self, File},
+	io::{self, Read, Seek, SeekFrom, Write},
+	path::{Path, PathBuf}
+};
+
+/// Settings that influence how a [`SpooledSource`] stores the data it reads.
+pub struct SpoolSettings {
+	/// Sets the maximum number of bytes of source data that will be held in memory. Data past
+	/// this limit is spooled to a temporary file, if allowed by
+	/// [`temporary_file_directory`](Self::temporary_file_directory).
+	///
+	/// **Default value**: `67108864` (64 MiB)
+	pub memory_limit: usize,
+	/// Sets the directory where the temporary file that holds the source data exceeding the
+	/// [`memory_limit`](Self::memory_limit) is created. The file is deleted when the
+	/// [`SpooledSource`] is dropped. If `None`, no temporary file is used, and reading more
+	/// data than the memory limit allows is an error.
+	///
+	/// **Default value**: `Some(std::env::temp_dir())`
+	pub temporary_file_directory: Option<PathBuf>
+}
+
+impl Default for SpoolSettings {
+	fn default() -> Self {
+		Self {
+			memory_limit: 64 * 1024 * 1024,
+			temporary_file_directory: Some(env::temp_dir())
+		}
+	}
+}
+
+/// A [`Read`] and [`Seek`] adapter for sources that only implement [`Read`], such as pipes or
+/// network streams, which makes them usable with the [`Remuxer::remux`](super::Remuxer::remux)
+/// method.
+///
+/// Remuxers read their source at least twice: once to analyze the Vorbis streams, and again to
+/// optimize them. This adapter keeps a copy of every byte read from the source, first in memory
+/// and then in a temporary file, so that it can be read again after seeking back. Therefore,
+/// memory usage is bounded by the configured [`SpoolSettings`], but the whole source is stored
+/// somewhere.
+pub struct SpooledSource<R: Read> {
+	source: R,
+	settings: SpoolSettings,
+	memory_spool: Vec<u8>,
+	file_spool: Option<TemporaryFile>,
+	spooled_length: u64,
+	position: u64,
+	source_exhausted: bool
+}
+
+impl<R: Read> SpooledSource<R> {
+	/// Creates a new spooled source that reads data from the specified source, storing it
+	/// as configured by the specified settings.
+	pub fn new(source: R, settings: SpoolSettings) -> Self {
+		Self {
+			source,
+			settings,
+			memory_spool: vec![],
+			file_spool: None,
+			spooled_length: 0,
+			position: 0,
+			source_exhausted: false
+		}
+	}
+
+	/// Reads more data from the source into the spool. Returns the number of bytes read, which
+	/// is zero if the end of the source was reached.
+	fn spool_more_data(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.source_exhausted {
+			return Ok(0);
+		}
+
+		let read_bytes = self.source.read(buf)?;
+		if read_bytes == 0 {
+			self.source_exhausted = true;
+			return Ok(0);
+		}
+
+		let read_data = &buf[..read_bytes];
+		let memory_spool_capacity = self.settings.memory_limit - self.memory_spool.len();
+		let (memory_spooled_data, file_spooled_data) =
+			read_data.split_at(read_bytes.min(memory_spool_capacity));
+
+		self.memory_spool.extend_from_slice(memory_spooled_data);
+
+		if !file_spooled_data.is_empty() {
+			let file_spool = match &mut self.file_spool {
+				Some(file_spool) => file_spool,
+				None => {
+					let Some(directory) = &self.settings.temporary_file_directory else {
+						return Err(io::Error::new(
+							io::ErrorKind::OutOfMemory,
+							"The source data exceeds the spool memory limit"
+						));
+					};
+
+					self.file_spool.insert(TemporaryFile::new(directory)?)
+				}
+			};
+
+			file_spool.file.seek(SeekFrom::End(0))?;
+			file_spool.file.write_all(file_spooled_data)?;
+		}
+
+		self.spooled_length += read_bytes as u64;
+
+		Ok(read_bytes)
+	}
+
+	/// Spools source data until the spool reaches the specified length or the source ends.
+	fn spool_until(&mut self, length: u64) -> io::Result<()> {
+		let mut buf = [0; 8192];
+
+		while self.spooled_length < length {
+			let remaining_length = (length - self.spooled_length).min(buf.len() as u64) as usize;
+			if self.spool_more_data(&mut buf[..remaining_length])? == 0 {
+				break;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl<R: Read> Read for SpooledSource<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.spool_until(self.position)?;
+
+		let read_bytes = if self.position == self.spooled_length {
+			// Nothing to replay, so read fresh data from the source
+			self.spool_more_data(buf)?
+		} else if self.position < self.memory_spool.len() as u64 {
+			let memory_spooled_data = &self.memory_spool[self.position as usize..];
+			let read_bytes = buf.len().min(memory_spooled_data.len());
+			buf[..read_bytes].copy_from_slice(&memory_spooled_data[..read_bytes]);
+			read_bytes
+		} else if let Some(file_spool) = &mut self.file_spool {
+			let file_spool_position = self.position - self.memory_spool.len() as u64;
+			let remaining_length = self.spooled_length.saturating_sub(self.position);
+			let read_length = (buf.len() as u64).min(remaining_length) as usize;
+
+			file_spool.file.seek(SeekFrom::Start(file_spool_position))?;
+			file_spool.file.read(&mut buf[..read_length])?
+		} else {
+			// Past the end of the source
+			0
+		};
+
+		self.position += read_bytes as u64;
+
+		Ok(read_bytes)
+	}
+}
+
+impl<R: Read> Seek for SpooledSource<R> {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		let (base_position, offset) = match pos {
+			SeekFrom::Start(position) => (position, 0),
+			SeekFrom::Current(offset) => (self.position, offset),
+			SeekFrom::End(offset) => {
+				// The end of the source is only known after reading all of it
+				self.spool_until(u64::MAX)?;
+				(self.spooled_length, offset)
+			}
+		};
+
+		self.position = base_position.checked_add_signed(offset).ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Invalid seek to a negative or overflowing position"
+			)
+		})?;
+
+		Ok(self.position)
+	}
+}
+
+/// A temporary file that is deleted when dropped.
+struct TemporaryFile {
+	file: File,
+	path: PathBuf
+}
+
+impl TemporaryFile {
+	/// Creates a new temporary file with a unique name in the specified directory.
+	fn new(directory: &Path) -> io::Result<Self> {
+		let mut random_bytes = [0; 8];
+		getrandom::fill(&mut random_bytes).map_err(io::Error::other)?;
+
+		let path = directory.join(format!(
+			"optivorbis-spool-{:016x}",
+			u64::from_ne_bytes(random_bytes)
+		));
+		let file = File::options()
+			.read(true)
+			.write(true)
+			.create_new(true)
+			.open(&path)?;
+
+		Ok(Self { file, path })
+	}
+}
+
+impl Drop for TemporaryFile {
+	fn drop(&mut self) {
+		// There is not much we can do if this fails
+		fs::remove_file(&self.path).ok();
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::io::Cursor;
+
+	use super::*;
+	use crate::{
+		OggToOgg,
+		remuxer::{Remuxer, ogg_to_ogg::Settings}
+	};
+
+	/// A source that is not seekable and returns its data in small chunks, like a pipe.
+	struct PipeSource<'data>(&'data [u8]);
+
+	impl Read for PipeSource<'_> {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			let read_bytes = buf.len().min(self.0.len()).min(100);
+			buf[..read_bytes].copy_from_slice(&self.0[..read_bytes]);
+			self.0 = &self.0[read_bytes..];
+			Ok(read_bytes)
+		}
+	}
+
+	#[test_log::test]
+	fn remuxing_spooled_source_works() {
+		let ogg_vorbis_data =
+			include_bytes!("../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg");
+
+		// Keep stream serials deterministic, so that the remuxed data can be compared
+		let remuxer = || {
+			OggToOgg::new(
+				Settings {
+					randomize_stream_serials: false,
+					..Default::default()
+				},
+				Default::default()
+			)
+		};
+
+		let expected_data = remuxer()
+			.remux(Cursor::new(ogg_vorbis_data), vec![])
+			.expect("Unexpected remuxing error");
+
+		// Exercise both the memory and the temporary file spools
+		for memory_limit in [usize::MAX, 1000, 0] {
+			let remuxed_data = remuxer()
+				.remux_unseekable(
+					PipeSource(ogg_vorbis_data),
+					vec![],
+					SpoolSettings {
+						memory_limit,
+						..Default::default()
+					}
+				)
+				.expect("Unexpected remuxing error");
+
+			assert_eq!(remuxed_data, expected_data);
+		}
+	}
+
+	#[test_log::test]
+	fn seeking_works() {
+		let data = (0..=255).collect::<Vec<u8>>();
+		let mut spooled_source = SpooledSource::new(
+			PipeSource(&data),
+			SpoolSettings {
+				memory_limit: 100,
+				..Default::default()
+			}
+		);
+
+		let mut buf = [0; 10];
+		spooled_source.seek(SeekFrom::Start(150)).unwrap();
+		spooled_source.read_exact(&mut buf).unwrap();
+		assert_eq!(buf, data[150..160]);
+
+		spooled_source.seek(SeekFrom::Current(-100)).unwrap();
+		spooled_source.read_exact(&mut buf).unwrap();
+		assert_eq!(buf, data[60..70]);
+
+		assert_eq!(spooled_source.seek(SeekFrom::End(-5)).unwrap(), 251);
+		let mut remaining_data = vec![];
+		spooled_source.read_to_end(&mut remaining_data).unwrap();
+		assert_eq!(remaining_data, data[251..]);
+	}
+
+	#[test_log::test]
+	fn exceeding_memory_limit_without_temporary_file_returns_error() {
+		let mut spooled_source = SpooledSource::new(
+			PipeSource(&[0; 200]),
+			SpoolSettings {
+				memory_limit: 100,
+				temporary_file_directory: None
+			}
+		);
+
+		assert_eq!(
+			spooled_source.read_to_end(&mut vec![]).unwrap_err().kind(),
+			io::ErrorKind::OutOfMemory
+		);
+	}
+}
@@ -85,9 +85,13 @@ impl Display for PacketType {
 /// specification, § 3.2.1.
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 #[repr(u8)]
-enum VectorLookupType {
+pub enum VectorLookupType {
+	/// The codebook is only used to decode scalar entry numbers, not vectors.
 	NoLookup = 0,
+	/// The codebook vectors are implicitly built from the Cartesian product of a
+	/// single list of values, which is shared by every vector dimension.
 	ImplicitlyPopulated = 1,
+	/// Every value of every codebook vector is explicitly listed.
 	ExplicitlyPopulated = 2
 }
 
@@ -99,9 +103,14 @@ try_from_impl! {
 /// Represents a residue vector type, defined in the Vorbis I specification, § 8.
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 #[repr(u16)]
-enum ResidueType {
+pub enum ResidueType {
+	/// Residue type 0, where the values of each channel vector are interleaved by
+	/// partition.
 	Interleaved = 0,
+	/// Residue type 1, where the values of each channel vector are coded in order.
 	Ordered = 1,
+	/// Residue type 2, where the channel vectors are interleaved into a single vector,
+	/// which is then coded like in type 1.
 	InterleavedVectors = 2
 }
 
@@ -0,0 +1,137 @@
+//! Contains the [`CodewordLookupTable`] definition and implementation.
+
+use std::num::NonZeroU8;
+
+use vorbis_bitpack::BitpackedIntegerWidth;
+
+use super::huffman_tree::VorbisHuffmanTree;
+
+/// The maximum number of bits a [`CodewordLookupTable`] resolves at once. Higher values
+/// make longer codewords decodable with a single lookup, at the cost of exponentially
+/// bigger tables, which are slower to build and less cache-friendly. Vorbis encoders
+/// usually generate codebooks whose most frequent codewords are shorter than this.
+const MAX_LOOKUP_BITS: u8 = 10;
+
+/// A lookup table that decodes codewords of a [`VorbisHuffmanTree`] up to a certain
+/// length in a single step, by indexing it with the next bits of the stream.
+///
+/// Lookups for bits that begin with a longer codeword, or with a codeword that is not
+/// assigned to any entry, yield no entry. Such codewords must be decoded by walking
+/// the tree instead.
+pub(super) struct CodewordLookupTable {
+	entries: Vec<Option<CodewordLookupTableEntry>>,
+	lookup_bits: BitpackedIntegerWidth
+}
+
+/// An entry decoded by a [`CodewordLookupTable`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct CodewordLookupTableEntry {
+	/// The number of the decoded entry.
+	pub(super) entry_number: u32,
+	/// The length of the codeword of the decoded entry, which is the number of bits
+	/// to consume from the stream.
+	pub(super) codeword_length: NonZeroU8
+}
+
+impl CodewordLookupTable {
+	/// Builds a lookup table for the codewords of the specified tree, whose longest
+	/// codeword has the specified length.
+	pub(super) fn new(huffman_tree: &VorbisHuffmanTree, max_codeword_length: u8) -> Self {
+		let lookup_bits = max_codeword_length.min(MAX_LOOKUP_BITS);
+		let mut entries = vec![None; 1 << lookup_bits];
+
+		huffman_tree.for_each_short_codeword(
+			lookup_bits,
+			|entry_number, codeword, codeword_length| {
+				// Entries are never at the tree root, so their codewords are never empty
+				let entry = NonZeroU8::new(codeword_length).map(|codeword_length| {
+					CodewordLookupTableEntry {
+						entry_number,
+						codeword_length
+					}
+				});
+
+				// Codewords are read LSB first, so every table index whose least
+				// significant bits are this codeword decodes to this entry
+				for index in (codeword as usize..entries.len()).step_by(1 << codeword_length) {
+					entries[index] = entry;
+				}
+			}
+		);
+
+		Self {
+			entries,
+			// Unwrap is safe: MAX_LOOKUP_BITS is a valid width
+			lookup_bits: BitpackedIntegerWidth::new(lookup_bits).unwrap()
+		}
+	}
+
+	/// Returns the number of bits that should be peeked from the stream to do a lookup.
+	pub(super) fn lookup_bits(&self) -> BitpackedIntegerWidth {
+		self.lookup_bits
+	}
+
+	/// Returns the entry that is decoded from the specified bits, read LSB first, if
+	/// they begin with a codeword of at most [`lookup_bits`](Self::lookup_bits) bits.
+	pub(super) fn lookup(&self, peeked_bits: u32) -> Option<CodewordLookupTableEntry> {
+		self.entries[peeked_bits as usize]
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn lookup_table_decodes_like_tree() {
+		// Example tree from the Vorbis I specification § 3.2.1
+		let tree = VorbisHuffmanTree::try_from_codeword_lengths([2, 4, 4, 4, 4, 2, 3, 3])
+			.expect("The Huffman tree was assumed to not be overspecified");
+
+		for lookup_bits in 0..=4 {
+			let lookup_table = CodewordLookupTable::new(&tree, lookup_bits);
+
+			for peeked_bits in 0..1 << lookup_bits {
+				// Decode the same bits by walking the tree, bit by bit
+				let (tree_entry_number, tree_codeword_length) = tree.with_walker(|mut walker| {
+					(0..)
+						.find_map(|i| {
+							walker
+								.walk(peeked_bits >> i & 1 != 0)
+								.expect("The Huffman tree was assumed to not be underspecified")
+								.map(|entry| (entry.number, i + 1))
+						})
+						.unwrap()
+				});
+
+				assert_eq!(
+					lookup_table.lookup(peeked_bits),
+					(tree_codeword_length <= lookup_bits).then(|| CodewordLookupTableEntry {
+						entry_number: tree_entry_number,
+						codeword_length: NonZeroU8::new(tree_codeword_length).unwrap()
+					}),
+					"Unexpected lookup result for bits {peeked_bits:0lookup_bits$b}",
+					lookup_bits = lookup_bits as usize
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn single_entry_lookup_table_works() {
+		let tree = VorbisHuffmanTree::try_from_codeword_lengths([1])
+			.expect("The Huffman tree was assumed to not be overspecified");
+		let lookup_table = CodewordLookupTable::new(&tree, 1);
+
+		// Like for the tree, reading any bit should return the entry zero
+		for peeked_bits in [0, 1] {
+			assert_eq!(
+				lookup_table.lookup(peeked_bits),
+				Some(CodewordLookupTableEntry {
+					entry_number: 0,
+					codeword_length: NonZeroU8::new(1).unwrap()
+				})
+			);
+		}
+	}
+}
@@ -3,8 +3,15 @@
 //! The functions are based on the Algorithm 2 described in [A. Moffat, ‘Huffman Coding’,
 //! ACM Comput. Surv., volume 52, issue 4, August 2019].
 //!
+//! Length-limited codeword lengths are computed with the package-merge algorithm described
+//! in [L. L. Larmore and D. S. Hirschberg, ‘A fast algorithm for optimal length-limited
+//! Huffman codes’, J. ACM, volume 37, issue 3, July 1990].
+//!
 //! [A. Moffat, ‘Huffman Coding’, ACM Comput. Surv., volume 52, issue 4, August 2019]:
 //! https://dl.acm.org/doi/10.1145/3342555
+//! [L. L. Larmore and D. S. Hirschberg, ‘A fast algorithm for optimal length-limited
+//! Huffman codes’, J. ACM, volume 37, issue 3, July 1990]:
+//! https://dl.acm.org/doi/10.1145/79147.79150
 
 use std::ops::{Index, IndexMut};
 
@@ -82,6 +89,63 @@ impl<T: AsMut<[u64]> + AsRef<[u64]>> VorbisCodebookNumberFrequenciesDecorator<T>
 			}
 		}
 	}
+
+	/// Like [`into_huffman_codeword_lengths`](Self::into_huffman_codeword_lengths), but
+	/// stores the optimal codeword lengths that do not exceed the specified maximum
+	/// length. If there are too many used entries for their codewords to fit in that
+	/// length, the smallest length that fits them is used as the maximum instead.
+	///
+	/// The number of additional bits needed to encode the decorated symbol frequencies
+	/// with these codeword lengths, compared to unlimited ones, is returned too. This
+	/// operation executes in O(n) time when the unlimited codeword lengths do not exceed
+	/// the maximum length, and in O(n L) time and space otherwise, where L is the maximum
+	/// length.
+	pub(super) fn into_length_limited_huffman_codeword_lengths(
+		self,
+		max_codeword_length: u8
+	) -> (T, u64) {
+		let used_codeword_count = self.number_index_map.len();
+
+		// Two used entries or less always get codeword lengths of 1, which are within any limit
+		if used_codeword_count <= 2 {
+			return (self.into_huffman_codeword_lengths(), 0);
+		}
+
+		let max_codeword_length =
+			max_codeword_length.max(used_codeword_count.next_power_of_two().ilog2() as u8);
+
+		// The codeword length computation overwrites the frequencies, so copy them in case
+		// we need to limit the codeword lengths
+		let frequencies = (0..used_codeword_count)
+			.map(|i| self[i])
+			.collect::<Vec<_>>();
+
+		// Frequencies are sorted in decreasing order, so the last codeword is the longest
+		let mut codeword_lengths = compute_huffman_codeword_lengths(self, used_codeword_count);
+		if codeword_lengths[used_codeword_count - 1] <= max_codeword_length as u64 {
+			return (codeword_lengths.number_frequencies, 0);
+		}
+
+		let limited_codeword_lengths =
+			compute_length_limited_huffman_codeword_lengths(&frequencies, max_codeword_length);
+
+		// Some codewords may get shorter, but the total encoded length never does
+		let mut additional_bits = 0;
+		for (i, (frequency, limited_codeword_length)) in frequencies
+			.into_iter()
+			.zip(limited_codeword_lengths)
+			.enumerate()
+		{
+			additional_bits +=
+				frequency as i128 * (limited_codeword_length as i128 - codeword_lengths[i] as i128);
+			codeword_lengths[i] = limited_codeword_length;
+		}
+
+		(
+			codeword_lengths.number_frequencies,
+			additional_bits.try_into().unwrap_or(u64::MAX)
+		)
+	}
 }
 
 impl<T: AsMut<[u64]> + AsRef<[u64]>> Index<usize> for VorbisCodebookNumberFrequenciesDecorator<T> {
@@ -174,6 +238,78 @@ fn compute_huffman_codeword_lengths<T: IndexMut<usize, Output = u64>>(
 	w
 }
 
+/// Implementation of the package-merge algorithm to compute optimal codeword lengths that
+/// do not exceed the specified maximum length. The frequencies must be sorted in decreasing
+/// order, and there must be between 2 and 2^`max_codeword_length` of them. The codeword
+/// length of each frequency is returned in the same order.
+fn compute_length_limited_huffman_codeword_lengths(
+	frequencies: &[u64],
+	max_codeword_length: u8
+) -> Vec<u64> {
+	let frequency_count = frequencies.len();
+
+	// The algorithm works on increasing weights. Package weights may not fit in 64 bits
+	let leaf_weight = |leaf: usize| frequencies[frequency_count - 1 - leaf] as u128;
+
+	// Build the item lists of the coin collector's problem for each depth, from the
+	// deepest to the shallowest. Each list merges the leaves with packages of two
+	// consecutive items of the previous list, in increasing weight order, and we only
+	// need to remember which of its items are leaves
+	let mut weights = (0..frequency_count).map(leaf_weight).collect::<Vec<_>>();
+	let mut list_leaf_flags = vec![vec![true; frequency_count]];
+	for _ in 1..max_codeword_length {
+		let mut package_weights = weights
+			.chunks_exact(2)
+			.map(|package| package[0] + package[1])
+			.peekable();
+
+		let mut merged_weights = Vec::with_capacity(frequency_count + weights.len() / 2);
+		let mut leaf_flags = Vec::with_capacity(merged_weights.capacity());
+		let mut leaf = 0;
+		while leaf < frequency_count || package_weights.peek().is_some() {
+			match package_weights.peek() {
+				Some(&package_weight)
+					if leaf == frequency_count || package_weight < leaf_weight(leaf) =>
+				{
+					merged_weights.push(package_weight);
+					leaf_flags.push(false);
+					package_weights.next();
+				}
+				_ => {
+					merged_weights.push(leaf_weight(leaf));
+					leaf_flags.push(true);
+					leaf += 1;
+				}
+			}
+		}
+
+		weights = merged_weights;
+		list_leaf_flags.push(leaf_flags);
+	}
+
+	// Select the 2n - 2 first items of the shallowest list. Every selected leaf adds one
+	// to the codeword length of its symbol, and every selected package selects the two
+	// items it was made of in the next deeper list. The selected leaves of each list are
+	// always the least frequent ones
+	let mut codeword_lengths = vec![0; frequency_count];
+	let mut selected_items = 2 * frequency_count - 2;
+	for leaf_flags in list_leaf_flags.iter().rev() {
+		let selected_leaves = leaf_flags[..selected_items]
+			.iter()
+			.filter(|&&is_leaf| is_leaf)
+			.count();
+
+		for codeword_length in &mut codeword_lengths[..selected_leaves] {
+			*codeword_length += 1;
+		}
+
+		selected_items = 2 * (selected_items - selected_leaves);
+	}
+
+	codeword_lengths.reverse();
+	codeword_lengths
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -220,6 +356,46 @@ mod test {
 		);
 	}
 
+	#[test]
+	fn compute_length_limited_huffman_codeword_lengths_works() {
+		const TWEAKED_PAPER_EXAMPLE_FREQUENCIES_ARRAY: [u64; 11] =
+			[1, 20, 2, 1, 6, 0, 2, 2, 3, 1, 17];
+		const LIMITED_CODELENGTHS_RESULT: [u64; 11] = [4, 2, 4, 5, 3, 0, 4, 4, 4, 5, 2];
+		const LIMITED_CODELENGTHS_RESULT_U8: [u8; 11] = [4, 2, 4, 5, 3, 0, 4, 4, 4, 5, 2];
+
+		// The unlimited codeword lengths encode these frequencies in 140 bits, and the
+		// optimal codeword lengths limited to 5 bits do so in 142 bits
+		assert_eq!(
+			VorbisCodebookNumberFrequenciesDecorator::new(TWEAKED_PAPER_EXAMPLE_FREQUENCIES_ARRAY)
+				.into_length_limited_huffman_codeword_lengths(5),
+			(LIMITED_CODELENGTHS_RESULT, 2)
+		);
+
+		VorbisCodebook::new(0, LIMITED_CODELENGTHS_RESULT_U8).expect(
+			"It should be possible to construct a Huffman tree with the computed codeword lengths"
+		);
+	}
+
+	#[test]
+	fn compute_length_limited_huffman_codeword_lengths_works_within_limit() {
+		const PAPER_EXAMPLE_FREQUENCIES_ARRAY: [u64; 10] = [20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
+		const PAPER_EXAMPLE_CODELENGTHS_RESULT: [u64; 10] = [1, 2, 4, 5, 5, 5, 5, 5, 6, 6];
+
+		assert_eq!(
+			VorbisCodebookNumberFrequenciesDecorator::new(PAPER_EXAMPLE_FREQUENCIES_ARRAY)
+				.into_length_limited_huffman_codeword_lengths(6),
+			(PAPER_EXAMPLE_CODELENGTHS_RESULT, 0)
+		);
+
+		// Too small limits are raised to the smallest length that fits every codeword, which
+		// yields a balanced tree here. Its cost is 8 * 1 + 4 * 0 - 2 * 1 - 1 * 1 = 5 bits
+		assert_eq!(
+			VorbisCodebookNumberFrequenciesDecorator::new([8, 4, 2, 1])
+				.into_length_limited_huffman_codeword_lengths(1),
+			([2, 2, 2, 2], 5)
+		);
+	}
+
 	#[test]
 	fn compute_huffman_codeword_lengths_works_for_no_used_entries() {
 		assert_eq!(
@@ -152,6 +152,25 @@ impl VorbisHuffmanTree {
 		Ok(codewords)
 	}
 
+	/// Calls the provided callback for every entry of this tree whose codeword is at
+	/// most `max_codeword_length` bits long, passing its number, codeword and codeword
+	/// length. Like elsewhere, codewords are in the order they are read from and
+	/// written to Vorbis streams (LSB -> MSB).
+	pub(super) fn for_each_short_codeword(
+		&self,
+		max_codeword_length: u8,
+		mut f: impl FnMut(u32, u32, u8)
+	) {
+		self.with_root(|root| {
+			root.for_each_entry_up_to_depth(
+				max_codeword_length,
+				0,
+				0,
+				&mut |entry, codeword, depth| f(entry.number, codeword, depth)
+			)
+		})
+	}
+
 	/// Executes the provided callback, passing a [walker][VorbisHuffmanTreeWalker]
 	/// that can be used to inspect the tree.
 	pub(super) fn with_walker<R>(
@@ -271,6 +290,37 @@ impl<'tree, V> VorbisHuffmanTreeNode<'tree, V> {
 					)
 			})
 	}
+
+	/// Calls the provided callback for every entry in the subtree rooted at this node
+	/// that is at most at `max_depth` depth. This node must be at the specified depth,
+	/// and be reached by the specified codeword, in LSB -> MSB order.
+	fn for_each_entry_up_to_depth(
+		&self,
+		max_depth: u8,
+		depth: u8,
+		codeword_so_far: u32,
+		f: &mut impl FnMut(&V, u32, u8)
+	) {
+		if let Some(entry) = &self.entry {
+			f(entry, codeword_so_far, depth);
+			return;
+		}
+
+		if depth == max_depth {
+			return;
+		}
+
+		for (child, bit) in [(&self.left_child, 0), (&self.right_child, 1)] {
+			if let Some(child) = child {
+				child.for_each_entry_up_to_depth(
+					max_depth,
+					depth + 1,
+					codeword_so_far | (bit << depth),
+					f
+				);
+			}
+		}
+	}
 }
 
 impl<V> Default for VorbisHuffmanTreeNode<'_, V> {
@@ -2,17 +2,19 @@
 
 use std::{
 	cell::Cell,
-	io::{self, ErrorKind, Read}
+	io::{self, ErrorKind}
 };
 
+use codeword_lookup_table::CodewordLookupTable;
 use huffman_codeword_lengths::VorbisCodebookNumberFrequenciesDecorator;
 use huffman_tree::{
 	TryFromCodewordLengthsListError, VorbisHuffmanTree, VorbisHuffmanTreeWalkerError
 };
 use log::trace;
 use thiserror::Error;
-use vorbis_bitpack::BitpackReader;
+use vorbis_bitpack::BitpackSliceReader;
 
+mod codeword_lookup_table;
 mod huffman_codeword_lengths;
 mod huffman_tree;
 
@@ -59,40 +61,53 @@ pub enum VorbisCodebookError {
 ///   [`decode_entry_number`](VorbisCodebook::decode_entry_number) returns every entry
 ///   are recorded. In other words, this mode records the entry number frequencies.
 /// - _Optimizing mode_, after
-///   [`optimal_codeword_lengths`](VorbisCodebook::optimal_codeword_lengths) is called.
-///   In this mode the decoding frequencies are no longer updated on read. It is not
-///   possible to go back to the previous mode once this one is reached.
+///   [`optimal_codeword_lengths`](VorbisCodebook::optimal_codeword_lengths) or
+///   [`limit_optimal_codeword_lengths`](VorbisCodebook::limit_optimal_codeword_lengths)
+///   is called. In this mode the decoding frequencies are no longer updated on read.
+///   It is not possible to go back to the previous mode once this one is reached.
 ///
 /// When created, a codebook is in _decode frequency recording mode_.
 pub(super) struct VorbisCodebook {
 	pub(super) codebook_number: u16,
 	huffman_tree: VorbisHuffmanTree,
+	codeword_lookup_table: CodewordLookupTable,
 	pub(super) entry_decode_frequencies_or_lengths: Cell<Vec<u64>>,
-	recording_decode_frequencies: bool
+	recording_decode_frequencies: bool,
+	/// Whether an entry decode was attempted in _decode frequency recording mode_,
+	/// even if it failed.
+	decode_attempted: Cell<bool>
 }
 
 impl VorbisCodebook {
 	/// Creates a new codebook from the specified list of codeword lengths.
 	///
-	/// The codebook number is used on error messages only, and it is not
-	/// relevant for the operation of the struct.
+	/// The codebook number is used on error messages and passed to callbacks only,
+	/// and it is not relevant for the operation of the struct.
 	pub(super) fn new<T: AsRef<[u8]>>(
 		codebook_number: u16,
 		codeword_lengths: T
 	) -> Result<Self, VorbisCodebookError> {
-		Ok(Self {
-			codebook_number,
-			entry_decode_frequencies_or_lengths: Cell::new(vec![
-				0;
-				codeword_lengths.as_ref().len()
-			]),
-			huffman_tree: VorbisHuffmanTree::try_from_codeword_lengths(codeword_lengths).map_err(
-				|error| VorbisCodebookError::InvalidCodebookCodewords {
+		let codeword_lengths = codeword_lengths.as_ref();
+
+		let huffman_tree =
+			VorbisHuffmanTree::try_from_codeword_lengths(codeword_lengths).map_err(|error| {
+				VorbisCodebookError::InvalidCodebookCodewords {
 					codebook_number,
 					error
 				}
-			)?,
-			recording_decode_frequencies: true
+			})?;
+		let codeword_lookup_table = CodewordLookupTable::new(
+			&huffman_tree,
+			codeword_lengths.iter().copied().max().unwrap_or(0)
+		);
+
+		Ok(Self {
+			codebook_number,
+			entry_decode_frequencies_or_lengths: Cell::new(vec![0; codeword_lengths.len()]),
+			huffman_tree,
+			codeword_lookup_table,
+			recording_decode_frequencies: true,
+			decode_attempted: Cell::new(false)
 		})
 	}
 
@@ -102,13 +117,33 @@ impl VorbisCodebook {
 	/// This method will also update an internal list of decoding frequencies
 	/// in the _decode frequency recording mode_.
 	// Decoding entry numbers is a very hot function, with well over a half of
-	// the execution time being spent here, as indicated by the perf profiler.
-	// This is caused due to audio packet residue decode. Any performance
-	// improvement here will be great
-	pub(super) fn decode_entry_number<R: Read>(
+	// the execution time being spent here before codeword lookup tables were
+	// introduced, as indicated by the perf profiler. This is caused due to audio
+	// packet residue decode. Any performance improvement here will be great
+	pub(super) fn decode_entry_number(
 		&self,
-		bitpack_reader: &mut BitpackReader<R>
+		bitpack_reader: &mut BitpackSliceReader<'_>
 	) -> Result<u32, VorbisCodebookError> {
+		if self.recording_decode_frequencies {
+			self.decode_attempted.set(true);
+		}
+
+		// Try decoding short codewords, which are the most frequent ones in optimal
+		// codebooks, with a single lookup first. Peeking fails when fewer bits than
+		// the lookup width are left in the packet, which may happen for the last
+		// codewords of a packet. In that case, and for longer or unassigned codewords,
+		// fall back to walking the tree, which handles any error appropriately
+		if let Some(lookup_table_entry) = bitpack_reader
+			.peek_bits(self.codeword_lookup_table.lookup_bits())
+			.ok()
+			.and_then(|peeked_bits| self.codeword_lookup_table.lookup(peeked_bits))
+		{
+			// This never fails, as the skipped bits were just peeked
+			bitpack_reader.skip_bits(lookup_table_entry.codeword_length.get() as u64)?;
+
+			return Ok(self.record_decoded_entry(lookup_table_entry.entry_number));
+		}
+
 		self.huffman_tree.with_walker(|mut walker| {
 			// Read a single bit from the bitstream until the word read so far
 			// can be decoded to an entry number. This loop is guaranteed to
@@ -136,26 +171,57 @@ impl VorbisCodebook {
 						codebook_number: self.codebook_number,
 						error
 					})? {
-					if self.recording_decode_frequencies {
-						let mut entry_decode_frequencies =
-							self.entry_decode_frequencies_or_lengths.take();
+					return Ok(self.record_decoded_entry(entry.number));
+				}
+			}
+		})
+	}
+
+	/// Records a decode of the specified entry number in the _decode frequency
+	/// recording mode_, returning that number.
+	fn record_decoded_entry(&self, entry_number: u32) -> u32 {
+		if self.recording_decode_frequencies {
+			let mut entry_decode_frequencies = self.entry_decode_frequencies_or_lengths.take();
 
-						entry_decode_frequencies[entry.number as usize] =
-							entry_decode_frequencies[entry.number as usize].saturating_add(1);
+			entry_decode_frequencies[entry_number as usize] =
+				entry_decode_frequencies[entry_number as usize].saturating_add(1);
 
-						self.entry_decode_frequencies_or_lengths
-							.set(entry_decode_frequencies);
-					}
+			self.entry_decode_frequencies_or_lengths
+				.set(entry_decode_frequencies);
+		}
 
-					trace!(
-						"Reading entry {} using codebook {}",
-						entry.number, self.codebook_number
-					);
+		trace!(
+			"Reading entry {} using codebook {}",
+			entry_number, self.codebook_number
+		);
 
-					return Ok(entry.number);
-				}
-			}
-		})
+		entry_number
+	}
+
+	/// Returns whether [`decode_entry_number`](Self::decode_entry_number) was called
+	/// in _decode frequency recording mode_, even if no entry could be decoded due to
+	/// an error such as reaching the end of the packet.
+	pub(super) fn decode_attempted(&self) -> bool {
+		self.decode_attempted.get()
+	}
+
+	/// Adds the entry decode frequencies recorded by the specified codebook to the ones
+	/// recorded by this codebook, so that optimal codewords are computed for the decodes
+	/// of both. The codebooks must have the same number of entries, and both must be in
+	/// _decode frequency recording mode_.
+	pub(super) fn merge_decode_frequencies(&mut self, other: &mut Self) {
+		debug_assert!(self.recording_decode_frequencies && other.recording_decode_frequencies);
+
+		for (frequency, &other_frequency) in self
+			.entry_decode_frequencies_or_lengths
+			.get_mut()
+			.iter_mut()
+			.zip(other.entry_decode_frequencies_or_lengths.get_mut().iter())
+		{
+			*frequency = frequency.saturating_add(other_frequency);
+		}
+
+		*self.decode_attempted.get_mut() |= other.decode_attempted();
 	}
 
 	/// Computes the optimal codeword length for every entry, transitioning this
@@ -177,6 +243,29 @@ impl VorbisCodebook {
 		}
 	}
 
+	/// Computes the optimal codeword length for every entry that does not exceed the
+	/// specified maximum length, transitioning this codebook into _optimizing mode_. The
+	/// codeword lengths can then be retrieved with
+	/// [`optimal_codeword_lengths`](Self::optimal_codeword_lengths). The maximum length
+	/// is raised as needed to fit the codewords of every used entry.
+	///
+	/// The returned value is the number of additional bits the recorded entry decodes
+	/// take to encode with the limited codeword lengths, compared to unlimited ones. If
+	/// this codebook already is in _optimizing mode_, nothing is done and zero is returned.
+	pub(super) fn limit_optimal_codeword_lengths(&mut self, max_codeword_length: u8) -> u64 {
+		if !self.recording_decode_frequencies {
+			return 0;
+		}
+
+		self.recording_decode_frequencies = false;
+		let (_, additional_bits) = VorbisCodebookNumberFrequenciesDecorator::new(
+			self.entry_decode_frequencies_or_lengths.get_mut()
+		)
+		.into_length_limited_huffman_codeword_lengths(max_codeword_length);
+
+		additional_bits
+	}
+
 	/// Computes the optimal codewords for every entry, implicitly transitioning
 	/// this codebook into _optimizing mode_ if necessary. The element in position
 	/// `n` of the returned `Vec` is a `(codeword, codeword_length)` pair for the
@@ -6,6 +6,7 @@
 
 use std::{
 	borrow::Cow,
+	fmt::{self, Display, Formatter},
 	io::{self, ErrorKind},
 	mem,
 	num::{NonZeroU8, NonZeroU32, TryFromIntError}
@@ -14,11 +15,16 @@ use std::{
 use audio_packet_analyze::AudioPacketAnalyze;
 use audio_packet_rewrite::AudioPacketRewrite;
 use comment_header_copy::CommentHeaderCopy;
+pub use comment_header_copy::picture::VorbisPicture;
 use comment_header_parse::{CommentHeaderParse, VorbisCommentData};
 use identification_header_copy::IdentificationHeaderCopy;
 use log::{info, trace};
 use setup_header_parse::{SetupHeaderParse, VorbisSetupData};
 use setup_header_rewrite::SetupHeaderRewrite;
+pub use stream_info::{
+	VorbisCodebookInfo, VorbisFloorInfo, VorbisMappingInfo, VorbisModeInfo, VorbisResidueInfo,
+	VorbisStreamInfo
+};
 use thiserror::Error;
 
 use super::{
@@ -89,6 +95,7 @@ mod comment_header_parse;
 mod identification_header_copy;
 mod setup_header_parse;
 mod setup_header_rewrite;
+mod stream_info;
 
 /// Holds settings that customize how Vorbis streams are optimized, irrespectively of
 /// their container encapsulation.
@@ -97,8 +104,85 @@ mod setup_header_rewrite;
 pub struct VorbisOptimizerSettings {
 	/// Describes how the vendor string in the Vorbis comment header will be optimized.
 	pub vendor_string_action: VorbisVendorStringAction,
-	/// Describes how the vendor string in the Vorbis comment header will be optimized.
-	pub comment_fields_action: VorbisCommentFieldsAction
+	/// Describes how the user comments in the Vorbis comment header will be optimized.
+	pub comment_fields_action: VorbisCommentFieldsAction,
+	/// The edits to apply, in order, to the user comments kept according to
+	/// [`comment_fields_action`](Self::comment_fields_action).
+	pub comment_operations: Vec<VorbisCommentOperation>,
+	/// Describes how the pictures embedded in the Vorbis comment header, such as cover
+	/// art, will be optimized. Pictures are handled before applying the
+	/// [`comment_operations`](Self::comment_operations).
+	pub picture_action: VorbisPictureAction,
+	/// Whether the data of embedded pictures will be made available in the
+	/// [`pictures`](VorbisOptimizerStatistics::pictures) optimizer statistics, so that
+	/// it can be extracted. This is disabled by default to save memory.
+	pub retain_picture_data: bool,
+	/// The maximum length, in bits, of the optimized codewords of every codebook. Some
+	/// embedded and hardware decoders decode codewords with lookup tables that do not
+	/// support longer codewords. Limiting codeword lengths makes audio packets slightly
+	/// bigger, as reported by the
+	/// [`codeword_length_limit_cost_bytes`](VorbisOptimizerStatistics::codeword_length_limit_cost_bytes)
+	/// optimizer statistic. Codebooks with too many used entries to fit in this length
+	/// get the shortest maximum length possible instead.
+	///
+	/// By default, codeword lengths are not limited beyond the 32 bits limit of the
+	/// Vorbis I specification.
+	pub max_codeword_length: Option<NonZeroU8>,
+	/// Bounds the resources that processing a Vorbis stream may take, which is useful
+	/// when dealing with untrusted streams. No limits are set by default.
+	pub resource_limits: VorbisResourceLimits
+}
+
+/// Holds limits for the resources that processing a single Vorbis stream may take.
+/// Valid but specially-crafted streams may take a significantly long time or lots of
+/// memory to process, so applications that deal with untrusted streams are encouraged
+/// to set these limits to values that bound their resource consumption sensibly.
+///
+/// A [`ResourceLimitExceeded`](VorbisOptimizerError::ResourceLimitExceeded) error is
+/// returned when any limit is exceeded. Limits that are `None` are not enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VorbisResourceLimits {
+	/// The maximum size of the setup header packet, in bytes. Streams encoded by
+	/// common encoders have setup headers of a few KiB.
+	pub max_setup_header_size: Option<usize>,
+	/// The maximum number of entries the setup header codebooks may have in total.
+	/// This bounds the memory used to store codebooks, and the time spent building
+	/// their prefix code trees.
+	pub max_total_codebook_entries: Option<u64>,
+	/// The maximum depth of the prefix code tree of every codebook, which is the length
+	/// of its longest codeword, in bits. Dense and deep trees take a long time to build.
+	/// The Vorbis I specification allows depths of up to 32 bits.
+	pub max_codebook_tree_depth: Option<u8>,
+	/// The maximum number of packets, including header packets, that the stream may
+	/// have. This bounds the time spent analyzing and optimizing the stream.
+	pub max_packets: Option<u64>
+}
+
+/// Identifies a resource limit of the [`VorbisResourceLimits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VorbisResourceLimit {
+	/// The [`max_setup_header_size`](VorbisResourceLimits::max_setup_header_size) limit.
+	SetupHeaderSize,
+	/// The [`max_total_codebook_entries`](VorbisResourceLimits::max_total_codebook_entries)
+	/// limit.
+	TotalCodebookEntries,
+	/// The [`max_codebook_tree_depth`](VorbisResourceLimits::max_codebook_tree_depth) limit.
+	CodebookTreeDepth,
+	/// The [`max_packets`](VorbisResourceLimits::max_packets) limit.
+	Packets
+}
+
+impl Display for VorbisResourceLimit {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::SetupHeaderSize => "maximum setup header size",
+			Self::TotalCodebookEntries => "maximum total codebook entries",
+			Self::CodebookTreeDepth => "maximum codebook tree depth",
+			Self::Packets => "maximum packets"
+		})
+	}
 }
 
 /// Represents an error that may occur while optimizing a Vorbis stream. This error can
@@ -255,7 +339,51 @@ pub enum VorbisOptimizerError {
 	CodebookError(#[from] VorbisCodebookError),
 	/// An I/O error occurred while handling a Vorbis packet.
 	#[error("I/O error: {0}")]
-	Io(#[from] io::Error)
+	Io(#[from] io::Error),
+	/// Processing the Vorbis stream would exceed a limit set in the
+	/// [`resource_limits`](VorbisOptimizerSettings::resource_limits) optimizer setting.
+	#[error("The {0} resource limit was exceeded")]
+	ResourceLimitExceeded(VorbisResourceLimit)
+}
+
+impl VorbisOptimizerError {
+	/// Returns a short, stable, machine-readable identifier for the kind of this error,
+	/// which is more suitable than its message for programmatic handling or reporting.
+	pub const fn code(&self) -> &'static str {
+		match self {
+			Self::InvalidPacketType(_) => "invalid_packet_type",
+			Self::UnexpectedPacketType { .. } => "unexpected_packet_type",
+			Self::TooSmallPacket(_) => "too_small_packet",
+			Self::InvalidPattern => "invalid_pattern",
+			Self::UnexpectedHeaderPacketLength { .. } => "unexpected_header_packet_length",
+			Self::IncompatibleVorbisVersion(_) => "incompatible_vorbis_version",
+			Self::InvalidChannelCount(_) => "invalid_channel_count",
+			Self::InvalidSamplingFrequency(_) => "invalid_sampling_frequency",
+			Self::InvalidBlocksizes(..) => "invalid_blocksizes",
+			Self::TooBigInteger(_) => "too_big_integer",
+			Self::InvalidSetupValue => "invalid_setup_value",
+			Self::TooBigCodewordLength => "too_big_codeword_length",
+			Self::ReservedLookupType(_) => "reserved_lookup_type",
+			Self::InvalidCodebookDimension { .. } => "invalid_codebook_dimension",
+			Self::UnsupportedFloorType(_) => "unsupported_floor_type",
+			Self::InvalidCodebookNumber(_) => "invalid_codebook_number",
+			Self::RepeatedFloor1Point(_) => "repeated_floor1_point",
+			Self::TooManyFloor1Points(_) => "too_many_floor1_points",
+			Self::ReservedResidueType(_) => "reserved_residue_type",
+			Self::ReservedMappingType(_) => "reserved_mapping_type",
+			Self::InvalidChannelMapping { .. } => "invalid_channel_mapping",
+			Self::InvalidChannelMultiplexing { .. } => "invalid_channel_multiplexing",
+			Self::InvalidFloorNumber(_) => "invalid_floor_number",
+			Self::InvalidResidueNumber(_) => "invalid_residue_number",
+			Self::InvalidMappingNumber(_) => "invalid_mapping_number",
+			Self::ScalarCodebookUsedInVectorContext(_) => "scalar_codebook_used_in_vector_context",
+			Self::InvalidVectorQuantizationClassbook(_) => "invalid_vector_quantization_classbook",
+			Self::InvalidModeNumber(_) => "invalid_mode_number",
+			Self::CodebookError(_) => "codebook_error",
+			Self::Io(_) => "io_error",
+			Self::ResourceLimitExceeded(_) => "resource_limit_exceeded"
+		}
+	}
 }
 
 /// Identifies which strategy to use to optimize the Vorbis vendor string
@@ -306,7 +434,81 @@ pub enum VorbisCommentFieldsAction {
 	///
 	/// This may delete comment strings which contain invalid UTF-8 characters
 	/// and are against the specification, improving interoperability.
-	Delete
+	Delete,
+	/// The comment pairs will be copied to the optimized stream after fixing
+	/// any violations of the Vorbis comment specification, improving
+	/// interoperability. Every change is logged.
+	///
+	/// Comments without a field name get a `COMMENT` one, invalid field name
+	/// characters are replaced with underscores, field names are converted to
+	/// uppercase, values that are not valid UTF-8 are transcoded from the
+	/// Windows-1252 code page, a superset of Latin-1, and exact duplicate
+	/// comments are removed.
+	Normalize
+}
+
+/// Identifies which strategy to use to optimize the pictures embedded in the Vorbis
+/// comment header as `METADATA_BLOCK_PICTURE` user comments, such as cover art. These
+/// pictures are commonly the biggest part of the comment header by far.
+///
+/// Embedded pictures that are not valid are only kept by the [`Copy`](Self::Copy)
+/// action.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+#[derive(Default)]
+pub enum VorbisPictureAction {
+	/// The embedded pictures will be copied as-is to the optimized stream.
+	#[default]
+	Copy,
+	/// Every embedded picture will be removed, to save space on the optimized stream.
+	Delete,
+	/// Only the first embedded picture whose type is front cover will be kept, which
+	/// is the picture that most players show.
+	KeepFrontCover
+}
+
+/// An edit to the user comments in the Vorbis comment header. Comments are
+/// `FIELD=value` strings, and their field names are compared in a case-insensitive
+/// way, as mandated by the Vorbis I specification.
+///
+/// Field name patterns may contain `*` wildcards, which match any sequence of
+/// characters, and `?` wildcards, which match any single character.
+///
+/// Field names must only contain ASCII characters in the 0x20 through 0x7D range,
+/// excluding `=`. Comments with invalid field names violate the specification and
+/// may not be read back correctly.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum VorbisCommentOperation {
+	/// Removes every comment with the specified field name, and then adds a comment
+	/// with that field name and value.
+	Set {
+		/// The field name of the comment to set.
+		field_name: String,
+		/// The value of the comment to set.
+		value: String
+	},
+	/// Adds a comment with the specified field name and value, keeping any comments
+	/// that already had that field name.
+	Append {
+		/// The field name of the comment to add.
+		field_name: String,
+		/// The value of the comment to add.
+		value: String
+	},
+	/// Changes the field name of every comment with the specified field name,
+	/// preserving their values.
+	Rename {
+		/// The field name of the comments to rename.
+		field_name: String,
+		/// The new field name of the comments.
+		new_field_name: String
+	},
+	/// Removes every comment whose field name matches the specified pattern.
+	Remove(String),
+	/// Removes every comment whose field name does not match any of the specified
+	/// patterns.
+	KeepOnly(Vec<String>)
 }
 
 /// Implementation detail that represents all the possible states a Vorbis
@@ -389,16 +591,20 @@ macro_rules! match_and_delegate {
 pub struct VorbisOptimizer<'settings> {
 	settings: &'settings VorbisOptimizerSettings,
 	pub(crate) identification_data: VorbisIdentificationHeaderData,
-	state: VorbisOptimizerState
+	statistics: VorbisOptimizerStatistics,
+	state: VorbisOptimizerState,
+	analyzed_packet_count: u64
 }
 
 /// Relevant data stored in the Vorbis identification header, which is the first
 /// packet of any Vorbis stream.
-pub(crate) struct VorbisIdentificationHeaderData {
-	channels: NonZeroU8,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VorbisIdentificationHeaderData {
+	/// The number of audio channels of the stream.
+	pub channels: NonZeroU8,
 	/// The sampling frequency of the encoded audio samples, used by players to
 	/// convert between sample counts and time.
-	pub(crate) sampling_frequency: NonZeroU32,
+	pub sampling_frequency: NonZeroU32,
 	/// The hard maximum bitrate of the Vorbis stream that the encoder reports
 	/// being told to heed.
 	///
@@ -438,20 +644,55 @@ pub(crate) struct VorbisIdentificationHeaderData {
 	/// have different bitrate management modes. The description above is a summary
 	/// of the relevant parts of the
 	/// [`libvorbisenc` documentation](https://xiph.org/vorbis/doc/vorbisenc/overview.html).
-	pub(crate) maximum_bitrate: i32,
+	pub maximum_bitrate: i32,
 	/// The average bitrate of the Vorbis stream that the encoder reports being told
 	/// to target.
 	///
 	/// For more information about the usual meaning of this and other bitrate fields,
 	/// see the documentation for [`maximum_bitrate`](Self::maximum_bitrate).
-	pub(crate) nominal_bitrate: i32,
+	pub nominal_bitrate: i32,
 	/// The minimum bitrate of the Vorbis stream that the encoder reports being told
 	/// to target.
 	///
 	/// For more information about the usual meaning of this and other bitrate fields,
 	/// see the documentation for [`maximum_bitrate`](Self::maximum_bitrate).
-	pub(crate) minimum_bitrate: i32,
-	blocksizes: (u16, u16)
+	pub minimum_bitrate: i32,
+	/// The short and long block sizes of the stream, in samples. Both are powers of two
+	/// between 64 and 8192, and the short block size is never greater than the long one.
+	pub blocksizes: (u16, u16)
+}
+
+/// Statistics about the optimizations done by a [`VorbisOptimizer`] while rewriting a
+/// Vorbis stream, which are updated as packets are optimized.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VorbisOptimizerStatistics {
+	/// The number of audio packets that were removed from the stream because decoders
+	/// would discard them, such as empty packets.
+	pub discarded_audio_packets: u64,
+	/// The number of audio packets whose trailing padding was removed.
+	pub padded_audio_packets: u64,
+	/// The total number of padding bytes removed from audio packets.
+	pub removed_padding_bytes: u64,
+	/// The number of entries of each codebook in the setup header, before and after
+	/// removing runs of unused entries at the end of the codebook, respectively. Removed
+	/// codebooks have zero entries after optimization. This is empty until the setup
+	/// header is optimized.
+	pub codebook_entry_counts: Vec<(u32, u32)>,
+	/// The number of codebooks removed from the setup header because no floor or residue
+	/// referenced them, because they were never decoded in the audio packets, or because
+	/// they were merged into an identical codebook.
+	pub removed_codebook_count: u64,
+	/// The estimated number of bytes the audio packets grow by because the codeword
+	/// lengths were limited to
+	/// [`max_codeword_length`](VorbisOptimizerSettings::max_codeword_length), compared
+	/// to unlimited optimal codeword lengths. This is zero until the setup header is
+	/// optimized, and when codeword lengths are not limited.
+	pub codeword_length_limit_cost_bytes: u64,
+	/// The valid pictures embedded in the comment header, in the order they appear,
+	/// including those that were removed. This is empty until the comment header is
+	/// optimized.
+	pub pictures: Vec<VorbisPicture>
 }
 
 impl<'settings> VorbisOptimizer<'settings> {
@@ -555,7 +796,9 @@ impl<'settings> VorbisOptimizer<'settings> {
 				minimum_bitrate,
 				blocksizes
 			},
-			state: CommentHeaderParse.into()
+			statistics: VorbisOptimizerStatistics::default(),
+			state: CommentHeaderParse.into(),
+			analyzed_packet_count: 1 // Just analyzed the identification header packet
 		})
 	}
 
@@ -580,9 +823,21 @@ impl<'settings> VorbisOptimizer<'settings> {
 	) -> Result<Option<u16>, VorbisOptimizerError> {
 		let packet = packet.as_ref();
 
+		self.analyzed_packet_count += 1;
+		if self
+			.settings
+			.resource_limits
+			.max_packets
+			.is_some_and(|max_packets| self.analyzed_packet_count > max_packets)
+		{
+			return Err(VorbisOptimizerError::ResourceLimitExceeded(
+				VorbisResourceLimit::Packets
+			));
+		}
+
 		match_and_delegate!(self {
 			CommentHeaderParse => analyze_packet(packet, self.settings),
-			SetupHeaderParse => analyze_packet(packet, &self.identification_data),
+			SetupHeaderParse => analyze_packet(packet, &self.identification_data, &self.settings.resource_limits),
 			AudioPacketAnalyze => analyze_packet(packet, &self.identification_data)
 		})
 	}
@@ -647,11 +902,23 @@ impl<'settings> VorbisOptimizer<'settings> {
 
 		match_and_delegate!(self {
 			IdentificationHeaderCopy => optimize_packet(packet, &self.identification_data),
-			CommentHeaderCopy => optimize_packet(packet),
-			SetupHeaderRewrite => optimize_packet(packet),
-			AudioPacketRewrite => optimize_packet(packet, &self.identification_data)
+			CommentHeaderCopy => optimize_packet(packet, self.settings, &mut self.statistics),
+			SetupHeaderRewrite => optimize_packet(packet, self.settings, &mut self.statistics),
+			AudioPacketRewrite => optimize_packet(packet, &self.identification_data, &mut self.statistics)
 		})
 	}
+
+	/// Returns the data of the identification header of the Vorbis stream this optimizer
+	/// was created for.
+	pub fn identification_header_data(&self) -> &VorbisIdentificationHeaderData {
+		&self.identification_data
+	}
+
+	/// Returns statistics about the optimizations done to the packets passed to
+	/// [`optimize_packet`](Self::optimize_packet) so far.
+	pub fn statistics(&self) -> &VorbisOptimizerStatistics {
+		&self.statistics
+	}
 }
 
 /// Checks that the common Vorbis header packet prelude is valid, according to section
@@ -1,7 +1,7 @@
 //! Contains the supporting code for the [`AudioPacketAnalyze`] Vorbis optimizer state.
 
 use log::trace;
-use vorbis_bitpack::BitpackReader;
+use vorbis_bitpack::BitpackSliceReader;
 
 use super::{
 	VorbisCommentData, VorbisIdentificationHeaderData, VorbisOptimizerError, VorbisSetupData,
@@ -19,13 +19,13 @@ pub(super) struct AudioPacketAnalyze {
 impl AudioPacketAnalyze {
 	pub(super) fn analyze_packet(
 		&mut self,
-		mut packet: &[u8],
+		packet: &[u8],
 		identification_data: &VorbisIdentificationHeaderData
 	) -> Result<(Option<u16>, Option<Self>), VorbisOptimizerError> {
 		trace!("Analyzing Vorbis audio packet");
 
 		let packet_length = packet.len();
-		let mut bitpacker = BitpackReader::new(&mut packet);
+		let mut bitpacker = BitpackSliceReader::new(packet);
 
 		// § 4.3.1, step 1: packet type decode. As mentioned in the end of that section of
 		// the spec, it is important to return early successfully if the read fails due to
@@ -46,8 +46,10 @@ impl AudioPacketAnalyze {
 			&self.codec_setup,
 			packet_length,
 			&mut bitpacker,
-			// We just let the codebook collect frequencies for now, so do nothing in the callbacks
+			// We just let the codebooks and modes collect usage data for now, so do nothing
+			// in the callbacks
 			|_, _, ()| Ok(()),
+			|_, _, _, ()| Ok(()),
 			|_, _, ()| Ok(()),
 			()
 		)?;
@@ -58,7 +60,8 @@ impl AudioPacketAnalyze {
 		// Comment this out when dealing with files that do not have a minimum bitrate set and do not
 		// have padded packets
 		/*debug_assert!(
-			identification_data.minimum_bitrate < 0 && bitpacker.into_inner().is_empty(),
+			identification_data.minimum_bitrate < 0
+				&& bitpacker.bit_position().div_ceil(8) == packet_length as u64,
 			"Trailing bytes at end of audio packet"
 		);*/
 
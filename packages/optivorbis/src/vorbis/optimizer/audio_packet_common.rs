@@ -1,11 +1,9 @@
 //! Contains the helper [`process_audio_packet`] function to parse audio packets and
 //! execute callbacks when some interesting piece of data is read.
 
-use std::io::Read;
-
 use log::trace;
 use tinyvec::TinyVec;
-use vorbis_bitpack::BitpackReader;
+use vorbis_bitpack::BitpackSliceReader;
 
 use super::{
 	VorbisIdentificationHeaderData, VorbisOptimizerError,
@@ -19,8 +17,11 @@ use crate::vorbis::{
 };
 
 /// Parses the specified audio packet, whose source is already wrapped in a
-/// [bitpacker](BitpackReader), and invokes the specified callbacks when a
-/// packed integer is read or a codebook entry is decoded, respectively.
+/// [bitpacker](BitpackSliceReader), and invokes the specified callbacks when the
+/// packet mode number is read, any other packed integer is read or a codebook
+/// entry is decoded, respectively. The callback for other packed integers also
+/// receives the bitpacker position right after the integer, so that callers can
+/// tell which bits of the packet it was read from.
 /// A successful `false` return value indicates that the packet was parsed
 /// without major errors, but that it should be discarded from the stream.
 /// A successful `true` return value means that the packet was parsed
@@ -29,16 +30,18 @@ use crate::vorbis::{
 /// The parsing is done according to the Vorbis I specification, § 4.3.1,
 /// step 2, up to § 4.3.4, as synthesizing the actual audio samples is not
 /// necessary.
+#[allow(clippy::too_many_arguments)]
 pub(super) fn process_audio_packet<
-	R: Read,
 	T,
-	F: FnMut(u32, u8, &mut T) -> Result<(), VorbisOptimizerError>,
+	M: FnOnce(u8, u8, &mut T) -> Result<(), VorbisOptimizerError>,
+	F: FnMut(u32, u8, u64, &mut T) -> Result<(), VorbisOptimizerError>,
 	G: FnMut(u16, u32, &mut T) -> Result<(), VorbisOptimizerError>
 >(
 	identification_data: &VorbisIdentificationHeaderData,
 	codec_setup: &VorbisSetupData,
 	packet_length: usize,
-	bitpacker: &mut BitpackReader<R>,
+	bitpacker: &mut BitpackSliceReader<'_>,
+	mode_read_callback: M,
 	mut bitpack_read_callback: F,
 	codebook_entry_decode_callback: G,
 	mut shared_callback_data: T
@@ -55,6 +58,7 @@ pub(super) fn process_audio_packet<
 			codec_setup,
 			packet_length,
 			bitpacker,
+			mode_read_callback,
 			&mut bitpack_read_callback,
 			&mut shared_callback_data
 		),
@@ -91,14 +95,15 @@ pub(super) fn process_audio_packet<
 /// these steps should discard the packet from the stream.
 fn process_audio_packet_first_part<
 	'setup,
-	R: Read,
 	T,
-	F: FnMut(u32, u8, &mut T) -> Result<(), VorbisOptimizerError>
+	M: FnOnce(u8, u8, &mut T) -> Result<(), VorbisOptimizerError>,
+	F: FnMut(u32, u8, u64, &mut T) -> Result<(), VorbisOptimizerError>
 >(
 	identification_data: &VorbisIdentificationHeaderData,
 	codec_setup: &'setup VorbisSetupData,
 	packet_length: usize,
-	bitpacker: &mut BitpackReader<R>,
+	bitpacker: &mut BitpackSliceReader<'_>,
+	mode_read_callback: M,
 	mut bitpack_read_callback: F,
 	shared_callback_data: &mut T
 ) -> Result<(&'setup Mode, u16), VorbisOptimizerError> {
@@ -118,7 +123,8 @@ fn process_audio_packet_first_part<
 		.ok_or(VorbisOptimizerError::InvalidModeNumber(mode))?;
 
 	trace!("Audio packet mode {mode}");
-	bitpack_read_callback(mode as u32, mode_bits, shared_callback_data)?;
+	mode_configuration.used.set(true);
+	mode_read_callback(mode, mode_bits, shared_callback_data)?;
 
 	let decode_blocksize = if mode_configuration.big_block {
 		identification_data.blocksizes.1
@@ -131,11 +137,13 @@ fn process_audio_packet_first_part<
 		bitpack_read_callback(
 			bitpack_packet_read!(bitpacker, read_flag, packet_length)? as u32,
 			1,
+			bitpacker.bit_position(),
 			shared_callback_data
 		)?;
 		bitpack_read_callback(
 			bitpack_packet_read!(bitpacker, read_flag, packet_length)? as u32,
 			1,
+			bitpacker.bit_position(),
 			shared_callback_data
 		)?;
 	}
@@ -148,15 +156,14 @@ fn process_audio_packet_first_part<
 /// performing these steps should return the audio data decoded so far.
 #[allow(clippy::too_many_arguments)]
 fn process_audio_packet_second_part<
-	R: Read,
 	T,
-	F: FnMut(u32, u8, &mut T) -> Result<(), VorbisOptimizerError>,
+	F: FnMut(u32, u8, u64, &mut T) -> Result<(), VorbisOptimizerError>,
 	G: FnMut(u16, u32, &mut T) -> Result<(), VorbisOptimizerError>
 >(
 	identification_data: &VorbisIdentificationHeaderData,
 	codec_setup: &VorbisSetupData,
 	packet_length: usize,
-	bitpacker: &mut BitpackReader<R>,
+	bitpacker: &mut BitpackSliceReader<'_>,
 	mode_configuration: &Mode,
 	decode_blocksize: u16,
 	mut bitpack_read_callback: F,
@@ -246,12 +253,11 @@ fn process_audio_packet_second_part<
 
 /// Implements the algorithm described in the Vorbis I specification, § 4.3.2, step 4.
 fn process_floor1<
-	R: Read,
 	T,
-	F: FnMut(u32, u8, &mut T) -> Result<(), VorbisOptimizerError>,
+	F: FnMut(u32, u8, u64, &mut T) -> Result<(), VorbisOptimizerError>,
 	G: FnMut(u16, u32, &mut T) -> Result<(), VorbisOptimizerError>
 >(
-	bitpacker: &mut BitpackReader<R>,
+	bitpacker: &mut BitpackSliceReader<'_>,
 	packet_length: usize,
 	floor_configuration: &Floor1Configuration,
 	codebook_configurations: &[CodebookConfiguration],
@@ -263,7 +269,12 @@ fn process_floor1<
 	// so there's no need to check type
 	let has_audio_energy = bitpack_packet_read!(bitpacker, read_flag, packet_length)?;
 	trace!("Audio energy this frame: {has_audio_energy}");
-	bitpack_read_callback(has_audio_energy as u32, 1, shared_callback_data)?;
+	bitpack_read_callback(
+		has_audio_energy as u32,
+		1,
+		bitpacker.bit_position(),
+		shared_callback_data
+	)?;
 
 	if has_audio_energy {
 		/// Array of range bits to read, straight from section 7.2.3 of the Vorbis I
@@ -282,7 +293,12 @@ fn process_floor1<
 				mut range_bits,
 				u8
 			)?;
-			bitpack_read_callback(value as u32, range_bits, shared_callback_data)?;
+			bitpack_read_callback(
+				value as u32,
+				range_bits,
+				bitpacker.bit_position(),
+				shared_callback_data
+			)?;
 		}
 
 		for class in &floor_configuration.partition_class_list {
@@ -331,8 +347,8 @@ fn process_floor1<
 ///
 /// # Preconditions
 /// At least one residue vector is to be decoded.
-fn process_residue<R: Read, T, F: FnMut(u16, u32, &mut T) -> Result<(), VorbisOptimizerError>>(
-	bitpacker: &mut BitpackReader<R>,
+fn process_residue<T, F: FnMut(u16, u32, &mut T) -> Result<(), VorbisOptimizerError>>(
+	bitpacker: &mut BitpackSliceReader<'_>,
 	residue_configuration: &ResidueConfiguration,
 	codebook_configurations: &[CodebookConfiguration],
 	original_residue_vectors_masks: &[bool],
@@ -489,11 +505,10 @@ fn process_residue<R: Read, T, F: FnMut(u16, u32, &mut T) -> Result<(), VorbisOp
 /// how the partition vectors are copied to the final residue vector, we can reuse
 /// the same code for both.
 fn process_residue_partition_vector<
-	R: Read,
 	T,
 	F: FnMut(u16, u32, &mut T) -> Result<(), VorbisOptimizerError>
 >(
-	bitpacker: &mut BitpackReader<R>,
+	bitpacker: &mut BitpackSliceReader<'_>,
 	residue_configuration: &ResidueConfiguration,
 	vq_book_configuration: &CodebookConfiguration,
 	mut codebook_entry_decode_callback: F,
@@ -520,12 +535,11 @@ fn process_residue_partition_vector<
 /// Helper function to decode an entry number from a codebook, invoking the
 /// specified callback on success.
 fn decode_codebook_entry_number<
-	R: Read,
 	T,
 	F: FnMut(u16, u32, &mut T) -> Result<(), VorbisOptimizerError>
 >(
 	codebook: &VorbisCodebook,
-	bitpacker: &mut BitpackReader<R>,
+	bitpacker: &mut BitpackSliceReader<'_>,
 	mut codebook_entry_decode_callback: F,
 	shared_callback_data: &mut T
 ) -> Result<u32, VorbisOptimizerError> {
@@ -1,28 +1,35 @@
 //! Contains the supporting code for the [`AudioPacketRewrite`] Vorbis optimizer state.
 
-use std::borrow::Cow;
+use std::{borrow::Cow, io, io::Write, mem, ops::Range};
 
 use log::trace;
 use vorbis_bitpack::{
-	BitpackReader, BitpackWriter, BitpackedIntegerWidth, bitpacked_integer_width
+	BitpackSliceReader, BitpackWriter, BitpackedIntegerWidth, bitpacked_integer_width
 };
 
 use super::{
-	VorbisIdentificationHeaderData, VorbisOptimizerError,
+	VorbisIdentificationHeaderData, VorbisOptimizerError, VorbisOptimizerStatistics,
 	audio_packet_common::process_audio_packet, setup_header_parse::VorbisSetupData
 };
+use crate::vorbis::ilog;
 
 /// Rewrites Huffman codewords contained in audio packets with their optimal ones.
 /// This is the terminal state of the optimization phase.
 pub(super) struct AudioPacketRewrite {
 	pub(super) codec_setup: VorbisSetupData,
-	codebook_optimal_codewords: Vec<Vec<Option<(u32, u8)>>>
+	codebook_optimal_codewords: Vec<Vec<Option<(u32, u8)>>>,
+	new_mode_numbers: Vec<Option<u8>>,
+	new_mode_number_width: BitpackedIntegerWidth
 }
 
 impl AudioPacketRewrite {
 	/// Creates a new instance of this optimizer state. This is relatively expensive,
-	/// as it will ask each codebook to generate its optimized codewords.
-	pub(super) fn new(mut codec_setup: VorbisSetupData) -> Self {
+	/// as it will ask each codebook to generate its optimized codewords. The mode numbers
+	/// read from audio packets are replaced by the specified new mode numbers, which
+	/// must match the modes written to the new setup header.
+	pub(super) fn new(mut codec_setup: VorbisSetupData, new_mode_numbers: Vec<Option<u8>>) -> Self {
+		let new_mode_count = new_mode_numbers.iter().flatten().count();
+
 		Self {
 			// Compute the optimal codeword for each codebook entry. Unused entries have None
 			codebook_optimal_codewords: codec_setup
@@ -30,7 +37,11 @@ impl AudioPacketRewrite {
 				.iter_mut()
 				.map(|codebook_configuration| codebook_configuration.codebook.optimal_codewords())
 				.collect(),
-			codec_setup
+			codec_setup,
+			new_mode_numbers,
+			// There are at most 64 modes, so the width is valid
+			new_mode_number_width: BitpackedIntegerWidth::new(ilog(new_mode_count as i32 - 1))
+				.unwrap()
 		}
 	}
 
@@ -38,27 +49,34 @@ impl AudioPacketRewrite {
 	pub(super) fn optimize_packet<'packet>(
 		&mut self,
 		packet: Cow<'packet, [u8]>,
-		identification_data: &VorbisIdentificationHeaderData
+		identification_data: &VorbisIdentificationHeaderData,
+		statistics: &mut VorbisOptimizerStatistics
 	) -> Result<(Option<(Cow<'packet, [u8]>, Option<u16>)>, Option<Self>), VorbisOptimizerError> {
 		trace!("Optimizing Vorbis audio packet");
 
-		let mut packet = &*packet;
 		let packet_length = packet.len();
-		let mut previous_packet_bitpacker = BitpackReader::new(&mut packet);
+		let mut previous_packet_bitpacker = BitpackSliceReader::new(&packet);
 
 		// We need to read from the unoptimized packet, and then write a potentially very different
 		// version of it. Thus, we need a whole new buffer. Usually, each packet is just slightly
 		// shorter than its unoptimized version, so the extra capacity is not that wasteful
 		let mut new_packet = Vec::with_capacity(packet_length);
-		let mut new_packet_bitpacker = BitpackWriter::new(&mut new_packet);
+		let mut rewrite_state = PacketRewriteState {
+			bitpacker: BitpackWriter::new(&mut new_packet),
+			packet: &packet,
+			pending_passthrough_bits: 0..0
+		};
 
 		// Packet type. As we've validated the stream previously, we can assume it's an audio packet.
 		// It is important to return early successfully if the read we do to keep sync fails due to
 		// EOP. See process_audio_packet for more details
-		new_packet_bitpacker.write_unsigned_integer(
+		rewrite_state.bitpacker.write_unsigned_integer(
 			eval_on_eop!(
 				bitpack_packet_read!(previous_packet_bitpacker, read_unsigned_integer, packet_length, const 1, u32),
-				return Ok((None, None))
+				{
+					statistics.discarded_audio_packets += 1;
+					return Ok((None, None));
+				}
 			)?,
 			bitpacked_integer_width!(1)
 		)?;
@@ -68,30 +86,91 @@ impl AudioPacketRewrite {
 			&self.codec_setup,
 			packet_length,
 			&mut previous_packet_bitpacker,
-			|unsigned_integer, width, bitpacker| {
-				// Any bitpacked data we read is necessary for decode, so pass it through
-				Ok(bitpacker.write_unsigned_integer(
-					unsigned_integer,
-					BitpackedIntegerWidth::new(width).unwrap()
-				)?)
+			|mode, _, rewrite_state| {
+				// Unused modes were removed from the new setup header, so renumber the mode.
+				// Packets with unused modes were not analyzed, so they are invalid
+				let new_mode = self.new_mode_numbers[mode as usize]
+					.ok_or(VorbisOptimizerError::InvalidModeNumber(mode))?;
+
+				rewrite_state.write_pending_passthrough_bits()?;
+				Ok(rewrite_state
+					.bitpacker
+					.write_unsigned_integer(new_mode as u32, self.new_mode_number_width)?)
+			},
+			|_, width, bit_position, rewrite_state| {
+				// Any bitpacked data we read is necessary for decode, so pass it through.
+				// Consecutive bits to pass through are copied from the packet in bulk
+				// when the next rewritten field is written
+				if rewrite_state.pending_passthrough_bits.is_empty() {
+					rewrite_state.pending_passthrough_bits.start = bit_position - width as u64;
+				}
+				rewrite_state.pending_passthrough_bits.end = bit_position;
+
+				Ok(())
 			},
-			|codebook_number, entry_number, bitpacker| {
+			|codebook_number, entry_number, rewrite_state| {
 				// Replace codebook codewords by their optimal versions, already in the new setup header
 				let (optimal_codeword, optimal_codeword_length) = self.codebook_optimal_codewords
 					[codebook_number as usize][entry_number as usize]
 					.unwrap();
 
-				Ok(bitpacker.write_unsigned_integer(
+				rewrite_state.write_pending_passthrough_bits()?;
+				Ok(rewrite_state.bitpacker.write_unsigned_integer(
 					optimal_codeword,
 					BitpackedIntegerWidth::new(optimal_codeword_length).unwrap()
 				)?)
 			},
-			new_packet_bitpacker
+			&mut rewrite_state
 		)?;
 
+		rewrite_state.finish()?;
+
+		// Any whole bytes after the last consumed bit are padding
+		let padding_bytes = previous_packet_bitpacker.remaining_bits() / 8;
+		if !keep_packet {
+			statistics.discarded_audio_packets += 1;
+		} else if padding_bytes > 0 {
+			statistics.padded_audio_packets += 1;
+			statistics.removed_padding_bytes += padding_bytes;
+		}
+
 		Ok((
 			keep_packet.then(|| (new_packet.into(), decode_blocksize)),
 			None
 		))
 	}
 }
+
+/// The data shared by the callbacks that rewrite an audio packet.
+struct PacketRewriteState<'packet, W: Write> {
+	/// The bitpacker that writes the rewritten packet.
+	bitpacker: BitpackWriter<W>,
+	/// The audio packet being rewritten.
+	packet: &'packet [u8],
+	/// The bits of the audio packet that are passed through as-is, but were not written
+	/// yet, so that consecutive runs of them can be copied in bulk.
+	pending_passthrough_bits: Range<u64>
+}
+
+impl<W: Write> PacketRewriteState<'_, W> {
+	/// Writes the pending bits to pass through, if any. This must be done before writing
+	/// any rewritten field, to keep the bits in order.
+	fn write_pending_passthrough_bits(&mut self) -> Result<(), io::Error> {
+		if self.pending_passthrough_bits.is_empty() {
+			return Ok(());
+		}
+
+		let Range { start, end } = mem::take(&mut self.pending_passthrough_bits);
+		let mut packet_bitpacker = BitpackSliceReader::new(self.packet);
+		packet_bitpacker.skip_bits(start)?;
+
+		self.bitpacker
+			.copy_bits_from_slice_reader(&mut packet_bitpacker, end - start)
+	}
+
+	/// Writes the pending bits to pass through and finalizes the rewritten packet.
+	fn finish(mut self) -> Result<(), io::Error> {
+		self.write_pending_passthrough_bits()?;
+		self.bitpacker.into_inner().map(|_| ())
+	}
+}
@@ -2,13 +2,19 @@
 
 use std::borrow::Cow;
 
-use log::trace;
+use log::{info, trace};
+use normalization::normalize_comments;
+use picture::process_pictures;
 
 use super::{
-	VorbisOptimizerError, comment_header_parse::VorbisCommentData,
+	VorbisCommentFieldsAction, VorbisCommentOperation, VorbisOptimizerError,
+	VorbisOptimizerSettings, VorbisOptimizerStatistics, comment_header_parse::VorbisCommentData,
 	setup_header_parse::VorbisSetupData, setup_header_rewrite::SetupHeaderRewrite
 };
 
+mod normalization;
+pub(super) mod picture;
+
 /// Copies the comment header according to the analyzed comment data, and then
 /// transitions to the codec setup header rewrite state.
 pub(super) struct CommentHeaderCopy {
@@ -20,7 +26,9 @@ impl CommentHeaderCopy {
 	#[allow(clippy::type_complexity)]
 	pub(super) fn optimize_packet<'packet>(
 		&mut self,
-		mut packet: Cow<'packet, [u8]>
+		mut packet: Cow<'packet, [u8]>,
+		settings: &VorbisOptimizerSettings,
+		statistics: &mut VorbisOptimizerStatistics
 	) -> Result<
 		(
 			Option<(Cow<'packet, [u8]>, Option<u16>)>,
@@ -33,7 +41,16 @@ impl CommentHeaderCopy {
 		// Unwrap is safe because the stream is assumed to have passed the analysis phase:
 		// if we optimize a comment header packet, we've analyzed it before, and thus we
 		// have that data available
-		let comment_data = self.comment_data.as_ref().unwrap();
+		let comment_data = self.comment_data.as_mut().unwrap();
+		if settings.comment_fields_action == VorbisCommentFieldsAction::Normalize {
+			normalize_comments(&mut comment_data.user_comments);
+		}
+		statistics.pictures = process_pictures(&mut comment_data.user_comments, settings);
+		apply_comment_operations(
+			&mut comment_data.user_comments,
+			&settings.comment_operations
+		);
+
 		let packet_data = packet.to_mut();
 
 		// It's very likely we will need to change the comment header contents in some way.
@@ -52,7 +69,7 @@ impl CommentHeaderCopy {
 		packet_data.extend_from_slice(&u32::try_from(vendor_string.len())?.to_le_bytes()[..]);
 		packet_data.extend_from_slice(vendor_string);
 
-		// Pass through the user comments we decided to keep during analysis
+		// Pass through the user comments we decided to keep during analysis, after editing them
 		packet_data.extend_from_slice(&(comment_data.user_comments.len() as u32).to_le_bytes()[..]);
 		for comment in &comment_data.user_comments {
 			packet_data.extend_from_slice(&(comment.len() as u32).to_le_bytes()[..]);
@@ -70,3 +87,202 @@ impl CommentHeaderCopy {
 		))
 	}
 }
+
+/// Applies the specified edits, in order, to a list of Vorbis user comments.
+fn apply_comment_operations(
+	user_comments: &mut Vec<Vec<u8>>,
+	operations: &[VorbisCommentOperation]
+) {
+	for operation in operations {
+		trace!("Applying user comment operation: {operation:?}");
+
+		match operation {
+			VorbisCommentOperation::Set { field_name, value } => {
+				remove_comments(user_comments, |comment_field_name| {
+					comment_field_name.eq_ignore_ascii_case(field_name.as_bytes())
+				});
+				add_comment(user_comments, field_name, value);
+			}
+			VorbisCommentOperation::Append { field_name, value } => {
+				add_comment(user_comments, field_name, value);
+			}
+			VorbisCommentOperation::Rename {
+				field_name,
+				new_field_name
+			} => {
+				for comment in user_comments.iter_mut() {
+					let comment_field_name_length = field_name_of(comment).len();
+
+					if comment[..comment_field_name_length]
+						.eq_ignore_ascii_case(field_name.as_bytes())
+					{
+						info!(
+							"Renaming user comment: {}",
+							String::from_utf8_lossy(comment)
+						);
+
+						comment.splice(..comment_field_name_length, new_field_name.bytes());
+					}
+				}
+			}
+			VorbisCommentOperation::Remove(pattern) => {
+				remove_comments(user_comments, |comment_field_name| {
+					field_name_matches(comment_field_name, pattern.as_bytes())
+				});
+			}
+			VorbisCommentOperation::KeepOnly(patterns) => {
+				remove_comments(user_comments, |comment_field_name| {
+					!patterns
+						.iter()
+						.any(|pattern| field_name_matches(comment_field_name, pattern.as_bytes()))
+				});
+			}
+		}
+	}
+}
+
+/// Removes the user comments whose field name satisfies the specified predicate.
+fn remove_comments(user_comments: &mut Vec<Vec<u8>>, mut predicate: impl FnMut(&[u8]) -> bool) {
+	user_comments.retain(|comment| {
+		let remove = predicate(field_name_of(comment));
+
+		if remove {
+			info!(
+				"Removing user comment: {}",
+				String::from_utf8_lossy(comment)
+			);
+		}
+
+		!remove
+	});
+}
+
+/// Adds a user comment with the specified field name and value.
+fn add_comment(user_comments: &mut Vec<Vec<u8>>, field_name: &str, value: &str) {
+	let comment = format!("{field_name}={value}");
+	info!("Adding user comment: {comment}");
+	user_comments.push(comment.into_bytes());
+}
+
+/// Returns the field name of a user comment, which is everything before the first `=`.
+/// Malformed comments without a `=` are considered to be just a field name.
+fn field_name_of(comment: &[u8]) -> &[u8] {
+	comment
+		.iter()
+		.position(|&byte| byte == b'=')
+		.map_or(comment, |separator_index| &comment[..separator_index])
+}
+
+/// Checks whether a user comment field name matches the specified pattern, which
+/// may contain `*` and `?` wildcards. The comparison is ASCII case-insensitive.
+fn field_name_matches(field_name: &[u8], pattern: &[u8]) -> bool {
+	let mut pattern_index = 0;
+	let mut field_name_index = 0;
+	// The position of the last `*` seen in the pattern, if any, and the position in the
+	// field name right after the bytes it currently matches
+	let mut last_star_indices = None;
+
+	while field_name_index < field_name.len() {
+		match pattern.get(pattern_index) {
+			Some(b'*') => {
+				// Try to match the `*` with no bytes first
+				last_star_indices = Some((pattern_index, field_name_index));
+				pattern_index += 1;
+			}
+			Some(pattern_byte)
+				if *pattern_byte == b'?'
+					|| pattern_byte.eq_ignore_ascii_case(&field_name[field_name_index]) =>
+			{
+				pattern_index += 1;
+				field_name_index += 1;
+			}
+			_ => {
+				// On a mismatch, backtrack to make the last `*` match one more byte. Earlier
+				// `*` wildcards do not need to be retried, as the last one can match anything
+				// they could
+				let Some((star_pattern_index, star_field_name_index)) = last_star_indices else {
+					return false;
+				};
+				last_star_indices = Some((star_pattern_index, star_field_name_index + 1));
+				pattern_index = star_pattern_index + 1;
+				field_name_index = star_field_name_index + 1;
+			}
+		}
+	}
+
+	// The field name was consumed, so only trailing `*` wildcards may be left
+	pattern[pattern_index..].iter().all(|&byte| byte == b'*')
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_log::test]
+	fn field_name_matching_works() {
+		assert!(field_name_matches(
+			b"REPLAYGAIN_TRACK_GAIN",
+			b"replaygain_*"
+		));
+		assert!(field_name_matches(b"Encoder", b"ENCODER"));
+		assert!(field_name_matches(b"DATE", b"D?T*"));
+		assert!(field_name_matches(b"", b"*"));
+		assert!(!field_name_matches(b"ENCODER", b"ENCODE"));
+		assert!(!field_name_matches(b"ARTIST", b"?ARTIST"));
+		assert!(field_name_matches(b"ALBUMARTIST", b"*A*T*ST"));
+		assert!(!field_name_matches(b"ALBUMARTIST", b"*A*T*SX"));
+	}
+
+	#[test_log::test]
+	fn long_field_name_matching_works() {
+		// Matching should neither take exponential time nor recurse once per byte
+		let field_name = vec![b'A'; 1 << 20];
+		assert!(field_name_matches(&field_name, b"*A*A*A*A*A*A*A*A*"));
+		assert!(!field_name_matches(&field_name, b"*A*A*A*A*A*A*A*A*B"));
+		assert!(!field_name_matches(
+			&field_name[..100],
+			b"*A*A*A*A*A*A*A*A*A*A*A*A*?B"
+		));
+	}
+
+	#[test_log::test]
+	fn comment_operations_work() {
+		let mut user_comments = vec![
+			b"ARTIST=Someone".to_vec(),
+			b"encoder=Something".to_vec(),
+			b"REPLAYGAIN_TRACK_GAIN=-1.0 dB".to_vec(),
+			b"REPLAYGAIN_TRACK_PEAK=0.9".to_vec(),
+			b"DESCRIPTION=Something else".to_vec(),
+			b"Malformed".to_vec(),
+		];
+
+		apply_comment_operations(
+			&mut user_comments,
+			&[
+				VorbisCommentOperation::Set {
+					field_name: "Artist".into(),
+					value: "Someone else".into()
+				},
+				VorbisCommentOperation::Append {
+					field_name: "ARTIST".into(),
+					value: "Another one".into()
+				},
+				VorbisCommentOperation::Remove("REPLAYGAIN_*".into()),
+				VorbisCommentOperation::Rename {
+					field_name: "description".into(),
+					new_field_name: "COMMENT".into()
+				},
+				VorbisCommentOperation::KeepOnly(vec!["ARTIST".into(), "COMM*".into()])
+			]
+		);
+
+		assert_eq!(
+			user_comments,
+			[
+				b"COMMENT=Something else".as_slice(),
+				b"Artist=Someone else",
+				b"ARTIST=Another one"
+			]
+		);
+	}
+}
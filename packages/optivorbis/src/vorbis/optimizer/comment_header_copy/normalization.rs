@@ -0,0 +1,140 @@
+//! Contains code for normalizing Vorbis user comments, so that they follow the Vorbis
+//! comment specification.
+
+use std::{collections::HashSet, str};
+
+use log::info;
+
+/// The field name given to user comments that do not have one.
+const DEFAULT_FIELD_NAME: &[u8] = b"COMMENT";
+
+/// The characters that the Windows-1252 code page assigns to the bytes from 0x80 to
+/// 0x9F, which ISO-8859-1 (Latin-1) leaves for control characters. The bytes that
+/// Windows-1252 does not define are mapped to their Latin-1 control characters.
+const WINDOWS_1252_HIGH_CONTROL_CHARACTERS: [char; 32] = [
+	'\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+	'\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+	'\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+	'\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}'
+];
+
+/// Normalizes the specified user comments, logging every change made to them:
+///
+/// - Comments without a `=` separator, or with an empty field name, are given a
+///   `COMMENT` field name, so that their text is not lost.
+/// - Field name characters outside the ASCII 0x20 to 0x7D range are replaced with
+///   underscores.
+/// - Field names are converted to uppercase. The Vorbis I specification mandates
+///   that field names are case-insensitive, but uppercase is the common convention.
+/// - Values that are not valid UTF-8 are assumed to be encoded with the Windows-1252
+///   code page, a superset of Latin-1 that was commonly used by old taggers, and are
+///   transcoded to UTF-8.
+/// - Exact duplicates of a previous comment are removed.
+pub(super) fn normalize_comments(user_comments: &mut Vec<Vec<u8>>) {
+	for comment in user_comments.iter_mut() {
+		let normalized_comment = normalize_comment(comment);
+
+		if normalized_comment != *comment {
+			info!(
+				"Normalizing user comment: {} -> {}",
+				String::from_utf8_lossy(comment),
+				String::from_utf8_lossy(&normalized_comment)
+			);
+
+			*comment = normalized_comment;
+		}
+	}
+
+	// Hash borrowed comments, so that comments with big embedded pictures are not copied
+	let mut seen_comments = HashSet::with_capacity(user_comments.len());
+	let mut is_duplicate = user_comments
+		.iter()
+		.map(|comment| !seen_comments.insert(comment.as_slice()))
+		.collect::<Vec<_>>()
+		.into_iter();
+
+	user_comments.retain(|comment| {
+		let remove = is_duplicate.next().unwrap();
+
+		if remove {
+			info!(
+				"Removing duplicate user comment: {}",
+				String::from_utf8_lossy(comment)
+			);
+		}
+
+		!remove
+	});
+}
+
+/// Returns the normalized version of a user comment. See [`normalize_comments`].
+fn normalize_comment(comment: &[u8]) -> Vec<u8> {
+	let (field_name, value) = match comment.iter().position(|&byte| byte == b'=') {
+		Some(separator_index) if separator_index > 0 => {
+			(&comment[..separator_index], &comment[separator_index + 1..])
+		}
+		Some(_) => (DEFAULT_FIELD_NAME, &comment[1..]),
+		None => (DEFAULT_FIELD_NAME, comment)
+	};
+
+	let mut normalized_comment = Vec::with_capacity(comment.len() + DEFAULT_FIELD_NAME.len() + 1);
+
+	// Separators can't appear in the field name, as it ends at the first one
+	normalized_comment.extend(field_name.iter().map(|&character| {
+		if (0x20..=0x7D).contains(&character) {
+			character.to_ascii_uppercase()
+		} else {
+			b'_'
+		}
+	}));
+	normalized_comment.push(b'=');
+
+	match str::from_utf8(value) {
+		Ok(_) => normalized_comment.extend_from_slice(value),
+		Err(_) => normalized_comment.extend(
+			value
+				.iter()
+				.map(|&byte| match byte {
+					0x80..=0x9F => WINDOWS_1252_HIGH_CONTROL_CHARACTERS[byte as usize - 0x80],
+					// The rest of Windows-1252 matches the first Unicode code points
+					_ => byte as char
+				})
+				.collect::<String>()
+				.bytes()
+		)
+	}
+
+	normalized_comment
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test_log::test]
+	fn comment_normalization_works() {
+		let mut user_comments = vec![
+			b"Artist=Someone".to_vec(),
+			b"TITLE=Caf\xE9 \x93au lait\x94".to_vec(),
+			b"ALBUM=Caf\xC3\xA9".to_vec(),
+			b"Just some text".to_vec(),
+			b"=Empty field name".to_vec(),
+			b"T\xC3\x8DTULO=Something".to_vec(),
+			b"ARTIST=Someone".to_vec(),
+		];
+
+		normalize_comments(&mut user_comments);
+
+		assert_eq!(
+			user_comments,
+			[
+				b"ARTIST=Someone".as_slice(),
+				"TITLE=Café “au lait”".as_bytes(),
+				"ALBUM=Café".as_bytes(),
+				b"COMMENT=Just some text",
+				b"COMMENT=Empty field name",
+				b"T__TULO=Something"
+			]
+		);
+	}
+}
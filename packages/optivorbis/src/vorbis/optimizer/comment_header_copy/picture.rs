@@ -0,0 +1,253 @@
+//! Contains code for handling pictures embedded in Vorbis user comments, such as cover art.
+//!
+//! Pictures are embedded in `METADATA_BLOCK_PICTURE` comments, whose values are base64
+//! encoded FLAC picture metadata blocks. This is not part of the Vorbis I specification,
+//! but it is a [widely used convention](https://wiki.xiph.org/VorbisComment#Cover_art).
+
+use log::{info, warn};
+
+use super::field_name_of;
+use crate::vorbis::optimizer::{VorbisOptimizerSettings, VorbisPictureAction};
+
+/// The field name of the user comments that contain embedded pictures.
+const PICTURE_FIELD_NAME: &[u8] = b"METADATA_BLOCK_PICTURE";
+
+/// A picture embedded in the user comments of a Vorbis comment header as a
+/// `METADATA_BLOCK_PICTURE` comment, such as cover art.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VorbisPicture {
+	/// The type of the picture, according to the ID3v2 APIC frame picture types.
+	/// For example, 3 is a front cover, and 4 a back cover.
+	pub picture_type: u32,
+	/// The MIME type of the picture data. A MIME type of `-->` means that the data is a
+	/// URL to the picture, instead of the picture itself.
+	pub mime_type: String,
+	/// A description of the picture, which is usually empty.
+	pub description: String,
+	/// The width of the picture, in pixels, or zero if unknown.
+	pub width: u32,
+	/// The height of the picture, in pixels, or zero if unknown.
+	pub height: u32,
+	/// The color depth of the picture, in bits per pixel, or zero if unknown.
+	pub color_depth: u32,
+	/// The number of colors of the picture, for indexed color pictures, or zero otherwise.
+	pub indexed_color_count: u32,
+	/// The size of the picture data, in bytes.
+	pub data_size: u64,
+	/// The size of the user comment that embeds the picture, in bytes. Due to the base64
+	/// encoding, it is about a third bigger than the picture data.
+	pub comment_size: u64,
+	/// Whether the picture was kept in the optimized stream.
+	pub kept: bool,
+	/// The picture data, which is only available when
+	/// [`retain_picture_data`](VorbisOptimizerSettings::retain_picture_data) is enabled.
+	pub data: Option<Vec<u8>>
+}
+
+impl VorbisPicture {
+	/// The picture type of front covers.
+	pub const FRONT_COVER_PICTURE_TYPE: u32 = 3;
+
+	/// Parses the picture embedded in the specified user comment value, returning it and
+	/// its data, or `None` if the value is not a valid FLAC picture metadata block.
+	fn parse(comment_value: &[u8]) -> Option<(Self, Vec<u8>)> {
+		let picture_block = decode_base64(comment_value)?;
+		let mut remaining_block = &picture_block[..];
+
+		let picture_type = read_u32(&mut remaining_block)?;
+		let mime_type_length = read_u32(&mut remaining_block)? as usize;
+		let mime_type = read_bytes(&mut remaining_block, mime_type_length)?;
+		let description_length = read_u32(&mut remaining_block)? as usize;
+		let description = read_bytes(&mut remaining_block, description_length)?;
+		let width = read_u32(&mut remaining_block)?;
+		let height = read_u32(&mut remaining_block)?;
+		let color_depth = read_u32(&mut remaining_block)?;
+		let indexed_color_count = read_u32(&mut remaining_block)?;
+		let data_length = read_u32(&mut remaining_block)? as usize;
+		let data = read_bytes(&mut remaining_block, data_length)?.to_vec();
+
+		Some((
+			Self {
+				picture_type,
+				mime_type: String::from_utf8_lossy(mime_type).into_owned(),
+				description: String::from_utf8_lossy(description).into_owned(),
+				width,
+				height,
+				color_depth,
+				indexed_color_count,
+				data_size: data.len() as u64,
+				comment_size: 0,
+				kept: false,
+				data: None
+			},
+			data
+		))
+	}
+}
+
+/// Finds the pictures embedded in the specified user comments, removing them according
+/// to the picture action in the settings, and returns them.
+pub(super) fn process_pictures(
+	user_comments: &mut Vec<Vec<u8>>,
+	settings: &VorbisOptimizerSettings
+) -> Vec<VorbisPicture> {
+	let mut pictures = vec![];
+	let mut front_cover_kept = false;
+
+	user_comments.retain(|comment| {
+		let field_name = field_name_of(comment);
+		if !field_name.eq_ignore_ascii_case(PICTURE_FIELD_NAME) {
+			return true;
+		}
+
+		let comment_value = comment.get(field_name.len() + 1..).unwrap_or_default();
+		let Some((mut picture, data)) = VorbisPicture::parse(comment_value) else {
+			warn!("Found an invalid embedded picture. It will be kept only if pictures are copied");
+
+			return settings.picture_action == VorbisPictureAction::Copy;
+		};
+
+		picture.comment_size = comment.len() as u64;
+		picture.kept = match settings.picture_action {
+			VorbisPictureAction::Copy => true,
+			VorbisPictureAction::Delete => false,
+			VorbisPictureAction::KeepFrontCover => {
+				// There should be at most one front cover, so keep only the first one
+				let is_kept_front_cover = picture.picture_type
+					== VorbisPicture::FRONT_COVER_PICTURE_TYPE
+					&& !front_cover_kept;
+				front_cover_kept |= is_kept_front_cover;
+
+				is_kept_front_cover
+			}
+		};
+		picture.data = settings.retain_picture_data.then_some(data);
+
+		info!(
+			"{} embedded picture of type {} ({}, {}x{}): {} bytes of data in a {} bytes comment",
+			if picture.kept { "Keeping" } else { "Removing" },
+			picture.picture_type,
+			picture.mime_type,
+			picture.width,
+			picture.height,
+			picture.data_size,
+			picture.comment_size
+		);
+
+		let kept = picture.kept;
+		pictures.push(picture);
+
+		kept
+	});
+
+	pictures
+}
+
+/// Reads a big-endian 32-bit integer field from a FLAC picture metadata block,
+/// advancing the block slice past it.
+fn read_u32(block: &mut &[u8]) -> Option<u32> {
+	read_bytes(block, 4).map(|field| u32::from_be_bytes(field.try_into().unwrap()))
+}
+
+/// Reads the specified number of bytes from a FLAC picture metadata block, advancing
+/// the block slice past them.
+fn read_bytes<'block>(block: &mut &'block [u8], length: usize) -> Option<&'block [u8]> {
+	let (bytes, remaining_block) = block.split_at_checked(length)?;
+	*block = remaining_block;
+	Some(bytes)
+}
+
+/// Decodes the specified base64 data, which must use the standard alphabet. Padding
+/// characters are optional. `None` is returned if the data is not valid base64.
+fn decode_base64(encoded_data: &[u8]) -> Option<Vec<u8>> {
+	const fn sextet(character: u8) -> Option<u32> {
+		match character {
+			b'A'..=b'Z' => Some((character - b'A') as u32),
+			b'a'..=b'z' => Some((character - b'a' + 26) as u32),
+			b'0'..=b'9' => Some((character - b'0' + 52) as u32),
+			b'+' => Some(62),
+			b'/' => Some(63),
+			_ => None
+		}
+	}
+
+	let encoded_data = encoded_data
+		.strip_suffix(b"==")
+		.or_else(|| encoded_data.strip_suffix(b"="))
+		.unwrap_or(encoded_data);
+
+	let mut decoded_data = Vec::with_capacity(encoded_data.len() / 4 * 3 + 2);
+	for chunk in encoded_data.chunks(4) {
+		// A single character can't encode a whole byte
+		if chunk.len() == 1 {
+			return None;
+		}
+
+		let mut bits = 0;
+		for (i, &character) in chunk.iter().enumerate() {
+			bits |= sextet(character)? << (18 - 6 * i);
+		}
+
+		decoded_data.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+	}
+
+	Some(decoded_data)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	const FRONT_COVER_COMMENT: &[u8] = b"METADATA_BLOCK_PICTURE=\
+		AAAAAwAAAAlpbWFnZS9wbmcAAAAFQ292ZXIAAAABAAAAAgAAABgAAAAAAAAABIlQTkc=";
+	const BACK_COVER_COMMENT: &[u8] = b"metadata_block_picture=\
+		AAAABAAAAAppbWFnZS9qcGVnAAAAAAAAAAEAAAACAAAAGAAAAAAAAAAD/9j/";
+
+	#[test_log::test]
+	fn picture_parsing_works() {
+		let (picture, data) =
+			VorbisPicture::parse(&FRONT_COVER_COMMENT[23..]).expect("The picture should be valid");
+
+		assert_eq!(
+			picture.picture_type,
+			VorbisPicture::FRONT_COVER_PICTURE_TYPE
+		);
+		assert_eq!(picture.mime_type, "image/png");
+		assert_eq!(picture.description, "Cover");
+		assert_eq!((picture.width, picture.height), (1, 2));
+		assert_eq!(picture.color_depth, 24);
+		assert_eq!(data, b"\x89PNG");
+
+		assert!(VorbisPicture::parse(b"AAAAAwAAAAlpbWFnZS9wbmc").is_none());
+		assert!(VorbisPicture::parse(b"Not base64!").is_none());
+	}
+
+	#[test_log::test]
+	fn keeping_front_cover_works() {
+		let mut user_comments = vec![
+			b"TITLE=Something".to_vec(),
+			BACK_COVER_COMMENT.to_vec(),
+			FRONT_COVER_COMMENT.to_vec(),
+			b"METADATA_BLOCK_PICTURE=Invalid".to_vec(),
+		];
+
+		let pictures = process_pictures(
+			&mut user_comments,
+			&VorbisOptimizerSettings {
+				picture_action: VorbisPictureAction::KeepFrontCover,
+				retain_picture_data: true,
+				..Default::default()
+			}
+		);
+
+		assert_eq!(
+			user_comments,
+			[b"TITLE=Something".as_slice(), FRONT_COVER_COMMENT]
+		);
+		assert_eq!(pictures.len(), 2);
+		assert!(!pictures[0].kept && pictures[1].kept);
+		assert_eq!(pictures[0].data.as_deref(), Some(&b"\xff\xd8\xff"[..]));
+		assert_eq!(pictures[1].comment_size, FRONT_COVER_COMMENT.len() as u64);
+	}
+}
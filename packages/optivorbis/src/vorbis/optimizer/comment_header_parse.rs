@@ -171,7 +171,10 @@ fn parse(
 	info!("User comment count: {user_comment_count}");
 
 	// Now read the user comment fields if they should be copied
-	if settings.comment_fields_action == VorbisCommentFieldsAction::Copy {
+	if matches!(
+		settings.comment_fields_action,
+		VorbisCommentFieldsAction::Copy | VorbisCommentFieldsAction::Normalize
+	) {
 		trace!("Copying user comments");
 
 		let mut user_comment_length_start_index = 4 + vendor_string_length + 4;
@@ -1,6 +1,6 @@
 //! Contains the supporting code for the [`VorbisSetupData`] Vorbis optimizer state.
 
-use std::{io::Read, mem};
+use std::{cell::Cell, io::Read, mem};
 
 use indexmap::IndexSet;
 use log::{debug, info, trace};
@@ -8,7 +8,7 @@ use vorbis_bitpack::BitpackReader;
 
 use super::{
 	AudioPacketAnalyze, VorbisCommentData, VorbisIdentificationHeaderData, VorbisOptimizerError,
-	common_header_validation
+	VorbisResourceLimit, VorbisResourceLimits, common_header_validation
 };
 use crate::vorbis::{
 	PacketType, ResidueType, VectorLookupType, codebook::VorbisCodebook, ilog, lookup1_values
@@ -43,6 +43,7 @@ pub(super) struct SetupHeaderParse {
 }
 
 /// A channel mapping configuration, used for coupling.
+#[derive(Eq, PartialEq)]
 pub(super) struct ChannelMapping {
 	pub(super) magnitude_channel: u8,
 	pub(super) angle_channel: u8
@@ -50,6 +51,7 @@ pub(super) struct ChannelMapping {
 
 /// A pair of floor and residue configuration indexes, used in [audio packet
 /// mode mappings](MappingConfiguration).
+#[derive(Eq, PartialEq)]
 pub(super) struct FloorAndResidueMapping {
 	pub(super) floor_number: u8,
 	pub(super) residue_number: u8
@@ -73,10 +75,14 @@ pub(super) struct CodebookConfiguration {
 	/// Called `codebook_value_bits` in the specification.
 	pub(super) codebook_vector_value_bits: u8,
 	/// Called `codebook_sequence_p` in the specification.
-	pub(super) codebook_vector_sequence_flag: bool
+	pub(super) codebook_vector_sequence_flag: bool,
+	/// The codeword length of each entry, as read from the setup header. Unused entries
+	/// have a length of zero.
+	pub(super) codeword_lengths: Vec<u8>
 }
 
 /// A configuration for a type 1 floor encoding.
+#[derive(Eq, PartialEq)]
 pub(super) struct Floor1Configuration {
 	pub(super) multiplier: u8,
 	pub(super) range_bits: u8,
@@ -96,6 +102,7 @@ pub(super) struct Floor1Configuration {
 }
 
 /// A configuration for a residue encoding.
+#[derive(Eq, PartialEq)]
 pub(super) struct ResidueConfiguration {
 	pub(super) residue_type: ResidueType,
 	/// 24-bit integer.
@@ -112,6 +119,7 @@ pub(super) struct ResidueConfiguration {
 
 /// A mapping configuration that relates audio channels with their
 /// coupling, floor and residue configurations.
+#[derive(Eq, PartialEq)]
 pub(super) struct MappingConfiguration {
 	/// Contains [1, 256] elements (length stored in offset-1 8-bit integer).
 	pub(super) channel_mappings: Vec<ChannelMapping>,
@@ -127,22 +135,36 @@ pub(super) struct Mode {
 	/// Called `vorbis_mode_blockflag` in the specification.
 	pub(super) big_block: bool,
 	/// Called `vorbis_mode_mapping` in the specification.
-	pub(super) mapping_number: u8
+	pub(super) mapping_number: u8,
+	/// Whether any audio packet using this mode was processed. Audio packets refer to
+	/// modes by number, so this is tracked with interior mutability, like codebook
+	/// entry decode frequencies.
+	pub(super) used: Cell<bool>
 }
 
 impl SetupHeaderParse {
 	pub(super) fn analyze_packet(
 		&mut self,
 		packet: &[u8],
-		identification_data: &VorbisIdentificationHeaderData
+		identification_data: &VorbisIdentificationHeaderData,
+		resource_limits: &VorbisResourceLimits
 	) -> Result<(Option<u16>, Option<AudioPacketAnalyze>), VorbisOptimizerError> {
 		trace!("Decoding setup header Vorbis packet");
 
+		if resource_limits
+			.max_setup_header_size
+			.is_some_and(|max_setup_header_size| packet.len() > max_setup_header_size)
+		{
+			return Err(VorbisOptimizerError::ResourceLimitExceeded(
+				VorbisResourceLimit::SetupHeaderSize
+			));
+		}
+
 		let mut setup_header = common_header_validation(packet, PacketType::SetupHeader)?;
 
 		// Vorbis I spec, § 4.2.4, step 1: read codebook configurations
 		let (codebook_configurations, mut bitpacker, header_length) =
-			parse_codebook_configurations(&mut setup_header)?;
+			parse_codebook_configurations(&mut setup_header, resource_limits)?;
 
 		// Vorbis I spec, § 4.2.4, step 2: discard time domain transforms, which are placeholders
 		// in Vorbis I, to keep sync.
@@ -202,7 +224,8 @@ impl SetupHeaderParse {
 /// the Vorbis I specification, § 3.2.1. Because this is the first thing parsed in a setup
 /// header, the [`BitpackReader`] and setup header packet size are also returned.
 fn parse_codebook_configurations<'pref, 'packet>(
-	setup_header: PacketSlice<'pref, 'packet>
+	setup_header: PacketSlice<'pref, 'packet>,
+	resource_limits: &VorbisResourceLimits
 ) -> Result<
 	(
 		Vec<CodebookConfiguration>,
@@ -245,6 +268,7 @@ fn parse_codebook_configurations<'pref, 'packet>(
 	let mut bitpacker = BitpackReader::new(setup_header);
 
 	let mut codebook_configurations = Vec::with_capacity(codebook_count as usize);
+	let mut total_codebook_entries = 0u64;
 	for i in 0..codebook_count {
 		// For development, it's good to check the sync pattern, which definitely marks
 		// this as a packed codebook, to catch logic errors. For release, however, we can
@@ -262,6 +286,18 @@ fn parse_codebook_configurations<'pref, 'packet>(
 			bitpack_packet_read!(bitpacker, read_unsigned_integer, header_length, const 24, u32)?;
 		let codebook_entries_usize = codebook_entries.try_into()?;
 
+		// Check this limit before allocating memory for the codebook entries
+		total_codebook_entries += codebook_entries as u64;
+		if resource_limits
+			.max_total_codebook_entries
+			.is_some_and(|max_total_codebook_entries| {
+				total_codebook_entries > max_total_codebook_entries
+			}) {
+			return Err(VorbisOptimizerError::ResourceLimitExceeded(
+				VorbisResourceLimit::TotalCodebookEntries
+			));
+		}
+
 		let ordered = bitpack_packet_read!(bitpacker, read_flag, header_length)?;
 
 		debug!(
@@ -412,6 +448,20 @@ fn parse_codebook_configurations<'pref, 'packet>(
 			}
 		}
 
+		// Building the prefix code tree is the expensive part for dense and deep trees,
+		// so check this limit before doing so
+		if resource_limits
+			.max_codebook_tree_depth
+			.is_some_and(|max_codebook_tree_depth| {
+				codeword_lengths
+					.iter()
+					.any(|&codeword_length| codeword_length > max_codebook_tree_depth)
+			}) {
+			return Err(VorbisOptimizerError::ResourceLimitExceeded(
+				VorbisResourceLimit::CodebookTreeDepth
+			));
+		}
+
 		codebook_configurations.push(CodebookConfiguration {
 			codebook: VorbisCodebook::new(i, &codeword_lengths)?,
 			entry_count: codebook_entries,
@@ -421,7 +471,8 @@ fn parse_codebook_configurations<'pref, 'packet>(
 			codebook_vector_multiplicands,
 			codebook_vector_value_bits,
 			codebook_vector_sequence_flag,
-			dimensions: codebook_dimensions
+			dimensions: codebook_dimensions,
+			codeword_lengths
 		});
 	}
 
@@ -889,7 +940,8 @@ fn parse_modes<R: Read>(
 
 		modes.push(Mode {
 			big_block,
-			mapping_number
+			mapping_number,
+			used: Cell::new(false)
 		});
 	}
 
@@ -2,17 +2,25 @@
 
 use std::{borrow::Cow, io, io::Write};
 
-use log::trace;
+use codebook_garbage_collection::remove_unused_codebooks;
+use configuration_deduplication::deduplicate_configurations;
+use log::{debug, trace};
+use mode_garbage_collection::remove_unused_modes;
 use slice_group_by::GroupBy;
 use vorbis_bitpack::{BitpackWriter, BitpackedIntegerWidth, bitpacked_integer_width};
 
 use super::{
-	VorbisOptimizerError, audio_packet_rewrite::AudioPacketRewrite,
-	setup_header_parse::VorbisSetupData
+	VorbisOptimizerError, VorbisOptimizerSettings, VorbisOptimizerStatistics,
+	audio_packet_rewrite::AudioPacketRewrite, setup_header_parse::VorbisSetupData
 };
 use crate::vorbis::{VectorLookupType, ilog};
 
+mod codebook_garbage_collection;
 mod codebook_truncation;
+mod configuration_deduplication;
+mod mode_garbage_collection;
+#[cfg(test)]
+mod test_fixtures;
 
 /// The Vorbis optimizer state reached when rewriting an optimized setup header.
 /// A state transition is made to the audio packet optimizing state.
@@ -24,7 +32,9 @@ impl SetupHeaderRewrite {
 	#[allow(clippy::type_complexity)]
 	pub(super) fn optimize_packet<'packet>(
 		&mut self,
-		mut packet: Cow<'packet, [u8]>
+		mut packet: Cow<'packet, [u8]>,
+		settings: &VorbisOptimizerSettings,
+		statistics: &mut VorbisOptimizerStatistics
 	) -> Result<
 		(
 			Option<(Cow<'packet, [u8]>, Option<u16>)>,
@@ -46,8 +56,61 @@ impl SetupHeaderRewrite {
 		packet_data.push(5); // Packet type
 		packet_data.extend_from_slice(b"vorbis"); // Header signature
 
+		// Remove the configurations unused modes need before codebooks, so that the
+		// codebooks only they referenced can be removed too
+		let new_mode_numbers = remove_unused_modes(&mut codec_setup);
+
 		// Codebooks
+		let original_codebook_entry_counts = codec_setup
+			.codebook_configurations
+			.iter()
+			.map(|codebook_configuration| codebook_configuration.entry_count)
+			.collect::<Vec<_>>();
+		let new_codebook_numbers = remove_unused_codebooks(&mut codec_setup);
+		let deduplicated_codebook_numbers =
+			deduplicate_configurations(&mut codec_setup, &new_mode_numbers);
+		let new_codebook_numbers = new_codebook_numbers
+			.into_iter()
+			.map(|new_codebook_number| {
+				new_codebook_number.and_then(|new_codebook_number| {
+					deduplicated_codebook_numbers[new_codebook_number as usize]
+				})
+			})
+			.collect::<Vec<_>>();
+
+		// Limit the optimal codeword lengths before they are computed for writing the codebooks
+		if let Some(max_codeword_length) = settings.max_codeword_length {
+			let additional_bits = codec_setup
+				.codebook_configurations
+				.iter_mut()
+				.map(|codebook_configuration| {
+					codebook_configuration
+						.codebook
+						.limit_optimal_codeword_lengths(max_codeword_length.get())
+				})
+				.fold(0, u64::saturating_add);
+
+			statistics.codeword_length_limit_cost_bytes = additional_bits.div_ceil(8);
+		}
+
 		let mut bitpacker = optimize_and_write_codebooks(&mut codec_setup, packet_data)?;
+		statistics.codebook_entry_counts = original_codebook_entry_counts
+			.into_iter()
+			.zip(&new_codebook_numbers)
+			.map(|(original_entry_count, new_codebook_number)| {
+				(
+					original_entry_count,
+					new_codebook_number.map_or(0, |new_codebook_number| {
+						codec_setup.codebook_configurations[new_codebook_number as usize]
+							.entry_count
+					})
+				)
+			})
+			.collect();
+		statistics.removed_codebook_count = new_codebook_numbers
+			.iter()
+			.filter(|new_codebook_number| new_codebook_number.is_none())
+			.count() as u64;
 
 		// Time domain transforms placeholder data. Write the minimum data possible:
 		// a single time domain transform value, set to zero
@@ -64,7 +127,7 @@ impl SetupHeaderRewrite {
 		write_mapping_configurations(&codec_setup, &mut bitpacker)?;
 
 		// Modes
-		write_modes(&codec_setup, &mut bitpacker)?;
+		write_modes(&codec_setup, &new_mode_numbers, &mut bitpacker)?;
 
 		// Framing flag
 		bitpacker.write_flag(true)?;
@@ -73,7 +136,7 @@ impl SetupHeaderRewrite {
 
 		Ok((
 			Some((packet, None)),
-			Some(AudioPacketRewrite::new(codec_setup))
+			Some(AudioPacketRewrite::new(codec_setup, new_mode_numbers))
 		))
 	}
 }
@@ -111,11 +174,8 @@ fn optimize_and_write_codebooks<W: Write>(
 
 		// Single-entry codebooks are not iterated in windows below, so has_unused_entries could end
 		// up with an incorrect value. Handle that by checking the first entry now.
-		// Zero-entry codebooks can be considered to not have any unused entries.
-		//
-		// TODO we should remove empty codebooks. Encoders are not broken enough to generate them,
-		// though, so that would not translate to real-world space savings. It also would require
-		// replacing any references to the old codebook numbers
+		// Zero-entry codebooks can be considered to not have any unused entries. They can't be
+		// decoded, so they were removed before, unless the stream has no other codebook
 		let mut has_unused_entries = optimal_codeword_lengths
 			.first()
 			.is_some_and(|&first_codeword_length| first_codeword_length == 0);
@@ -465,27 +525,27 @@ fn write_mapping_configurations<W: Write>(
 	Ok(())
 }
 
-/// Writes all the audio packet modes as dictated by the Vorbis stream format.
+/// Writes the audio packet modes that have a new mode number as dictated by the Vorbis
+/// stream format.
 fn write_modes<W: Write>(
 	codec_setup: &VorbisSetupData,
+	new_mode_numbers: &[Option<u8>],
 	bitpacker: &mut BitpackWriter<W>
 ) -> Result<(), io::Error> {
-	// Mode count
-	bitpacker.write_unsigned_integer(
-		codec_setup.modes.len() as u32 - 1,
-		bitpacked_integer_width!(6)
-	)?;
+	let kept_modes = || {
+		codec_setup
+			.modes
+			.iter()
+			.zip(new_mode_numbers)
+			.filter(|(_, new_mode_number)| new_mode_number.is_some())
+			.map(|(mode, _)| mode)
+	};
+
+	// Mode count. At least one mode is always kept
+	bitpacker
+		.write_unsigned_integer(kept_modes().count() as u32 - 1, bitpacked_integer_width!(6))?;
 
-	// TODO unused mode removal. Most audio files use every mode, and there tend to be few modes, but
-	// it's possible to find reasonable counterexamples. This would cascade to removing mappings that
-	// were only referred by deleted modes, floors and residues that were only referred by deleted
-	// mappings, and codebooks that were only referred by deleted floors and residues. It would be
-	// necessary to map mode, mapping, floor, residue and codebook numbers accordingly.
-	//
-	// TODO also remove unused codec configuration elements even if the removal is not cascaded by mode
-	// removal. Only broken or adversarial encoders would generate such setup headers, however, so in
-	// practice it does not matter
-	for mode in &codec_setup.modes {
+	for mode in kept_modes() {
 		bitpacker.write_flag(mode.big_block)?;
 
 		// Window and time transform types. They must be 0
@@ -497,3 +557,54 @@ fn write_modes<W: Write>(
 
 	Ok(())
 }
+
+/// Computes the number every configuration of some kind will have after removing the ones
+/// that do not survive, given the survivor of every configuration: the configuration itself
+/// if it is kept, a kept configuration before it if it is merged into that one, or `None`
+/// if it is removed. Merged configurations take the new number of their survivor. The
+/// merged and removed configurations are logged with the specified kind name.
+fn new_configuration_numbers(
+	survivors: &[Option<usize>],
+	configuration_kind: &str
+) -> Vec<Option<u8>> {
+	let mut new_configuration_numbers = Vec::with_capacity(survivors.len());
+	let mut next_configuration_number = 0;
+
+	for (configuration_number, &survivor) in survivors.iter().enumerate() {
+		let new_configuration_number = match survivor {
+			Some(survivor) if survivor == configuration_number => {
+				next_configuration_number += 1;
+
+				// Configuration numbers are in [0, 255] by construction
+				Some((next_configuration_number - 1) as u8)
+			}
+			Some(survivor) => {
+				debug!(
+					"Merging {configuration_kind} {configuration_number} into identical \
+					{configuration_kind} {survivor}"
+				);
+
+				new_configuration_numbers[survivor]
+			}
+			None => {
+				debug!("Removing unused {configuration_kind} {configuration_number}");
+
+				None
+			}
+		};
+
+		new_configuration_numbers.push(new_configuration_number);
+	}
+
+	new_configuration_numbers
+}
+
+/// Removes the configurations that are not their own survivor, according to the specified
+/// survivor of every configuration. See [`new_configuration_numbers`].
+fn retain_survivors<T>(configurations: &mut Vec<T>, survivors: &[Option<usize>]) {
+	let mut configuration_number = 0;
+	configurations.retain(|_| {
+		configuration_number += 1;
+		survivors[configuration_number - 1] == Some(configuration_number - 1)
+	});
+}
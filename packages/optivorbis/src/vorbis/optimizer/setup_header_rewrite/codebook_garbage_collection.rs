@@ -0,0 +1,200 @@
+use super::{new_configuration_numbers, retain_survivors};
+use crate::vorbis::optimizer::VorbisSetupData;
+
+/// Removes the codebooks of the specified codec setup that are not referenced by any
+/// floor or residue, or that were never decoded in the analyzed audio packets,
+/// renumbering the remaining codebooks and their references to fill the gaps. The
+/// returned `Vec` maps every original codebook number to its new number, or `None` if
+/// it was removed.
+///
+/// References to codebooks that were never decoded are dropped whenever the Vorbis
+/// stream format allows doing so: decoding never reached these references, so
+/// dropping them does not change how audio packets are decoded. Residue classbooks
+/// are mandatory, so codebooks used as such are always kept.
+///
+/// The entry decode frequencies must have been recorded for every audio packet
+/// before calling this function, or it will remove codebooks that are still needed.
+pub(super) fn remove_unused_codebooks(codec_setup: &mut VorbisSetupData) -> Vec<Option<u8>> {
+	let codebook_decoded = codec_setup
+		.codebook_configurations
+		.iter()
+		.map(|codebook_configuration| codebook_configuration.codebook.decode_attempted())
+		.collect::<Vec<_>>();
+
+	drop_undecoded_codebook_references(codec_setup, &codebook_decoded);
+
+	// Referenced codebooks survive as themselves, and unreferenced ones are removed
+	let mut codebook_survivors = vec![None; codec_setup.codebook_configurations.len()];
+	for_each_codebook_reference(codec_setup, |book| {
+		codebook_survivors[*book as usize] = Some(*book as usize)
+	});
+
+	// The Vorbis stream format requires at least one codebook
+	if codebook_survivors.iter().all(Option::is_none) {
+		codebook_survivors[0] = Some(0);
+	}
+
+	let new_codebook_numbers = new_configuration_numbers(&codebook_survivors, "codebook");
+	for_each_codebook_reference(codec_setup, |book| {
+		// Every referenced codebook is kept, so this unwrap is safe
+		*book = new_codebook_numbers[*book as usize].unwrap();
+	});
+	retain_survivors(
+		&mut codec_setup.codebook_configurations,
+		&codebook_survivors
+	);
+
+	for (codebook_number, codebook_configuration) in
+		codec_setup.codebook_configurations.iter_mut().enumerate()
+	{
+		codebook_configuration.codebook.codebook_number = codebook_number as u16;
+	}
+
+	new_codebook_numbers
+}
+
+/// Drops the optional floor and residue references to codebooks that were never
+/// decoded, according to the specified decode status of every codebook.
+fn drop_undecoded_codebook_references(
+	codec_setup: &mut VorbisSetupData,
+	codebook_decoded: &[bool]
+) {
+	let drop_undecoded_book = |book: &mut Option<u8>| {
+		if book.is_some_and(|book| !codebook_decoded[book as usize]) {
+			*book = None;
+		}
+	};
+
+	for floor_configuration in &mut codec_setup.floor_configurations {
+		let class_configuration = floor_configuration
+			.class_subclasses
+			.iter_mut()
+			.zip(&mut floor_configuration.class_masterbooks)
+			.zip(&mut floor_configuration.subclass_books);
+
+		for ((class_subclasses, class_masterbook), subclass_books) in class_configuration {
+			// A class whose masterbook was never decoded was never decoded either, so it
+			// can become a class without subclasses, which do not have a masterbook
+			if class_masterbook.is_some_and(|book| !codebook_decoded[book as usize]) {
+				*class_subclasses = 0;
+				*class_masterbook = None;
+				*subclass_books = vec![None];
+			}
+
+			subclass_books.iter_mut().for_each(drop_undecoded_book);
+		}
+	}
+
+	for residue_configuration in &mut codec_setup.residue_configurations {
+		residue_configuration
+			.books
+			.iter_mut()
+			.flatten()
+			.for_each(drop_undecoded_book);
+	}
+}
+
+/// Calls the specified function with a mutable reference to every codebook number
+/// referenced by the floor and residue configurations.
+pub(super) fn for_each_codebook_reference(
+	codec_setup: &mut VorbisSetupData,
+	mut f: impl FnMut(&mut u8)
+) {
+	for floor_configuration in &mut codec_setup.floor_configurations {
+		floor_configuration
+			.class_masterbooks
+			.iter_mut()
+			.chain(floor_configuration.subclass_books.iter_mut().flatten())
+			.flatten()
+			.for_each(&mut f);
+	}
+
+	for residue_configuration in &mut codec_setup.residue_configurations {
+		f(&mut residue_configuration.classbook);
+
+		residue_configuration
+			.books
+			.iter_mut()
+			.flatten()
+			.flatten()
+			.for_each(&mut f);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vorbis::{
+		ResidueType,
+		optimizer::{
+			setup_header_parse::{Floor1Configuration, ResidueConfiguration},
+			setup_header_rewrite::test_fixtures::codebook_configuration
+		}
+	};
+
+	#[test]
+	fn removes_unreferenced_and_undecoded_codebooks() {
+		let mut setup_data = VorbisSetupData {
+			codebook_configurations: [false, true, true, false, true, false, true]
+				.into_iter()
+				.enumerate()
+				.map(|(codebook_number, decoded)| {
+					codebook_configuration(codebook_number as u16, &[1, 1], decoded as usize)
+				})
+				.collect(),
+			floor_configurations: vec![Floor1Configuration {
+				multiplier: 1,
+				range_bits: 1,
+				partition_class_list: vec![0, 1],
+				class_dimensions: vec![1, 1],
+				class_subclasses: vec![1, 0],
+				class_masterbooks: vec![Some(0), None],
+				subclass_books: vec![vec![Some(1), None], vec![Some(2)]],
+				x_list: vec![0, 1]
+			}],
+			residue_configurations: vec![ResidueConfiguration {
+				residue_type: ResidueType::Ordered,
+				begin: 0,
+				end: 0,
+				partition_size: 1,
+				classifications: 2,
+				classbook: 3,
+				books: vec![
+					[Some(4), Some(5), None, None, None, None, None, None],
+					[None; 8],
+				]
+			}],
+			..Default::default()
+		};
+
+		assert_eq!(
+			remove_unused_codebooks(&mut setup_data),
+			[None, None, Some(0), Some(1), Some(2), None, None]
+		);
+
+		let floor_configuration = &setup_data.floor_configurations[0];
+		assert_eq!(floor_configuration.class_subclasses, [0, 0]);
+		assert_eq!(floor_configuration.class_masterbooks, [None, None]);
+		assert_eq!(
+			floor_configuration.subclass_books,
+			[vec![None], vec![Some(0)]]
+		);
+
+		let residue_configuration = &setup_data.residue_configurations[0];
+		assert_eq!(residue_configuration.classbook, 1);
+		assert_eq!(
+			residue_configuration.books[0],
+			[Some(2), None, None, None, None, None, None, None]
+		);
+
+		assert_eq!(setup_data.codebook_configurations.len(), 3);
+		for (codebook_number, codebook_configuration) in
+			setup_data.codebook_configurations.iter().enumerate()
+		{
+			assert_eq!(
+				codebook_configuration.codebook.codebook_number,
+				codebook_number as u16
+			);
+		}
+	}
+}
@@ -83,6 +83,7 @@ impl CodebookConfiguration {
 			.entry_decode_frequencies_or_lengths
 			.get_mut()
 			.truncate(new_entry_count as usize);
+		self.codeword_lengths.truncate(new_entry_count as usize);
 
 		self.codebook_vector_multiplicands
 			.truncate(new_multiplicand_count);
@@ -125,7 +126,8 @@ mod tests {
 			codebook_vector_delta_value: 0.0,
 			codebook_vector_multiplicands: vec![0; multiplicand_count],
 			codebook_vector_value_bits: 1,
-			codebook_vector_sequence_flag: false
+			codebook_vector_sequence_flag: false,
+			codeword_lengths: vec![prefix_tree_height; entry_count]
 		}
 	}
 
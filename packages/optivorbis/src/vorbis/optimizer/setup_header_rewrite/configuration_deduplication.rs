@@ -0,0 +1,266 @@
+use super::{
+	codebook_garbage_collection::for_each_codebook_reference, new_configuration_numbers,
+	retain_survivors
+};
+use crate::vorbis::optimizer::{VorbisSetupData, setup_header_parse::CodebookConfiguration};
+
+/// The ways a codebook is referenced by the floor and residue configurations.
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+struct CodebookRoles {
+	floor_masterbook: bool,
+	floor_subclass_book: bool,
+	residue_classbook: bool,
+	residue_vq_book: bool
+}
+
+/// Merges the identical codebooks, floors, residues and mappings of the specified codec
+/// setup, keeping the first configuration of each group of identical configurations and
+/// rewriting the references to the rest so that they point to it. The returned `Vec`
+/// maps every original codebook number to its new number, or `None` if it was merged
+/// into another codebook.
+///
+/// Identical configurations decode audio packets in the same way, so merging them does
+/// not change how audio packets are decoded. Codebooks are only merged when they also
+/// play the same roles, as their entries then tend to be decoded with similar
+/// frequencies. The entry decode frequencies of merged codebooks are added to the ones
+/// of the codebook they are merged into, so that its optimal codewords are computed
+/// for the decodes of every merged codebook.
+///
+/// Unused codebooks and modes must have been removed before calling this function,
+/// and the codebooks must still be in _decode frequency recording mode_. Only the
+/// modes with a new mode number are updated to refer to the merged mappings.
+pub(super) fn deduplicate_configurations(
+	codec_setup: &mut VorbisSetupData,
+	new_mode_numbers: &[Option<u8>]
+) -> Vec<Option<u8>> {
+	let new_codebook_numbers = deduplicate_codebooks(codec_setup);
+
+	// Floors and residues, now that their codebook references can be compared
+	let floor_survivors = find_survivors(codec_setup.floor_configurations.len(), |a, b| {
+		codec_setup.floor_configurations[a] == codec_setup.floor_configurations[b]
+	});
+	let residue_survivors = find_survivors(codec_setup.residue_configurations.len(), |a, b| {
+		codec_setup.residue_configurations[a] == codec_setup.residue_configurations[b]
+	});
+
+	let new_floor_numbers = new_configuration_numbers(&floor_survivors, "floor");
+	let new_residue_numbers = new_configuration_numbers(&residue_survivors, "residue");
+	for floor_and_residue_mapping in codec_setup
+		.mapping_configurations
+		.iter_mut()
+		.flat_map(|mapping_configuration| &mut mapping_configuration.floor_and_residue_mappings)
+	{
+		// Every floor and residue is kept or merged into a kept one, so these unwraps are safe
+		floor_and_residue_mapping.floor_number =
+			new_floor_numbers[floor_and_residue_mapping.floor_number as usize].unwrap();
+		floor_and_residue_mapping.residue_number =
+			new_residue_numbers[floor_and_residue_mapping.residue_number as usize].unwrap();
+	}
+	retain_survivors(&mut codec_setup.floor_configurations, &floor_survivors);
+	retain_survivors(&mut codec_setup.residue_configurations, &residue_survivors);
+
+	// Mappings, now that their floor and residue references can be compared
+	let mapping_survivors = find_survivors(codec_setup.mapping_configurations.len(), |a, b| {
+		codec_setup.mapping_configurations[a] == codec_setup.mapping_configurations[b]
+	});
+
+	let new_mapping_numbers = new_configuration_numbers(&mapping_survivors, "mapping");
+	for (mode, _) in codec_setup
+		.modes
+		.iter_mut()
+		.zip(new_mode_numbers)
+		.filter(|(_, new_mode_number)| new_mode_number.is_some())
+	{
+		// Every mapping is kept or merged into a kept one, so this unwrap is safe
+		mode.mapping_number = new_mapping_numbers[mode.mapping_number as usize].unwrap();
+	}
+	retain_survivors(&mut codec_setup.mapping_configurations, &mapping_survivors);
+
+	new_codebook_numbers
+}
+
+/// Merges the identical codebooks of the specified codec setup that play the same
+/// roles, combining their entry decode frequencies. The returned `Vec` maps every
+/// codebook number to its new number, or `None` if it was merged into another codebook.
+fn deduplicate_codebooks(codec_setup: &mut VorbisSetupData) -> Vec<Option<u8>> {
+	let codebook_roles = codebook_roles(codec_setup);
+
+	let survivors = find_survivors(codec_setup.codebook_configurations.len(), |a, b| {
+		codebook_roles[a] == codebook_roles[b]
+			&& codebooks_are_identical(
+				&codec_setup.codebook_configurations[a],
+				&codec_setup.codebook_configurations[b]
+			)
+	});
+
+	for (codebook_number, survivor) in survivors.iter().enumerate() {
+		// Every codebook has a survivor
+		let survivor = survivor.unwrap();
+		if survivor != codebook_number {
+			// Survivors always precede the codebooks merged into them
+			let (codebook_configurations, merged_codebook_configurations) = codec_setup
+				.codebook_configurations
+				.split_at_mut(codebook_number);
+
+			codebook_configurations[survivor]
+				.codebook
+				.merge_decode_frequencies(&mut merged_codebook_configurations[0].codebook);
+		}
+	}
+
+	let new_codebook_numbers = new_configuration_numbers(&survivors, "codebook");
+	for_each_codebook_reference(codec_setup, |book| {
+		// Every codebook is kept or merged into a kept one, so this unwrap is safe
+		*book = new_codebook_numbers[*book as usize].unwrap();
+	});
+	retain_survivors(&mut codec_setup.codebook_configurations, &survivors);
+
+	for (codebook_number, codebook_configuration) in
+		codec_setup.codebook_configurations.iter_mut().enumerate()
+	{
+		codebook_configuration.codebook.codebook_number = codebook_number as u16;
+	}
+
+	survivors
+		.iter()
+		.zip(new_codebook_numbers)
+		.enumerate()
+		.map(|(codebook_number, (&survivor, new_codebook_number))| {
+			new_codebook_number.filter(|_| survivor == Some(codebook_number))
+		})
+		.collect()
+}
+
+/// Computes the roles every codebook of the specified codec setup plays.
+fn codebook_roles(codec_setup: &VorbisSetupData) -> Vec<CodebookRoles> {
+	let mut codebook_roles =
+		vec![CodebookRoles::default(); codec_setup.codebook_configurations.len()];
+
+	for floor_configuration in &codec_setup.floor_configurations {
+		for &book in floor_configuration.class_masterbooks.iter().flatten() {
+			codebook_roles[book as usize].floor_masterbook = true;
+		}
+
+		for &book in floor_configuration
+			.subclass_books
+			.iter()
+			.flatten()
+			.flatten()
+		{
+			codebook_roles[book as usize].floor_subclass_book = true;
+		}
+	}
+
+	for residue_configuration in &codec_setup.residue_configurations {
+		codebook_roles[residue_configuration.classbook as usize].residue_classbook = true;
+
+		for &book in residue_configuration.books.iter().flatten().flatten() {
+			codebook_roles[book as usize].residue_vq_book = true;
+		}
+	}
+
+	codebook_roles
+}
+
+/// Checks whether the specified codebook configurations decode the same entries from the
+/// same codewords, and map those entries to the same VQ lookup vectors.
+fn codebooks_are_identical(a: &CodebookConfiguration, b: &CodebookConfiguration) -> bool {
+	a.entry_count == b.entry_count
+		&& a.dimensions == b.dimensions
+		&& a.vector_lookup_type == b.vector_lookup_type
+		// Compare the bit patterns to not consider different zero signs equal
+		&& a.codebook_vector_minimum_value.to_bits() == b.codebook_vector_minimum_value.to_bits()
+		&& a.codebook_vector_delta_value.to_bits() == b.codebook_vector_delta_value.to_bits()
+		&& a.codebook_vector_multiplicands == b.codebook_vector_multiplicands
+		&& a.codebook_vector_value_bits == b.codebook_vector_value_bits
+		&& a.codebook_vector_sequence_flag == b.codebook_vector_sequence_flag
+		&& a.codeword_lengths == b.codeword_lengths
+}
+
+/// Finds, for each of the specified number of configurations, the number of the first
+/// configuration identical to it according to the specified function. Configurations
+/// with no identical configuration before them are their own survivors. No configuration
+/// is removed, so every configuration has a survivor.
+fn find_survivors(
+	configuration_count: usize,
+	identical: impl Fn(usize, usize) -> bool
+) -> Vec<Option<usize>> {
+	let mut survivors = Vec::with_capacity(configuration_count);
+
+	for configuration_number in 0..configuration_count {
+		let survivor = (0..configuration_number)
+			.find(|&candidate| {
+				survivors[candidate] == Some(candidate)
+					&& identical(candidate, configuration_number)
+			})
+			.unwrap_or(configuration_number);
+
+		survivors.push(Some(survivor));
+	}
+
+	survivors
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vorbis::optimizer::setup_header_rewrite::test_fixtures::{
+		codebook_configuration, floor_configuration, mapping_configuration, mode,
+		residue_configuration
+	};
+
+	#[test]
+	fn merges_identical_configurations() {
+		let mut setup_data = VorbisSetupData {
+			codebook_configurations: vec![
+				codebook_configuration(0, &[1, 1], 1),
+				codebook_configuration(1, &[1, 1], 2),
+				codebook_configuration(2, &[1, 2, 2], 0),
+				codebook_configuration(3, &[1, 1], 4),
+				codebook_configuration(4, &[1, 1], 8),
+			],
+			floor_configurations: vec![floor_configuration(0), floor_configuration(1)],
+			residue_configurations: vec![residue_configuration(2, 3), residue_configuration(2, 4)],
+			mapping_configurations: vec![
+				mapping_configuration(&[(0, 0)]),
+				mapping_configuration(&[(1, 1)]),
+				mapping_configuration(&[(1, 0)]),
+			],
+			modes: (0..3)
+				.map(|mapping_number| mode(mapping_number, true))
+				.collect()
+		};
+
+		assert_eq!(
+			deduplicate_configurations(&mut setup_data, &[Some(0), Some(1), Some(2)]),
+			[Some(0), None, Some(1), Some(2), None]
+		);
+
+		// Codebooks 1 and 4 were merged into the codebooks 0 and 3 that play the same role,
+		// but codebooks 0 and 3 are not merged, because they play different roles
+		assert_eq!(setup_data.codebook_configurations.len(), 3);
+		let first_entry_frequencies = setup_data
+			.codebook_configurations
+			.iter_mut()
+			.map(|codebook_configuration| {
+				codebook_configuration
+					.codebook
+					.entry_decode_frequencies_or_lengths
+					.get_mut()[0]
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(first_entry_frequencies, [3, 0, 12]);
+
+		assert_eq!(setup_data.floor_configurations.len(), 1);
+		assert_eq!(
+			setup_data.floor_configurations[0].subclass_books,
+			[vec![Some(0)]]
+		);
+		assert_eq!(setup_data.residue_configurations.len(), 1);
+		assert_eq!(setup_data.residue_configurations[0].classbook, 1);
+		assert_eq!(setup_data.residue_configurations[0].books[0][0], Some(2));
+
+		assert_eq!(setup_data.mapping_configurations.len(), 1);
+		assert!(setup_data.modes.iter().all(|mode| mode.mapping_number == 0));
+	}
+}
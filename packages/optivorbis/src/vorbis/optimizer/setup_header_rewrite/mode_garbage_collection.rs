@@ -0,0 +1,167 @@
+use super::{new_configuration_numbers, retain_survivors};
+use crate::vorbis::optimizer::VorbisSetupData;
+
+/// Removes the mappings, floors and residues of the specified codec setup that are not
+/// referenced by any mode used by the analyzed audio packets, renumbering the remaining
+/// configurations and their references to fill the gaps. The returned `Vec` maps every
+/// original mode number to its new number, or `None` if the mode is unused and must not
+/// be written to the setup header.
+///
+/// Unused modes are not removed from the codec setup, because the audio packets to
+/// optimize refer to modes by their original numbers. The mapping numbers of unused
+/// modes are meaningless after calling this function.
+///
+/// Every audio packet must have been analyzed before calling this function, or it will
+/// remove configurations that are still needed. Codebooks referenced only by the removed
+/// floors and residues are left for the codebook garbage collection to remove.
+pub(super) fn remove_unused_modes(codec_setup: &mut VorbisSetupData) -> Vec<Option<u8>> {
+	// Used configurations survive as themselves, and unused ones are removed
+	let mut mode_survivors = codec_setup
+		.modes
+		.iter()
+		.enumerate()
+		.map(|(mode_number, mode)| mode.used.get().then_some(mode_number))
+		.collect::<Vec<_>>();
+
+	// The Vorbis stream format requires at least one mode
+	if mode_survivors.iter().all(Option::is_none) {
+		mode_survivors[0] = Some(0);
+	}
+
+	let new_mode_numbers = new_configuration_numbers(&mode_survivors, "mode");
+
+	// Mappings referenced by used modes
+	let mut mapping_survivors = vec![None; codec_setup.mapping_configurations.len()];
+	for (mode, _) in codec_setup
+		.modes
+		.iter()
+		.zip(&new_mode_numbers)
+		.filter(|(_, new_mode_number)| new_mode_number.is_some())
+	{
+		mapping_survivors[mode.mapping_number as usize] = Some(mode.mapping_number as usize);
+	}
+
+	let new_mapping_numbers = new_configuration_numbers(&mapping_survivors, "mapping");
+	for (mode, _) in codec_setup
+		.modes
+		.iter_mut()
+		.zip(&new_mode_numbers)
+		.filter(|(_, new_mode_number)| new_mode_number.is_some())
+	{
+		// Every mapping referenced by a used mode is kept, so this unwrap is safe
+		mode.mapping_number = new_mapping_numbers[mode.mapping_number as usize].unwrap();
+	}
+	retain_survivors(&mut codec_setup.mapping_configurations, &mapping_survivors);
+
+	// Floors and residues referenced by the remaining mappings
+	let mut floor_survivors = vec![None; codec_setup.floor_configurations.len()];
+	let mut residue_survivors = vec![None; codec_setup.residue_configurations.len()];
+	for floor_and_residue_mapping in codec_setup
+		.mapping_configurations
+		.iter()
+		.flat_map(|mapping_configuration| &mapping_configuration.floor_and_residue_mappings)
+	{
+		let floor_number = floor_and_residue_mapping.floor_number as usize;
+		let residue_number = floor_and_residue_mapping.residue_number as usize;
+		floor_survivors[floor_number] = Some(floor_number);
+		residue_survivors[residue_number] = Some(residue_number);
+	}
+
+	let new_floor_numbers = new_configuration_numbers(&floor_survivors, "floor");
+	let new_residue_numbers = new_configuration_numbers(&residue_survivors, "residue");
+	for floor_and_residue_mapping in codec_setup
+		.mapping_configurations
+		.iter_mut()
+		.flat_map(|mapping_configuration| &mut mapping_configuration.floor_and_residue_mappings)
+	{
+		// Every referenced floor and residue is kept, so these unwraps are safe
+		floor_and_residue_mapping.floor_number =
+			new_floor_numbers[floor_and_residue_mapping.floor_number as usize].unwrap();
+		floor_and_residue_mapping.residue_number =
+			new_residue_numbers[floor_and_residue_mapping.residue_number as usize].unwrap();
+	}
+	retain_survivors(&mut codec_setup.floor_configurations, &floor_survivors);
+	retain_survivors(&mut codec_setup.residue_configurations, &residue_survivors);
+
+	new_mode_numbers
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vorbis::optimizer::setup_header_rewrite::test_fixtures::{
+		floor_configuration, mapping_configuration, mode, residue_configuration
+	};
+
+	#[test]
+	fn removes_unused_modes_and_configurations() {
+		let mut setup_data = VorbisSetupData {
+			floor_configurations: (0..3).map(floor_configuration).collect(),
+			residue_configurations: (0..3)
+				.map(|classbook| residue_configuration(classbook, 0))
+				.collect(),
+			mapping_configurations: vec![
+				mapping_configuration(&[(0, 0)]),
+				mapping_configuration(&[(1, 2)]),
+				mapping_configuration(&[(2, 2), (0, 2)]),
+			],
+			modes: vec![mode(0, false), mode(2, true), mode(2, false), mode(1, true)],
+			..Default::default()
+		};
+
+		assert_eq!(
+			remove_unused_modes(&mut setup_data),
+			[None, Some(0), None, Some(1)]
+		);
+
+		assert_eq!(setup_data.modes[1].mapping_number, 1);
+		assert_eq!(setup_data.modes[3].mapping_number, 0);
+
+		let floor_and_residue_numbers = setup_data
+			.mapping_configurations
+			.iter()
+			.map(|mapping_configuration| {
+				mapping_configuration
+					.floor_and_residue_mappings
+					.iter()
+					.map(|mapping| (mapping.floor_number, mapping.residue_number))
+					.collect::<Vec<_>>()
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			floor_and_residue_numbers,
+			[vec![(1, 0)], vec![(2, 0), (0, 0)]]
+		);
+
+		assert_eq!(setup_data.floor_configurations.len(), 3);
+		assert_eq!(setup_data.residue_configurations.len(), 1);
+		assert_eq!(setup_data.residue_configurations[0].classbook, 2);
+	}
+
+	#[test]
+	fn keeps_a_mode_when_none_is_used() {
+		let mut setup_data = VorbisSetupData {
+			floor_configurations: (0..2).map(floor_configuration).collect(),
+			residue_configurations: (0..2)
+				.map(|classbook| residue_configuration(classbook, 0))
+				.collect(),
+			mapping_configurations: vec![
+				mapping_configuration(&[(1, 1)]),
+				mapping_configuration(&[(0, 0)]),
+			],
+			modes: vec![mode(1, false), mode(0, false)],
+			..Default::default()
+		};
+
+		assert_eq!(remove_unused_modes(&mut setup_data), [Some(0), None]);
+		assert_eq!(setup_data.modes[0].mapping_number, 0);
+		assert_eq!(setup_data.mapping_configurations.len(), 1);
+		assert_eq!(setup_data.floor_configurations.len(), 1);
+		assert_eq!(
+			setup_data.floor_configurations[0].subclass_books,
+			[vec![Some(0)]]
+		);
+		assert_eq!(setup_data.residue_configurations.len(), 1);
+		assert_eq!(setup_data.residue_configurations[0].classbook, 0);
+	}
+}
@@ -0,0 +1,97 @@
+//! Contains functions that create the codec setup configurations used by the tests of the
+//! setup header rewriting passes.
+
+use std::cell::Cell;
+
+use vorbis_bitpack::BitpackSliceReader;
+
+use crate::vorbis::{
+	ResidueType, VectorLookupType,
+	codebook::VorbisCodebook,
+	optimizer::setup_header_parse::{
+		CodebookConfiguration, Floor1Configuration, FloorAndResidueMapping, MappingConfiguration,
+		Mode, ResidueConfiguration
+	}
+};
+
+/// Creates a [`CodebookConfiguration`] with the specified number and codeword lengths,
+/// decoding its first entry the specified number of times.
+pub(super) fn codebook_configuration(
+	codebook_number: u16,
+	codeword_lengths: &[u8],
+	first_entry_decodes: usize
+) -> CodebookConfiguration {
+	let codebook = VorbisCodebook::new(codebook_number, codeword_lengths).unwrap();
+	for _ in 0..first_entry_decodes {
+		codebook
+			.decode_entry_number(&mut BitpackSliceReader::new(&[0]))
+			.unwrap();
+	}
+
+	CodebookConfiguration {
+		codebook,
+		entry_count: codeword_lengths.len() as u32,
+		dimensions: 1,
+		vector_lookup_type: VectorLookupType::NoLookup,
+		codebook_vector_minimum_value: 0.0,
+		codebook_vector_delta_value: 0.0,
+		codebook_vector_multiplicands: vec![],
+		codebook_vector_value_bits: 1,
+		codebook_vector_sequence_flag: false,
+		codeword_lengths: codeword_lengths.to_vec()
+	}
+}
+
+/// Creates a [`Floor1Configuration`] with a single class that uses the specified
+/// subclass book.
+pub(super) fn floor_configuration(subclass_book: u8) -> Floor1Configuration {
+	Floor1Configuration {
+		multiplier: 1,
+		range_bits: 1,
+		partition_class_list: vec![0],
+		class_dimensions: vec![1],
+		class_subclasses: vec![0],
+		class_masterbooks: vec![None],
+		subclass_books: vec![vec![Some(subclass_book)]],
+		x_list: vec![0, 1]
+	}
+}
+
+/// Creates a [`ResidueConfiguration`] with the specified classbook and VQ book.
+pub(super) fn residue_configuration(classbook: u8, book: u8) -> ResidueConfiguration {
+	ResidueConfiguration {
+		residue_type: ResidueType::Ordered,
+		begin: 0,
+		end: 0,
+		partition_size: 1,
+		classifications: 1,
+		classbook,
+		books: vec![[Some(book), None, None, None, None, None, None, None]]
+	}
+}
+
+/// Creates a single channel [`MappingConfiguration`] with the specified submaps.
+pub(super) fn mapping_configuration(
+	floor_and_residue_numbers: &[(u8, u8)]
+) -> MappingConfiguration {
+	MappingConfiguration {
+		channel_mappings: vec![],
+		mapping_mux: vec![0],
+		floor_and_residue_mappings: floor_and_residue_numbers
+			.iter()
+			.map(|&(floor_number, residue_number)| FloorAndResidueMapping {
+				floor_number,
+				residue_number
+			})
+			.collect()
+	}
+}
+
+/// Creates a [`Mode`] that uses the specified mapping.
+pub(super) fn mode(mapping_number: u8, used: bool) -> Mode {
+	Mode {
+		big_block: false,
+		mapping_number,
+		used: Cell::new(used)
+	}
+}
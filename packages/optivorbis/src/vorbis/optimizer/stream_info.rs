@@ -0,0 +1,362 @@
+//! Contains the [`VorbisStreamInfo`] struct, which provides a read-only view of the
+//! information in the header packets of a Vorbis stream.
+
+use super::{
+	VorbisCommentFieldsAction, VorbisIdentificationHeaderData, VorbisOptimizer,
+	VorbisOptimizerError, VorbisOptimizerSettings, VorbisOptimizerState, VorbisPictureAction,
+	VorbisResourceLimits, VorbisVendorStringAction,
+	setup_header_parse::{
+		CodebookConfiguration, Floor1Configuration, MappingConfiguration, Mode,
+		ResidueConfiguration
+	}
+};
+use crate::vorbis::{ResidueType, VectorLookupType};
+
+/// The information contained in the three header packets of a Vorbis stream, as parsed by
+/// the same lenient parsers the [`VorbisOptimizer`] uses. This is useful for inspecting
+/// Vorbis streams without optimizing them, e.g., to build validators or statistics about
+/// how streams were encoded.
+///
+/// The setup header information mirrors the codec setup data structures described in the
+/// Vorbis I specification, § 4.2.4, with offset-encoded counts already decoded.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct VorbisStreamInfo {
+	/// The data of the identification header.
+	pub identification_header_data: VorbisIdentificationHeaderData,
+	/// The vendor string of the comment header, which usually identifies the encoder. It
+	/// is `None` if the comment header was truncated before it.
+	pub vendor_string: Option<Vec<u8>>,
+	/// The user comments of the comment header, which are usually `KEY=value` pairs. Like
+	/// the vendor string, they are not guaranteed to be valid UTF-8.
+	pub user_comments: Vec<Vec<u8>>,
+	/// The codebook configurations of the setup header.
+	pub codebooks: Vec<VorbisCodebookInfo>,
+	/// The floor configurations of the setup header. Only floor type 1 is supported.
+	pub floors: Vec<VorbisFloorInfo>,
+	/// The residue configurations of the setup header.
+	pub residues: Vec<VorbisResidueInfo>,
+	/// The channel mapping configurations of the setup header.
+	pub mappings: Vec<VorbisMappingInfo>,
+	/// The audio packet modes of the setup header.
+	pub modes: Vec<VorbisModeInfo>
+}
+
+/// A codebook configuration in a Vorbis setup header, defined in the Vorbis I
+/// specification, § 3.2.1.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct VorbisCodebookInfo {
+	/// The number of values of each vector this codebook yields in a vector quantization
+	/// context.
+	pub dimensions: u16,
+	/// The number of entries of this codebook.
+	pub entry_count: u32,
+	/// The codeword length of each entry, in bits. Unused entries of sparse codebooks
+	/// have a length of zero.
+	pub codeword_lengths: Vec<u8>,
+	/// The vector lookup type of this codebook.
+	pub lookup_type: VectorLookupType,
+	/// The minimum value of the vector components, called `codebook_minimum_value` in
+	/// the specification.
+	pub minimum_value: f64,
+	/// The delta between vector component values, called `codebook_delta_value` in the
+	/// specification.
+	pub delta_value: f64,
+	/// The width, in bits, of each multiplicand, called `codebook_value_bits` in the
+	/// specification.
+	pub value_bits: u8,
+	/// Whether vector components are accumulated, called `codebook_sequence_p` in the
+	/// specification.
+	pub sequence_flag: bool,
+	/// The vector component multiplicands, called `codebook_multiplicands` in the
+	/// specification. This is empty if the codebook has no vector lookup.
+	pub multiplicands: Vec<u16>
+}
+
+/// A type 1 floor configuration in a Vorbis setup header, defined in the Vorbis I
+/// specification, § 7.2.2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VorbisFloorInfo {
+	/// The floor curve amplitude multiplier, in the [1, 4] range.
+	pub multiplier: u8,
+	/// The width, in bits, of the floor curve X coordinates.
+	pub range_bits: u8,
+	/// The class of each partition.
+	pub partition_class_list: Vec<u8>,
+	/// The dimensions of each class.
+	pub class_dimensions: Vec<u8>,
+	/// The number of subclasses of each class, as a power of two exponent.
+	pub class_subclasses: Vec<u8>,
+	/// The master codebook number of each class, if it has one.
+	pub class_masterbooks: Vec<Option<u8>>,
+	/// The codebook number of each subclass of each class, if it has one.
+	pub subclass_books: Vec<Vec<Option<u8>>>,
+	/// The X coordinates of the floor curve points, including the two implicit ones.
+	pub x_list: Vec<u16>
+}
+
+/// A residue configuration in a Vorbis setup header, defined in the Vorbis I
+/// specification, § 8.6.1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VorbisResidueInfo {
+	/// The residue type.
+	pub residue_type: ResidueType,
+	/// The position of the first coded residue vector value.
+	pub begin: u32,
+	/// The position after the last coded residue vector value.
+	pub end: u32,
+	/// The number of residue vector values of each partition.
+	pub partition_size: u32,
+	/// The number of partition classifications.
+	pub classifications: u8,
+	/// The number of the codebook used to decode partition classifications.
+	pub classbook: u8,
+	/// The codebook number used in each of the eight passes for each classification, if
+	/// any.
+	pub books: Vec<[Option<u8>; 8]>
+}
+
+/// A channel mapping configuration in a Vorbis setup header, defined in the Vorbis I
+/// specification, § 4.2.4.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VorbisMappingInfo {
+	/// The magnitude and angle channels of each channel coupling step, respectively.
+	pub coupling_steps: Vec<(u8, u8)>,
+	/// The submap number of each audio channel.
+	pub channel_submaps: Vec<u8>,
+	/// The floor and residue configuration numbers of each submap, respectively.
+	pub submaps: Vec<(u8, u8)>
+}
+
+/// An audio packet mode in a Vorbis setup header, defined in the Vorbis I specification,
+/// § 4.2.4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VorbisModeInfo {
+	/// Whether audio packets with this mode use the long block size. Called
+	/// `vorbis_mode_blockflag` in the specification.
+	pub long_block: bool,
+	/// The number of the channel mapping configuration used by audio packets with this
+	/// mode.
+	pub mapping_number: u8
+}
+
+impl VorbisStreamInfo {
+	/// Parses the specified identification, comment and setup header packets of a Vorbis
+	/// stream, returning the information they contain. An error will be returned if the
+	/// headers are not valid. Like the [`VorbisOptimizer`] does, some errors that are not
+	/// fatal for decoding, such as a truncated comment header, are tolerated.
+	///
+	/// The specified resource limits are enforced while parsing the headers, as described
+	/// for the [`resource_limits`](VorbisOptimizerSettings::resource_limits) optimizer
+	/// setting. Applications dealing with untrusted headers should set them.
+	pub fn from_headers<B: AsRef<[u8]>>(
+		identification_header: B,
+		comment_header: B,
+		setup_header: B,
+		resource_limits: VorbisResourceLimits
+	) -> Result<Self, VorbisOptimizerError> {
+		let optimizer_settings = VorbisOptimizerSettings {
+			vendor_string_action: VorbisVendorStringAction::Copy,
+			comment_fields_action: VorbisCommentFieldsAction::Copy,
+			picture_action: VorbisPictureAction::Copy,
+			resource_limits,
+			..Default::default()
+		};
+
+		let mut optimizer = VorbisOptimizer::new(&optimizer_settings, identification_header)?;
+		optimizer.analyze_packet(comment_header)?;
+		optimizer.analyze_packet(setup_header)?;
+
+		let VorbisOptimizerState::AudioPacketAnalyze(audio_packet_analyzer) = optimizer.state
+		else {
+			// Analyzing a setup header always transitions to this state
+			unreachable!()
+		};
+		let comment_data = audio_packet_analyzer.comment_data;
+		let codec_setup = audio_packet_analyzer.codec_setup;
+
+		Ok(Self {
+			identification_header_data: optimizer.identification_data,
+			vendor_string: comment_data.vendor_string,
+			user_comments: comment_data.user_comments,
+			codebooks: codec_setup
+				.codebook_configurations
+				.into_iter()
+				.map(VorbisCodebookInfo::from)
+				.collect(),
+			floors: codec_setup
+				.floor_configurations
+				.into_iter()
+				.map(VorbisFloorInfo::from)
+				.collect(),
+			residues: codec_setup
+				.residue_configurations
+				.into_iter()
+				.map(VorbisResidueInfo::from)
+				.collect(),
+			mappings: codec_setup
+				.mapping_configurations
+				.into_iter()
+				.map(VorbisMappingInfo::from)
+				.collect(),
+			modes: codec_setup
+				.modes
+				.into_iter()
+				.map(VorbisModeInfo::from)
+				.collect()
+		})
+	}
+}
+
+impl From<CodebookConfiguration> for VorbisCodebookInfo {
+	fn from(codebook_configuration: CodebookConfiguration) -> Self {
+		Self {
+			dimensions: codebook_configuration.dimensions,
+			entry_count: codebook_configuration.entry_count,
+			codeword_lengths: codebook_configuration.codeword_lengths,
+			lookup_type: codebook_configuration.vector_lookup_type,
+			minimum_value: codebook_configuration.codebook_vector_minimum_value,
+			delta_value: codebook_configuration.codebook_vector_delta_value,
+			value_bits: codebook_configuration.codebook_vector_value_bits,
+			sequence_flag: codebook_configuration.codebook_vector_sequence_flag,
+			multiplicands: codebook_configuration.codebook_vector_multiplicands
+		}
+	}
+}
+
+impl From<Floor1Configuration> for VorbisFloorInfo {
+	fn from(floor_configuration: Floor1Configuration) -> Self {
+		Self {
+			multiplier: floor_configuration.multiplier,
+			range_bits: floor_configuration.range_bits,
+			partition_class_list: floor_configuration.partition_class_list,
+			class_dimensions: floor_configuration.class_dimensions,
+			class_subclasses: floor_configuration.class_subclasses,
+			class_masterbooks: floor_configuration.class_masterbooks,
+			subclass_books: floor_configuration.subclass_books,
+			x_list: floor_configuration.x_list
+		}
+	}
+}
+
+impl From<ResidueConfiguration> for VorbisResidueInfo {
+	fn from(residue_configuration: ResidueConfiguration) -> Self {
+		Self {
+			residue_type: residue_configuration.residue_type,
+			begin: residue_configuration.begin,
+			end: residue_configuration.end,
+			partition_size: residue_configuration.partition_size,
+			classifications: residue_configuration.classifications,
+			classbook: residue_configuration.classbook,
+			books: residue_configuration.books
+		}
+	}
+}
+
+impl From<MappingConfiguration> for VorbisMappingInfo {
+	fn from(mapping_configuration: MappingConfiguration) -> Self {
+		Self {
+			coupling_steps: mapping_configuration
+				.channel_mappings
+				.into_iter()
+				.map(|channel_mapping| {
+					(
+						channel_mapping.magnitude_channel,
+						channel_mapping.angle_channel
+					)
+				})
+				.collect(),
+			channel_submaps: mapping_configuration.mapping_mux,
+			submaps: mapping_configuration
+				.floor_and_residue_mappings
+				.into_iter()
+				.map(|floor_and_residue_mapping| {
+					(
+						floor_and_residue_mapping.floor_number,
+						floor_and_residue_mapping.residue_number
+					)
+				})
+				.collect()
+		}
+	}
+}
+
+impl From<Mode> for VorbisModeInfo {
+	fn from(mode: Mode) -> Self {
+		Self {
+			long_block: mode.big_block,
+			mapping_number: mode.mapping_number
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use ogg::PacketReader;
+
+	use super::*;
+	use crate::vorbis::optimizer::VorbisResourceLimit;
+
+	#[test_log::test]
+	fn from_headers_works() {
+		let mut packet_reader = PacketReader::new(std::io::Cursor::new(include_bytes!(
+			"../../../resources/test/8khz_500ms_mono_400hz_sine_wave.ogg"
+		)));
+		let mut read_packet = || packet_reader.read_packet_expected().unwrap().data;
+		let (identification_header, comment_header, setup_header) =
+			(read_packet(), read_packet(), read_packet());
+
+		let stream_info = VorbisStreamInfo::from_headers(
+			&identification_header,
+			&comment_header,
+			&setup_header,
+			Default::default()
+		)
+		.expect("Unexpected header parsing error");
+
+		assert_eq!(stream_info.identification_header_data.channels.get(), 1);
+		assert!(stream_info.vendor_string.is_some());
+		assert!(!stream_info.codebooks.is_empty());
+		assert!(
+			stream_info.codebooks.iter().all(|codebook| {
+				codebook.codeword_lengths.len() == codebook.entry_count as usize
+			})
+		);
+		assert!(!stream_info.floors.is_empty());
+		assert!(!stream_info.residues.is_empty());
+		assert!(!stream_info.modes.is_empty());
+		assert!(
+			stream_info
+				.modes
+				.iter()
+				.all(|mode| { (mode.mapping_number as usize) < stream_info.mappings.len() })
+		);
+		assert!(stream_info.mappings.iter().all(|mapping| {
+			mapping.channel_submaps.len() == 1
+				&& mapping
+					.channel_submaps
+					.iter()
+					.all(|&submap| (submap as usize) < mapping.submaps.len())
+		}));
+
+		assert!(matches!(
+			VorbisStreamInfo::from_headers(
+				&identification_header,
+				&comment_header,
+				&setup_header,
+				VorbisResourceLimits {
+					max_setup_header_size: Some(64),
+					..Default::default()
+				}
+			),
+			Err(VorbisOptimizerError::ResourceLimitExceeded(
+				VorbisResourceLimit::SetupHeaderSize
+			))
+		));
+	}
+}
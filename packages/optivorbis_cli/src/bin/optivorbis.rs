@@ -1,21 +1,34 @@
 use std::{
 	borrow::Cow,
 	env,
+	error::Error,
+	ffi::OsString,
 	fmt::Display,
-	fs::File,
-	io::{BufReader, BufWriter, Read, Seek, Write, stdout},
-	path::Path,
-	process::exit,
+	fs::{self, File, FileTimes},
+	io::{self, BufReader, BufWriter, Read, Seek, Write, stdin, stdout},
+	num::NonZeroUsize,
+	path::{Path, PathBuf},
+	process::{self, exit},
 	str::FromStr,
-	time::Instant
+	sync::atomic::{AtomicUsize, Ordering},
+	thread,
+	time::{Duration, Instant}
 };
 
 use getopts::{Matches, Options, ParsingStyle};
 use log::info;
 use optivorbis::{
-	OPTIVORBIS_VERSION_TAG, OggToOgg, Remuxer, VorbisCommentFieldsAction, VorbisOptimizerSettings,
-	VorbisVendorStringAction, remuxer::ogg_to_ogg
+	MatroskaToMatroska, OPTIVORBIS_VERSION_TAG, OggToOgg, OggToWebm, RawToRaw, Remuxer,
+	VorbisCommentFieldsAction, VorbisCommentOperation, VorbisOptimizerError,
+	VorbisOptimizerSettings, VorbisPictureAction, VorbisVendorStringAction,
+	remuxer::{
+		matroska_to_matroska,
+		ogg_to_ogg::{self, OggVorbisStreamPassthroughMangler, RemuxReport},
+		ogg_to_webm, raw_to_raw,
+		spooled_source::{SpoolSettings, SpooledSource}
+	}
 };
+use serde_json::{Value, json};
 use stderrlog::ColorChoice;
 
 fn main() {
@@ -52,12 +65,59 @@ fn run() -> Result<(), Cow<'static, str>> {
 			"verbose",
 			"Increases the verbosity of the messages. Can be repeated several times."
 		)
+		.optflag(
+			"b",
+			"batch",
+			"Enables batch mode, where every file argument but the last is an input file or directory, \
+			and the last one is the output directory. Input directories are searched recursively for \
+			Ogg and Matroska files, and their directory structure is replicated in the output directory. \
+			Processing continues after errors, which are reported at the end."
+		)
+		.optopt(
+			"j",
+			"jobs",
+			"In batch and in-place modes, sets the number of files that will be processed at the same time. \
+			The default value is the number of available CPU cores.",
+			"COUNT"
+		)
+		.optflag(
+			"i",
+			"in_place",
+			"Optimizes the input files in place, replacing them only if their optimized versions are \
+			smaller. Every file argument is an input file or directory, as in batch mode. Optimized \
+			files are written to temporary files in the same directory first, which are then atomically \
+			renamed over the original files, preserving their permissions and modification times."
+		)
+		.optflag(
+			"f",
+			"force",
+			"In in-place mode, replaces the input files even if their optimized versions are not smaller."
+		)
+		.optflag(
+			"n",
+			"dry_run",
+			"Analyzes the input files without writing any output, reporting the sizes of their headers, \
+			audio data and container overhead before and after optimization. Every file argument is an \
+			input file or directory, as in batch mode. Only the ogg2ogg remuxer supports this mode."
+		)
+		.optopt(
+			"",
+			"report",
+			"Writes a JSON report about every processed input file to the specified file, or to standard \
+			output if it is -. The report contains the outcome of processing each file, with machine-readable \
+			error codes on failure. With the ogg2ogg remuxer, it also contains the identification header \
+			data of each Vorbis stream, the number of dummy and padded audio packets removed, the codebook \
+			entry counts before and after truncating unused entries, the number of unused and duplicate \
+			codebooks removed, the cost of limiting codeword lengths, the size of the embedded pictures, \
+			and the size of the headers, audio data and container overhead before and after optimization.",
+			"FILE"
+		)
 		.optopt(
 			"r",
 			"remuxer",
 			"The remuxer to use for managing the encapsulation of Vorbis streams in a container. \
-			If not specified, it will be automatically deduced from the extension of the output file.\n\
-			Available remuxers: ogg2ogg",
+			If not specified, it will be automatically deduced from the extensions of the input and output files.\n\
+			Available remuxers: ogg2ogg, mka2mka, ogg2webm, raw2raw",
 			"REMUXER"
 		)
 		.optopt(
@@ -70,10 +130,83 @@ fn run() -> Result<(), Cow<'static, str>> {
 		.optopt(
 			"",
 			"comment_fields_action",
-			"Changes how the user comment fields contained in the Vorbis comment header will be dealt with.\n\
-			Available actions: copy, delete",
+			"Changes how the user comment fields contained in the Vorbis comment header will be dealt with. \
+			normalize fixes invalid field names, converts them to uppercase, transcodes values that are not \
+			valid UTF-8 from Windows-1252 and removes duplicate fields.\n\
+			Available actions: copy, delete, normalize",
 			"COMMENT-FIELDS-ACTION"
 		)
+		.optopt(
+			"",
+			"picture_action",
+			"Changes how the pictures embedded in the Vorbis comment header as METADATA_BLOCK_PICTURE \
+			user comment fields, such as cover art, will be dealt with. keepFrontCover keeps only the \
+			front cover.\n\
+			Available actions: copy, delete, keepFrontCover",
+			"PICTURE-ACTION"
+		)
+		.optopt(
+			"",
+			"extract_pictures",
+			"Writes the pictures embedded in the Vorbis comment header of every input file to the \
+			specified directory, whether they are kept or not. Picture files are named after their \
+			input file, Vorbis stream and picture numbers. Only the ogg2ogg remuxer supports this option.",
+			"DIRECTORY"
+		)
+		.optmulti(
+			"",
+			"keep_tag",
+			"Removes every user comment field whose name does not match any of the patterns given by \
+			this option. Patterns are case-insensitive and may contain * and ? wildcards. This filter \
+			is applied before any other tag option. Can be repeated several times.",
+			"PATTERN"
+		)
+		.optmulti(
+			"",
+			"remove_tag",
+			"Removes every user comment field whose name matches the specified case-insensitive pattern, \
+			which may contain * and ? wildcards. Can be repeated several times.",
+			"PATTERN"
+		)
+		.optmulti(
+			"",
+			"rename_tag",
+			"Renames every user comment field with the specified name, preserving their values. Can be \
+			repeated several times.",
+			"NAME=NEW-NAME"
+		)
+		.optmulti(
+			"",
+			"tag",
+			"Sets a user comment field to a value, removing any other fields with the same name. Can be \
+			repeated several times. Except for --keep_tag, tag options are applied in the order they \
+			are specified.",
+			"NAME=VALUE"
+		)
+		.optmulti(
+			"",
+			"append_tag",
+			"Adds a user comment field with the specified value, keeping any other fields with the same \
+			name. Can be repeated several times.",
+			"NAME=VALUE"
+		)
+		.optopt(
+			"",
+			"max_codeword_length",
+			"Limits the length of the optimized codewords of every codebook to the specified number of \
+			bits, for decoders that do not support longer codewords. This makes audio packets slightly \
+			bigger. Codebooks with too many used entries to fit in this length get the shortest maximum \
+			length possible instead.",
+			"BITS"
+		)
+		.optopt(
+			"",
+			"spool_memory_limit",
+			"When reading the input file from standard input, sets the maximum number of bytes of it \
+			that will be kept in memory. Any data past this limit will be stored in a temporary file, \
+			which is deleted once processing ends. The default value is 67108864 (64 MiB).",
+			"BYTES"
+		)
 		.optmulti(
 			"",
 			"remuxer_option",
@@ -105,7 +238,52 @@ fn run() -> Result<(), Cow<'static, str>> {
 			Vorbis stream that was corrupted in transit or improperly modified and is thus likely to be \
 			broken beyond repair. However, for repairing Ogg Vorbis streams that are otherwise mostly \
 			okay, fuzzing, or for advanced data recovery use cases, it can be a good idea to let OptiVorbis \
-			ignore such errors and recompute such checksums. The default value is true.",
+			ignore such errors and recompute such checksums. The default value is true.\n\
+			- preserve_stream_multiplexing=BOOLEAN\n\
+			Sets whether Ogg files with grouped logical bitstreams, such as several audio tracks or Theora \
+			video, will be remuxed keeping their original page interleaving, copying non-Vorbis streams \
+			through as-is. Otherwise, non-Vorbis streams are dropped, and grouped Vorbis streams are an \
+			error. The default value is false.\n\
+			- preserve_non_vorbis_streams=BOOLEAN\n\
+			Sets whether non-Vorbis streams, such as Ogg Skeleton metadata, Kate subtitles or Theora \
+			video, will be copied through as-is instead of being dropped, even without preserving the \
+			stream multiplexing. Ogg Skeleton streams are updated to refer to the new Vorbis stream \
+			serials and page offsets. The default value is false.\n\
+			- remux_non_vorbis_audio_streams=BOOLEAN\n\
+			Sets whether Opus, Speex and FLAC streams will be remuxed without changing their audio data, \
+			putting their packets in new pages and recomputing their granule positions. Otherwise, these \
+			streams are dealt with like any other non-Vorbis stream. The default value is false.\n\
+			- max_page_payload_size=INTEGER\n\
+			The maximum size, in bytes, of the Vorbis audio packet data in a single Ogg page. Smaller pages \
+			make starting and seeking playback faster when streaming, at the cost of some container \
+			overhead. By default, pages are as large as possible.\n\
+			- max_page_duration=INTEGER\n\
+			The maximum duration, in samples, of the Vorbis packets in a single Ogg page. Shorter \
+			pages make seeking more precise. By default, page duration is not limited.\n\
+			- single_packet_audio_page_count=INTEGER\n\
+			The number of audio packets at the beginning of Vorbis streams that will be put in a page \
+			of their own, which makes starting playback faster when streaming. The default value is 0.\n\
+			-----------------------\n\
+			mka2mka remuxer options\n\
+			-----------------------\n\
+			- error_on_no_vorbis_tracks=BOOLEAN\n\
+			Sets whether not finding any Vorbis track within the Matroska container will be considered an \
+			error condition. The default value is true, which means that not finding any Vorbis track will be \
+			considered an error. This usually is the most desirable behavior.\n\
+			------------------------\n\
+			ogg2webm remuxer options\n\
+			------------------------\n\
+			- verify_ogg_page_checksums=BOOLEAN\n\
+			Same as the ogg2ogg remuxer option of the same name. The default value is true.\n\
+			-----------------------\n\
+			raw2raw remuxer options\n\
+			-----------------------\n\
+			- audio_packet_length_width=INTEGER\n\
+			The width in bits of the length that prefixes audio packets in the raw packet stream. Either \
+			16 or 32. The default value is 32.\n\
+			- granule_side_table=BOOLEAN\n\
+			Sets whether the raw packet stream contains an audio packet count before the audio packets and \
+			a table with their granule positions after them. The default value is false.",
 			"OPTION=VALUE"
 		)
 		.parsing_style(ParsingStyle::StopAtFirstFree);
@@ -117,7 +295,10 @@ fn run() -> Result<(), Cow<'static, str>> {
 				println!();
 				println!("Usage:");
 				print!(
-					"    {} [OPTION]... <input file> <output file or ->",
+					"    {0} [OPTION]... <input file or -> <output file or ->\n    \
+					{0} [OPTION]... -b <input file or directory>... <output directory>\n    \
+					{0} [OPTION]... -i <input file or directory>...\n    \
+					{0} [OPTION]... -n <input file or directory>...",
 					env!("CARGO_BIN_NAME")
 				);
 				println!("{}", options.usage(""));
@@ -126,11 +307,19 @@ fn run() -> Result<(), Cow<'static, str>> {
 			} else {
 				let quiet_mode = matches.opt_present("q");
 
-				if !quiet_mode {
+				// Do not mix the header with a report written to standard output
+				if !quiet_mode && matches.opt_str("report").as_deref() != Some("-") {
 					print_header();
 					println!();
 				}
 
+				if matches.opt_present("b") || matches.opt_present("i") || matches.opt_present("n")
+				{
+					init_logging(&matches, quiet_mode);
+
+					return run_batch(&matches);
+				}
+
 				if matches.free.len() != 2 {
 					Err(format!(
 						"Too many or few file arguments specified. Run {} -h to see command line argument help",
@@ -139,12 +328,37 @@ fn run() -> Result<(), Cow<'static, str>> {
 				}
 
 				let input_file_name = &*matches.free[0];
-				let input_file = BufReader::new(
-					File::open(input_file_name)
-						.map_err(|err| format!("Could not open input file: {err}"))?
-				);
+				let (mut input_stdin, mut input_file);
+				let input_file: &mut dyn ReadSeek = match input_file_name {
+					"-" => {
+						let mut spool_settings = SpoolSettings::default();
+						if let Some(spool_memory_limit) =
+							matches.opt_get("spool_memory_limit").map_err(|err| {
+								format!("Invalid value for spool_memory_limit option: {err}")
+							})? {
+							spool_settings.memory_limit = spool_memory_limit;
+						}
+
+						input_stdin = SpooledSource::new(stdin().lock(), spool_settings);
+
+						&mut input_stdin
+					}
+					file_path => {
+						input_file = BufReader::new(
+							File::open(file_path)
+								.map_err(|err| format!("Could not open input file: {err}"))?
+						);
+
+						&mut input_file
+					}
+				};
 
 				let output_file_name = &*matches.free[1];
+				let report_path = matches.opt_str("report");
+				if output_file_name == "-" && report_path.as_deref() == Some("-") {
+					Err("The output file and the report can't be both written to standard output")?;
+				}
+
 				let (mut output_stdout, mut output_file);
 				let (output_file, guessed_remuxer): (&mut dyn Write, Option<AvailableRemuxer>) =
 					match output_file_name {
@@ -159,15 +373,7 @@ fn run() -> Result<(), Cow<'static, str>> {
 									.map_err(|err| format!("Could not open output file: {err}"))?
 							);
 
-							let guessed_remuxer = match Path::new(file_path)
-								.extension()
-								.and_then(|extension| extension.to_str())
-							{
-								Some("ogg" | "oga" | "ogx") => Some(AvailableRemuxer::OggToOgg),
-								_ => None
-							};
-
-							(&mut output_file, guessed_remuxer)
+							(&mut output_file, guess_remuxer(input_file_name, file_path))
 						}
 					};
 
@@ -177,14 +383,44 @@ fn run() -> Result<(), Cow<'static, str>> {
 
 				init_logging(&matches, quiet_mode);
 
-				remux(
+				let remux_result = remux(
 					&matches,
 					input_file,
 					input_file_name,
 					output_file,
 					output_file_name,
 					chosen_remuxer
-				)?;
+				);
+
+				if let Some(report_path) = report_path {
+					let file_sizes = remux_result.as_ref().ok().and_then(|(_, remux_report)| {
+						remux_report.as_ref().map(|remux_report| {
+							(
+								remux_report.original.total(),
+								remux_report.optimized.total()
+							)
+						})
+					});
+
+					write_report(
+						&report_path,
+						vec![file_report(
+							input_file_name,
+							Some(output_file_name),
+							file_sizes,
+							remux_result
+								.as_ref()
+								.map(|(_, remux_report)| remux_report.as_ref())
+						)]
+					)?;
+				}
+
+				let (duration, _) = remux_result?;
+
+				info!(
+					"Optimization and repairs completed in {:.3} s. Have a nice day!",
+					duration.as_secs_f64()
+				);
 			}
 
 			Ok(())
@@ -197,22 +433,665 @@ fn run() -> Result<(), Cow<'static, str>> {
 	}
 }
 
-fn remux<F: Read + Seek>(
-	option_matches: &Matches,
-	input_file: F,
-	input_file_name: &str,
-	output_file: &mut dyn Write,
-	output_file_name: &str,
-	chosen_remuxer: AvailableRemuxer
-) -> Result<(), Cow<'static, str>> {
-	macro_rules! set_remuxer_option_value {
-		( $remuxer_settings:expr, $option:ident ) => {
-			if let Some($option) = get_remuxer_option_value(option_matches, stringify!($option))? {
-				$remuxer_settings.$option = $option;
+/// Optimizes or analyzes every file specified in batch, in-place or dry run mode, using
+/// several worker threads.
+fn run_batch(matches: &Matches) -> Result<(), Cow<'static, str>> {
+	let (output_directory, input_paths) = match matches.free.split_last() {
+		Some(_) if matches.opt_present("i") || matches.opt_present("n") => (None, &*matches.free),
+		Some((output_directory, input_paths)) if !input_paths.is_empty() => {
+			(Some(Path::new(output_directory)), input_paths)
+		}
+		_ => Err(format!(
+			"Too few file arguments specified. Run {} -h to see command line argument help",
+			env!("CARGO_BIN_NAME")
+		))?
+	};
+
+	if input_paths.iter().any(|input_path| input_path == "-")
+		|| output_directory == Some(Path::new("-"))
+	{
+		Err("Standard input and output can't be used in batch, in-place or dry run mode")?;
+	}
+
+	let mut batch_files = vec![];
+	for input_path in input_paths.iter().map(Path::new) {
+		if input_path.is_dir() {
+			collect_batch_files(input_path, output_directory, &mut batch_files).map_err(|err| {
+				format!(
+					"Could not list the files of input directory {}: {err}",
+					input_path.display()
+				)
+			})?;
+		} else {
+			let output_path = output_directory
+				.map(|output_directory| {
+					input_path
+						.file_name()
+						.map(|file_name| output_directory.join(file_name))
+						.ok_or_else(|| format!("Invalid input file: {}", input_path.display()))
+				})
+				.transpose()?;
+
+			batch_files.push(BatchFile {
+				input_path: input_path.to_path_buf(),
+				output_path
+			});
+		}
+	}
+
+	let job_count = matches
+		.opt_get("jobs")
+		.map_err(|err| format!("Invalid value for jobs option: {err}"))?
+		.unwrap_or_else(|| thread::available_parallelism().unwrap_or(NonZeroUsize::MIN));
+
+	info!(
+		"Processing {} files with {job_count} worker threads...",
+		batch_files.len()
+	);
+
+	let report_path = matches.opt_str("report");
+	let next_batch_file_index = AtomicUsize::new(0);
+	let (file_sizes, mut file_reports) = thread::scope(|scope| {
+		let workers = (0..job_count.get().min(batch_files.len()))
+			.map(|_| {
+				scope.spawn(|| {
+					let mut file_sizes = vec![];
+					let mut file_reports = vec![];
+
+					loop {
+						let batch_file_index =
+							next_batch_file_index.fetch_add(1, Ordering::Relaxed);
+						let Some(batch_file) = batch_files.get(batch_file_index) else {
+							break;
+						};
+
+						let batch_file_result = optimize_batch_file(matches, batch_file);
+
+						if report_path.is_some() {
+							file_reports.push((
+								batch_file_index,
+								file_report(
+									&batch_file.input_path.to_string_lossy(),
+									batch_file
+										.output_path
+										.as_deref()
+										.map(Path::to_string_lossy)
+										.as_deref(),
+									batch_file_result
+										.as_ref()
+										.ok()
+										.map(|outcome| (outcome.input_size, outcome.output_size)),
+									batch_file_result
+										.as_ref()
+										.map(|outcome| outcome.remux_report.as_ref())
+								)
+							));
+						}
+
+						match batch_file_result {
+							Ok(BatchFileOutcome {
+								input_size,
+								output_size,
+								duration,
+								remux_report: Some(remux_report),
+								..
+							}) if matches.opt_present("n") => {
+								info!(
+									"Analyzed {} in {:.3} s: {}\n{}",
+									batch_file.input_path.display(),
+									duration.as_secs_f64(),
+									size_savings(input_size, output_size),
+									dry_run_report_table(&remux_report)
+								);
+
+								file_sizes.push((input_size, output_size));
+							}
+							Ok(outcome) if outcome.original_kept => {
+								info!(
+									"Kept {} as is, because its optimized version is not smaller: {}",
+									batch_file.input_path.display(),
+									size_savings(outcome.input_size, outcome.output_size)
+								);
+
+								file_sizes.push((outcome.input_size, outcome.input_size));
+							}
+							Ok(outcome) => {
+								info!(
+									"Optimized {} in {:.3} s: {}",
+									batch_file.input_path.display(),
+									outcome.duration.as_secs_f64(),
+									size_savings(outcome.input_size, outcome.output_size)
+								);
+
+								file_sizes.push((outcome.input_size, outcome.output_size));
+							}
+							Err(err) => {
+								// Report errors even in quiet mode, like fatal errors
+								eprintln!("{}: {err}", batch_file.input_path.display());
+							}
+						}
+					}
+
+					(file_sizes, file_reports)
+				})
+			})
+			.collect::<Vec<_>>();
+
+		workers
+			.into_iter()
+			.map(|worker| worker.join().unwrap())
+			.fold(
+				(vec![], vec![]),
+				|(mut file_sizes, mut file_reports), (worker_file_sizes, worker_file_reports)| {
+					file_sizes.extend(worker_file_sizes);
+					file_reports.extend(worker_file_reports);
+
+					(file_sizes, file_reports)
+				}
+			)
+	});
+
+	if let Some(report_path) = report_path {
+		// Report files in the order they were found, no matter which worker processed them
+		file_reports.sort_unstable_by_key(|(batch_file_index, _)| *batch_file_index);
+
+		write_report(
+			&report_path,
+			file_reports
+				.into_iter()
+				.map(|(_, file_report)| file_report)
+				.collect()
+		)?;
+	}
+
+	let failed_file_count = batch_files.len() - file_sizes.len();
+	let (total_input_size, total_output_size) = file_sizes.iter().fold(
+		(0, 0),
+		|(total_input_size, total_output_size), (input_size, output_size)| {
+			(
+				total_input_size + input_size,
+				total_output_size + output_size
+			)
+		}
+	);
+
+	info!(
+		"{} {} files, {failed_file_count} failed. Total: {}",
+		if matches.opt_present("n") {
+			"Analyzed"
+		} else {
+			"Optimized"
+		},
+		file_sizes.len(),
+		size_savings(total_input_size, total_output_size)
+	);
+
+	if failed_file_count > 0 {
+		Err(format!(
+			"{failed_file_count} of {} files could not be processed",
+			batch_files.len()
+		))?;
+	}
+
+	Ok(())
+}
+
+/// Recursively adds the Ogg and Matroska files within the specified input directory to the
+/// list of files to process in batch mode, mapping them to the same relative paths within the
+/// specified output directory, if any.
+fn collect_batch_files(
+	input_directory: &Path,
+	output_directory: Option<&Path>,
+	batch_files: &mut Vec<BatchFile>
+) -> io::Result<()> {
+	let mut directory_entries = fs::read_dir(input_directory)?.collect::<Result<Vec<_>, _>>()?;
+	// Process files in a predictable order
+	directory_entries.sort_by_key(|directory_entry| directory_entry.file_name());
+
+	for directory_entry in directory_entries {
+		let input_path = directory_entry.path();
+		let output_path = output_directory
+			.map(|output_directory| output_directory.join(directory_entry.file_name()));
+
+		if input_path.is_dir() {
+			collect_batch_files(&input_path, output_path.as_deref(), batch_files)?;
+		} else if matches!(
+			input_path
+				.extension()
+				.and_then(|extension| extension.to_str()),
+			Some("ogg" | "oga" | "ogx" | "mka" | "mkv" | "webm")
+		) {
+			batch_files.push(BatchFile {
+				input_path,
+				output_path
+			});
+		}
+	}
+
+	Ok(())
+}
+
+/// Optimizes a single file in batch or in-place mode.
+fn optimize_batch_file(
+	matches: &Matches,
+	batch_file: &BatchFile
+) -> Result<BatchFileOutcome, FileError> {
+	let input_file_name = batch_file.input_path.to_string_lossy();
+	let output_file_name = batch_file
+		.output_path
+		.as_deref()
+		.unwrap_or(&batch_file.input_path)
+		.to_string_lossy();
+
+	let chosen_remuxer = matches
+		.opt_get("remuxer")?
+		.or_else(|| guess_remuxer(&input_file_name, &output_file_name))
+		.ok_or(
+			"No remuxer was specified, and no remuxer could be guessed from the file extension"
+		)?;
+
+	let input_file = File::open(&batch_file.input_path)
+		.map_err(|err| format!("Could not open input file: {err}"))?;
+	let input_metadata = input_file
+		.metadata()
+		.map_err(|err| format!("Could not get the metadata of the input file: {err}"))?;
+	let input_size = input_metadata.len();
+
+	if matches.opt_present("n") {
+		if !matches!(chosen_remuxer, AvailableRemuxer::OggToOgg) {
+			Err("Dry runs are only supported by the ogg2ogg remuxer")?;
+		}
+
+		info!("Analyzing {input_file_name} with Ogg Vorbis remuxer...");
+
+		let dry_run_begin = Instant::now();
+		let dry_run_report =
+			OggToOgg::new(ogg_to_ogg_settings(matches)?, optimizer_settings(matches)?)
+				.dry_run(BufReader::new(input_file))
+				.map_err(|err| {
+					FileError::remux("Error while analyzing the input file", err.code(), &err)
+				})?;
+		extract_pictures(matches, &input_file_name, &dry_run_report)?;
+
+		return Ok(BatchFileOutcome {
+			input_size,
+			output_size: dry_run_report.optimized.total(),
+			duration: dry_run_begin.elapsed(),
+			original_kept: true,
+			remux_report: Some(dry_run_report)
+		});
+	}
+
+	let (output_path, output_file) = match &batch_file.output_path {
+		Some(output_path) => {
+			if let Some(output_file_directory) = output_path.parent() {
+				fs::create_dir_all(output_file_directory)
+					.map_err(|err| format!("Could not create output directory: {err}"))?;
 			}
+
+			(output_path.clone(), File::create(output_path))
+		}
+		None => {
+			let temporary_file_path = temporary_file_path(&batch_file.input_path);
+			let temporary_file = File::create_new(&temporary_file_path);
+
+			(temporary_file_path, temporary_file)
+		}
+	};
+	let mut output_file =
+		BufWriter::new(output_file.map_err(|err| format!("Could not open output file: {err}"))?);
+
+	let ((duration, remux_report), output_size) = remux(
+		matches,
+		&mut BufReader::new(input_file),
+		&input_file_name,
+		&mut output_file,
+		&output_file_name,
+		chosen_remuxer
+	)
+	.and_then(|remux_outcome| {
+		let output_file = output_file
+			.into_inner()
+			.map_err(|err| format!("Could not write output file: {}", err.error()))?;
+
+		if batch_file.output_path.is_none() {
+			preserve_file_metadata(&output_file, &input_metadata)
+				.map_err(|err| format!("Could not copy the input file metadata: {err}"))?;
+
+			// Make sure the optimized data is on disk before the input file is replaced
+			output_file
+				.sync_all()
+				.map_err(|err| format!("Could not write output file: {err}"))?;
+		}
+
+		let output_size = output_file
+			.metadata()
+			.map_err(|err| format!("Could not get the size of the output file: {err}"))?
+			.len();
+
+		Ok((remux_outcome, output_size))
+	})
+	.inspect_err(|_| {
+		// Do not leave incomplete output files behind
+		fs::remove_file(&output_path).ok();
+	})?;
+
+	let mut original_kept = false;
+	if batch_file.output_path.is_none() {
+		if output_size < input_size || matches.opt_present("f") {
+			replace_file(&output_path, &batch_file.input_path)
+				.map_err(|err| format!("Could not replace the input file: {err}"))
+				.inspect_err(|_| {
+					fs::remove_file(&output_path).ok();
+				})?;
+		} else {
+			fs::remove_file(&output_path).ok();
+			original_kept = true;
+		}
+	}
+
+	Ok(BatchFileOutcome {
+		input_size,
+		output_size,
+		duration,
+		original_kept,
+		remux_report
+	})
+}
+
+/// Returns the path of the temporary file that holds the optimized version of a file being
+/// optimized in place. It is in the same directory, so that it can be atomically renamed over
+/// the original file.
+fn temporary_file_path(file_path: &Path) -> PathBuf {
+	let mut temporary_file_name = OsString::from(".");
+	temporary_file_name.push(file_path.file_name().unwrap_or_default());
+	temporary_file_name.push(format!(".optivorbis-{}.tmp", process::id()));
+
+	file_path.with_file_name(temporary_file_name)
+}
+
+/// Copies the permissions and access and modification times of a file to another file.
+fn preserve_file_metadata(file: &File, original_metadata: &fs::Metadata) -> io::Result<()> {
+	file.set_permissions(original_metadata.permissions())?;
+	file.set_times(
+		FileTimes::new()
+			.set_accessed(original_metadata.accessed()?)
+			.set_modified(original_metadata.modified()?)
+	)
+}
+
+/// Atomically replaces a file with another file in the same directory, making sure that the
+/// change is persisted to disk.
+fn replace_file(new_file_path: &Path, file_path: &Path) -> io::Result<()> {
+	fs::rename(new_file_path, file_path)?;
+
+	// Renames are only durable once the directory that contains the renamed file is synced,
+	// which only Unix-like systems allow doing
+	#[cfg(unix)]
+	{
+		let directory = match file_path.parent() {
+			Some(directory) if !directory.as_os_str().is_empty() => directory,
+			_ => Path::new(".")
 		};
+
+		File::open(directory)?.sync_all()?;
+	}
+
+	Ok(())
+}
+
+/// Formats the size breakdowns of a dry run report as a table.
+fn dry_run_report_table(dry_run_report: &RemuxReport) -> String {
+	let RemuxReport {
+		original,
+		optimized,
+		..
+	} = dry_run_report;
+
+	let mut table = format!(
+		"{:<24}{:>12}{:>12}{:>12}",
+		"", "Original", "Optimized", "Saved"
+	);
+	for (row_name, original_size, optimized_size) in [
+		(
+			"Identification headers",
+			original.identification_headers,
+			optimized.identification_headers
+		),
+		(
+			"Comment headers",
+			original.comment_headers,
+			optimized.comment_headers
+		),
+		(
+			"Setup headers",
+			original.setup_headers,
+			optimized.setup_headers
+		),
+		(
+			"Audio packets",
+			original.audio_packets,
+			optimized.audio_packets
+		),
+		(
+			"Other streams",
+			original.other_streams,
+			optimized.other_streams
+		),
+		(
+			"Ogg overhead",
+			original.ogg_overhead,
+			optimized.ogg_overhead
+		),
+		("Total", original.total(), optimized.total())
+	] {
+		table.push_str(&format!(
+			"\n{row_name:<24}{original_size:>12}{optimized_size:>12}{:>12}",
+			original_size as i64 - optimized_size as i64
+		));
+	}
+
+	table
+}
+
+/// Builds the JSON report about a processed file, from its size and the size of its optimized
+/// version, if known, and the report of its remux, if available, or the error that happened
+/// while processing it.
+fn file_report(
+	input_file_name: &str,
+	output_file_name: Option<&str>,
+	file_sizes: Option<(u64, u64)>,
+	outcome: Result<Option<&RemuxReport>, &FileError>
+) -> Value {
+	let mut file_report = json!({
+		"input": input_file_name,
+		"output": output_file_name,
+		"success": outcome.is_ok()
+	});
+
+	if let Some((input_size, output_size)) = file_sizes {
+		file_report["input_size"] = input_size.into();
+		file_report["output_size"] = output_size.into();
 	}
 
+	match outcome {
+		Ok(remux_report) => {
+			if let Some(remux_report) = remux_report {
+				let (original, optimized) = (&remux_report.original, &remux_report.optimized);
+				let mut sections = json!({});
+				for (section_name, original_size, optimized_size) in [
+					(
+						"identification_headers",
+						original.identification_headers,
+						optimized.identification_headers
+					),
+					(
+						"comment_headers",
+						original.comment_headers,
+						optimized.comment_headers
+					),
+					(
+						"setup_headers",
+						original.setup_headers,
+						optimized.setup_headers
+					),
+					(
+						"audio_packets",
+						original.audio_packets,
+						optimized.audio_packets
+					),
+					(
+						"other_streams",
+						original.other_streams,
+						optimized.other_streams
+					),
+					(
+						"ogg_overhead",
+						original.ogg_overhead,
+						optimized.ogg_overhead
+					),
+					("total", original.total(), optimized.total())
+				] {
+					sections[section_name] = json!({
+						"original": original_size,
+						"optimized": optimized_size,
+						"saved": original_size as i64 - optimized_size as i64
+					});
+				}
+
+				file_report["sections"] = sections;
+				file_report["vorbis_stream_count"] = remux_report.vorbis_streams.len().into();
+				file_report["vorbis_streams"] = remux_report
+					.vorbis_streams
+					.iter()
+					.map(|stream_report| {
+						let identification_header_data = &stream_report.identification_header_data;
+						let optimizer_statistics = &stream_report.optimizer_statistics;
+
+						json!({
+							"stream_serial": stream_report.stream_serial,
+							"channels": identification_header_data.channels,
+							"sampling_frequency": identification_header_data.sampling_frequency,
+							"maximum_bitrate": identification_header_data.maximum_bitrate,
+							"nominal_bitrate": identification_header_data.nominal_bitrate,
+							"minimum_bitrate": identification_header_data.minimum_bitrate,
+							"blocksizes": [
+								identification_header_data.blocksizes.0,
+								identification_header_data.blocksizes.1
+							],
+							"discarded_audio_packets": optimizer_statistics.discarded_audio_packets,
+							"padded_audio_packets": optimizer_statistics.padded_audio_packets,
+							"removed_padding_bytes": optimizer_statistics.removed_padding_bytes,
+							"removed_codebook_count": optimizer_statistics.removed_codebook_count,
+							"codeword_length_limit_cost_bytes": optimizer_statistics
+								.codeword_length_limit_cost_bytes,
+							"codebook_entry_counts": optimizer_statistics
+								.codebook_entry_counts
+								.iter()
+								.map(|(original_entry_count, entry_count)| {
+									json!({
+										"original": original_entry_count,
+										"optimized": entry_count
+									})
+								})
+								.collect::<Vec<_>>(),
+							"pictures": optimizer_statistics
+								.pictures
+								.iter()
+								.map(|picture| {
+									json!({
+										"picture_type": picture.picture_type,
+										"mime_type": picture.mime_type,
+										"description": picture.description,
+										"width": picture.width,
+										"height": picture.height,
+										"data_size": picture.data_size,
+										"comment_size": picture.comment_size,
+										"kept": picture.kept
+									})
+								})
+								.collect::<Vec<_>>()
+						})
+					})
+					.collect();
+			}
+		}
+		Err(file_error) => {
+			file_report["error"] = json!({
+				"code": file_error.code,
+				"cause_code": file_error.cause_code,
+				"message": file_error.message
+			});
+		}
+	}
+
+	file_report
+}
+
+/// Writes a JSON report with the specified file reports to a file, or to standard output
+/// if its path is `-`.
+fn write_report(report_path: &str, file_reports: Vec<Value>) -> Result<(), Cow<'static, str>> {
+	let (mut report_stdout, mut report_file);
+	let report_writer: &mut dyn Write = match report_path {
+		"-" => {
+			report_stdout = stdout().lock();
+
+			&mut report_stdout
+		}
+		file_path => {
+			report_file = BufWriter::new(
+				File::create(file_path)
+					.map_err(|err| format!("Could not open report file: {err}"))?
+			);
+
+			&mut report_file
+		}
+	};
+
+	serde_json::to_writer_pretty(&mut *report_writer, &Value::Array(file_reports))
+		.map_err(io::Error::from)
+		.and_then(|()| writeln!(report_writer))
+		.and_then(|()| report_writer.flush())
+		.map_err(|err| format!("Could not write report file: {err}").into())
+}
+
+/// Formats the size change of a file, or set of files, after optimization.
+fn size_savings(input_size: u64, output_size: u64) -> String {
+	let saved_bytes = input_size as i64 - output_size as i64;
+	let saved_percentage = if input_size > 0 {
+		saved_bytes as f64 / input_size as f64 * 100.0
+	} else {
+		0.0
+	};
+
+	format!(
+		"{input_size} -> {output_size} bytes ({saved_bytes} bytes, {saved_percentage:.2} % saved)"
+	)
+}
+
+/// Guesses the remuxer to use from the extensions of the input and output files.
+fn guess_remuxer(input_file_name: &str, output_file_name: &str) -> Option<AvailableRemuxer> {
+	let file_extension = |file_name| {
+		Path::new(file_name)
+			.extension()
+			.and_then(|extension| extension.to_str())
+	};
+
+	match (
+		file_extension(input_file_name),
+		file_extension(output_file_name)
+	) {
+		(_, Some("ogg" | "oga" | "ogx")) => Some(AvailableRemuxer::OggToOgg),
+		(Some("ogg" | "oga" | "ogx"), Some("webm" | "mka")) => Some(AvailableRemuxer::OggToWebm),
+		(_, Some("mka" | "mkv" | "webm")) => Some(AvailableRemuxer::MatroskaToMatroska),
+		_ => None
+	}
+}
+
+/// Builds the Vorbis optimizer settings from the command line options.
+fn optimizer_settings(
+	option_matches: &Matches
+) -> Result<VorbisOptimizerSettings, Cow<'static, str>> {
 	let mut optimizer_settings = VorbisOptimizerSettings::default();
 
 	macro_rules! set_optimizer_setting {
@@ -227,7 +1106,8 @@ fn remux<F: Read + Seek>(
 
 	set_optimizer_setting!(comment_fields_action, match {
 		"copy" => VorbisCommentFieldsAction::Copy,
-		"delete" => VorbisCommentFieldsAction::Delete
+		"delete" => VorbisCommentFieldsAction::Delete,
+		"normalize" => VorbisCommentFieldsAction::Normalize
 	});
 	set_optimizer_setting!(vendor_string_action, match {
 		"copy" => VorbisVendorStringAction::Copy,
@@ -236,38 +1116,272 @@ fn remux<F: Read + Seek>(
 		"appendShortTag" => VorbisVendorStringAction::AppendShortTag,
 		"empty" => VorbisVendorStringAction::Empty
 	});
+	set_optimizer_setting!(picture_action, match {
+		"copy" => VorbisPictureAction::Copy,
+		"delete" => VorbisPictureAction::Delete,
+		"keepFrontCover" => VorbisPictureAction::KeepFrontCover
+	});
+	optimizer_settings.retain_picture_data = option_matches.opt_present("extract_pictures");
+	optimizer_settings.max_codeword_length = option_matches
+		.opt_get("max_codeword_length")
+		.map_err(|err| format!("Invalid value for max_codeword_length option: {err}"))?;
+
+	let keep_tag_patterns = option_matches.opt_strs("keep_tag");
+	if !keep_tag_patterns.is_empty() {
+		optimizer_settings
+			.comment_operations
+			.push(VorbisCommentOperation::KeepOnly(keep_tag_patterns));
+	}
+
+	let mut tag_operations = vec![];
+	for option in ["remove_tag", "rename_tag", "tag", "append_tag"] {
+		for (position, argument) in option_matches.opt_strs_pos(option) {
+			let operation = if option == "remove_tag" {
+				VorbisCommentOperation::Remove(argument)
+			} else {
+				let (field_name, value) = argument
+					.split_once('=')
+					.filter(|(field_name, _)| is_valid_field_name(field_name))
+					.ok_or_else(|| format!("Invalid value for {option} option: {argument}"))?;
+				let (field_name, value) = (field_name.to_string(), value.to_string());
+
+				match option {
+					"rename_tag" if is_valid_field_name(&value) => VorbisCommentOperation::Rename {
+						field_name,
+						new_field_name: value
+					},
+					"rename_tag" => Err(format!("Invalid value for {option} option: {argument}"))?,
+					"tag" => VorbisCommentOperation::Set { field_name, value },
+					_ => VorbisCommentOperation::Append { field_name, value }
+				}
+			};
+
+			tag_operations.push((position, operation));
+		}
+	}
+	tag_operations.sort_unstable_by_key(|(position, _)| *position);
+	optimizer_settings
+		.comment_operations
+		.extend(tag_operations.into_iter().map(|(_, operation)| operation));
+
+	Ok(optimizer_settings)
+}
+
+/// Checks whether the specified string is a valid Vorbis comment field name, according
+/// to the Vorbis I specification.
+fn is_valid_field_name(field_name: &str) -> bool {
+	!field_name.is_empty()
+		&& field_name
+			.bytes()
+			.all(|byte| (0x20..=0x7D).contains(&byte) && byte != b'=')
+}
+
+/// Builds the settings of the Ogg to Ogg remuxer from the command line options.
+fn ogg_to_ogg_settings(
+	option_matches: &Matches
+) -> Result<ogg_to_ogg::Settings<OggVorbisStreamPassthroughMangler>, Cow<'static, str>> {
+	macro_rules! set_remuxer_option_value {
+		( $remuxer_settings:expr, $option:ident ) => {
+			if let Some($option) = get_remuxer_option_value(option_matches, stringify!($option))? {
+				$remuxer_settings.$option = $option;
+			}
+		};
+	}
+
+	let mut remuxer_settings = ogg_to_ogg::Settings::default();
+	set_remuxer_option_value!(remuxer_settings, randomize_stream_serials);
+	set_remuxer_option_value!(remuxer_settings, first_stream_serial_offset);
+	set_remuxer_option_value!(remuxer_settings, ignore_start_sample_offset);
+	set_remuxer_option_value!(remuxer_settings, error_on_no_vorbis_streams);
+	set_remuxer_option_value!(remuxer_settings, verify_ogg_page_checksums);
+	set_remuxer_option_value!(remuxer_settings, preserve_stream_multiplexing);
+	set_remuxer_option_value!(remuxer_settings, preserve_non_vorbis_streams);
+	set_remuxer_option_value!(remuxer_settings, remux_non_vorbis_audio_streams);
+	if let Some(max_page_payload_size) =
+		get_remuxer_option_value(option_matches, "max_page_payload_size")?
+	{
+		remuxer_settings.max_page_payload_size = Some(max_page_payload_size);
+	}
+	if let Some(max_page_duration) = get_remuxer_option_value(option_matches, "max_page_duration")?
+	{
+		remuxer_settings.max_page_duration = Some(max_page_duration);
+	}
+	set_remuxer_option_value!(remuxer_settings, single_packet_audio_page_count);
+
+	Ok(remuxer_settings)
+}
+
+fn remux(
+	option_matches: &Matches,
+	input_file: &mut dyn ReadSeek,
+	input_file_name: &str,
+	output_file: &mut dyn Write,
+	output_file_name: &str,
+	chosen_remuxer: AvailableRemuxer
+) -> Result<(Duration, Option<RemuxReport>), FileError> {
+	macro_rules! set_remuxer_option_value {
+		( $remuxer_settings:expr, $option:ident ) => {
+			if let Some($option) = get_remuxer_option_value(option_matches, stringify!($option))? {
+				$remuxer_settings.$option = $option;
+			}
+		};
+	}
+
+	const REMUX_ERROR_CONTEXT: &str = "Error while optimizing the input file";
 
-	match match chosen_remuxer {
+	if option_matches.opt_present("extract_pictures")
+		&& !matches!(chosen_remuxer, AvailableRemuxer::OggToOgg)
+	{
+		Err("Picture extraction is only supported by the ogg2ogg remuxer")?;
+	}
+
+	let optimizer_settings = optimizer_settings(option_matches)?;
+
+	match chosen_remuxer {
 		AvailableRemuxer::OggToOgg => {
-			let mut remuxer_settings = ogg_to_ogg::Settings::default();
-			set_remuxer_option_value!(remuxer_settings, randomize_stream_serials);
-			set_remuxer_option_value!(remuxer_settings, first_stream_serial_offset);
-			set_remuxer_option_value!(remuxer_settings, ignore_start_sample_offset);
-			set_remuxer_option_value!(remuxer_settings, error_on_no_vorbis_streams);
-			set_remuxer_option_value!(remuxer_settings, verify_ogg_page_checksums);
+			let remuxer_settings = ogg_to_ogg_settings(option_matches)?;
 
 			info!(
 				"Processing {input_file_name} and saving to {output_file_name} with Ogg Vorbis remuxer..."
 			);
 
 			let remux_begin = Instant::now();
-			OggToOgg::new(remuxer_settings, optimizer_settings)
+			let remuxer = OggToOgg::new(remuxer_settings, optimizer_settings);
+			let (duration, remux_report) = if option_matches.opt_present("report")
+				|| option_matches.opt_present("extract_pictures")
+			{
+				remuxer
+					.remux_with_report(input_file, output_file)
+					.map(|(_, remux_report)| (remux_begin.elapsed(), Some(remux_report)))
+			} else {
+				remuxer
+					.remux(input_file, output_file)
+					.map(|_| (remux_begin.elapsed(), None))
+			}
+			.map_err(|err| FileError::remux(REMUX_ERROR_CONTEXT, err.code(), &err))?;
+
+			if let Some(remux_report) = &remux_report {
+				extract_pictures(option_matches, input_file_name, remux_report)?;
+			}
+
+			Ok((duration, remux_report))
+		}
+		AvailableRemuxer::MatroskaToMatroska => {
+			let mut remuxer_settings = matroska_to_matroska::Settings::default();
+			set_remuxer_option_value!(remuxer_settings, error_on_no_vorbis_tracks);
+
+			info!(
+				"Processing {input_file_name} and saving to {output_file_name} with Matroska Vorbis remuxer..."
+			);
+
+			let remux_begin = Instant::now();
+			MatroskaToMatroska::new(remuxer_settings, optimizer_settings)
 				.remux(input_file, output_file)
-				.map(|_| remux_begin.elapsed())
+				.map(|_| (remux_begin.elapsed(), None))
+				.map_err(|err| FileError::remux(REMUX_ERROR_CONTEXT, err.code(), &err))
 		}
-	} {
-		Ok(duration) => {
+		AvailableRemuxer::RawToRaw => {
+			let mut remuxer_settings = raw_to_raw::Settings::default();
+			if let Some(audio_packet_length_width) =
+				get_remuxer_option_value::<_, u8>(option_matches, "audio_packet_length_width")?
+			{
+				remuxer_settings.audio_packet_length_width = match audio_packet_length_width {
+					16 => raw_to_raw::AudioPacketLengthWidth::U16,
+					32 => raw_to_raw::AudioPacketLengthWidth::U32,
+					_ => Err(format!(
+						"Invalid value for audio_packet_length_width remuxer option: {audio_packet_length_width}"
+					))?
+				};
+			}
+			set_remuxer_option_value!(remuxer_settings, granule_side_table);
+
 			info!(
-				"Optimization and repairs completed in {:.3} s. Have a nice day!",
-				duration.as_secs_f64()
+				"Processing {input_file_name} and saving to {output_file_name} with raw Vorbis packet remuxer..."
 			);
 
-			Ok(())
+			let remux_begin = Instant::now();
+			RawToRaw::new(remuxer_settings, optimizer_settings)
+				.remux(input_file, output_file)
+				.map(|_| (remux_begin.elapsed(), None))
+				.map_err(|err| FileError::remux(REMUX_ERROR_CONTEXT, err.code(), &err))
+		}
+		AvailableRemuxer::OggToWebm => {
+			let mut remuxer_settings = ogg_to_webm::Settings::default();
+			set_remuxer_option_value!(remuxer_settings, verify_ogg_page_checksums);
+
+			info!(
+				"Processing {input_file_name} and saving to {output_file_name} with Ogg Vorbis to WebM remuxer..."
+			);
+
+			let remux_begin = Instant::now();
+			OggToWebm::new(remuxer_settings, optimizer_settings)
+				.remux(input_file, output_file)
+				.map(|_| (remux_begin.elapsed(), None))
+				.map_err(|err| FileError::remux(REMUX_ERROR_CONTEXT, err.code(), &err))
 		}
-		Err(err) => Err(format!("Error while optimizing the input file: {err}"))?
 	}
 }
 
+/// Writes the pictures embedded in the Vorbis streams of a processed file to the picture
+/// extraction directory, if one was specified.
+fn extract_pictures(
+	option_matches: &Matches,
+	input_file_name: &str,
+	remux_report: &RemuxReport
+) -> Result<(), FileError> {
+	let Some(picture_directory) = option_matches.opt_str("extract_pictures") else {
+		return Ok(());
+	};
+
+	let input_file_name = match Path::new(input_file_name).file_name() {
+		Some(file_name) if input_file_name != "-" => file_name.to_string_lossy(),
+		_ => "stdin".into()
+	};
+
+	for (stream_index, stream_report) in remux_report.vorbis_streams.iter().enumerate() {
+		for (picture_index, picture) in stream_report
+			.optimizer_statistics
+			.pictures
+			.iter()
+			.enumerate()
+		{
+			let Some(picture_data) = &picture.data else {
+				continue;
+			};
+
+			let picture_file_extension = match &*picture.mime_type {
+				"image/png" => "png",
+				"image/jpeg" | "image/jpg" => "jpg",
+				"image/gif" => "gif",
+				"image/webp" => "webp",
+				"image/bmp" => "bmp",
+				"-->" => "url",
+				_ => "bin"
+			};
+			let picture_path = Path::new(&picture_directory).join(format!(
+				"{input_file_name}.{stream_index}.{picture_index}.{picture_file_extension}"
+			));
+
+			fs::create_dir_all(&picture_directory)
+				.and_then(|()| fs::write(&picture_path, picture_data))
+				.map_err(|err| {
+					format!(
+						"Could not write picture file {}: {err}",
+						picture_path.display()
+					)
+				})?;
+
+			info!(
+				"Extracted a {} bytes picture to {}",
+				picture_data.len(),
+				picture_path.display()
+			);
+		}
+	}
+
+	Ok(())
+}
+
 fn get_remuxer_option_value<E: Display, T: FromStr<Err = E>>(
 	option_matches: &Matches,
 	option: &str
@@ -309,8 +1423,101 @@ fn print_header() {
 	println!("Copyright (C) {}", env!("CARGO_PKG_AUTHORS"));
 }
 
+/// A source of data that can be both read and seeked, so that it can be used as a trait object.
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A file to optimize in batch or in-place mode.
+struct BatchFile {
+	input_path: PathBuf,
+	/// The path where the optimized file will be saved, or `None` to optimize it in place.
+	output_path: Option<PathBuf>
+}
+
+/// The outcome of successfully optimizing a file in batch or in-place mode.
+struct BatchFileOutcome {
+	input_size: u64,
+	output_size: u64,
+	duration: Duration,
+	/// Whether the input file was kept as is when optimizing in place, because its optimized
+	/// version was not smaller.
+	original_kept: bool,
+	/// The report of the remux, for dry runs, or when a report was requested for the
+	/// ogg2ogg remuxer.
+	remux_report: Option<RemuxReport>
+}
+
+/// An error that happened while processing a file, along with machine-readable codes that
+/// identify its kind and cause, if known.
+struct FileError {
+	message: Cow<'static, str>,
+	code: &'static str,
+	cause_code: Option<&'static str>
+}
+
+impl FileError {
+	/// Creates a file error from a remuxer error, which has the specified code. The code of its
+	/// cause is found by looking for known error types in its source.
+	fn remux(context: &str, code: &'static str, err: &(dyn Error + 'static)) -> Self {
+		let cause_code = err.source().and_then(|source| {
+			source
+				.downcast_ref::<VorbisOptimizerError>()
+				.map(VorbisOptimizerError::code)
+				.or_else(|| {
+					source
+						.downcast_ref::<ogg_to_ogg::RemuxError>()
+						.map(ogg_to_ogg::RemuxError::code)
+				})
+		});
+
+		Self {
+			message: format!("{context}: {err}").into(),
+			code,
+			cause_code
+		}
+	}
+}
+
+impl From<Cow<'static, str>> for FileError {
+	fn from(message: Cow<'static, str>) -> Self {
+		Self {
+			message,
+			code: "other",
+			cause_code: None
+		}
+	}
+}
+
+impl From<String> for FileError {
+	fn from(message: String) -> Self {
+		Cow::<'static, str>::from(message).into()
+	}
+}
+
+impl From<&'static str> for FileError {
+	fn from(message: &'static str) -> Self {
+		Cow::from(message).into()
+	}
+}
+
+impl From<FileError> for Cow<'static, str> {
+	fn from(file_error: FileError) -> Self {
+		file_error.message
+	}
+}
+
+impl Display for FileError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.message)
+	}
+}
+
 enum AvailableRemuxer {
-	OggToOgg
+	OggToOgg,
+	MatroskaToMatroska,
+	OggToWebm,
+	RawToRaw
 }
 
 impl FromStr for AvailableRemuxer {
@@ -319,6 +1526,9 @@ impl FromStr for AvailableRemuxer {
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
 		match s {
 			"ogg2ogg" => Ok(Self::OggToOgg),
+			"mka2mka" => Ok(Self::MatroskaToMatroska),
+			"ogg2webm" => Ok(Self::OggToWebm),
+			"raw2raw" => Ok(Self::RawToRaw),
 			_ => Err(format!("The specified remuxer is not valid: {s}").into())
 		}
 	}
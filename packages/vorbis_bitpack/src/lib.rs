@@ -31,6 +31,12 @@
 //! These types can be freely used as a base to implement new data types. For example, a 64-bit integer
 //! can be implemented as two 32-bit integers combined.
 //!
+//! # Reading from byte slices
+//!
+//! [`BitpackReader`] reads from any byte source, one byte at a time. When the whole bitpacked
+//! data is already in memory, [`BitpackSliceReader`] provides the same interface with better
+//! performance, and can also tell how many bits are left to read.
+//!
 //! # `#![no_std]` compatibility
 //!
 //! By default, this crate depends on the Rust standard library, but it is compatible with `#![no_std]`
@@ -77,10 +83,10 @@
 #![forbid(rustdoc::broken_intra_doc_links)]
 
 #[cfg(not(feature = "no-std"))]
-use std::io::{Read, Result, Write};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 
 #[cfg(feature = "no-std")]
-use acid_io::{Read, Result, Write};
+use acid_io::{Error, ErrorKind, Read, Result, Write};
 
 #[cfg(test)]
 mod test;
@@ -140,8 +146,14 @@ macro_rules! bitpacked_integer_width {
 /// packed according to the Vorbis I bitpack convention.
 #[derive(Debug)]
 pub struct BitpackReader<R: Read> {
-	last_read_byte: u8,
-	remaining_bits: u8,
+	/// Bits read from the source but not consumed yet, in LSB to MSB order.
+	bit_buffer: u64,
+	/// The number of valid bits in the bit buffer. Because the buffer is only
+	/// refilled to fulfill read requests of at most 32 bits, this value is always
+	/// in the [0, 40) range.
+	buffered_bits: u8,
+	/// The number of bits consumed so far.
+	bit_position: u64,
 	source: R
 }
 
@@ -153,8 +165,9 @@ impl<R: Read> BitpackReader<R> {
 	/// Therefore, for top performance it is recommended to use buffered byte sources.
 	pub fn new(source: R) -> Self {
 		Self {
-			last_read_byte: 0,
-			remaining_bits: 0,
+			bit_buffer: 0,
+			buffered_bits: 0,
+			bit_position: 0,
 			source
 		}
 	}
@@ -162,70 +175,73 @@ impl<R: Read> BitpackReader<R> {
 	/// Reads a single bitpacked unsigned integer of the specified width from the
 	/// source associated to this bitpack reader.
 	pub fn read_unsigned_integer(&mut self, width: BitpackedIntegerWidth) -> Result<u32> {
-		// This value is in the [0, 8) range
-		let remaining_bits = self.remaining_bits;
-		let result;
+		let result = self.peek_bits(width)?;
 
-		if remaining_bits >= width.get() {
-			// We can satisfy this read request by just extracting bits from the last byte
-			// we've already read. Advance the bit position cursor accordingly
-			result = self.last_read_byte as u32 & ones_mask(width);
+		self.consume_buffered_bits(width.get());
 
-			self.remaining_bits -= width.get();
-			self.last_read_byte >>= width.get();
-		} else {
-			// We need to read up to 4 bytes to fulfill this read request, in case
-			// this read request wants to read 32 bits. width > remaining_bits at
-			// this point
-			let mut read_buf = [0u8; 4];
-
-			// Now read the fewest amount of bytes needed to satisfy this request.
-			// Contrary to intuition, reading bytes one by one is faster for the buffered
-			// sources we should be using anyway, as that way we can leverage small-copy
-			// optimizations in Rust's standard library to avoid emitting a call to memcpy,
-			// which has notoriously detrimental performance effects, especially for musl
-			// targets (a perf report showed that roughly ~12.12% of execution time for
-			// a test file was spent calling memcpy for musl, but this also benefited
-			// glibc due to the lesser call overhead). Unbuffered byte sources where a
-			// read_exact call translates to a syscall will likely perform significantly
-			// worse, but most application code should not be using such sources anyway.
-			// Related read:
-			// https://github.com/rust-lang/rust/pull/37573
-			let bits_to_read = width.get() - remaining_bits;
-			let bytes_to_read = (1 + (bits_to_read - 1) / 8) as usize;
-			for byte_to_read in &mut read_buf[..bytes_to_read] {
-				self.source
-					.read_exact(core::slice::from_mut(byte_to_read))?;
-			}
+		Ok(result)
+	}
 
-			// Put the remaining bits in the least significant positions of the result integer.
-			// Due to the rotate_right call below we can't guarantee that upper bits are always
-			// set to zero
-			let mut partial_result = self.last_read_byte as u32
-				& ones_mask(BitpackedIntegerWidth::__internal_unchecked_new(
-					remaining_bits
-				));
-
-			// Now concat the bits we've read from the source to the result, in increasingly
-			// significant positions
-			for (i, byte) in read_buf.iter().enumerate().take(bytes_to_read) {
-				partial_result |= (*byte as u32) << (remaining_bits + 8 * i as u8);
-			}
+	/// Returns the next `width` bits of the source associated to this bitpack reader
+	/// as an unsigned integer, without consuming them. The returned bits will be
+	/// returned again by the next read operation.
+	///
+	/// If the source does not have enough bits left, an error is returned. Any bits
+	/// read until that point are kept, so they can still be read with narrower
+	/// read operations.
+	///
+	/// Peeking is useful to decode variable-length codes, such as Huffman codes, with
+	/// lookup tables: once the length of the code is known, the consumed bits can be
+	/// skipped with [`skip_bits`](Self::skip_bits).
+	pub fn peek_bits(&mut self, width: BitpackedIntegerWidth) -> Result<u32> {
+		// Read the fewest amount of bytes needed to satisfy this request.
+		// Contrary to intuition, reading bytes one by one is faster for the buffered
+		// sources we should be using anyway, as that way we can leverage small-copy
+		// optimizations in Rust's standard library to avoid emitting a call to memcpy,
+		// which has notoriously detrimental performance effects, especially for musl
+		// targets (a perf report showed that roughly ~12.12% of execution time for
+		// a test file was spent calling memcpy for musl, but this also benefited
+		// glibc due to the lesser call overhead). Unbuffered byte sources where a
+		// read_exact call translates to a syscall will likely perform significantly
+		// worse, but most application code should not be using such sources anyway.
+		// Related read:
+		// https://github.com/rust-lang/rust/pull/37573
+		while self.buffered_bits < width.get() {
+			let mut byte = 0;
+			self.source.read_exact(core::slice::from_mut(&mut byte))?;
+
+			// Concat the bits we've just read to the buffered ones, in
+			// increasingly significant positions
+			self.bit_buffer |= (byte as u64) << self.buffered_bits;
+			self.buffered_bits += 8;
+		}
 
-			// It may happen that we should not fully read the last byte, because we only
-			// wanted to extract some bits from it, not all. If that's the case, clear those
-			// extra bits in the most significant positions and store the remainder in
-			// last_read_byte, so future reads will use those in the first place
-			result = partial_result & ones_mask(width);
-
-			// Take into account that the read might have satisfied the request entirely and
-			// thus there might be no remaining bits
-			self.remaining_bits = bytes_to_read as u8 * 8 - bits_to_read;
-			self.last_read_byte =
-				read_buf[bytes_to_read - 1].rotate_right(8 - self.remaining_bits as u32);
+		Ok(self.bit_buffer as u32 & ones_mask(width))
+	}
+
+	/// Consumes the next `bit_count` bits of the source associated to this bitpack
+	/// reader, discarding them.
+	///
+	/// If the source does not have enough bits left, an error is returned, and the
+	/// amount of skipped bits is unspecified.
+	pub fn skip_bits(&mut self, mut bit_count: u64) -> Result<()> {
+		// First, consume bits that were already buffered, which is enough for skipping
+		// bits that were just peeked
+		let buffered_bits_to_skip = bit_count.min(self.buffered_bits as u64) as u8;
+		self.consume_buffered_bits(buffered_bits_to_skip);
+		bit_count -= buffered_bits_to_skip as u64;
+
+		// The bit buffer is empty at this point if there are more bits to skip.
+		// Skip any whole bytes, and then any remaining bits
+		for _ in 0..bit_count / 8 {
+			self.source.read_exact(&mut [0])?;
+			self.bit_position += 8;
 		}
 
-		Ok(result)
+		self.read_unsigned_integer(BitpackedIntegerWidth::__internal_unchecked_new(
+			(bit_count % 8) as u8
+		))
+		.map(|_| ())
 	}
 
 	/// Reads a single bitpacked signed integer of the specified width from the source
@@ -251,15 +267,143 @@ impl<R: Read> BitpackReader<R> {
 		Ok(self.read_unsigned_integer(bitpacked_integer_width!(1))? != 0)
 	}
 
+	/// Returns the number of bits consumed from the source associated to this bitpack
+	/// reader so far, which is the position of the next bit to read.
+	///
+	/// Peeked bits are not considered consumed until they are read or skipped.
+	pub fn bit_position(&self) -> u64 {
+		self.bit_position
+	}
+
 	/// Consumes and tears down this bitpack reader, returning the underlying byte source.
 	///
-	/// This is an one-way operation: any information about what particular bit this bitpack
-	/// reader is pointing to within the last byte read from the source will be lost.
+	/// This is an one-way operation: any bits read from the source but not consumed yet,
+	/// such as the remaining bits of the last byte read or peeked bits, will be lost.
 	/// In other words, constructing another [`BitpackReader`] with the returned byte
 	/// source is not guaranteed to keep the bitstream sync.
 	pub fn into_inner(self) -> R {
 		self.source
 	}
+
+	/// Consumes the specified number of bits from the bit buffer, which must have
+	/// at least that many bits.
+	fn consume_buffered_bits(&mut self, bit_count: u8) {
+		debug_assert!(bit_count <= self.buffered_bits);
+
+		self.bit_buffer >>= bit_count;
+		self.buffered_bits -= bit_count;
+		self.bit_position += bit_count as u64;
+	}
+}
+
+/// Reads variable-length primitive types from a byte slice, packed according to the
+/// Vorbis I bitpack convention.
+///
+/// This reader has the same interface as a [`BitpackReader`] reading from a slice,
+/// but it is faster, as it extracts bits from the slice directly instead of reading
+/// bytes one by one through [`Read`]. Because the length of the data is known upfront,
+/// it can also tell how many bits are left with [`remaining_bits`](Self::remaining_bits).
+#[derive(Debug, Clone)]
+pub struct BitpackSliceReader<'slice> {
+	bytes: &'slice [u8],
+	bit_position: u64
+}
+
+impl<'slice> BitpackSliceReader<'slice> {
+	/// Constructs a Vorbis I bitpack reader that will read variable-length primitive
+	/// types from the specified byte slice.
+	pub const fn new(bytes: &'slice [u8]) -> Self {
+		Self {
+			bytes,
+			bit_position: 0
+		}
+	}
+
+	/// Reads a single bitpacked unsigned integer of the specified width from the
+	/// slice associated to this bitpack reader.
+	pub fn read_unsigned_integer(&mut self, width: BitpackedIntegerWidth) -> Result<u32> {
+		let result = self.peek_bits(width)?;
+
+		self.bit_position += width.get() as u64;
+
+		Ok(result)
+	}
+
+	/// Reads a single bitpacked signed integer of the specified width from the slice
+	/// associated to this bitpack reader.
+	pub fn read_signed_integer(&mut self, width: BitpackedIntegerWidth) -> Result<i32> {
+		Ok(sign_extend(self.read_unsigned_integer(width)?, width))
+	}
+
+	/// Reads a single bitpacked Vorbis `float32` value from the slice associated
+	/// to this bitpack reader, and losslessly converts it to a `f64`.
+	pub fn read_float32(&mut self) -> Result<f64> {
+		Ok(float32_unpack(
+			self.read_unsigned_integer(bitpacked_integer_width!(32))?
+		))
+	}
+
+	/// Reads a single bitpacked flag (i.e., boolean) value from the slice associated
+	/// to this bitpack reader.
+	///
+	/// Reading a flag consumes a single bit from the slice. `true` is returned if
+	/// that bit is equal to 1; otherwise, 0 is returned.
+	pub fn read_flag(&mut self) -> Result<bool> {
+		Ok(self.read_unsigned_integer(bitpacked_integer_width!(1))? != 0)
+	}
+
+	/// Returns the next `width` bits of the slice associated to this bitpack reader
+	/// as an unsigned integer, without consuming them. If the slice does not have
+	/// enough bits left, an error is returned.
+	pub fn peek_bits(&self, width: BitpackedIntegerWidth) -> Result<u32> {
+		if width.get() as u64 > self.remaining_bits() {
+			return Err(Error::from(ErrorKind::UnexpectedEof));
+		}
+
+		// Reading 32 bits at a non-zero bit offset spans 5 bytes, so loading up to
+		// 8 bytes into a 64-bit word is enough to satisfy any request
+		let byte_position = (self.bit_position / 8) as usize;
+		let bit_offset = self.bit_position % 8;
+		let bytes = &self.bytes[byte_position..];
+
+		let word = match bytes.get(..8) {
+			// Converting fixed-size chunks can be compiled to a single unaligned load,
+			// which is significantly faster than gathering bytes one by one
+			Some(word_bytes) => u64::from_le_bytes(word_bytes.try_into().unwrap()),
+			// Near the end of the slice, gather the remaining bytes one by one
+			None => bytes
+				.iter()
+				.enumerate()
+				.fold(0, |word, (i, &byte)| word | (byte as u64) << (8 * i))
+		};
+
+		Ok((word >> bit_offset) as u32 & ones_mask(width))
+	}
+
+	/// Consumes the next `bit_count` bits of the slice associated to this bitpack
+	/// reader, discarding them. If the slice does not have enough bits left, an error
+	/// is returned, and no bits are consumed.
+	pub fn skip_bits(&mut self, bit_count: u64) -> Result<()> {
+		if bit_count > self.remaining_bits() {
+			return Err(Error::from(ErrorKind::UnexpectedEof));
+		}
+
+		self.bit_position += bit_count;
+
+		Ok(())
+	}
+
+	/// Returns the number of bits consumed from the slice associated to this bitpack
+	/// reader so far, which is the position of the next bit to read.
+	pub const fn bit_position(&self) -> u64 {
+		self.bit_position
+	}
+
+	/// Returns the number of bits that are left to read in the slice associated to
+	/// this bitpack reader.
+	pub const fn remaining_bits(&self) -> u64 {
+		self.bytes.len() as u64 * 8 - self.bit_position
+	}
 }
 
 /// Wraps a byte sink to write variable-length primitive types to it,
@@ -268,7 +412,11 @@ impl<R: Read> BitpackReader<R> {
 pub struct BitpackWriter<W: Write> {
 	byte_to_be_written: u8,
 	bits_to_be_written: u8,
-	sink: W
+	/// The number of bits written so far, including padding bits.
+	bit_position: u64,
+	/// The wrapped byte sink. It is only `None` after it is reclaimed by
+	/// [`into_inner`](Self::into_inner), which consumes the writer.
+	sink: Option<W>
 }
 
 impl<W: Write> BitpackWriter<W> {
@@ -281,7 +429,8 @@ impl<W: Write> BitpackWriter<W> {
 		Self {
 			byte_to_be_written: 0,
 			bits_to_be_written: 0,
-			sink
+			bit_position: 0,
+			sink: Some(sink)
 		}
 	}
 
@@ -299,6 +448,7 @@ impl<W: Write> BitpackWriter<W> {
 		width: BitpackedIntegerWidth
 	) -> Result<()> {
 		let mut remaining_bits = width.get();
+		self.bit_position += remaining_bits as u64;
 
 		// First, try to complete the pending byte with bits from this integer
 		let free_bits_in_byte_to_be_written = 8 - self.bits_to_be_written;
@@ -315,7 +465,8 @@ impl<W: Write> BitpackWriter<W> {
 
 		// If the pending byte is now complete, write it to the stream
 		if self.bits_to_be_written == 8 {
-			self.sink.write_all(&[self.byte_to_be_written])?;
+			let byte_to_be_written = self.byte_to_be_written;
+			self.sink().write_all(&[byte_to_be_written])?;
 			self.byte_to_be_written = 0;
 			self.bits_to_be_written = 0;
 		}
@@ -341,14 +492,19 @@ impl<W: Write> BitpackWriter<W> {
 		// machine code for the buffered sinks we should be using. Read the similar comment
 		// at BitpackReader::read_unsigned_integer for more details
 		for byte_to_write in &integer.to_le_bytes()[..bytes_to_write as usize] {
-			self.sink.write_all(&[*byte_to_write])?;
+			self.sink().write_all(&[*byte_to_write])?;
 		}
 
 		// Consume the bytes we've just written to the stream. We always write
 		// at most 3 bytes, so the shift never overflows
 		integer >>= 8 * bytes_to_write;
 
-		self.byte_to_be_written = integer as u8;
+		// Clear any bits beyond the integer width, so that they do not leak into the
+		// next bits written
+		self.byte_to_be_written = (integer
+			& ones_mask(BitpackedIntegerWidth::__internal_unchecked_new(
+				remainder_bits
+			))) as u8;
 		self.bits_to_be_written = remainder_bits;
 
 		Ok(())
@@ -392,6 +548,123 @@ impl<W: Write> BitpackWriter<W> {
 		self.write_unsigned_integer(flag as u32, bitpacked_integer_width!(1))
 	}
 
+	/// Writes the first `bit_count` bits of the specified byte slice to the sink associated
+	/// to this bitpack writer, in the same order a bitpack reader would read them.
+	///
+	/// This is faster than writing the bits in integers of at most 32 bits, especially
+	/// when the bits written so far complete a whole number of bytes, which allows
+	/// writing the bytes of the slice to the sink verbatim.
+	///
+	/// # Panics
+	/// If the slice has fewer than `bit_count` bits.
+	pub fn write_bits_from_slice(&mut self, bytes: &[u8], bit_count: u64) -> Result<()> {
+		assert!(
+			bit_count <= bytes.len() as u64 * 8,
+			"The slice has fewer bits than requested"
+		);
+
+		let (whole_bytes, remaining_bytes) = bytes.split_at((bit_count / 8) as usize);
+
+		if self.bits_to_be_written == 0 {
+			// The bits of whole bytes stay in the same positions, so write them in bulk
+			self.sink().write_all(whole_bytes)?;
+			self.bit_position += whole_bytes.len() as u64 * 8;
+		} else {
+			// The bits of every byte are split between two bytes, so shift them in 32-bit
+			// words, which is the widest integer we can write at once
+			let mut words = whole_bytes.chunks_exact(4);
+			for word in &mut words {
+				self.write_unsigned_integer(
+					u32::from_le_bytes(word.try_into().unwrap()),
+					bitpacked_integer_width!(32)
+				)?;
+			}
+
+			for &byte in words.remainder() {
+				self.write_unsigned_integer(byte as u32, bitpacked_integer_width!(8))?;
+			}
+		}
+
+		// Finally, write any bits that do not complete a byte
+		let remainder_bits = (bit_count % 8) as u8;
+		if remainder_bits > 0 {
+			self.write_unsigned_integer(
+				remaining_bytes[0] as u32,
+				BitpackedIntegerWidth::__internal_unchecked_new(remainder_bits)
+			)?;
+		}
+
+		Ok(())
+	}
+
+	/// Reads the next `bit_count` bits from the specified bitpack reader, and writes them
+	/// as-is to the sink associated to this bitpack writer.
+	///
+	/// This is useful to copy runs of fields verbatim from one bitpacked stream to another,
+	/// without decoding them. The bits are copied in integers of at most 32 bits, as the
+	/// bytes of the source are not available to copy in bulk. If the reader reaches the end
+	/// of its source, an error is returned, and the number of copied bits is unspecified.
+	pub fn copy_bits_from<R: Read>(
+		&mut self,
+		reader: &mut BitpackReader<R>,
+		bit_count: u64
+	) -> Result<()> {
+		for _ in 0..bit_count / 32 {
+			self.write_unsigned_integer(
+				reader.read_unsigned_integer(bitpacked_integer_width!(32))?,
+				bitpacked_integer_width!(32)
+			)?;
+		}
+
+		let remainder_width =
+			BitpackedIntegerWidth::__internal_unchecked_new((bit_count % 32) as u8);
+		self.write_unsigned_integer(
+			reader.read_unsigned_integer(remainder_width)?,
+			remainder_width
+		)
+	}
+
+	/// Reads the next `bit_count` bits from the specified bitpack slice reader, and writes
+	/// them as-is to the sink associated to this bitpack writer.
+	///
+	/// Unlike [`copy_bits_from`](Self::copy_bits_from), this copies the bits in bulk with
+	/// [`write_bits_from_slice`](Self::write_bits_from_slice), which is faster for long runs
+	/// of bits, especially when the reader and this writer are at the same bit offset within
+	/// a byte. If the reader does not have enough bits left, an error is returned, and no
+	/// bits are copied.
+	pub fn copy_bits_from_slice_reader(
+		&mut self,
+		reader: &mut BitpackSliceReader<'_>,
+		bit_count: u64
+	) -> Result<()> {
+		if bit_count > reader.remaining_bits() {
+			return Err(Error::from(ErrorKind::UnexpectedEof));
+		}
+
+		// Copy the bits before the next byte boundary of the reader in a single integer,
+		// so that the rest of the bits start at a byte of its slice
+		let leading_width = BitpackedIntegerWidth::__internal_unchecked_new(
+			((8 - reader.bit_position % 8) % 8).min(bit_count) as u8
+		);
+		self.write_unsigned_integer(reader.read_unsigned_integer(leading_width)?, leading_width)?;
+
+		let remaining_bit_count = bit_count - leading_width.get() as u64;
+		self.write_bits_from_slice(
+			&reader.bytes[(reader.bit_position / 8) as usize..],
+			remaining_bit_count
+		)?;
+		reader.bit_position += remaining_bit_count;
+
+		Ok(())
+	}
+
+	/// Returns the number of bits written to this bitpack writer so far, which is the
+	/// position of the next bit to write. This includes the bits that did not yet
+	/// complete a byte, and the padding bits written by [`finalize`](Self::finalize).
+	pub fn bit_position(&self) -> u64 {
+		self.bit_position
+	}
+
 	/// Immediately writes any bits that did not yet complete a byte, padding that byte
 	/// with zeroes in the most significant positions.
 	///
@@ -404,8 +677,13 @@ impl<W: Write> BitpackWriter<W> {
 	/// destination after a call to [`flush`](Self::flush).
 	pub fn finalize(&mut self) -> Result<()> {
 		if self.bits_to_be_written > 0 {
+			let byte_to_be_written = self.byte_to_be_written;
+
+			self.byte_to_be_written = 0;
 			self.bits_to_be_written = 0;
-			self.sink.write_all(&[self.byte_to_be_written])
+			self.bit_position = self.bit_position.next_multiple_of(8);
+
+			self.sink().write_all(&[byte_to_be_written])
 		} else {
 			Ok(())
 		}
@@ -416,7 +694,26 @@ impl<W: Write> BitpackWriter<W> {
 	/// This method will not force writing out any bits that did not yet made it to
 	/// a completed byte. To do that, use [`finalize`](Self::finalize).
 	pub fn flush(&mut self) -> Result<()> {
-		self.sink.flush()
+		self.sink().flush()
+	}
+
+	/// [Finalizes](Self::finalize) and tears down this bitpack writer, returning the
+	/// underlying byte sink.
+	///
+	/// Unlike dropping the writer, this allows reclaiming the sink and knowing whether
+	/// the finalization is successful. If it is not, the sink is dropped.
+	pub fn into_inner(mut self) -> Result<W> {
+		self.finalize()?;
+
+		// Unwrap is safe: the sink is only taken here, and this method consumes the
+		// writer. The finalization on drop does not use the sink after this
+		Ok(self.sink.take().unwrap())
+	}
+
+	/// Returns the wrapped byte sink.
+	fn sink(&mut self) -> &mut W {
+		// Unwrap is safe: the sink is only taken by into_inner, which consumes the writer
+		self.sink.as_mut().unwrap()
 	}
 }
 
@@ -67,8 +67,9 @@ fn reading_zero_length_integer_works() {
 	// Initialize the bitpacker to a state where lots of non-zero
 	// bits are available. This should help catching non-conformances
 	let mut bitpacker = BitpackReader {
-		last_read_byte: 0xFF,
-		remaining_bits: 8,
+		bit_buffer: 0xFF,
+		buffered_bits: 8,
+		bit_position: 0,
 		source: {
 			#[cfg(not(feature = "no-std"))]
 			{
@@ -89,8 +90,144 @@ fn reading_zero_length_integer_works() {
 	);
 
 	// Also assert that the internal bitpacker state is the same
-	assert_eq!(bitpacker.last_read_byte, 0xFF, "Unexpected internal state");
-	assert_eq!(bitpacker.remaining_bits, 8, "Unexpected internal state");
+	assert_eq!(bitpacker.bit_buffer, 0xFF, "Unexpected internal state");
+	assert_eq!(bitpacker.buffered_bits, 8, "Unexpected internal state");
+}
+
+#[test]
+fn peeking_and_skipping_bits_works() {
+	let mut bitpacked_data = &[0b1111_1100, 0b0100_1000, 0b1100_1110, 0b0000_0110][..];
+	let mut bitpacker = BitpackReader::new(&mut bitpacked_data);
+
+	// Peeking does not consume bits, so peeking the same bits twice returns them again
+	for _ in 0..2 {
+		assert_eq!(
+			bitpacker
+				.peek_bits(bitpacked_integer_width!(11))
+				.expect("No EOF expected"),
+			0b000_1111_1100
+		);
+	}
+
+	bitpacker.skip_bits(4).expect("No EOF expected");
+	assert_eq!(
+		bitpacker
+			.read_signed_integer(bitpacked_integer_width!(3))
+			.expect("No EOF expected"),
+		-1
+	);
+
+	// Skip more bits than are buffered, spanning a whole byte
+	bitpacker.skip_bits(7 + 8).expect("No EOF expected");
+	assert_eq!(
+		bitpacker
+			.peek_bits(bitpacked_integer_width!(6))
+			.expect("No EOF expected"),
+		0b0110_11
+	);
+
+	// Peeking past the end of the source fails, but keeps the buffered bits readable
+	bitpacker
+		.peek_bits(bitpacked_integer_width!(16))
+		.expect_err("Expected EOF");
+	assert_eq!(
+		bitpacker
+			.read_unsigned_integer(bitpacked_integer_width!(6))
+			.expect("No EOF expected"),
+		0b0110_11
+	);
+
+	bitpacker.skip_bits(4).expect("No EOF expected");
+	assert_eq!(bitpacker.bit_position(), 32);
+	bitpacker.skip_bits(1).expect_err("Expected EOF");
+}
+
+#[test]
+fn slice_reader_reads_like_stream_reader() {
+	// Read a pseudorandom sequence of integers with pseudorandom widths, which covers
+	// unaligned reads at every bit offset, from both readers
+	let bytes: [u8; 64] =
+		core::array::from_fn(|i| (i as u32).wrapping_mul(0x9E37_79B9).rotate_left(i as u32) as u8);
+	let mut stream_bitpacker = BitpackReader::new(&bytes[..]);
+	let mut slice_bitpacker = BitpackSliceReader::new(&bytes);
+
+	let mut width = 0;
+	loop {
+		width = (width * 7 + 3) % 33;
+		let width = BitpackedIntegerWidth::new(width).unwrap();
+
+		let expected_result = stream_bitpacker.read_unsigned_integer(width);
+		let actual_result = slice_bitpacker.read_unsigned_integer(width);
+
+		match (expected_result, actual_result) {
+			(Ok(expected), Ok(actual)) => assert_eq!(
+				actual,
+				expected,
+				"Unexpected integer read at bit {}",
+				slice_bitpacker.bit_position()
+			),
+			(Err(_), Err(_)) => break,
+			(expected, actual) => {
+				panic!("Unexpected read result: expected {expected:?}, got {actual:?}")
+			}
+		}
+
+		assert_eq!(
+			slice_bitpacker.bit_position(),
+			stream_bitpacker.bit_position()
+		);
+	}
+
+	// Failed reads should not consume any bits
+	assert_eq!(
+		slice_bitpacker.bit_position() + slice_bitpacker.remaining_bits(),
+		bytes.len() as u64 * 8
+	);
+}
+
+#[test]
+fn slice_reader_peeking_and_skipping_bits_works() {
+	let bitpacked_data = [0b1111_1100, 0b0100_1000, 0b1100_1110, 0b0000_0110];
+	let mut bitpacker = BitpackSliceReader::new(&bitpacked_data);
+
+	assert_eq!(bitpacker.remaining_bits(), 32);
+	assert_eq!(
+		bitpacker
+			.peek_bits(bitpacked_integer_width!(11))
+			.expect("No EOF expected"),
+		0b000_1111_1100
+	);
+	assert_eq!(bitpacker.bit_position(), 0);
+
+	bitpacker.skip_bits(4).expect("No EOF expected");
+	assert_eq!(
+		bitpacker
+			.read_signed_integer(bitpacked_integer_width!(3))
+			.expect("No EOF expected"),
+		-1
+	);
+
+	bitpacker.skip_bits(7 + 8).expect("No EOF expected");
+	assert_eq!(bitpacker.bit_position(), 22);
+	assert_eq!(bitpacker.remaining_bits(), 10);
+
+	bitpacker
+		.peek_bits(bitpacked_integer_width!(11))
+		.expect_err("Expected EOF");
+	bitpacker.skip_bits(11).expect_err("Expected EOF");
+	assert_eq!(
+		bitpacker
+			.read_unsigned_integer(bitpacked_integer_width!(10))
+			.expect("No EOF expected"),
+		0b0000_0110_11
+	);
+	assert_eq!(bitpacker.remaining_bits(), 0);
+	assert_eq!(
+		bitpacker
+			.peek_bits(bitpacked_integer_width!(0))
+			.expect("No EOF expected"),
+		0
+	);
 }
 
 #[test]
@@ -156,6 +293,45 @@ fn writing_zero_width_integers_does_nothing() {
 	);
 }
 
+#[test]
+#[cfg(not(feature = "no-std"))]
+fn writing_integers_ignores_bits_beyond_width() {
+	let mut bitpacked_data = vec![];
+	let mut bitpacker = BitpackWriter::new(&mut bitpacked_data);
+
+	// The second integer does not fit in the pending byte, and has a bit set
+	// beyond its width that should not be written
+	bitpacker
+		.write_unsigned_integer(0b1111, bitpacked_integer_width!(4))
+		.expect("No I/O error expected");
+	bitpacker
+		.write_unsigned_integer(0b1000_0001, bitpacked_integer_width!(5))
+		.expect("No I/O error expected");
+
+	drop(bitpacker);
+
+	assert_eq!(bitpacked_data, &[0b0001_1111, 0b0000_0000]);
+}
+
+#[test]
+#[cfg(not(feature = "no-std"))]
+fn writing_after_finalizing_starts_new_byte() {
+	let mut bitpacked_data = vec![];
+	let mut bitpacker = BitpackWriter::new(&mut bitpacked_data);
+
+	bitpacker
+		.write_unsigned_integer(0b101, bitpacked_integer_width!(3))
+		.expect("No I/O error expected");
+	bitpacker.finalize().expect("No I/O error expected");
+	bitpacker
+		.write_unsigned_integer(0b11, bitpacked_integer_width!(2))
+		.expect("No I/O error expected");
+
+	drop(bitpacker);
+
+	assert_eq!(bitpacked_data, &[0b101, 0b11]);
+}
+
 #[test]
 fn float32_unpack_works() {
 	assert_eq!(float32_unpack(VORBIS_FLOAT_WORD), VORBIS_FLOAT_VALUE);
@@ -179,3 +355,161 @@ fn float32_pack_real_values_works() {
 		assert_eq!(float32_pack(unpacked_float), word);
 	}
 }
+
+#[test]
+#[cfg(not(feature = "no-std"))]
+fn writing_bits_from_slice_works() {
+	let bitpacked_data = [
+		0b1111_1100,
+		0b0100_1000,
+		0b1100_1110,
+		0b0000_0110,
+		0b1010_0101
+	];
+
+	// Write the slice bits at every bit offset, which covers both the aligned and
+	// unaligned paths, and compare the result with writing them one by one
+	for bit_offset in 0..8 {
+		for bit_count in [0, 3, 8, 13, 32, 37, 40] {
+			let mut expected_data = vec![];
+			let mut expected_bitpacker = BitpackWriter::new(&mut expected_data);
+			expected_bitpacker
+				.write_unsigned_integer(u32::MAX, BitpackedIntegerWidth::new(bit_offset).unwrap())
+				.expect("No I/O error expected");
+			for i in 0..bit_count {
+				expected_bitpacker
+					.write_flag(bitpacked_data[i / 8] >> (i % 8) & 1 != 0)
+					.expect("No I/O error expected");
+			}
+			drop(expected_bitpacker);
+
+			let mut actual_data = vec![];
+			let mut actual_bitpacker = BitpackWriter::new(&mut actual_data);
+			actual_bitpacker
+				.write_unsigned_integer(u32::MAX, BitpackedIntegerWidth::new(bit_offset).unwrap())
+				.expect("No I/O error expected");
+			actual_bitpacker
+				.write_bits_from_slice(&bitpacked_data, bit_count as u64)
+				.expect("No I/O error expected");
+			assert_eq!(
+				actual_bitpacker.bit_position(),
+				bit_offset as u64 + bit_count as u64
+			);
+			drop(actual_bitpacker);
+
+			assert_eq!(
+				actual_data, expected_data,
+				"Unexpected bitpack write result for {bit_count} bits at offset {bit_offset}"
+			);
+		}
+	}
+}
+
+#[test]
+#[cfg(not(feature = "no-std"))]
+fn copying_bits_works() {
+	let bitpacked_data = [
+		0b1111_1100,
+		0b0100_1000,
+		0b1100_1110,
+		0b0000_0110,
+		0b1010_0101
+	];
+	let mut bitpack_reader = BitpackReader::new(&bitpacked_data[..]);
+
+	let mut bitpack_writer = BitpackWriter::new(vec![]);
+	bitpack_writer
+		.copy_bits_from(&mut bitpack_reader, 33)
+		.expect("No I/O error expected");
+	bitpack_writer
+		.copy_bits_from(&mut bitpack_reader, 7)
+		.expect("No I/O error expected");
+	bitpack_writer
+		.copy_bits_from(&mut bitpack_reader, 1)
+		.expect_err("Expected EOF");
+
+	assert_eq!(bitpack_reader.bit_position(), 40);
+	assert_eq!(bitpack_writer.bit_position(), 40);
+	assert_eq!(
+		bitpack_writer.into_inner().expect("No I/O error expected"),
+		bitpacked_data
+	);
+}
+
+#[test]
+#[cfg(not(feature = "no-std"))]
+fn copying_bits_from_slice_reader_works() {
+	let bytes: [u8; 16] =
+		core::array::from_fn(|i| (i as u32).wrapping_mul(0x9E37_79B9).rotate_left(i as u32) as u8);
+
+	// Copy runs of bits of every length at every reader and writer bit offset, which
+	// covers both the aligned and unaligned bulk copies, and check that they match
+	// the runs copied integer by integer
+	for reader_offset in 0..8 {
+		for writer_offset in 0..8 {
+			for bit_count in 0..=bytes.len() as u64 * 8 - reader_offset {
+				let mut slice_reader = BitpackSliceReader::new(&bytes);
+				slice_reader.skip_bits(reader_offset).unwrap();
+				let mut stream_reader = BitpackReader::new(&bytes[..]);
+				stream_reader.skip_bits(reader_offset).unwrap();
+
+				let mut expected_writer = BitpackWriter::new(vec![]);
+				expected_writer
+					.write_unsigned_integer(0, BitpackedIntegerWidth::new(writer_offset).unwrap())
+					.unwrap();
+				expected_writer
+					.copy_bits_from(&mut stream_reader, bit_count)
+					.unwrap();
+
+				let mut actual_writer = BitpackWriter::new(vec![]);
+				actual_writer
+					.write_unsigned_integer(0, BitpackedIntegerWidth::new(writer_offset).unwrap())
+					.unwrap();
+				actual_writer
+					.copy_bits_from_slice_reader(&mut slice_reader, bit_count)
+					.expect("No I/O error expected");
+
+				assert_eq!(slice_reader.bit_position(), reader_offset + bit_count);
+				assert_eq!(actual_writer.bit_position(), expected_writer.bit_position());
+				assert_eq!(
+					actual_writer.into_inner().unwrap(),
+					expected_writer.into_inner().unwrap(),
+					"Unexpected bits copied from reader offset {reader_offset} to writer \
+					offset {writer_offset}, bit count {bit_count}"
+				);
+			}
+		}
+	}
+
+	// Copying more bits than are left fails without consuming any
+	let mut slice_reader = BitpackSliceReader::new(&bytes);
+	slice_reader.skip_bits(3).unwrap();
+	let mut bitpack_writer = BitpackWriter::new(vec![]);
+	bitpack_writer
+		.copy_bits_from_slice_reader(&mut slice_reader, bytes.len() as u64 * 8 - 2)
+		.expect_err("Expected EOF");
+	assert_eq!(slice_reader.bit_position(), 3);
+	assert_eq!(bitpack_writer.bit_position(), 0);
+}
+
+#[test]
+#[cfg(not(feature = "no-std"))]
+fn reclaiming_writer_sink_finalizes_it() {
+	let mut bitpack_writer = BitpackWriter::new(vec![]);
+	bitpack_writer
+		.write_unsigned_integer(0b101, bitpacked_integer_width!(3))
+		.expect("No I/O error expected");
+	bitpack_writer.finalize().expect("No I/O error expected");
+	assert_eq!(bitpack_writer.bit_position(), 8);
+
+	// Bits written after finalizing should start a new byte
+	bitpack_writer
+		.write_unsigned_integer(0b11, bitpacked_integer_width!(2))
+		.expect("No I/O error expected");
+	assert_eq!(bitpack_writer.bit_position(), 10);
+
+	assert_eq!(
+		bitpack_writer.into_inner().expect("No I/O error expected"),
+		[0b101, 0b11]
+	);
+}